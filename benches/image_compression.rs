@@ -21,6 +21,7 @@ use anyhow::Error;
 use criterion::criterion_group;
 use criterion::criterion_main;
 use criterion::Criterion;
+use criterion::Throughput;
 use png::BitDepth;
 use png::ColorType;
 use png::Decoder;
@@ -28,6 +29,8 @@ use wprs::arc_slice::ArcSlice;
 use wprs::buffer_pointer::BufferPointer;
 use wprs::filtering;
 use wprs::sharding_compression::CompressedShard;
+use wprs::sharding_compression::CompressionAlgorithm;
+use wprs::sharding_compression::CompressionConfig;
 use wprs::sharding_compression::ShardingCompressor;
 use wprs::sharding_compression::ShardingDecompressor;
 use wprs::vec4u8::Vec4u8s;
@@ -103,7 +106,7 @@ fn compress_png(c: &mut Criterion, path: &Path) -> f64 {
 
     let n_compressors = NonZeroUsize::new(16).unwrap();
     let n_shards = NonZeroUsize::new(32).unwrap();
-    let compressor = ShardingCompressor::new(n_compressors, 1).unwrap();
+    let compressor = ShardingCompressor::new(n_compressors, CompressionConfig::default()).unwrap();
 
     let data_arcslice = ArcSlice::new(data);
 
@@ -165,7 +168,7 @@ fn filter_compress_png(c: &mut Criterion, path: &Path) -> f64 {
 
     let n_compressors = NonZeroUsize::new(16).unwrap();
     let n_shards = NonZeroUsize::new(32).unwrap();
-    let compressor = ShardingCompressor::new(n_compressors, 1).unwrap();
+    let compressor = ShardingCompressor::new(n_compressors, CompressionConfig::default()).unwrap();
 
     let mut compressed_shards = Vec::new();
 
@@ -266,5 +269,62 @@ fn compression_benchmark(c: &mut Criterion) {
     println!("");
 }
 
-criterion_group!(benches, compression_benchmark);
+/// A 4k BGRA frame resembling a desktop: flat areas, a gradient, and rows of
+/// "text", so that it compresses neither trivially nor not at all.
+fn synthetic_4k_frame() -> Vec<u8> {
+    let (width, height) = (3840, 2160);
+    let mut data = vec![0; width * height * 4];
+    for (i, pixel) in data.chunks_mut(4).enumerate() {
+        let (x, y) = (i % width, i / width);
+        let value = if y < 1080 {
+            0xf0
+        } else if x < 1920 {
+            (x * 255 / 1920) as u8
+        } else if (y / 16) % 2 == 0 && (x * 7 + y * 13) % 11 < 3 {
+            0x20
+        } else {
+            0xe0
+        };
+        pixel.copy_from_slice(&[value, value, value, 0xff]);
+    }
+    data
+}
+
+fn algorithm_benchmark(c: &mut Criterion) {
+    let data = ArcSlice::new(synthetic_4k_frame());
+    let uncompressed_size = data.len();
+    let n_compressors = NonZeroUsize::new(16).unwrap();
+    let n_shards = NonZeroUsize::new(32).unwrap();
+
+    let mut group = c.benchmark_group("compression algorithms: 4k frame");
+    group.throughput(Throughput::Bytes(uncompressed_size as u64));
+    for &algorithm in CompressionAlgorithm::ALL {
+        if !algorithm.is_supported() {
+            println!("skipping {algorithm:?}, which this build doesn't support");
+            continue;
+        }
+        let config = CompressionConfig {
+            algorithm,
+            ..Default::default()
+        };
+        let compressor = ShardingCompressor::new(n_compressors, config).unwrap();
+
+        let mut compressed_size = 0;
+        group.bench_function(format!("{algorithm:?}"), |b| {
+            b.iter(|| {
+                compressed_size = compressor
+                    .compress(n_shards, data.clone())
+                    .map(|shard| shard.data.len())
+                    .sum::<usize>();
+            })
+        });
+        println!(
+            "{algorithm:?} compression ratio: {:.1}",
+            uncompressed_size as f64 / compressed_size as f64
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, algorithm_benchmark, compression_benchmark);
 criterion_main!(benches);
@@ -25,44 +25,52 @@ fn prefix_sum_benchmark(c: &mut Criterion) {
     c.bench_function("prefix-sum-scalar", |b| {
         b.iter(|| prefix_sum::prefix_sum_scalar(black_box(&mut arr), 0))
     });
-    c.bench_function("prefix-sum-32", |b| {
-        b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<32>(black_box(&mut arr)) })
-    });
-    c.bench_function("prefix-sum-64", |b| {
-        b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<64>(black_box(&mut arr)) })
-    });
-    c.bench_function("prefix-sum-128", |b| {
-        b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<128>(black_box(&mut arr)) })
-    });
-    c.bench_function("prefix-sum-256", |b| {
-        b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<256>(black_box(&mut arr)) })
-    });
-    c.bench_function("prefix-sum-512", |b| {
-        b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<512>(black_box(&mut arr)) })
-    });
-    c.bench_function("prefix-sum-1024", |b| {
-        b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<1024>(black_box(&mut arr)) })
-    });
-    c.bench_function("prefix-sum-2048", |b| {
-        b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<2048>(black_box(&mut arr)) })
-    });
-    c.bench_function("prefix-sum-4096", |b| {
-        b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<4096>(black_box(&mut arr)) })
-    });
-    c.bench_function("prefix-sum-8192", |b| {
-        b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<8192>(black_box(&mut arr)) })
-    });
-    c.bench_function("prefix-sum-16384", |b| {
-        b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<16384>(black_box(&mut arr)) })
-    });
-    c.bench_function("prefix-sum-32768", |b| {
-        b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<32768>(black_box(&mut arr)) })
-    });
-    c.bench_function("prefix-sum-65536", |b| {
-        b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<65536>(black_box(&mut arr)) })
-    });
-    c.bench_function("prefix-sum-131072", |b| {
-        b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<131072>(black_box(&mut arr)) })
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        c.bench_function("prefix-sum-32", |b| {
+            b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<32>(black_box(&mut arr)) })
+        });
+        c.bench_function("prefix-sum-64", |b| {
+            b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<64>(black_box(&mut arr)) })
+        });
+        c.bench_function("prefix-sum-128", |b| {
+            b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<128>(black_box(&mut arr)) })
+        });
+        c.bench_function("prefix-sum-256", |b| {
+            b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<256>(black_box(&mut arr)) })
+        });
+        c.bench_function("prefix-sum-512", |b| {
+            b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<512>(black_box(&mut arr)) })
+        });
+        c.bench_function("prefix-sum-1024", |b| {
+            b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<1024>(black_box(&mut arr)) })
+        });
+        c.bench_function("prefix-sum-2048", |b| {
+            b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<2048>(black_box(&mut arr)) })
+        });
+        c.bench_function("prefix-sum-4096", |b| {
+            b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<4096>(black_box(&mut arr)) })
+        });
+        c.bench_function("prefix-sum-8192", |b| {
+            b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<8192>(black_box(&mut arr)) })
+        });
+        c.bench_function("prefix-sum-16384", |b| {
+            b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<16384>(black_box(&mut arr)) })
+        });
+        c.bench_function("prefix-sum-32768", |b| {
+            b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<32768>(black_box(&mut arr)) })
+        });
+        c.bench_function("prefix-sum-65536", |b| {
+            b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<65536>(black_box(&mut arr)) })
+        });
+        c.bench_function("prefix-sum-131072", |b| {
+            b.iter(|| unsafe { prefix_sum::prefix_sum_bs::<131072>(black_box(&mut arr)) })
+        });
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    c.bench_function("prefix-sum-neon", |b| {
+        b.iter(|| unsafe { prefix_sum::prefix_sum_neon(black_box(&mut arr)) })
     });
 }
 
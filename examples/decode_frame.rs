@@ -0,0 +1,58 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decodes a captured frame into a png.
+//!
+//! Usage: cargo run --example decode_frame -- <frame> <output.png>
+//!
+//! <frame> is an rkyv-serialized wprs::serialization::wayland::Buffer, as
+//! produced by `rkyv::to_bytes::<_, 1024>(&buffer)`.
+
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+
+use png::BitDepth;
+use png::ColorType;
+use png::Encoder;
+use wprs::prelude::*;
+use wprs::serialization::wayland::Buffer;
+use wprs::utils::frame;
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let [_, input, output] = args.as_slice() else {
+        bail!("usage: decode_frame <frame> <output.png>");
+    };
+
+    let bytes = fs::read(input).location(loc!())?;
+    let buffer: Buffer = rkyv::from_bytes(&bytes)
+        // The error type is not Send + Sync, which anyhow requires.
+        .map_err(|e| anyhow!("{e}"))
+        .location(loc!())?;
+
+    let image = frame::decode(&buffer.metadata, &buffer.data).location(loc!())?;
+
+    let writer = BufWriter::new(File::create(output).location(loc!())?);
+    let mut encoder = Encoder::new(writer, image.width, image.height);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(BitDepth::Eight);
+    encoder
+        .write_header()
+        .location(loc!())?
+        .write_image_data(&image.data)
+        .location(loc!())?;
+    Ok(())
+}
@@ -15,6 +15,7 @@
 use std::env;
 use std::fmt::Debug;
 use std::fs;
+use std::net::SocketAddr;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process;
@@ -160,10 +161,23 @@ pub fn socket() -> impl Parser<Option<PathBuf>> {
     bpaf::long("socket").argument::<PathBuf>("PATH").optional()
 }
 
+pub fn backend() -> impl Parser<Option<String>> {
+    bpaf::long("backend")
+        .argument::<String>("NAME")
+        .help("The name under which wprsd serves its desktop on the socket, and the name of the desktop wprsc connects to. wprsd instances with different names can share a socket. Empty by default.")
+        .optional()
+}
+
 pub fn default_control_socket_path(prefix: &str) -> PathBuf {
     Path::join(&socket_dir(), format!("{prefix}-ctrl.sock"))
 }
 
+/// user@host of the current process.
+pub fn default_client_identity() -> String {
+    let hostname = whoami::fallible::hostname().unwrap_or_else(|_| "unknown".to_string());
+    format!("{}@{}", whoami::username(), hostname)
+}
+
 pub fn control_socket() -> impl Parser<Option<PathBuf>> {
     bpaf::long("control-socket")
         .argument::<PathBuf>("PATH")
@@ -236,6 +250,29 @@ pub fn title_prefix() -> impl Parser<Option<String>> {
         .optional()
 }
 
+pub fn metrics_address() -> impl Parser<Option<Option<SocketAddr>>> {
+    bpaf::long("metrics-address")
+        .argument::<SocketAddr>("ADDR")
+        .help("Serve Prometheus metrics at http://ADDR/metrics and health checks at http://ADDR/health, for example 127.0.0.1:9100. Disabled if unset.")
+        .optional()
+        .map(|addr| addr.map(Some))
+}
+
+pub fn auth_secret_file() -> impl Parser<Option<Option<PathBuf>>> {
+    bpaf::long("auth-secret-file")
+        .argument::<PathBuf>("PATH")
+        .help("Authenticate the connection between wprsc and wprsd with the secret in PATH, which both ends need to have. Takes precedence over WPRS_AUTH_SECRET. Disabled if neither is set.")
+        .optional()
+        .map(|path| path.map(Some))
+}
+
+pub fn skip_unknown_objects() -> impl Parser<Option<bool>> {
+    bpaf::long("skip-unknown-objects")
+        .argument::<bool>("BOOL")
+        .help("Skip, with a warning, messages from the other end which fail to deserialize instead of disconnecting. Only meant for running wprsd and wprsc at different versions, e.g. during a staged upgrade: a skipped message can leave the two ends out of sync.")
+        .optional()
+}
+
 pub static LOG_PRIV_DATA: AtomicBool = AtomicBool::new(false);
 
 pub fn set_log_priv_data(val: bool) {
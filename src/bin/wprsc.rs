@@ -12,14 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::fs;
+use std::net::SocketAddr;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
 use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
 
 use bpaf::Parser;
 use optional_struct::optional_struct;
 use optional_struct::Applyable;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
+use smithay::reexports::calloop::channel;
 use smithay::reexports::calloop::channel::Event;
 use smithay::reexports::calloop::EventLoop;
 use smithay_client_toolkit::reexports::calloop_wayland_source::WaylandSource;
@@ -32,11 +39,17 @@ use wprs::args::Config;
 use wprs::args::OptionalConfig;
 use wprs::args::SerializableLevel;
 use wprs::client::ClientOptions;
+use wprs::client::PresentationMode;
 use wprs::client::WprsClientState;
+use wprs::client::PING_TIMEOUT;
 use wprs::control_server;
+use wprs::metrics;
 use wprs::prelude::*;
 use wprs::serialization;
+use wprs::serialization::auth::AuthSecret;
+use wprs::serialization::router::DEFAULT_BACKEND;
 use wprs::serialization::Serializer;
+use wprs::serialization::SerializerOptions;
 use wprs::utils;
 
 #[optional_struct]
@@ -48,7 +61,10 @@ pub struct WprscConfig {
     print_default_config_and_exit: bool,
     #[serde(skip_serializing)]
     config_file: PathBuf,
+    #[serde(skip_serializing)]
+    check: bool,
     pub socket: PathBuf,
+    pub backend: String,
     pub control_socket: PathBuf,
     // Optional fields don't get wrapped unless we specify it ourselves
     #[optional_wrap]
@@ -57,6 +73,23 @@ pub struct WprscConfig {
     pub file_log_level: SerializableLevel,
     pub log_priv_data: bool,
     pub title_prefix: String,
+    pub max_windows: usize,
+    pub min_window_size: i32,
+    pub presentation_mode: PresentationMode,
+    pub idle_timeout: u32,
+    pub predicted_cursor: bool,
+    pub restore_input_on_reconnect: bool,
+    pub clipboard_push_on_focus: bool,
+    pub skip_unknown_objects: bool,
+    #[optional_wrap]
+    pub identity: Option<String>,
+    #[optional_wrap]
+    pub profile: Option<String>,
+    #[optional_wrap]
+    pub auth_secret_file: Option<PathBuf>,
+    #[optional_wrap]
+    pub metrics_address: Option<SocketAddr>,
+    pub profiles: HashMap<String, SerializerOptions>,
 }
 
 impl Default for WprscConfig {
@@ -64,13 +97,28 @@ impl Default for WprscConfig {
         Self {
             print_default_config_and_exit: false,
             config_file: args::default_config_file("wprsc"),
+            check: false,
             socket: args::default_socket_path(),
+            backend: DEFAULT_BACKEND.to_string(),
             control_socket: args::default_control_socket_path("wprsc"),
             log_file: None,
             stderr_log_level: SerializableLevel(Level::INFO),
             file_log_level: SerializableLevel(Level::TRACE),
             log_priv_data: false,
             title_prefix: String::new(),
+            max_windows: 200,
+            min_window_size: 0,
+            presentation_mode: PresentationMode::FrameCallback,
+            idle_timeout: 0,
+            predicted_cursor: false,
+            restore_input_on_reconnect: true,
+            clipboard_push_on_focus: false,
+            skip_unknown_objects: false,
+            identity: None,
+            profile: None,
+            auth_secret_file: None,
+            metrics_address: None,
+            profiles: HashMap::new(),
         }
     }
 }
@@ -81,27 +129,150 @@ impl Config for WprscConfig {
     }
 }
 
+fn profile() -> impl Parser<Option<Option<String>>> {
+    bpaf::long("profile")
+        .argument::<String>("NAME")
+        .help("Name of the entry in profiles to use for tuning the connection. The wprs launcher sets this to the destination host.")
+        .optional()
+        .map(|profile| profile.map(Some))
+}
+
+fn check() -> impl Parser<Option<bool>> {
+    bpaf::long("check")
+        .help("Check that the configuration and environment are usable (the local compositor, the server socket, etc.), print the result of each check, and exit with a non-zero status if any failed.")
+        .flag(Some(true), None)
+}
+
+fn max_windows() -> impl Parser<Option<usize>> {
+    bpaf::long("max-windows")
+        .argument::<usize>("N")
+        .help("Maximum number of windows to create. Windows beyond the limit are not shown.")
+        .optional()
+}
+
+fn min_window_size() -> impl Parser<Option<i32>> {
+    bpaf::long("min-window-size")
+        .argument::<i32>("PIXELS")
+        .help("Windows aren't shown until they have a buffer at least this wide and tall, to hide the tiny helper windows some applications create. Windows whose contents are entirely in subsurfaces are never shown when this is set. 0 disables this.")
+        .optional()
+}
+
+fn presentation_mode() -> impl Parser<Option<PresentationMode>> {
+    bpaf::long("presentation-mode")
+        .argument::<String>("FrameCallback|Immediate")
+        .help("When to present buffers received from the server. FrameCallback waits for the local compositor to be ready for a new frame. Immediate presents each buffer as soon as it arrives, for lower latency at the cost of more CPU use.")
+        .parse(|s| ron::from_str(&s))
+        .optional()
+}
+
+fn idle_timeout() -> impl Parser<Option<u32>> {
+    bpaf::long("idle-timeout")
+        .argument::<u32>("SECONDS")
+        .help("Tell the server that the user is idle after this many seconds without input to the local compositor, and active again on the next input. Requires a compositor supporting ext-idle-notify-v1. 0, the default, disables this.")
+        .optional()
+}
+
+fn predicted_cursor() -> impl Parser<Option<bool>> {
+    bpaf::long("predicted-cursor")
+        .argument::<bool>("BOOL")
+        .help("Show the last cursor image as soon as the pointer enters a window instead of waiting for the application to set one, which takes a round trip to the server. The application's cursor image replaces it when it arrives.")
+        .optional()
+}
+
+fn restore_input_on_reconnect() -> impl Parser<Option<bool>> {
+    bpaf::long("restore-input-on-reconnect")
+        .argument::<bool>("BOOL")
+        .help("After reconnecting, tell the server which keys and buttons are held and where the pointer is, so that keys released while disconnected don't stay stuck in remote applications.")
+        .optional()
+}
+
+fn clipboard_push_on_focus() -> impl Parser<Option<bool>> {
+    bpaf::long("clipboard-push-on-focus")
+        .argument::<bool>("BOOL")
+        .help("Send the text in the local clipboard to the server whenever a remote window is focused, so that pasting into remote applications doesn't wait for a round trip. This exposes the clipboard to the server even if nothing is pasted.")
+        .optional()
+}
+
+fn identity() -> impl Parser<Option<Option<String>>> {
+    bpaf::long("identity")
+        .argument::<String>("STRING")
+        .help("Identifies this client in the server's logs and control server. Defaults to user@host.")
+        .optional()
+        .map(|identity| identity.map(Some))
+}
+
+fn serializer_options(
+    profile: Option<&str>,
+    profiles: &HashMap<String, SerializerOptions>,
+) -> SerializerOptions {
+    let Some(profile) = profile else {
+        return SerializerOptions::default();
+    };
+    match profiles.get(profile) {
+        Some(options) => {
+            let options = options.migrate_compression_level();
+            info!("using connection profile {profile:?}: {options:?}");
+            options
+        },
+        None => {
+            debug!("no connection profile named {profile:?}, using defaults");
+            SerializerOptions::default()
+        },
+    }
+}
+
 impl OptionalConfig<WprscConfig> for OptionalWprscConfig {
     fn parse_args() -> Self {
         let print_default_config_and_exit = args::print_default_config_and_exit();
         let config_file = args::config_file();
+        let check = check();
         let socket = args::socket();
+        let backend = args::backend();
         let control_socket = args::control_socket();
         let log_file = args::log_file();
         let stderr_log_level = args::stderr_log_level();
         let file_log_level = args::file_log_level();
         let log_priv_data = args::log_priv_data();
         let title_prefix = args::title_prefix();
+        let max_windows = max_windows();
+        let min_window_size = min_window_size();
+        let presentation_mode = presentation_mode();
+        let idle_timeout = idle_timeout();
+        let predicted_cursor = predicted_cursor();
+        let restore_input_on_reconnect = restore_input_on_reconnect();
+        let clipboard_push_on_focus = clipboard_push_on_focus();
+        let skip_unknown_objects = args::skip_unknown_objects();
+        let identity = identity();
+        let profile = profile();
+        let auth_secret_file = args::auth_secret_file();
+        let metrics_address = args::metrics_address();
+        // Profiles are only read from the config file.
+        let profiles = bpaf::pure(None);
         bpaf::construct!(Self {
             print_default_config_and_exit,
             config_file,
+            check,
             socket,
+            backend,
             control_socket,
             log_file,
             stderr_log_level,
             file_log_level,
             log_priv_data,
             title_prefix,
+            max_windows,
+            min_window_size,
+            presentation_mode,
+            idle_timeout,
+            predicted_cursor,
+            restore_input_on_reconnect,
+            clipboard_push_on_focus,
+            skip_unknown_objects,
+            identity,
+            profile,
+            auth_secret_file,
+            metrics_address,
+            profiles,
         })
         .to_options()
         .run()
@@ -116,9 +287,83 @@ impl OptionalConfig<WprscConfig> for OptionalWprscConfig {
     }
 }
 
+fn connect_to_compositor() -> Result<Connection> {
+    Connection::connect_to_env().map_err(|e| match e {
+        // give a more helpful/actionable message, since people who aren't familiar with wayland will run into this
+        ConnectError::NoCompositor => {
+            anyhow!("{e}, make sure you're running wprs from a wayland desktop environment")
+        },
+        _ => anyhow!(e),
+    })
+}
+
+/// The globals which WprsClientState::new requires.
+const REQUIRED_GLOBALS: [&str; 5] = [
+    "wl_compositor",
+    "wl_subcompositor",
+    "wl_shm",
+    "xdg_wm_base",
+    "wl_data_device_manager",
+];
+
+fn check_compositor() -> Result<()> {
+    let conn = connect_to_compositor().location(loc!())?;
+    let (globals, _) = registry_queue_init::<WprsClientState>(&conn).location(loc!())?;
+    let interfaces: Vec<String> = globals
+        .contents()
+        .with_list(|list| list.iter().map(|global| global.interface.clone()).collect());
+    for required in REQUIRED_GLOBALS {
+        if !interfaces.iter().any(|interface| interface == required) {
+            bail!("the compositor doesn't support {required}");
+        }
+    }
+    Ok(())
+}
+
+fn check_server_socket(socket: &Path) -> Result<()> {
+    // wprsd handles clients which disconnect without saying anything.
+    UnixStream::connect(socket).with_context(loc!(), || {
+        format!("unable to connect to {socket:?}, is wprsd running and the socket forwarded?")
+    })?;
+    Ok(())
+}
+
+fn check_socket_dir(socket: &Path) -> Result<()> {
+    fs::create_dir_all(socket.parent().location(loc!())?).with_context(loc!(), || {
+        format!("unable to create the directory for {socket:?}")
+    })
+}
+
+/// Runs all checks, printing their results, and returns whether they all
+/// passed.
+fn run_checks(config: &WprscConfig) -> bool {
+    let results = [
+        ("local compositor", check_compositor()),
+        ("server socket", check_server_socket(&config.socket)),
+        (
+            "control socket directory",
+            check_socket_dir(&config.control_socket),
+        ),
+    ];
+    let mut passed = true;
+    for (name, result) in results {
+        match result {
+            Ok(()) => println!("ok: {name}"),
+            Err(err) => {
+                println!("FAILED: {name}: {err:?}");
+                passed = false;
+            },
+        }
+    }
+    passed
+}
+
 fn main() -> Result<()> {
     let config = args::init_config::<WprscConfig, OptionalWprscConfig>();
     args::set_log_priv_data(config.log_priv_data);
+    if config.check {
+        process::exit(if run_checks(&config) { 0 } else { 1 });
+    }
     utils::configure_tracing(
         config.stderr_log_level.0,
         config.log_file,
@@ -127,18 +372,22 @@ fn main() -> Result<()> {
     .location(loc!())?;
     utils::exit_on_thread_panic();
 
-    let conn = Connection::connect_to_env().map_err(|e| match e {
-        // give a more helpful/actionable message, since people who aren't familiar with wayland will run into this
-        ConnectError::NoCompositor => {
-            anyhow!("{e}, make sure you're running wprs from a wayland desktop environment")
-        },
-        _ => anyhow!(e),
-    })?;
+    let conn = connect_to_compositor().location(loc!())?;
 
-    let (globals, event_queue) = registry_queue_init(&conn)?;
+    let (globals, mut event_queue) = registry_queue_init(&conn)?;
 
     fs::create_dir_all(config.socket.parent().location(loc!())?).location(loc!())?;
-    let mut serializer = Serializer::new_client(&config.socket).with_context(loc!(), || {
+    let mut serializer_options = serializer_options(config.profile.as_deref(), &config.profiles);
+    // Either the flag or the profile can turn it on.
+    serializer_options.skip_unknown_objects |= config.skip_unknown_objects;
+    let auth_secret = AuthSecret::load(config.auth_secret_file.as_deref()).location(loc!())?;
+    let mut serializer = Serializer::new_client_for_backend(
+        &config.socket,
+        &config.backend,
+        serializer_options,
+        auth_secret,
+    )
+    .with_context(loc!(), || {
         format!(
             "Serializer unable to connect to socket {:?}.",
             &config.socket
@@ -146,12 +395,22 @@ fn main() -> Result<()> {
     })?;
     let reader = serializer.reader().location(loc!())?;
     let writer = serializer.writer();
-    writer.send(serialization::SendType::Object(
-        serialization::Event::WprsClientConnect,
-    ));
+    if let Some(metrics_address) = config.metrics_address {
+        metrics::start(metrics_address, serializer.stats()).location(loc!())?;
+    }
 
     let options = ClientOptions {
         title_prefix: config.title_prefix,
+        max_windows: config.max_windows,
+        min_window_size: config.min_window_size,
+        presentation_mode: config.presentation_mode,
+        idle_timeout: config.idle_timeout,
+        predicted_cursor: config.predicted_cursor,
+        restore_input_on_reconnect: config.restore_input_on_reconnect,
+        clipboard_push_on_focus: config.clipboard_push_on_focus,
+        identity: config
+            .identity
+            .unwrap_or_else(args::default_client_identity),
     };
     let mut state = WprsClientState::new(
         event_queue.handle(),
@@ -162,6 +421,13 @@ fn main() -> Result<()> {
     )
     .location(loc!())?;
 
+    // Receive the wl_shm formats before advertising them to the server.
+    event_queue.roundtrip(&mut state).location(loc!())?;
+    writer.send(serialization::SendType::Object(
+        serialization::Event::WprsClientConnect(state.client_capabilities()),
+    ));
+    state.announce_outputs();
+
     let mut event_loop = EventLoop::try_new()?;
 
     event_loop.handle().insert_source(
@@ -176,6 +442,34 @@ fn main() -> Result<()> {
         },
     ).unwrap();
 
+    // The control server runs on its own thread, so queries of the client
+    // state are sent to the event loop along with a channel for the reply.
+    let (surfaces_tx, surfaces_rx) = channel::channel::<crossbeam_channel::Sender<String>>();
+    event_loop
+        .handle()
+        .insert_source(
+            surfaces_rx,
+            |event, _metadata, state: &mut WprsClientState| {
+                if let Event::Msg(reply) = event {
+                    let mappings = serde_json::to_string(&state.surface_mappings())
+                        .expect("SurfaceMapping contains no maps with non-string keys");
+                    // The control connection may have gone away in the meantime.
+                    _ = reply.send(mappings);
+                }
+            },
+        )
+        .unwrap();
+
+    let (rtt_tx, rtt_rx) = channel::channel::<crossbeam_channel::Sender<Duration>>();
+    event_loop
+        .handle()
+        .insert_source(rtt_rx, |event, _metadata, state: &mut WprsClientState| {
+            if let Event::Msg(reply) = event {
+                state.ping(reply);
+            }
+        })
+        .unwrap();
+
     {
         let capabilities = state.capabilities.clone();
         control_server::start(config.control_socket, move |input: &str| {
@@ -183,6 +477,20 @@ fn main() -> Result<()> {
                 // TODO: make the input use json when we have more commands
                 "caps" => serde_json::to_string(&capabilities.get())
                     .expect("a map with non-string keys was added to Capabilities"),
+                "surfaces" => {
+                    let (reply_tx, reply_rx) = crossbeam_channel::bounded(1);
+                    surfaces_tx.send(reply_tx).location(loc!())?;
+                    reply_rx.recv().location(loc!())?
+                },
+                // Pings wprsd and returns the round-trip time in milliseconds.
+                "rtt" => {
+                    let (reply_tx, reply_rx) = crossbeam_channel::bounded(1);
+                    rtt_tx.send(reply_tx).location(loc!())?;
+                    let rtt = reply_rx
+                        .recv_timeout(PING_TIMEOUT)
+                        .context(loc!(), "wprsd didn't answer, is wprsc connected?")?;
+                    serde_json::to_string(&(rtt.as_secs_f64() * 1000.0)).location(loc!())?
+                },
                 _ => {
                     bail!("Unknown command: {input:?}")
                 },
@@ -195,7 +503,11 @@ fn main() -> Result<()> {
         .insert(event_loop.handle())
         .location(loc!())?;
 
-    event_loop.run(None, &mut state, |_| {}).location(loc!())?;
-
-    Ok(())
+    let result = event_loop
+        .run(None, &mut state, WprsClientState::send_touch_frame)
+        .location(loc!());
+    // Stop receiving from the serializer before shutting it down.
+    drop(event_loop);
+    state.shutdown().location(loc!())?;
+    result
 }
@@ -13,8 +13,10 @@
 // limitations under the License.
 
 use std::fs;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::process::Command;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -23,6 +25,7 @@ use optional_struct::optional_struct;
 use optional_struct::Applyable;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
+use smithay::reexports::calloop::channel;
 use smithay::reexports::calloop::channel::Event;
 use smithay::reexports::calloop::generic::Generic;
 use smithay::reexports::calloop::EventLoop;
@@ -30,16 +33,30 @@ use smithay::reexports::calloop::Interest;
 use smithay::reexports::calloop::Mode;
 use smithay::reexports::calloop::PostAction;
 use smithay::reexports::wayland_server::Display;
+use smithay::utils::SERIAL_COUNTER;
 use smithay::wayland::socket::ListeningSocketSource;
 use tracing::Level;
 use wprs::args;
 use wprs::args::Config;
 use wprs::args::OptionalConfig;
 use wprs::args::SerializableLevel;
+use wprs::control_server;
+use wprs::metrics;
 use wprs::prelude::*;
+use wprs::serialization::auth::AuthSecret;
+use wprs::serialization::router::DEFAULT_BACKEND;
+use wprs::serialization::wayland::WlSurfaceId;
+use wprs::serialization::RecvType;
 use wprs::serialization::Serializer;
+use wprs::serialization::SerializerOptions;
+use wprs::server::input_recording::InputRecorder;
+use wprs::server::input_recording::InputReplay;
 use wprs::server::smithay_handlers::ClientState;
+use wprs::server::DamageOnlyCommits;
+use wprs::server::FramePacing;
+use wprs::server::KeyboardFocusLossBehavior;
 use wprs::server::WprsServerState;
+use wprs::sharding_compression::CompressionConfig;
 use wprs::utils;
 
 #[optional_struct]
@@ -53,7 +70,11 @@ pub struct WprsdConfig {
     config_file: PathBuf,
     wayland_display: String,
     socket: PathBuf,
+    backend: String,
+    control_socket: PathBuf,
     framerate: u32,
+    frame_pacing: FramePacing,
+    damage_only_commits: DamageOnlyCommits,
     // Optional fields don't get wrapped unless we specify it ourselves
     #[optional_wrap]
     log_file: Option<PathBuf>,
@@ -65,6 +86,24 @@ pub struct WprsdConfig {
     xwayland_xdg_shell_wayland_debug: bool,
     xwayland_xdg_shell_args: Vec<String>,
     kde_server_side_decorations: bool,
+    keyboard_focus_loss_behavior: KeyboardFocusLossBehavior,
+    max_object_size: usize,
+    max_raw_buffer_size: usize,
+    raw_buffer_flush_delay_us: u64,
+    compression: CompressionConfig,
+    skip_unknown_objects: bool,
+    compress_video: bool,
+    frame_checksums: bool,
+    max_in_flight_frames: u32,
+    max_clipboard_cache_bytes: usize,
+    #[optional_wrap]
+    metrics_address: Option<SocketAddr>,
+    #[optional_wrap]
+    auth_secret_file: Option<PathBuf>,
+    #[optional_wrap]
+    record_input: Option<PathBuf>,
+    #[optional_wrap]
+    replay_input: Option<PathBuf>,
 }
 
 impl Default for WprsdConfig {
@@ -74,7 +113,11 @@ impl Default for WprsdConfig {
             config_file: args::default_config_file("wprsd"),
             wayland_display: "wprs-0".to_string(),
             socket: args::default_socket_path(),
+            backend: DEFAULT_BACKEND.to_string(),
+            control_socket: args::default_control_socket_path("wprsd"),
             framerate: 60,
+            frame_pacing: FramePacing::Timer,
+            damage_only_commits: DamageOnlyCommits::Forward,
             log_file: None,
             stderr_log_level: SerializableLevel(Level::INFO),
             file_log_level: SerializableLevel(Level::TRACE),
@@ -84,6 +127,20 @@ impl Default for WprsdConfig {
             xwayland_xdg_shell_wayland_debug: false,
             xwayland_xdg_shell_args: Vec::new(),
             kde_server_side_decorations: false,
+            keyboard_focus_loss_behavior: KeyboardFocusLossBehavior::Release,
+            max_object_size: SerializerOptions::default().max_object_size,
+            max_raw_buffer_size: SerializerOptions::default().max_raw_buffer_size,
+            raw_buffer_flush_delay_us: SerializerOptions::default().raw_buffer_flush_delay_us,
+            compression: SerializerOptions::default().compression(),
+            skip_unknown_objects: SerializerOptions::default().skip_unknown_objects,
+            compress_video: true,
+            frame_checksums: false,
+            max_in_flight_frames: 3,
+            max_clipboard_cache_bytes: 16 << 20,
+            metrics_address: None,
+            auth_secret_file: None,
+            record_input: None,
+            replay_input: None,
         }
     }
 }
@@ -128,13 +185,114 @@ fn kde_server_side_decorations() -> impl Parser<Option<bool>> {
         .optional()
 }
 
+fn frame_pacing() -> impl Parser<Option<FramePacing>> {
+    bpaf::long("frame-pacing")
+        .argument::<String>("Timer|Client")
+        .help("When to tell applications to draw their next frame. Timer does so at the configured framerate. Client does so when wprsc presents the previous frame, so that applications draw only as fast as frames are displayed.")
+        .parse(|s| ron::from_str(&s))
+        .optional()
+}
+
+fn damage_only_commits() -> impl Parser<Option<DamageOnlyCommits>> {
+    bpaf::long("damage-only-commits")
+        .argument::<String>("Forward|Ignore")
+        .help("What to do when an application damages a window without attaching a new buffer. Forward has wprsc present the window's current buffer again. Ignore drops such updates.")
+        .parse(|s| ron::from_str(&s))
+        .optional()
+}
+
+fn keyboard_focus_loss_behavior() -> impl Parser<Option<KeyboardFocusLossBehavior>> {
+    bpaf::long("keyboard-focus-loss-behavior")
+        .argument::<String>("Release|Reconcile")
+        .help("What to do with keys which are held when a window loses keyboard focus. Release releases them immediately. Reconcile keeps them held and, when a window regains focus, releases any which were released in the meantime.")
+        .parse(|s| ron::from_str(&s))
+        .optional()
+}
+
+fn max_object_size() -> impl Parser<Option<usize>> {
+    bpaf::long("max-object-size")
+        .argument::<usize>("BYTES")
+        .help("The largest protocol object, in bytes and before compression, to accept from wprsc. A connection which sends a larger object is dropped.")
+        .optional()
+}
+
+fn max_raw_buffer_size() -> impl Parser<Option<usize>> {
+    bpaf::long("max-raw-buffer-size")
+        .argument::<usize>("BYTES")
+        .help("The largest raw buffer, in bytes and before compression, to accept from wprsc. Surface contents are sent by wprsd, not wprsc, so this only drops misbehaving clients; the limit on the surface contents wprsc accepts is max_raw_buffer_size in its profiles.")
+        .optional()
+}
+
+fn record_input() -> impl Parser<Option<Option<PathBuf>>> {
+    bpaf::long("record-input")
+        .argument::<PathBuf>("PATH")
+        .help("Record the keyboard, pointer, and touch events received from wprsc, with their timings, to PATH.")
+        .optional()
+        .map(|path| path.map(Some))
+}
+
+fn replay_input() -> impl Parser<Option<Option<PathBuf>>> {
+    bpaf::long("replay-input")
+        .argument::<PathBuf>("PATH")
+        .help("Replay the input events recorded with --record-input from PATH, at their original timings relative to wprsd startup.")
+        .optional()
+        .map(|path| path.map(Some))
+}
+
+fn raw_buffer_flush_delay_us() -> impl Parser<Option<u64>> {
+    bpaf::long("raw-buffer-flush-delay-us")
+        .argument::<u64>("MICROSECONDS")
+        .help("How long to hold back surface contents so that messages sent shortly after are written to wprsc with them in a single flush. 0 sends them as soon as nothing else is queued.")
+        .optional()
+}
+
+fn compression() -> impl Parser<Option<CompressionConfig>> {
+    bpaf::long("compression")
+        .argument::<String>("(algorithm: None|Zstd|Lz4|Brotli, level: LEVEL)")
+        .help("How to compress data sent to wprsc. Lz4 and Brotli require building wprs with the lz4 and brotli features, both on this end and on wprsc's. The level's range depends on the algorithm: 1 to 22 for Zstd, 0 to 11 for Brotli, and above 1 for Lz4's high compression mode.")
+        .parse(|s| ron::from_str(&s))
+        .optional()
+}
+
+fn compress_video() -> impl Parser<Option<bool>> {
+    bpaf::long("compress-video")
+        .argument::<bool>("BOOL")
+        .help("Whether to compress the contents of surfaces which applications mark as video with the content-type protocol. Video compresses poorly, so sending it uncompressed saves CPU time on the server at the cost of bandwidth.")
+        .optional()
+}
+
+fn frame_checksums() -> impl Parser<Option<bool>> {
+    bpaf::long("frame-checksums")
+        .argument::<bool>("BOOL")
+        .help("Send a checksum of each frame before it is encoded, which wprsc compares with the frame it decoded and logs, to tell whether corruption happens in transport and decoding or before wprsd receives the frame. Costs some CPU time on both ends.")
+        .optional()
+}
+
+fn max_in_flight_frames() -> impl Parser<Option<u32>> {
+    bpaf::long("max-in-flight-frames")
+        .argument::<u32>("FRAMES")
+        .help("How many frames of a window may be on their way to wprsc before further frames of the window are dropped, until wprsc catches up. This keeps a slow connection or wprsc from falling further and further behind a fast application. 0 never drops frames.")
+        .optional()
+}
+
+fn max_clipboard_cache_bytes() -> impl Parser<Option<usize>> {
+    bpaf::long("max-clipboard-cache-bytes")
+        .argument::<usize>("BYTES")
+        .help("How much of the contents of the client's clipboard to keep, so that applications can paste them without asking the client again, including while the client is disconnected. Larger contents are requested from the client on every paste.")
+        .optional()
+}
+
 impl OptionalConfig<WprsdConfig> for OptionalWprsdConfig {
     fn parse_args() -> Self {
         let print_default_config_and_exit = args::print_default_config_and_exit();
         let config_file = args::config_file();
         let wayland_display = args::wayland_display();
         let socket = args::socket();
+        let backend = args::backend();
+        let control_socket = args::control_socket();
         let framerate = args::framerate();
+        let frame_pacing = frame_pacing();
+        let damage_only_commits = damage_only_commits();
         let log_file = args::log_file();
         let stderr_log_level = args::stderr_log_level();
         let file_log_level = args::file_log_level();
@@ -144,12 +302,30 @@ impl OptionalConfig<WprsdConfig> for OptionalWprsdConfig {
         let xwayland_xdg_shell_wayland_debug = xwayland_xdg_shell_wayland_debug();
         let xwayland_xdg_shell_args = xwayland_xdg_shell_args();
         let kde_server_side_decorations = kde_server_side_decorations();
+        let keyboard_focus_loss_behavior = keyboard_focus_loss_behavior();
+        let max_object_size = max_object_size();
+        let max_raw_buffer_size = max_raw_buffer_size();
+        let raw_buffer_flush_delay_us = raw_buffer_flush_delay_us();
+        let compression = compression();
+        let skip_unknown_objects = args::skip_unknown_objects();
+        let compress_video = compress_video();
+        let frame_checksums = frame_checksums();
+        let max_in_flight_frames = max_in_flight_frames();
+        let max_clipboard_cache_bytes = max_clipboard_cache_bytes();
+        let metrics_address = args::metrics_address();
+        let auth_secret_file = args::auth_secret_file();
+        let record_input = record_input();
+        let replay_input = replay_input();
         bpaf::construct!(Self {
             print_default_config_and_exit,
             config_file,
             wayland_display,
             socket,
+            backend,
+            control_socket,
             framerate,
+            frame_pacing,
+            damage_only_commits,
             log_file,
             stderr_log_level,
             file_log_level,
@@ -159,6 +335,20 @@ impl OptionalConfig<WprsdConfig> for OptionalWprsdConfig {
             xwayland_xdg_shell_wayland_debug,
             xwayland_xdg_shell_args,
             kde_server_side_decorations,
+            keyboard_focus_loss_behavior,
+            max_object_size,
+            max_raw_buffer_size,
+            raw_buffer_flush_delay_us,
+            compression,
+            skip_unknown_objects,
+            compress_video,
+            frame_checksums,
+            max_in_flight_frames,
+            max_clipboard_cache_bytes,
+            metrics_address,
+            auth_secret_file,
+            record_input,
+            replay_input,
         })
         .to_options()
         .run()
@@ -173,6 +363,24 @@ impl OptionalConfig<WprsdConfig> for OptionalWprsdConfig {
     }
 }
 
+#[derive(Debug, Serialize)]
+struct Connection {
+    client: String,
+    /// Whether the client reported that its user is idle.
+    idle: bool,
+}
+
+/// Keyboard focus commands from the control server, which runs on its own
+/// thread, to the event loop.
+enum FocusRequest {
+    Get,
+    Set(WlSurfaceId),
+}
+
+fn focus_json(state: &WprsServerState) -> Result<String> {
+    serde_json::to_string(&state.keyboard_focus().map(|surface_id| surface_id.0)).location(loc!())
+}
+
 fn init_wayland_listener(
     wayland_display: &str,
     mut display: Display<WprsServerState>,
@@ -243,8 +451,26 @@ pub fn main() -> Result<()> {
     utils::exit_on_thread_panic();
 
     fs::create_dir_all(config.socket.parent().location(loc!())?).location(loc!())?;
-    let mut serializer = Serializer::new_server(&config.socket).location(loc!())?;
+    let serializer_options = SerializerOptions {
+        max_object_size: config.max_object_size,
+        max_raw_buffer_size: config.max_raw_buffer_size,
+        raw_buffer_flush_delay_us: config.raw_buffer_flush_delay_us,
+        compression: Some(config.compression),
+        skip_unknown_objects: config.skip_unknown_objects,
+        ..Default::default()
+    };
+    let auth_secret = AuthSecret::load(config.auth_secret_file.as_deref()).location(loc!())?;
+    let mut serializer = Serializer::new_server_for_backend(
+        &config.socket,
+        &config.backend,
+        serializer_options,
+        auth_secret,
+    )
+    .location(loc!())?;
     let reader = serializer.reader().location(loc!())?;
+    if let Some(metrics_address) = config.metrics_address {
+        metrics::start(metrics_address, serializer.stats()).location(loc!())?;
+    }
 
     let mut event_loop = EventLoop::try_new().location(loc!())?;
     let display: Display<WprsServerState> = Display::new().location(loc!())?;
@@ -258,11 +484,83 @@ pub fn main() -> Result<()> {
         config.enable_xwayland,
         frame_interval,
         config.kde_server_side_decorations,
+        config.keyboard_focus_loss_behavior,
+        config.frame_pacing,
+        config.damage_only_commits,
+        config.compress_video,
+        config.frame_checksums,
+        config.max_in_flight_frames,
+        config.max_clipboard_cache_bytes,
     );
 
     init_wayland_listener(&config.wayland_display, display, &mut state, &event_loop)
         .location(loc!())?;
 
+    let (focus_tx, focus_rx) =
+        channel::channel::<(FocusRequest, crossbeam_channel::Sender<Result<String>>)>();
+    event_loop
+        .handle()
+        .insert_source(focus_rx, |event, _metadata, state| {
+            if let Event::Msg((request, reply)) = event {
+                let result = match request {
+                    FocusRequest::Get => focus_json(state),
+                    FocusRequest::Set(surface_id) => state
+                        .set_keyboard_focus(&surface_id, SERIAL_COUNTER.next_serial())
+                        .and_then(|()| focus_json(state)),
+                };
+                // The control connection may have gone away in the meantime.
+                _ = reply.send(result);
+            }
+        })
+        .unwrap();
+
+    {
+        let stats = state.serializer.stats();
+        let client_identity = state.client_identity.clone();
+        let client_idle = state.client_idle.clone();
+        let focus = move |request: FocusRequest| -> Result<String> {
+            let (reply_tx, reply_rx) = crossbeam_channel::bounded(1);
+            focus_tx.send((request, reply_tx)).location(loc!())?;
+            reply_rx.recv().location(loc!())?
+        };
+        control_server::start(config.control_socket, move |input: &str| {
+            Ok(match input {
+                // Returns the id of the surface with keyboard focus, as in
+                // wprsc's "surfaces" command, or null.
+                "focus" => focus(FocusRequest::Get)?,
+                "connections" => {
+                    let connections: Vec<Connection> = if stats.connected() {
+                        vec![Connection {
+                            client: client_identity.lock().unwrap().clone(),
+                            idle: client_idle.load(Ordering::Relaxed),
+                        }]
+                    } else {
+                        Vec::new()
+                    };
+                    serde_json::to_string(&connections).location(loc!())?
+                },
+                // Bytes sent for each surface's buffers, the surfaces with the
+                // most bytes sent first.
+                "surface_stats" => {
+                    serde_json::to_string(&stats.surface_stats()).location(loc!())?
+                },
+                // The same as http://METRICS_ADDRESS/health.
+                "health" => serde_json::to_string(&stats.health()).location(loc!())?,
+                _ => match input.split_once(' ') {
+                    // Gives keyboard focus to the surface with the given id.
+                    Some(("focus", surface_id)) => {
+                        let surface_id = surface_id.parse::<u64>().location(loc!())?;
+                        focus(FocusRequest::Set(WlSurfaceId(surface_id)))?
+                    },
+                    _ => {
+                        bail!("Unknown command: {input:?}")
+                    },
+                },
+            })
+        })
+        .location(loc!())?;
+    }
+
     if config.enable_xwayland {
         start_xwayland_xdg_shell(
             &config.wayland_display,
@@ -278,12 +576,33 @@ pub fn main() -> Result<()> {
         .add_keyboard(Default::default(), 200, 200)
         .location(loc!())?;
     let _pointer = state.seat.add_pointer();
+    let _touch = state.seat.add_touch();
 
+    if let Some(replay_input) = &config.replay_input {
+        InputReplay::open(replay_input)
+            .location(loc!())?
+            .start(&event_loop.handle())
+            .location(loc!())?;
+    }
+
+    let mut input_recorder = config
+        .record_input
+        .as_ref()
+        .map(InputRecorder::create)
+        .transpose()
+        .location(loc!())?;
     event_loop
         .handle()
-        .insert_source(reader, |event, _metadata, state| {
+        .insert_source(reader, move |event, _metadata, state| {
             match event {
-                Event::Msg(msg) => state.handle_event(msg),
+                Event::Msg(msg) => {
+                    if let (Some(input_recorder), RecvType::Object(event)) =
+                        (&mut input_recorder, &msg)
+                    {
+                        input_recorder.record(event).log_and_ignore(loc!());
+                    }
+                    state.handle_event(msg)
+                },
                 Event::Closed => {
                     unreachable!("reader is an in-memory channel whose write end has the same lifetime as serializer: the lifetime of the program.")
                 },
@@ -20,6 +20,8 @@ use optional_struct::optional_struct;
 use optional_struct::Applyable;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
+use smithay::reexports::calloop::channel;
+use smithay::reexports::calloop::channel::Event;
 use smithay::reexports::calloop::generic::Generic;
 use smithay::reexports::calloop::EventLoop;
 use smithay::reexports::calloop::Interest;
@@ -35,9 +37,12 @@ use wprs::args;
 use wprs::args::Config;
 use wprs::args::OptionalConfig;
 use wprs::args::SerializableLevel;
+use wprs::control_server;
 use wprs::prelude::*;
 use wprs::utils;
 use wprs::xwayland_xdg_shell::compositor::DecorationBehavior;
+use wprs::xwayland_xdg_shell::compositor::FrameTheme;
+use wprs::xwayland_xdg_shell::compositor::TitleButtons;
 use wprs::xwayland_xdg_shell::compositor::XwaylandOptions;
 use wprs::xwayland_xdg_shell::WprsState;
 
@@ -52,6 +57,7 @@ pub struct XwaylandXdgShellConfig {
     config_file: PathBuf,
     wayland_display: String,
     display: u32,
+    control_socket: PathBuf,
     // Optional fields don't get wrapped unless we specify it ourselves
     #[optional_wrap]
     log_file: Option<PathBuf>,
@@ -60,6 +66,8 @@ pub struct XwaylandXdgShellConfig {
     log_priv_data: bool,
     xwayland_wayland_debug: bool,
     decoration_behavior: DecorationBehavior,
+    title_buttons: TitleButtons,
+    frame_theme: FrameTheme,
 }
 
 impl Default for XwaylandXdgShellConfig {
@@ -69,12 +77,15 @@ impl Default for XwaylandXdgShellConfig {
             config_file: args::default_config_file("xwayland-xdg-shell"),
             wayland_display: "xwayland-xdg-shell-0".to_string(),
             display: 100,
+            control_socket: args::default_control_socket_path("xwayland-xdg-shell"),
             log_file: None,
             stderr_log_level: SerializableLevel(Level::INFO),
             file_log_level: SerializableLevel(Level::TRACE),
             log_priv_data: false,
             xwayland_wayland_debug: false,
             decoration_behavior: DecorationBehavior::Auto,
+            title_buttons: TitleButtons::All,
+            frame_theme: FrameTheme::Dark,
         }
     }
 }
@@ -102,29 +113,50 @@ fn decoration_behavior() -> impl Parser<Option<DecorationBehavior>> {
         .optional()
 }
 
+fn title_buttons() -> impl Parser<Option<TitleButtons>> {
+    bpaf::long("title-buttons")
+        .argument::<String>("All|Maximize|Minimize|CloseOnly")
+        .parse(|s| ron::from_str(&s))
+        .optional()
+}
+
+fn frame_theme() -> impl Parser<Option<FrameTheme>> {
+    bpaf::long("frame-theme")
+        .help("Colors of the decorations drawn for X11 windows. Custom colors can be set in the config file.")
+        .argument::<String>("Dark|Light")
+        .parse(|s| ron::from_str(&s))
+        .optional()
+}
+
 impl OptionalConfig<XwaylandXdgShellConfig> for OptionalXwaylandXdgShellConfig {
     fn parse_args() -> Self {
         let print_default_config_and_exit = args::print_default_config_and_exit();
         let config_file = args::config_file();
         let wayland_display = args::wayland_display();
         let display = display();
+        let control_socket = args::control_socket();
         let log_file = args::log_file();
         let stderr_log_level = args::stderr_log_level();
         let file_log_level = args::file_log_level();
         let log_priv_data = args::log_priv_data();
         let xwayland_wayland_debug = xwayland_wayland_debug();
         let decoration_behavior = decoration_behavior();
+        let title_buttons = title_buttons();
+        let frame_theme = frame_theme();
         bpaf::construct!(Self {
             print_default_config_and_exit,
             config_file,
             wayland_display,
             display,
+            control_socket,
             log_file,
             stderr_log_level,
             file_log_level,
             log_priv_data,
             xwayland_wayland_debug,
             decoration_behavior,
+            title_buttons,
+            frame_theme,
         })
         .to_options()
         .run()
@@ -202,6 +234,8 @@ pub fn main() -> Result<()> {
         conn.clone(),
         event_loop.handle(),
         config.decoration_behavior,
+        config.title_buttons,
+        config.frame_theme,
         xwayland_options,
     )
     .location(loc!())?;
@@ -214,6 +248,36 @@ pub fn main() -> Result<()> {
         .add_keyboard(Default::default(), 200, 200)
         .location(loc!())?;
     let _pointer = seat.add_pointer();
+    let _touch = seat.add_touch();
+
+    // The control server runs on its own thread, so queries of the state are
+    // sent to the event loop along with a channel for the reply.
+    let (damage_tx, damage_rx) = channel::channel::<crossbeam_channel::Sender<String>>();
+    event_loop
+        .handle()
+        .insert_source(damage_rx, |event, _metadata, state: &mut WprsState| {
+            if let Event::Msg(reply) = event {
+                let stats = serde_json::to_string(&state.damage_stats())
+                    .expect("SurfaceDamageStats contains no maps with non-string keys");
+                // The control connection may have gone away in the meantime.
+                _ = reply.send(stats);
+            }
+        })
+        .unwrap();
+
+    control_server::start(config.control_socket, move |input: &str| {
+        Ok(match input {
+            "damage" => {
+                let (reply_tx, reply_rx) = crossbeam_channel::bounded(1);
+                damage_tx.send(reply_tx).location(loc!())?;
+                reply_rx.recv().location(loc!())?
+            },
+            _ => {
+                bail!("Unknown command: {input:?}")
+            },
+        })
+    })
+    .location(loc!())?;
 
     WaylandSource::new(conn, event_queue)
         .insert(event_loop.handle())
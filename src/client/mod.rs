@@ -14,11 +14,19 @@
 
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::hash::Hash;
+use std::mem;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::OnceLock;
+use std::time::Duration;
+use std::time::Instant;
 
 use bimap::BiMap;
 use enum_as_inner::EnumAsInner;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use smithay_client_toolkit::activation::ActivationState;
 use smithay_client_toolkit::compositor::CompositorState;
 use smithay_client_toolkit::compositor::Surface;
 use smithay_client_toolkit::data_device_manager::data_offer::DragOffer;
@@ -34,15 +42,39 @@ use smithay_client_toolkit::primary_selection::PrimarySelectionManagerState;
 use smithay_client_toolkit::reexports::client::backend::ObjectId as SctkObjectId;
 use smithay_client_toolkit::reexports::client::globals::GlobalList;
 use smithay_client_toolkit::reexports::client::protocol::wl_output::Transform;
+use smithay_client_toolkit::reexports::client::protocol::wl_pointer::WlPointer;
+use smithay_client_toolkit::reexports::client::protocol::wl_region::WlRegion;
+use smithay_client_toolkit::reexports::client::protocol::wl_seat::WlSeat;
 use smithay_client_toolkit::reexports::client::protocol::wl_subcompositor::WlSubcompositor;
 use smithay_client_toolkit::reexports::client::protocol::wl_surface::WlSurface;
 use smithay_client_toolkit::reexports::client::Connection;
 use smithay_client_toolkit::reexports::client::Proxy;
 use smithay_client_toolkit::reexports::client::QueueHandle;
+use smithay_client_toolkit::reexports::protocols::ext::idle_notify::v1::client::ext_idle_notification_v1::ExtIdleNotificationV1;
+use smithay_client_toolkit::reexports::protocols::ext::idle_notify::v1::client::ext_idle_notifier_v1::ExtIdleNotifierV1;
+use smithay_client_toolkit::reexports::protocols::wp::fractional_scale::v1::client::wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::fractional_scale::v1::client::wp_fractional_scale_v1::WpFractionalScaleV1;
+use smithay_client_toolkit::reexports::protocols::wp::idle_inhibit::zv1::client::zwp_idle_inhibit_manager_v1::ZwpIdleInhibitManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::idle_inhibit::zv1::client::zwp_idle_inhibitor_v1::ZwpIdleInhibitorV1;
+use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibit_manager_v1::ZwpKeyboardShortcutsInhibitManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibitor_v1::ZwpKeyboardShortcutsInhibitorV1;
+use smithay_client_toolkit::reexports::protocols::wp::presentation_time::client::wp_presentation::WpPresentation;
+use smithay_client_toolkit::reexports::protocols::wp::pointer_constraints::zv1::client::zwp_confined_pointer_v1::ZwpConfinedPointerV1;
+use smithay_client_toolkit::reexports::protocols::wp::pointer_constraints::zv1::client::zwp_locked_pointer_v1::ZwpLockedPointerV1;
+use smithay_client_toolkit::reexports::protocols::wp::pointer_constraints::zv1::client::zwp_pointer_constraints_v1::Lifetime;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_manager_v2::ZwpTabletManagerV2;
+use smithay_client_toolkit::reexports::protocols::wp::text_input::zv3::client::zwp_text_input_manager_v3::ZwpTextInputManagerV3;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewport::WpViewport;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewporter::WpViewporter;
 use smithay_client_toolkit::reexports::protocols::xdg::shell::client::xdg_surface;
 use smithay_client_toolkit::registry::RegistryState;
 use smithay_client_toolkit::seat::pointer::ThemedPointer;
+use smithay_client_toolkit::seat::pointer_constraints::PointerConstraintsState;
+use smithay_client_toolkit::seat::relative_pointer::RelativePointerState;
 use smithay_client_toolkit::seat::SeatState;
+use smithay_client_toolkit::session_lock::SessionLock;
+use smithay_client_toolkit::session_lock::SessionLockState;
+use smithay_client_toolkit::shell::wlr_layer::LayerShell;
 use smithay_client_toolkit::shell::xdg::XdgShell;
 use smithay_client_toolkit::shell::xdg::XdgSurface;
 use smithay_client_toolkit::shell::WaylandSurface;
@@ -56,30 +88,56 @@ use crate::filtering;
 use crate::prelude::*;
 use crate::serialization::geometry::Point;
 use crate::serialization::geometry::Rectangle;
+use crate::serialization::tablet::Tablet;
+use crate::serialization::wayland;
 use crate::serialization::wayland::Buffer;
 use crate::serialization::wayland::BufferAssignment;
 use crate::serialization::wayland::BufferMetadata;
+use crate::serialization::wayland::CursorImageStatus;
+use crate::serialization::wayland::OutputEvent;
+use crate::serialization::wayland::PointerConstraintKind;
+use crate::serialization::wayland::PointerConstraintState;
 use crate::serialization::wayland::Region;
 use crate::serialization::wayland::SubsurfacePosition;
+use crate::serialization::wayland::ViewportState;
 use crate::serialization::wayland::WlSurfaceId;
+use crate::serialization::wlr_layer_shell::LayerSurfaceId;
+use crate::serialization::xdg_shell::XdgPopupId;
+use crate::serialization::xdg_shell::XdgToplevelId;
 use crate::serialization::Capabilities;
+use crate::serialization::ClientCapabilities;
 use crate::serialization::ClientId;
 use crate::serialization::Event;
 use crate::serialization::ObjectId;
 use crate::serialization::Request;
+use crate::serialization::SendType;
 use crate::serialization::Serializer;
 use crate::vec4u8::Vec4u8s;
 
+mod pending_pings;
 pub mod server_handlers;
+mod session_lock;
 pub mod smithay_handlers;
 mod subsurface;
+mod tablet;
+mod text_input;
+mod wlr_layer_shell;
 mod xdg_shell;
 
+use pending_pings::PendingPings;
+use session_lock::RemoteSessionLockSurface;
 use smithay_handlers::SubCompositorData;
 use subsurface::RemoteSubSurface;
+use tablet::LocalTabletTool;
+use text_input::LocalTextInput;
+use wlr_layer_shell::RemoteLayerSurface;
 use xdg_shell::RemoteXdgPopup;
 use xdg_shell::RemoteXdgToplevel;
 
+/// How long a ping waits for the server to answer, e.g. for the rtt control
+/// command.
+pub const PING_TIMEOUT: Duration = Duration::from_secs(10);
+
 type ObjectBimap = BiMap<(ClientId, ObjectId), SctkObjectId>;
 
 pub trait ObjectBimapExt {
@@ -96,8 +154,116 @@ impl ObjectBimapExt for ObjectBimap {
     }
 }
 
+/// Whether a local selection or drag, offered in mime_types, is the server's
+/// own, which wprsc offers to the local compositor marked with _wprs_marker.
+/// Those aren't sent back to the server.
+fn offered_by_server(mime_types: &[String]) -> bool {
+    mime_types.iter().any(|m| m == "_wprs_marker")
+}
+
+/// Removes the mappings for all objects belonging to `client`, leaving those
+/// of other clients in place.
+fn remove_client_objects<R>(object_bimap: &mut BiMap<(ClientId, ObjectId), R>, client: ClientId)
+where
+    R: Eq + Hash,
+{
+    object_bimap.retain(|(client_id, _), _| *client_id != client);
+}
+
+/// Removes the mappings for the given objects of `client`. Objects which were
+/// already removed are skipped, so that the server's requests for destroying a
+/// role and then its surface can arrive in either order.
+fn remove_objects<R>(
+    object_bimap: &mut BiMap<(ClientId, ObjectId), R>,
+    client: ClientId,
+    object_ids: impl IntoIterator<Item = ObjectId>,
+) where
+    R: Eq + Hash,
+{
+    for object_id in object_ids {
+        object_bimap.remove_by_left(&(client, object_id));
+    }
+}
+
+/// When a buffer received from the server is presented locally.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub enum PresentationMode {
+    /// Wait for the local compositor's frame callback for the previous buffer
+    /// before presenting the next one. Buffers which arrive in the meantime
+    /// replace the pending one.
+    #[default]
+    FrameCallback,
+    /// Present each buffer as soon as it arrives, trading CPU and compositor
+    /// work for latency. The rate is still capped by wprsd's framerate.
+    Immediate,
+}
+
+/// What the server has resent since the client reconnected to it.
+#[derive(Debug, Default)]
+struct Resync {
+    surfaces: HashSet<(ClientId, WlSurfaceId)>,
+    session_locked: bool,
+}
+
+impl Resync {
+    /// The surfaces among known which the server didn't resend, i.e., which
+    /// went away while the client was disconnected. The others keep their
+    /// local windows.
+    fn stale_surfaces(
+        &self,
+        known: impl IntoIterator<Item = (ClientId, WlSurfaceId)>,
+    ) -> Vec<(ClientId, WlSurfaceId)> {
+        known
+            .into_iter()
+            .filter(|key| !self.surfaces.contains(key))
+            .collect()
+    }
+}
+
 pub struct ClientOptions {
     pub title_prefix: String,
+    /// Maximum number of toplevel windows to create locally, so that a buggy
+    /// or malicious server can't flood the local compositor with windows.
+    pub max_windows: usize,
+    /// Toplevels aren't created locally until they have a buffer at least
+    /// this wide and tall, so that hidden helper windows (e.g., 1x1) don't
+    /// show up as stray windows. 0 disables this.
+    pub min_window_size: i32,
+    /// Sent to the server to identify this client.
+    pub identity: String,
+    pub presentation_mode: PresentationMode,
+    /// The server is told that the user is idle after this many seconds
+    /// without input to the local compositor. 0 disables this.
+    pub idle_timeout: u32,
+    /// Show the last cursor image as soon as the pointer enters a surface
+    /// instead of waiting for the application to set one.
+    pub predicted_cursor: bool,
+    /// After reconnecting, send the server the held keys and buttons and the
+    /// pointer position, so that it can release what was released while the
+    /// client was disconnected.
+    pub restore_input_on_reconnect: bool,
+    /// Send the text in the local clipboard to the server whenever the local
+    /// compositor offers it, which it does when a remote window is focused.
+    pub clipboard_push_on_focus: bool,
+}
+
+/// Where a remote surface is displayed locally, for tools (automation,
+/// accessibility) which drive remote applications through the local
+/// compositor. Wayland doesn't expose the position of windows on screen, so
+/// positions are relative to the containing window.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct SurfaceMapping {
+    pub client: u64,
+    pub surface: u64,
+    /// Protocol id of the local wl_surface.
+    pub local_surface: u32,
+    /// Protocol id of the local wl_surface of the toplevel or popup containing
+    /// this surface, if it is part of one.
+    pub window: Option<u32>,
+    pub title: Option<String>,
+    /// Offset of this surface within window.
+    pub position: (i32, i32),
+    pub buffer_size: Option<(i32, i32)>,
 }
 
 pub struct WprsClientState {
@@ -108,13 +274,31 @@ pub struct WprsClientState {
     registry_state: RegistryState,
     seat_state: SeatState,
     output_state: OutputState,
+    /// Whether the initial set of outputs has been sent. Until then,
+    /// individual output changes aren't sent.
+    outputs_announced: bool,
     compositor_state: CompositorState,
     subcompositor: WlSubcompositor,
     shm_state: Shm,
     xdg_shell_state: XdgShell,
+    layer_shell: Option<LayerShell>,
 
     data_device_manager_state: DataDeviceManagerState,
     primary_selection_manager_state: Option<PrimarySelectionManagerState>,
+    viewporter: Option<WpViewporter>,
+    fractional_scale_manager: Option<WpFractionalScaleManagerV1>,
+    presentation: Option<WpPresentation>,
+    keyboard_shortcuts_inhibit_manager: Option<ZwpKeyboardShortcutsInhibitManagerV1>,
+    idle_inhibit_manager: Option<ZwpIdleInhibitManagerV1>,
+    idle_notifier: Option<ExtIdleNotifierV1>,
+    idle_notification: Option<ExtIdleNotificationV1>,
+    activation_state: Option<ActivationState>,
+    relative_pointer_state: RelativePointerState,
+    pointer_constraints_state: PointerConstraintsState,
+    session_lock_state: SessionLockState,
+    /// The lock of the local session, while an application on the server has
+    /// the session locked.
+    session_lock: Option<SessionLock>,
 
     pool: SlotPool,
 
@@ -129,6 +313,13 @@ pub struct WprsClientState {
     primary_selection_source: Option<PrimarySelectionSource>,
     primary_selection_pipe: Option<WritePipe>,
     primary_selection_offer: Option<PrimarySelectionOffer>,
+    /// Touch events which haven't been sent yet, see send_touch_frame.
+    touch_frame: Vec<wayland::TouchEvent>,
+    tablet_manager: Option<ZwpTabletManagerV2>,
+    tablets: HashMap<SctkObjectId, Tablet>,
+    tablet_tools: HashMap<SctkObjectId, LocalTabletTool>,
+    text_input_manager: Option<ZwpTextInputManagerV3>,
+    text_input: Option<LocalTextInput>,
 
     serializer: Serializer<Event, Request>,
     remote_display: RemoteDisplay,
@@ -138,9 +329,41 @@ pub struct WprsClientState {
     last_enter_serial: u32,
     last_implicit_grab_serial: Option<u32>,
     last_mouse_down_serial: Option<u32>,
+    /// The surface the pointer is over and its position, for relative motion
+    /// events, which don't include either.
+    pointer_focus: Option<(WlSurface, Point<f64>)>,
     current_focus: Option<WlSurface>,
+    /// Raw keycodes of the held keys, for InputSnapshot.
+    pressed_keys: HashSet<u32>,
+    pressed_buttons: HashSet<u32>,
 
     title_prefix: String,
+    max_windows: usize,
+    min_window_size: i32,
+    identity: String,
+    presentation_mode: PresentationMode,
+    idle_timeout: u32,
+    predicted_cursor: bool,
+    restore_input_on_reconnect: bool,
+    clipboard_push_on_focus: bool,
+    /// Incremented with each selection offered to the server. Held while the
+    /// selection is sent, so that the contents of an older selection are never
+    /// pushed after a newer selection.
+    selection_generation: Arc<Mutex<u64>>,
+    /// The last cursor image set by the server.
+    cursor_image: Option<CursorImageStatus>,
+    /// Toplevels which weren't created because max_windows was reached.
+    refused_windows: HashSet<(ClientId, WlSurfaceId)>,
+    /// Toplevels which haven't been created yet because they don't have a
+    /// buffer of at least min_window_size.
+    suppressed_windows: HashSet<(ClientId, WlSurfaceId)>,
+    /// Set from reconnecting to the server until the server has resent its
+    /// state. Local objects are kept in the meantime, so that windows survive
+    /// the reconnection.
+    resync: Option<Resync>,
+    /// Pings which haven't been answered yet, with where to send the
+    /// round-trip time.
+    pending_pings: PendingPings<crossbeam_channel::Sender<Duration>>,
 
     buffer_cache: Option<Arc<Vec4u8s>>,
 }
@@ -167,6 +390,7 @@ impl WprsClientState {
             registry_state: RegistryState::new(&globals),
             seat_state: SeatState::new(&globals, &qh),
             output_state: OutputState::new(&globals, &qh),
+            outputs_announced: false,
             compositor_state: CompositorState::bind(&globals, &qh)
                 .context(loc!(), "wl_compositor is not available")?,
             subcompositor: globals
@@ -175,12 +399,62 @@ impl WprsClientState {
             shm_state,
             xdg_shell_state: XdgShell::bind(&globals, &qh)
                 .context(loc!(), "xdg shell is not available")?,
+            layer_shell: LayerShell::bind(&globals, &qh)
+                .context(loc!(), "wlr layer shell is not available")
+                .warn(loc!())
+                .ok(),
             data_device_manager_state: DataDeviceManagerState::bind(&globals, &qh)
                 .context(loc!(), "data device manager is not available")?,
             primary_selection_manager_state: PrimarySelectionManagerState::bind(&globals, &qh)
                 .context(loc!(), "primary selection manager is not available")
                 .warn(loc!())
                 .ok(),
+            viewporter: globals
+                .bind(&qh, 1..=1, ())
+                .context(loc!(), "wp_viewporter is not available")
+                .warn(loc!())
+                .ok(),
+            fractional_scale_manager: globals
+                .bind(&qh, 1..=1, ())
+                .context(loc!(), "wp_fractional_scale_manager_v1 is not available")
+                .warn(loc!())
+                .ok(),
+            presentation: globals
+                .bind(&qh, 1..=1, ())
+                .context(loc!(), "wp_presentation is not available")
+                .warn(loc!())
+                .ok(),
+            keyboard_shortcuts_inhibit_manager: globals
+                .bind(&qh, 1..=1, ())
+                .context(
+                    loc!(),
+                    "zwp_keyboard_shortcuts_inhibit_manager_v1 is not available",
+                )
+                .warn(loc!())
+                .ok(),
+            idle_inhibit_manager: globals
+                .bind(&qh, 1..=1, ())
+                .context(loc!(), "zwp_idle_inhibit_manager_v1 is not available")
+                .warn(loc!())
+                .ok(),
+            idle_notifier: if options.idle_timeout > 0 {
+                globals
+                    .bind(&qh, 1..=1, ())
+                    .context(loc!(), "ext_idle_notifier_v1 is not available")
+                    .warn(loc!())
+                    .ok()
+            } else {
+                None
+            },
+            idle_notification: None,
+            activation_state: ActivationState::bind(&globals, &qh)
+                .context(loc!(), "xdg_activation_v1 is not available")
+                .warn(loc!())
+                .ok(),
+            relative_pointer_state: RelativePointerState::bind(&globals, &qh),
+            pointer_constraints_state: PointerConstraintsState::bind(&globals, &qh),
+            session_lock_state: SessionLockState::new(&globals, &qh),
+            session_lock: None,
 
             pool,
 
@@ -195,6 +469,20 @@ impl WprsClientState {
             primary_selection_source: None,
             primary_selection_offer: None,
             primary_selection_pipe: None,
+            touch_frame: Vec::new(),
+            tablet_manager: globals
+                .bind(&qh, 1..=1, ())
+                .context(loc!(), "zwp_tablet_manager_v2 is not available")
+                .warn(loc!())
+                .ok(),
+            tablets: HashMap::new(),
+            tablet_tools: HashMap::new(),
+            text_input_manager: globals
+                .bind(&qh, 1..=1, ())
+                .context(loc!(), "zwp_text_input_manager_v3 is not available")
+                .warn(loc!())
+                .ok(),
+            text_input: None,
 
             serializer,
             remote_display: RemoteDisplay::new(),
@@ -203,11 +491,140 @@ impl WprsClientState {
             last_enter_serial: 0,
             last_implicit_grab_serial: None,
             last_mouse_down_serial: None,
+            pointer_focus: None,
             current_focus: None,
+            pressed_keys: HashSet::new(),
+            pressed_buttons: HashSet::new(),
             title_prefix: options.title_prefix,
+            max_windows: options.max_windows,
+            min_window_size: options.min_window_size,
+            identity: options.identity,
+            presentation_mode: options.presentation_mode,
+            idle_timeout: options.idle_timeout,
+            predicted_cursor: options.predicted_cursor,
+            restore_input_on_reconnect: options.restore_input_on_reconnect,
+            clipboard_push_on_focus: options.clipboard_push_on_focus,
+            selection_generation: Arc::new(Mutex::new(0)),
+            cursor_image: None,
+            refused_windows: HashSet::new(),
+            suppressed_windows: HashSet::new(),
+            resync: None,
+            pending_pings: PendingPings::new(PING_TIMEOUT),
             buffer_cache: None,
         })
     }
+
+    /// Where each remote surface is displayed locally, for the control server's
+    /// surfaces command.
+    pub fn surface_mappings(&self) -> Vec<SurfaceMapping> {
+        let mut mappings = Vec::new();
+        for client in self.remote_display.clients.values() {
+            for surface in client.surfaces.values() {
+                // Walk up to the surface which owns the window, accumulating
+                // subsurface offsets along the way.
+                let mut position = Point { x: 0, y: 0 };
+                let mut root = surface;
+                // A chain of parents longer than the client's surfaces is a
+                // cycle, which the server shouldn't but could send.
+                for _ in 0..client.surfaces.len() {
+                    let Some(Role::SubSurface(subsurface)) = &root.role else {
+                        break;
+                    };
+                    let Some(parent) = client.surfaces.get(&subsurface.parent) else {
+                        break;
+                    };
+                    if let Some(child) = parent
+                        .z_ordered_children
+                        .iter()
+                        .find(|child| child.id == root.id)
+                    {
+                        position.x += child.position.x;
+                        position.y += child.position.y;
+                    }
+                    root = parent;
+                }
+
+                let (window, title) = match &root.role {
+                    Some(Role::XdgToplevel(toplevel)) => (
+                        Some(root.wl_surface().id().protocol_id()),
+                        toplevel.title.clone(),
+                    ),
+                    Some(Role::XdgPopup(_)) => (Some(root.wl_surface().id().protocol_id()), None),
+                    _ => (None, None),
+                };
+
+                mappings.push(SurfaceMapping {
+                    client: client.id.0,
+                    surface: surface.id.0,
+                    local_surface: surface.wl_surface().id().protocol_id(),
+                    window,
+                    title,
+                    position: (position.x, position.y),
+                    buffer_size: surface
+                        .buffer
+                        .as_ref()
+                        .map(|buffer| (buffer.metadata.width, buffer.metadata.height)),
+                });
+            }
+        }
+        mappings
+    }
+
+    /// Pings the server, reply receives the round-trip time once the server
+    /// answers. The ping waits behind whatever is already queued for the
+    /// server, so the time includes any backlog.
+    pub fn ping(&mut self, reply: crossbeam_channel::Sender<Duration>) {
+        let nonce = self.pending_pings.push(Instant::now(), reply);
+        self.serializer
+            .writer()
+            .send(SendType::Object(Event::Ping(nonce)));
+    }
+
+    /// Sends all currently known outputs to the server in a single event.
+    /// Later output changes are sent individually.
+    pub fn announce_outputs(&mut self) {
+        let outputs = self
+            .output_state
+            .outputs()
+            .filter_map(|output| self.output_state.info(&output))
+            .map(Into::into)
+            .collect();
+        self.serializer
+            .writer()
+            .send(SendType::Object(Event::Output(OutputEvent::Set(outputs))));
+        self.outputs_announced = true;
+    }
+
+    /// Sends the touch events received since the last call as a single frame.
+    /// Should be called after each dispatch of wayland events.
+    pub fn send_touch_frame(&mut self) {
+        if self.touch_frame.is_empty() {
+            return;
+        }
+        let events = mem::take(&mut self.touch_frame);
+        self.serializer
+            .writer()
+            .send(SendType::Object(Event::TouchFrame(events)));
+    }
+
+    /// Disconnects from the server and joins the serializer's threads.
+    pub fn shutdown(&mut self) -> Result<()> {
+        self.serializer.shutdown()
+    }
+
+    /// The formats advertised by wl_shm are only known after a roundtrip
+    /// following the creation of this state.
+    pub fn client_capabilities(&self) -> ClientCapabilities {
+        ClientCapabilities {
+            buffer_formats: self
+                .shm_state
+                .formats()
+                .iter()
+                .filter_map(|format| (*format).try_into().ok())
+                .collect(),
+            identity: self.identity.clone(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -216,6 +633,9 @@ pub struct RemoteBuffer {
     pub data: Vec4u8s,
     pub active_buffer: SlotBuffer,
     pub dirty: bool,
+    /// The checksum wprsd sent for data, until data is written out and
+    /// checked against it.
+    pub checksum: Option<u64>,
 }
 
 impl RemoteBuffer {
@@ -240,6 +660,7 @@ impl RemoteBuffer {
             data: Arc::into_inner(buffer_msg.data).unwrap(),
             active_buffer,
             dirty: true,
+            checksum: buffer_msg.checksum,
         })
     }
 
@@ -250,10 +671,11 @@ impl RemoteBuffer {
         // serialization/deserialization.
         self.data = Arc::into_inner(buffer.data).unwrap();
         self.dirty = true;
+        self.checksum = buffer.checksum;
     }
 
     #[instrument(skip_all, level = "debug")]
-    fn write_data(&mut self, pool: &mut SlotPool) -> Result<()> {
+    fn write_data(&mut self, surface_id: WlSurfaceId, pool: &mut SlotPool) -> Result<()> {
         let canvas = match pool.canvas(&self.active_buffer) {
             Some(canvas) => canvas,
             None => {
@@ -274,6 +696,17 @@ impl RemoteBuffer {
             },
         };
         filtering::unfilter(&mut self.data, canvas);
+
+        if let Some(expected) = self.checksum.take() {
+            let actual = filtering::frame_checksum(canvas.iter().copied());
+            if actual == expected {
+                trace!("frame checksum of surface {surface_id:?} matches: {actual:016x}");
+            } else {
+                warn!(
+                    "frame checksum mismatch for surface {surface_id:?}: wprsd sent {expected:016x}, decoded {actual:016x}"
+                );
+            }
+        }
         Ok(())
     }
 }
@@ -284,6 +717,53 @@ pub enum Role {
     SubSurface(RemoteSubSurface),
     XdgToplevel(RemoteXdgToplevel),
     XdgPopup(RemoteXdgPopup),
+    LayerSurface(RemoteLayerSurface),
+    SessionLockSurface(RemoteSessionLockSurface),
+}
+
+/// What identifies a surface's role, see RemoteSurface::role_matches.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum RoleKey {
+    Cursor,
+    SubSurface { parent: WlSurfaceId },
+    XdgToplevel(XdgToplevelId),
+    XdgPopup(XdgPopupId),
+    LayerSurface(LayerSurfaceId),
+    SessionLockSurface { output: u32 },
+}
+
+impl From<&Role> for RoleKey {
+    fn from(role: &Role) -> Self {
+        match role {
+            Role::Cursor(_) => Self::Cursor,
+            Role::SubSurface(subsurface) => Self::SubSurface {
+                parent: subsurface.parent,
+            },
+            Role::XdgToplevel(toplevel) => Self::XdgToplevel(toplevel.id),
+            Role::XdgPopup(popup) => Self::XdgPopup(popup.id),
+            Role::LayerSurface(layer_surface) => Self::LayerSurface(layer_surface.id),
+            Role::SessionLockSurface(lock_surface) => Self::SessionLockSurface {
+                output: lock_surface.output,
+            },
+        }
+    }
+}
+
+impl From<&wayland::Role> for RoleKey {
+    fn from(role: &wayland::Role) -> Self {
+        match role {
+            wayland::Role::Cursor(_) => Self::Cursor,
+            wayland::Role::SubSurface(subsurface) => Self::SubSurface {
+                parent: subsurface.parent,
+            },
+            wayland::Role::XdgToplevel(toplevel) => Self::XdgToplevel(toplevel.id),
+            wayland::Role::XdgPopup(popup) => Self::XdgPopup(popup.id),
+            wayland::Role::LayerSurface(layer_surface) => Self::LayerSurface(layer_surface.id),
+            wayland::Role::SessionLockSurface(lock_surface) => Self::SessionLockSurface {
+                output: lock_surface.output,
+            },
+        }
+    }
 }
 
 impl WaylandSurface for RemoteSurface {
@@ -296,6 +776,12 @@ impl WaylandSurface for RemoteSurface {
                 remote_xdg_toplevel.local_window.wl_surface()
             },
             Some(Role::XdgPopup(remote_xdg_popup)) => remote_xdg_popup.local_popup.wl_surface(),
+            Some(Role::LayerSurface(remote_layer_surface)) => {
+                remote_layer_surface.local_layer_surface.wl_surface()
+            },
+            Some(Role::SessionLockSurface(remote_lock_surface)) => {
+                remote_lock_surface.local_lock_surface.wl_surface()
+            },
         }
     }
 }
@@ -312,6 +798,12 @@ impl XdgSurface for RemoteXdgToplevel {
     }
 }
 
+impl WaylandSurface for RemoteLayerSurface {
+    fn wl_surface(&self) -> &WlSurface {
+        self.local_layer_surface.wl_surface()
+    }
+}
+
 impl WaylandSurface for RemoteXdgPopup {
     fn wl_surface(&self) -> &WlSurface {
         self.local_popup.wl_surface()
@@ -324,6 +816,54 @@ impl XdgSurface for RemoteXdgPopup {
     }
 }
 
+/// Whether a surface's buffer has to be attached and damaged: it has new
+/// contents, or a commit without a new buffer damaged the surface, which asks
+/// for the buffer to be presented again.
+fn needs_present(buffer_dirty: bool, frame_damage: Option<&[Rectangle<i32>]>) -> bool {
+    buffer_dirty || frame_damage.is_some_and(|damage| !damage.is_empty())
+}
+
+/// What has to happen to a surface's local idle inhibitor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IdleInhibitorChange {
+    Create,
+    Destroy,
+    Keep,
+}
+
+/// The local surface holds an idle inhibitor while the application holds one
+/// on the surface, or while the surface is fullscreen. has_local_surface is
+/// false once the local surface went away along with the surface's role, then
+/// there is nothing to create the inhibitor on.
+fn idle_inhibitor_change(
+    requested: bool,
+    fullscreen: bool,
+    has_inhibitor: bool,
+    has_local_surface: bool,
+) -> IdleInhibitorChange {
+    match (requested || fullscreen, has_inhibitor) {
+        (false, true) => IdleInhibitorChange::Destroy,
+        (true, false) if has_local_surface => IdleInhibitorChange::Create,
+        _ => IdleInhibitorChange::Keep,
+    }
+}
+
+fn attach_buffer(buffer: &RemoteBuffer, wl_surface: &WlSurface) -> Result<()> {
+    if buffer.dirty {
+        buffer.active_buffer.attach_to(wl_surface).context(
+            loc!(),
+            "attaching a buffer failed, this probably means we're leaking buffers",
+        )
+    } else {
+        // The buffer is being presented again. If the local compositor hasn't
+        // released it, it's still attached and only needs to be damaged.
+        if buffer.active_buffer.attach_to(wl_surface).is_err() {
+            debug!("buffer is still attached");
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct RemoteSurface {
     pub client: ClientId,
@@ -337,6 +877,15 @@ pub struct RemoteSurface {
     pub z_ordered_children: Vec<SubsurfacePosition>,
     pub frame_callback_completed: bool,
     pub frame_damage: Option<Vec<Rectangle<i32>>>,
+    pub viewport: Option<WpViewport>,
+    pub viewport_state: Option<ViewportState>,
+    pub fractional_scale: Option<WpFractionalScaleV1>,
+    pub pointer_constraint: Option<LocalPointerConstraint>,
+    pub pointer_constraint_state: Option<PointerConstraintState>,
+    pub keyboard_shortcuts_inhibitor: Option<ZwpKeyboardShortcutsInhibitorV1>,
+    /// Whether the application holds an idle inhibitor on the surface.
+    pub idle_inhibit_requested: bool,
+    pub idle_inhibitor: Option<ZwpIdleInhibitorV1>,
 }
 
 impl RemoteSurface {
@@ -368,6 +917,14 @@ impl RemoteSurface {
             }],
             frame_callback_completed: true,
             frame_damage: None,
+            viewport: None,
+            viewport_state: None,
+            fractional_scale: None,
+            pointer_constraint: None,
+            pointer_constraint_state: None,
+            keyboard_shortcuts_inhibitor: None,
+            idle_inhibit_requested: false,
+            idle_inhibitor: None,
         })
     }
 
@@ -418,7 +975,7 @@ impl RemoteSurface {
 
     pub fn write_data(&mut self, pool: &mut SlotPool) -> Result<()> {
         if let Some(buffer) = &mut self.buffer {
-            buffer.write_data(pool).location(loc!())?;
+            buffer.write_data(self.id, pool).location(loc!())?;
         }
         Ok(())
     }
@@ -447,7 +1004,7 @@ impl RemoteSurface {
         };
 
         if buffer.dirty {
-            buffer.write_data(pool).location(loc!())?;
+            buffer.write_data(self.id, pool).location(loc!())?;
         }
         Ok(())
     }
@@ -468,18 +1025,9 @@ impl RemoteSurface {
     ) -> Result<()> {
         match new_buffer {
             Some(BufferAssignment::New(mut new_buffer)) => {
-                if let Some(buffer_data) = buffer_cache.take() {
-                    new_buffer.data = buffer_data;
-                }
-                // else use the data in new_buffer as the buffer is data is
-                // still sent inline on connection.
-
-                if new_buffer.data.is_empty() {
-                    // TODO: do we want to log a warning and let the rest of the
-                    // commit work? Unclear that it matters.
-                    return Err(anyhow!("Received buffer commit with empty data. This can if wprsc reattaches between wprsd sending a buffer message and a commit message."));
-                }
-
+                // TODO: do we want to log a warning and let the rest of the
+                // commit work? Unclear that it matters.
+                new_buffer.fill_data(buffer_cache.take()).location(loc!())?;
                 self.set_buffer(new_buffer, pool).location(loc!())?;
             },
             Some(BufferAssignment::Removed) => {
@@ -493,11 +1041,8 @@ impl RemoteSurface {
     pub fn draw_buffer(&mut self) -> Result<()> {
         let wl_surface = &self.wl_surface().clone();
         if let Some(buffer) = &mut self.buffer {
-            if buffer.dirty {
-                buffer.active_buffer.attach_to(wl_surface).context(
-                    loc!(),
-                    "attaching a buffer failed, this probably means we're leaking buffers",
-                )?;
+            if needs_present(buffer.dirty, self.frame_damage.as_deref()) {
+                attach_buffer(buffer, wl_surface).location(loc!())?;
                 if let Some(damage_rects) = self.frame_damage.take() {
                     // avoid overwhelming wayland connection
                     if damage_rects.len() < constants::SENT_DAMAGE_LIMIT {
@@ -525,11 +1070,8 @@ impl RemoteSurface {
     pub fn draw_buffer_send_frame(&mut self, qh: &QueueHandle<WprsClientState>) -> Result<()> {
         let wl_surface = &self.wl_surface().clone();
         if let Some(buffer) = &mut self.buffer {
-            if buffer.dirty {
-                buffer.active_buffer.attach_to(wl_surface).context(
-                    loc!(),
-                    "attaching a buffer failed, this probably means we're leaking buffers",
-                )?;
+            if needs_present(buffer.dirty, self.frame_damage.as_deref()) {
+                attach_buffer(buffer, wl_surface).location(loc!())?;
                 if let Some(damage_rects) = self.frame_damage.take() {
                     for damage_rect in damage_rects {
                         wl_surface.damage_buffer(
@@ -558,6 +1100,197 @@ impl RemoteSurface {
         }
     }
 
+    /// Without wp_viewporter support in the local compositor, the surface is
+    /// shown at its buffer size instead.
+    pub fn set_viewport(
+        &mut self,
+        viewport_state: Option<ViewportState>,
+        viewporter: Option<&WpViewporter>,
+        qh: &QueueHandle<WprsClientState>,
+    ) {
+        if self.viewport_state == viewport_state {
+            return;
+        }
+        let Some(viewporter) = viewporter else {
+            debug!("wp_viewporter is not available, ignoring viewport {viewport_state:?}");
+            return;
+        };
+        self.viewport_state = viewport_state;
+
+        let wl_surface = self.wl_surface().clone();
+        let viewport = self
+            .viewport
+            .get_or_insert_with(|| viewporter.get_viewport(&wl_surface, qh, ()));
+        // -1 unsets the source rectangle and destination size.
+        match viewport_state.and_then(|state| state.src_f64()) {
+            Some((x, y, w, h)) => viewport.set_source(x, y, w, h),
+            None => viewport.set_source(-1.0, -1.0, -1.0, -1.0),
+        }
+        match viewport_state.and_then(|state| state.dst) {
+            Some(dst) => viewport.set_destination(dst.w, dst.h),
+            None => viewport.set_destination(-1, -1),
+        }
+    }
+
+    /// Subscribes to the preferred fractional scale of the surface, which is
+    /// forwarded to the server by the WpFractionalScaleV1 dispatch handler.
+    pub fn init_fractional_scale(
+        &mut self,
+        fractional_scale_manager: Option<&WpFractionalScaleManagerV1>,
+        qh: &QueueHandle<WprsClientState>,
+    ) {
+        let Some(fractional_scale_manager) = fractional_scale_manager else {
+            return;
+        };
+        if self.fractional_scale.is_none() {
+            let wl_surface = self.wl_surface().clone();
+            self.fractional_scale = Some(fractional_scale_manager.get_fractional_scale(
+                &wl_surface,
+                qh,
+                wl_surface.clone(),
+            ));
+        }
+    }
+
+    /// Mirrors the application's keyboard shortcuts inhibitor with an inhibitor
+    /// on the local surface. The server-side inhibitor is activated when the
+    /// local one is.
+    pub fn set_keyboard_shortcuts_inhibited(
+        &mut self,
+        inhibit: bool,
+        keyboard_shortcuts_inhibit_manager: Option<&ZwpKeyboardShortcutsInhibitManagerV1>,
+        seat: Option<&WlSeat>,
+        qh: &QueueHandle<WprsClientState>,
+    ) {
+        if !inhibit {
+            if let Some(inhibitor) = self.keyboard_shortcuts_inhibitor.take() {
+                inhibitor.destroy();
+            }
+            return;
+        }
+        if self.keyboard_shortcuts_inhibitor.is_some() {
+            return;
+        }
+        let Some(keyboard_shortcuts_inhibit_manager) = keyboard_shortcuts_inhibit_manager else {
+            debug!(
+                "zwp_keyboard_shortcuts_inhibit_manager_v1 is not available, ignoring inhibitor"
+            );
+            return;
+        };
+        let Some(seat) = seat else {
+            debug!("no seat, ignoring keyboard shortcuts inhibitor");
+            return;
+        };
+        let wl_surface = self.wl_surface().clone();
+        self.keyboard_shortcuts_inhibitor =
+            Some(keyboard_shortcuts_inhibit_manager.inhibit_shortcuts(
+                &wl_surface,
+                seat,
+                qh,
+                wl_surface.clone(),
+            ));
+    }
+
+    /// Holds an idle inhibitor on the local surface while the application
+    /// holds one on the surface, or while the surface is a fullscreen toplevel,
+    /// since fullscreen videos and games don't necessarily ask.
+    pub fn update_idle_inhibitor(
+        &mut self,
+        idle_inhibit_manager: Option<&ZwpIdleInhibitManagerV1>,
+        qh: &QueueHandle<WprsClientState>,
+    ) {
+        let fullscreen =
+            matches!(&self.role, Some(Role::XdgToplevel(toplevel)) if toplevel.fullscreen);
+        match idle_inhibitor_change(
+            self.idle_inhibit_requested,
+            fullscreen,
+            self.idle_inhibitor.is_some(),
+            self.role.is_some() || self.local_surface.is_some(),
+        ) {
+            IdleInhibitorChange::Create => {
+                let Some(idle_inhibit_manager) = idle_inhibit_manager else {
+                    debug!("zwp_idle_inhibit_manager_v1 is not available, ignoring idle inhibitor");
+                    return;
+                };
+                self.idle_inhibitor =
+                    Some(idle_inhibit_manager.create_inhibitor(self.wl_surface(), qh, ()));
+            },
+            IdleInhibitorChange::Destroy => {
+                if let Some(inhibitor) = self.idle_inhibitor.take() {
+                    inhibitor.destroy();
+                }
+            },
+            IdleInhibitorChange::Keep => {},
+        }
+    }
+
+    /// Mirrors the application's pointer constraint with a constraint on the
+    /// local surface. The server-side constraint is activated when the local
+    /// one is, see the PointerConstraintsHandler.
+    pub fn set_pointer_constraint(
+        &mut self,
+        pointer_constraint_state: Option<PointerConstraintState>,
+        pointer_constraints_state: &PointerConstraintsState,
+        pointer: Option<&WlPointer>,
+        compositor_state: &CompositorState,
+        qh: &QueueHandle<WprsClientState>,
+    ) -> Result<()> {
+        if self.pointer_constraint_state == pointer_constraint_state {
+            return Ok(());
+        }
+        let Some(pointer) = pointer else {
+            debug!("no pointer, ignoring pointer constraint {pointer_constraint_state:?}");
+            return Ok(());
+        };
+        let old_kind = self
+            .pointer_constraint_state
+            .as_ref()
+            .map(|state| state.kind);
+        self.pointer_constraint_state = pointer_constraint_state;
+
+        let Some(state) = &self.pointer_constraint_state else {
+            if let Some(pointer_constraint) = self.pointer_constraint.take() {
+                pointer_constraint.destroy();
+            }
+            return Ok(());
+        };
+
+        let region = state
+            .region
+            .as_ref()
+            .map(|region| region.create_compositor_region(compositor_state))
+            .transpose()
+            .location(loc!())?;
+        let wl_region = region.as_ref().map(|region| region.wl_region());
+
+        // Only the region changed, update it in place so that the pointer
+        // isn't briefly unconstrained.
+        if old_kind == Some(state.kind) {
+            if let Some(pointer_constraint) = &self.pointer_constraint {
+                pointer_constraint.set_region(wl_region);
+                return Ok(());
+            }
+        }
+
+        if let Some(pointer_constraint) = self.pointer_constraint.take() {
+            pointer_constraint.destroy();
+        }
+        let wl_surface = self.wl_surface().clone();
+        self.pointer_constraint = Some(match state.kind {
+            PointerConstraintKind::Lock => LocalPointerConstraint::Locked(
+                pointer_constraints_state
+                    .lock_pointer(&wl_surface, pointer, wl_region, Lifetime::Persistent, qh)
+                    .location(loc!())?,
+            ),
+            PointerConstraintKind::Confine => LocalPointerConstraint::Confined(
+                pointer_constraints_state
+                    .confine_pointer(&wl_surface, pointer, wl_region, Lifetime::Persistent, qh)
+                    .location(loc!())?,
+            ),
+        });
+        Ok(())
+    }
+
     pub fn set_input_region(
         &mut self,
         region: Option<Region>,
@@ -614,6 +1347,43 @@ impl RemoteSurface {
         self.wl_surface().frame(qh, self.wl_surface().clone());
     }
 
+    /// Whether the role of this surface is the one described by a commit for
+    /// the same surface id. A mismatch means that the surface id was reused
+    /// before we saw the destruction of the old surface.
+    pub fn role_matches(&self, role: Option<&wayland::Role>) -> bool {
+        match (self.role.as_ref(), role) {
+            (Some(local), Some(remote)) => RoleKey::from(local) == RoleKey::from(remote),
+            _ => true,
+        }
+    }
+
+    /// The ids of the remote objects which map to this surface's local objects.
+    pub fn object_ids(&self) -> Vec<ObjectId> {
+        let mut ids = vec![ObjectId::WlSurface(self.id)];
+        match &self.role {
+            Some(Role::XdgToplevel(toplevel)) => ids.push(ObjectId::XdgToplevel(toplevel.id)),
+            Some(Role::XdgPopup(popup)) => ids.push(ObjectId::XdgPopup(popup.id)),
+            _ => {},
+        }
+        ids
+    }
+
+    /// Destroys the surface's role object (e.g., its xdg_toplevel) while
+    /// keeping the surface itself. Does nothing if the role is already gone.
+    pub fn clear_role(&mut self, object_bimap: &mut ObjectBimap) {
+        let role_object_ids = self
+            .object_ids()
+            .into_iter()
+            .filter(|object_id| !matches!(object_id, ObjectId::WlSurface(_)));
+        remove_objects(object_bimap, self.client, role_object_ids);
+        // The inhibitor is on the role's local surface, which goes away along
+        // with the role.
+        if let Some(inhibitor) = self.idle_inhibitor.take() {
+            inhibitor.destroy();
+        }
+        self.role = None;
+    }
+
     pub fn get_role(&self) -> Result<&Role> {
         self.role.as_ref().context(loc!(), "Role was None.")
     }
@@ -700,6 +1470,28 @@ impl RemoteClient {
     }
 }
 
+#[derive(Debug)]
+pub enum LocalPointerConstraint {
+    Locked(ZwpLockedPointerV1),
+    Confined(ZwpConfinedPointerV1),
+}
+
+impl LocalPointerConstraint {
+    fn set_region(&self, region: Option<&WlRegion>) {
+        match self {
+            Self::Locked(locked_pointer) => locked_pointer.set_region(region),
+            Self::Confined(confined_pointer) => confined_pointer.set_region(region),
+        }
+    }
+
+    fn destroy(self) {
+        match self {
+            Self::Locked(locked_pointer) => locked_pointer.destroy(),
+            Self::Confined(confined_pointer) => confined_pointer.destroy(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct RemoteDisplay {
     pub clients: HashMap<ClientId, RemoteClient>,
@@ -715,6 +1507,14 @@ impl RemoteDisplay {
     pub fn client(&mut self, id: &ClientId) -> &mut RemoteClient {
         self.clients.entry(*id).or_insert(RemoteClient::new(*id))
     }
+
+    pub fn toplevel_count(&self) -> usize {
+        self.clients
+            .values()
+            .flat_map(|client| client.surfaces.values())
+            .filter(|surface| matches!(surface.role, Some(Role::XdgToplevel(_))))
+            .count()
+    }
 }
 
 impl Default for RemoteDisplay {
@@ -722,3 +1522,144 @@ impl Default for RemoteDisplay {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialization::session_lock::SessionLockSurfaceState;
+
+    #[test]
+    fn test_role_key() {
+        let subsurface = |parent| {
+            RoleKey::from(&wayland::Role::SubSurface(wayland::SubSurfaceState {
+                parent: WlSurfaceId(parent),
+                location: (0, 0).into(),
+                sync: false,
+            }))
+        };
+        assert_eq!(
+            subsurface(1),
+            RoleKey::SubSurface {
+                parent: WlSurfaceId(1)
+            }
+        );
+        // A surface id reused for a subsurface of another parent is a new
+        // surface.
+        assert_ne!(subsurface(1), subsurface(2));
+        assert_ne!(subsurface(1), RoleKey::Cursor);
+        assert_eq!(
+            RoleKey::from(&wayland::Role::Cursor((1, 2).into())),
+            RoleKey::Cursor
+        );
+        assert_eq!(
+            RoleKey::from(&wayland::Role::SessionLockSurface(
+                SessionLockSurfaceState { output: 3 }
+            )),
+            RoleKey::SessionLockSurface { output: 3 }
+        );
+        assert_ne!(
+            RoleKey::XdgToplevel(XdgToplevelId(1)),
+            RoleKey::XdgToplevel(XdgToplevelId(2))
+        );
+    }
+
+    #[test]
+    fn test_resync_stale_surfaces() {
+        let client_a = ClientId(1);
+        let client_b = ClientId(2);
+        let known = [
+            (client_a, WlSurfaceId(1)),
+            (client_a, WlSurfaceId(2)),
+            (client_b, WlSurfaceId(1)),
+        ];
+
+        // Nothing was resent yet.
+        let mut resync = Resync::default();
+        assert_eq!(resync.stale_surfaces(known), known.to_vec());
+
+        // The windows of resent surfaces survive the reconnection, even if
+        // another client has a surface with the same id.
+        resync.surfaces.insert((client_a, WlSurfaceId(1)));
+        resync.surfaces.insert((client_b, WlSurfaceId(1)));
+        assert_eq!(
+            resync.stale_surfaces(known),
+            vec![(client_a, WlSurfaceId(2))]
+        );
+    }
+
+    #[test]
+    fn test_offered_by_server() {
+        let local = ["text/plain".to_string(), "UTF8_STRING".to_string()];
+        assert!(!offered_by_server(&local));
+        // Copied from an application on the server.
+        let remote = ["text/plain".to_string(), "_wprs_marker".to_string()];
+        assert!(offered_by_server(&remote));
+    }
+
+    #[test]
+    fn test_remove_client_objects() {
+        let client_a = ClientId(1);
+        let client_b = ClientId(2);
+        let mut object_bimap = BiMap::new();
+        object_bimap.insert((client_a, ObjectId::WlSurface(WlSurfaceId(1))), 10);
+        object_bimap.insert((client_a, ObjectId::WlSurface(WlSurfaceId(2))), 11);
+        object_bimap.insert((client_b, ObjectId::WlSurface(WlSurfaceId(1))), 20);
+
+        remove_client_objects(&mut object_bimap, client_a);
+
+        assert_eq!(object_bimap.len(), 1);
+        assert_eq!(
+            object_bimap.get_by_right(&20),
+            Some(&(client_b, ObjectId::WlSurface(WlSurfaceId(1))))
+        );
+    }
+
+    #[test]
+    fn test_remove_objects_is_idempotent() {
+        let client = ClientId(1);
+        let toplevel = ObjectId::XdgToplevel(XdgToplevelId(2));
+        let surface = ObjectId::WlSurface(WlSurfaceId(1));
+        let mut object_bimap = BiMap::new();
+        object_bimap.insert((client, surface), 10);
+        object_bimap.insert((client, toplevel), 11);
+
+        // The toplevel is destroyed before its surface, and the surface's
+        // removal covers the toplevel again.
+        remove_objects(&mut object_bimap, client, [toplevel]);
+        remove_objects(&mut object_bimap, client, [surface, toplevel]);
+        assert!(object_bimap.is_empty());
+
+        remove_objects(&mut object_bimap, client, [surface, toplevel]);
+        assert!(object_bimap.is_empty());
+    }
+
+    #[test]
+    fn test_needs_present() {
+        let damage = [Rectangle::new(0, 0, 10, 10)];
+        // A new buffer.
+        assert!(needs_present(true, None));
+        assert!(needs_present(true, Some(&[])));
+        // A commit with damage but no new buffer.
+        assert!(needs_present(false, Some(&damage)));
+        // A commit which only changed metadata.
+        assert!(!needs_present(false, None));
+        assert!(!needs_present(false, Some(&[])));
+    }
+
+    #[test]
+    fn test_idle_inhibitor_change() {
+        use IdleInhibitorChange::*;
+        // Requested by the application.
+        assert_eq!(idle_inhibitor_change(true, false, false, true), Create);
+        assert_eq!(idle_inhibitor_change(true, false, true, true), Keep);
+        // A fullscreen toplevel which didn't ask.
+        assert_eq!(idle_inhibitor_change(false, true, false, true), Create);
+        assert_eq!(idle_inhibitor_change(false, true, true, true), Keep);
+        // Released by the application, or the toplevel left fullscreen.
+        assert_eq!(idle_inhibitor_change(false, false, true, true), Destroy);
+        assert_eq!(idle_inhibitor_change(false, false, false, true), Keep);
+        // The surface lost its role and with it the local surface.
+        assert_eq!(idle_inhibitor_change(true, false, false, false), Keep);
+        assert_eq!(idle_inhibitor_change(false, false, true, false), Destroy);
+    }
+}
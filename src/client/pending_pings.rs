@@ -0,0 +1,102 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pings sent to the server which haven't been answered yet.
+//!
+//! Whoever asked for a ping gives up on it after PING_TIMEOUT, so pings older
+//! than that are dropped whenever a new one is sent rather than being kept
+//! until the server answers, which it may never do.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(Debug)]
+pub struct PendingPings<R> {
+    timeout: Duration,
+    next_nonce: u64,
+    /// When each ping was sent and where to send its round-trip time, by
+    /// nonce.
+    pending: HashMap<u64, (Instant, R)>,
+}
+
+impl<R> PendingPings<R> {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            next_nonce: 0,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Adds a ping sent at now, dropping the ones which timed out. Returns the
+    /// ping's nonce.
+    pub fn push(&mut self, now: Instant, reply: R) -> u64 {
+        self.pending
+            .retain(|_, (sent, _)| now.duration_since(*sent) < self.timeout);
+        let nonce = self.next_nonce;
+        self.next_nonce += 1;
+        self.pending.insert(nonce, (now, reply));
+        nonce
+    }
+
+    /// Takes the ping with nonce, along with when it was sent. None if it
+    /// timed out or was never sent.
+    pub fn take(&mut self, nonce: u64) -> Option<(Instant, R)> {
+        self.pending.remove(&nonce)
+    }
+
+    /// Forgets all pings, e.g. because the server won't answer them.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take() {
+        let start = Instant::now();
+        let mut pings = PendingPings::new(Duration::from_secs(10));
+        let first = pings.push(start, "a");
+        let second = pings.push(start, "b");
+        assert_ne!(first, second);
+
+        assert_eq!(pings.take(second), Some((start, "b")));
+        assert_eq!(pings.take(second), None);
+        assert_eq!(pings.take(first), Some((start, "a")));
+
+        pings.push(start, "c");
+        pings.clear();
+        assert!(pings.pending.is_empty());
+    }
+
+    #[test]
+    fn test_timed_out_pings_are_dropped() {
+        let start = Instant::now();
+        let mut pings = PendingPings::new(Duration::from_secs(10));
+        let old = pings.push(start, "a");
+        let recent = pings.push(start + Duration::from_secs(5), "b");
+
+        pings.push(start + Duration::from_secs(10), "c");
+        assert_eq!(pings.pending.len(), 2);
+        assert_eq!(pings.take(old), None);
+        assert_eq!(
+            pings.take(recent),
+            Some((start + Duration::from_secs(5), "b"))
+        );
+    }
+}
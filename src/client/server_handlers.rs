@@ -19,19 +19,34 @@ use std::io::Write;
 use std::os::fd::OwnedFd;
 use std::sync::Arc;
 use std::thread;
+use std::time::Instant;
 
+use smithay_client_toolkit::activation::RequestData;
+use smithay_client_toolkit::reexports::client::protocol::wl_surface::WlSurface;
+use smithay_client_toolkit::reexports::client::Proxy;
+use smithay_client_toolkit::seat::pointer::ThemedPointer;
 use smithay_client_toolkit::shell::WaylandSurface;
 
+use crate::client::offered_by_server;
+use crate::client::remove_client_objects;
+use crate::client::remove_objects;
 use crate::client::subsurface;
 use crate::client::subsurface::RemoteSubSurface;
+use crate::client::ObjectBimapExt;
+use crate::client::PresentationMode;
 use crate::client::RemoteCursor;
+use crate::client::RemoteLayerSurface;
+use crate::client::RemoteSessionLockSurface;
 use crate::client::RemoteSurface;
 use crate::client::RemoteXdgPopup;
 use crate::client::RemoteXdgToplevel;
+use crate::client::Resync;
 use crate::client::Role;
 use crate::client::WprsClientState;
 use crate::fallible_entry::FallibleEntryExt;
 use crate::prelude::*;
+use crate::serialization::session_lock::SessionLockEvent;
+use crate::serialization::session_lock::SessionLockRequest;
 use crate::serialization::tuple::Tuple2;
 use crate::serialization::wayland;
 use crate::serialization::wayland::ClientSurface;
@@ -43,10 +58,15 @@ use crate::serialization::wayland::DataRequest;
 use crate::serialization::wayland::DataSource;
 use crate::serialization::wayland::DataSourceRequest;
 use crate::serialization::wayland::DataToTransfer;
+use crate::serialization::wayland::InputSnapshot;
+use crate::serialization::wayland::PresentationFeedback;
+use crate::serialization::wayland::SurfaceEvent;
+use crate::serialization::wayland::SurfaceEventPayload;
 use crate::serialization::wayland::SurfaceRequest;
 use crate::serialization::wayland::SurfaceRequestPayload;
 use crate::serialization::wayland::SurfaceState;
 use crate::serialization::wayland::WlSurfaceId;
+use crate::serialization::wlr_layer_shell::LayerSurfaceEvent;
 use crate::serialization::xdg_shell;
 use crate::serialization::xdg_shell::PopupRequest;
 use crate::serialization::xdg_shell::PopupRequestPayload;
@@ -59,14 +79,81 @@ use crate::serialization::RecvType;
 use crate::serialization::Request;
 use crate::serialization::SendType;
 
+/// What to send back for a commit, however far handling it got: the server
+/// expects exactly one PresentationFeedback and one FrameAck per commit with a
+/// new buffer, see the WpPresentationFeedback dispatch handler. The feedback is
+/// Discarded unless the local compositor was asked for it.
+fn commit_acks(new_buffer: bool, feedback_requested: bool) -> Vec<SurfaceEventPayload> {
+    if !new_buffer {
+        return Vec::new();
+    }
+    let mut acks = Vec::new();
+    if !feedback_requested {
+        acks.push(SurfaceEventPayload::PresentationFeedback(
+            PresentationFeedback::Discarded,
+        ));
+    }
+    acks.push(SurfaceEventPayload::FrameAck);
+    acks
+}
+
 impl WprsClientState {
     #[instrument(skip(self), level = "debug")]
     fn handle_commit(
+        &mut self,
+        client_id: ClientId,
+        surface_id: WlSurfaceId,
+        surface_state: SurfaceState,
+    ) -> Result<()> {
+        let new_buffer = matches!(
+            surface_state.buffer,
+            Some(wayland::BufferAssignment::New(_))
+        );
+        let mut feedback_requested = false;
+        let result = self.apply_commit(
+            client_id,
+            surface_id,
+            surface_state,
+            &mut feedback_requested,
+        );
+        for payload in commit_acks(new_buffer, feedback_requested) {
+            self.serializer
+                .writer()
+                .send(SendType::Object(Event::Surface(SurfaceEvent {
+                    surface_id,
+                    payload,
+                })));
+        }
+        result
+    }
+
+    /// Sets feedback_requested if the local compositor was asked for
+    /// presentation feedback for the commit's buffer.
+    fn apply_commit(
         &mut self,
         client_id: ClientId,
         surface_id: WlSurfaceId,
         mut surface_state: SurfaceState,
+        feedback_requested: &mut bool,
     ) -> Result<()> {
+        if self.refuse_window(client_id, surface_id, &surface_state) {
+            // Don't let the buffer for this commit be used by the next one.
+            self.buffer_cache = None;
+            return Ok(());
+        }
+
+        let reused = self
+            .remote_display
+            .client(&client_id)
+            .surfaces
+            .get(&surface_id)
+            .is_some_and(|surface| !surface.role_matches(surface_state.role.as_ref()));
+        if reused {
+            warn!("surface id {surface_id:?} was reused with a different role, recreating surface");
+            self.handle_surface_destroy(client_id, surface_id)
+                .location(loc!())?;
+        }
+
         let client = self.remote_display.client(&client_id);
         let surfaces = &mut client.surfaces;
 
@@ -84,6 +171,11 @@ impl WprsClientState {
                 })
                 .location(loc!())?;
 
+            let new_buffer = matches!(
+                surface_state.buffer,
+                Some(wayland::BufferAssignment::New(_))
+            );
+            let decode_start = Instant::now();
             remote_surface
                 .apply_buffer(
                     surface_state.buffer.take(),
@@ -91,11 +183,38 @@ impl WprsClientState {
                     &mut self.pool,
                 )
                 .location(loc!())?;
+            if new_buffer {
+                self.serializer
+                    .stats()
+                    .record_frame_decoded(decode_start.elapsed());
+            }
 
             remote_surface.set_transformation(
                 surface_state.buffer_scale,
                 surface_state.buffer_transform.map(Into::into),
             );
+            remote_surface.set_viewport(surface_state.viewport, self.viewporter.as_ref(), &self.qh);
+            remote_surface.init_fractional_scale(self.fractional_scale_manager.as_ref(), &self.qh);
+            // See commit_acks.
+            if new_buffer {
+                if let Some(presentation) = &self.presentation {
+                    let wl_surface = remote_surface.wl_surface().clone();
+                    presentation.feedback(&wl_surface, &self.qh, wl_surface.clone());
+                    *feedback_requested = true;
+                }
+            }
+            remote_surface
+                .set_pointer_constraint(
+                    surface_state.pointer_constraint.take(),
+                    &self.pointer_constraints_state,
+                    self.seat_objects
+                        .iter()
+                        .find_map(|seat_object| seat_object.pointer.as_ref())
+                        .map(ThemedPointer::pointer),
+                    &self.compositor_state,
+                    &self.qh,
+                )
+                .location(loc!())?;
 
             remote_surface
                 .set_input_region(surface_state.input_region.take(), &self.compositor_state)
@@ -126,6 +245,8 @@ impl WprsClientState {
         )
         .location(loc!())?;
         subsurface::reorder_subsurfaces(surface_id, &surface_state, surfaces).location(loc!())?;
+        let idle_inhibit = surface_state.idle_inhibit;
+        let keyboard_shortcuts_inhibit = surface_state.keyboard_shortcuts_inhibit;
 
         match &surface_state.role {
             Some(wayland::Role::Cursor(_)) => {},
@@ -161,10 +282,62 @@ impl WprsClientState {
                 &mut self.object_bimap,
             )
             .location(loc!())?,
+            Some(wayland::Role::LayerSurface(_)) => {
+                if let Some(layer_shell) = &self.layer_shell {
+                    RemoteLayerSurface::apply(
+                        client.id,
+                        surface_state,
+                        surface_id,
+                        surfaces,
+                        layer_shell,
+                        &self.output_state,
+                        &self.qh,
+                    )
+                    .location(loc!())?
+                } else {
+                    // Otherwise the application would wait forever for the
+                    // initial configure.
+                    debug!("wlr layer shell is not available, closing layer surface");
+                    self.serializer
+                        .writer()
+                        .send(SendType::Object(Event::LayerSurface(
+                            LayerSurfaceEvent::Closed(surface_id),
+                        )));
+                }
+            },
+            Some(wayland::Role::SessionLockSurface(_)) => {
+                if let Some(session_lock) = &self.session_lock {
+                    RemoteSessionLockSurface::set_role(
+                        client.id,
+                        &surface_state,
+                        surface_id,
+                        surfaces,
+                        session_lock,
+                        &self.output_state,
+                        &self.qh,
+                    )
+                    .location(loc!())?
+                } else {
+                    debug!("the local session isn't locked, not showing lock surface");
+                }
+            },
             None => {},
         }
 
-        if frame_callback_completed {
+        // After the role, which the local idle inhibitor needs.
+        let remote_surface = surfaces.get_mut(&surface_id).location(loc!())?;
+        remote_surface.idle_inhibit_requested = idle_inhibit;
+        remote_surface.update_idle_inhibitor(self.idle_inhibit_manager.as_ref(), &self.qh);
+        remote_surface.set_keyboard_shortcuts_inhibited(
+            keyboard_shortcuts_inhibit,
+            self.keyboard_shortcuts_inhibit_manager.as_ref(),
+            self.seat_objects
+                .first()
+                .map(|seat_object| &seat_object.seat),
+            &self.qh,
+        );
+
+        if frame_callback_completed || self.presentation_mode == PresentationMode::Immediate {
             subsurface::commit_sync_children(surface_id, surfaces).location(loc!())?;
             let remote_surface = surfaces.get_mut(&surface_id).location(loc!())?;
             match &remote_surface.role {
@@ -175,6 +348,11 @@ impl WprsClientState {
                 Some(Role::XdgPopup(popup)) if !popup.configured => {
                     popup.commit();
                 },
+                Some(Role::LayerSurface(layer_surface)) if !layer_surface.configured => {
+                    layer_surface.commit();
+                },
+                // Lock surfaces mustn't be committed before they're configured.
+                Some(Role::SessionLockSurface(lock_surface)) if !lock_surface.configured => {},
                 _ => remote_surface
                     .draw_buffer_send_frame(&self.qh)
                     .location(loc!())?,
@@ -183,14 +361,76 @@ impl WprsClientState {
         Ok(())
     }
 
+    /// Returns whether surface_state would create a new toplevel beyond
+    /// max_windows or one whose buffer is smaller than min_window_size.
+    fn refuse_window(
+        &mut self,
+        client_id: ClientId,
+        surface_id: WlSurfaceId,
+        surface_state: &SurfaceState,
+    ) -> bool {
+        if !matches!(surface_state.role, Some(wayland::Role::XdgToplevel(_))) {
+            return false;
+        }
+        let key = (client_id, surface_id);
+        if self.refused_windows.contains(&key) {
+            return true;
+        }
+        let is_new = !self
+            .remote_display
+            .client(&client_id)
+            .surfaces
+            .get(&surface_id)
+            .is_some_and(|surface| matches!(surface.role, Some(Role::XdgToplevel(_))));
+        if !is_new {
+            return false;
+        }
+
+        let large_enough = self.min_window_size <= 0
+            || match &surface_state.buffer {
+                Some(wayland::BufferAssignment::New(buffer)) => {
+                    buffer.metadata.width >= self.min_window_size
+                        && buffer.metadata.height >= self.min_window_size
+                },
+                _ => false,
+            };
+        if !large_enough {
+            debug!(
+                "not creating window for surface {surface_id:?} until it has a large enough buffer"
+            );
+            self.suppressed_windows.insert(key);
+            return true;
+        }
+        self.suppressed_windows.remove(&key);
+
+        if self.remote_display.toplevel_count() >= self.max_windows {
+            warn!(
+                "refusing to create window for surface {surface_id:?}: limit of {} windows reached",
+                self.max_windows
+            );
+            self.refused_windows.insert(key);
+            return true;
+        }
+        false
+    }
+
     #[instrument(skip(self), level = "debug")]
     fn handle_surface_destroy(
         &mut self,
         client_id: ClientId,
         surface_id: WlSurfaceId,
     ) -> Result<()> {
+        self.refused_windows.remove(&(client_id, surface_id));
+        self.suppressed_windows.remove(&(client_id, surface_id));
         let client = self.remote_display.client(&client_id);
         if let Some(surface) = client.surfaces.remove(&surface_id) {
+            if let Some(inhibitor) = &surface.keyboard_shortcuts_inhibitor {
+                inhibitor.destroy();
+            }
+            if let Some(inhibitor) = &surface.idle_inhibitor {
+                inhibitor.destroy();
+            }
+            remove_objects(&mut self.object_bimap, client_id, surface.object_ids());
             if let Ok(Role::SubSurface(subsurface)) = surface.get_role() {
                 // The parent surface may have already been destroyed.
                 if let Some(parent) = client.surfaces.get_mut(&subsurface.parent) {
@@ -203,6 +443,39 @@ impl WprsClientState {
         Ok(())
     }
 
+    #[instrument(skip(self), level = "debug")]
+    fn handle_surface_activate(
+        &mut self,
+        client_id: ClientId,
+        surface_id: WlSurfaceId,
+        user_input: bool,
+    ) -> Result<()> {
+        let Some(activation_state) = &self.activation_state else {
+            debug!("xdg_activation_v1 is not available, ignoring activation request");
+            return Ok(());
+        };
+        let client = self.remote_display.client(&client_id);
+        let surface = client.surface(&surface_id).location(loc!())?;
+        // The local compositor uses the seat and serial to decide whether the
+        // activation follows user input; if not, it may only mark the surface
+        // as urgent instead of focusing it. Only vouch for the application if
+        // its own token followed input on the server.
+        let seat_and_serial = self
+            .seat_objects
+            .first()
+            .filter(|_| user_input)
+            .map(|seat_object| (seat_object.seat.clone(), self.last_enter_serial));
+        activation_state.request_token(
+            &self.qh,
+            RequestData {
+                app_id: None,
+                seat_and_serial,
+                surface: Some(surface.wl_surface().clone()),
+            },
+        );
+        Ok(())
+    }
+
     #[instrument(skip(self), level = "debug")]
     fn handle_surface(&mut self, request: SurfaceRequest) -> Result<()> {
         if (matches!(request.payload, SurfaceRequestPayload::Destroyed)
@@ -215,6 +488,9 @@ impl WprsClientState {
         let surface_id = request.surface;
         match request.payload {
             SurfaceRequestPayload::Commit(surface_state) => {
+                if let Some(resync) = &mut self.resync {
+                    resync.surfaces.insert((request.client, surface_id));
+                }
                 self.handle_commit(request.client, surface_id, surface_state)
                     .location(loc!())?;
             },
@@ -222,6 +498,10 @@ impl WprsClientState {
                 self.handle_surface_destroy(request.client, surface_id)
                     .location(loc!())?;
             },
+            SurfaceRequestPayload::Activate { user_input } => {
+                self.handle_surface_activate(request.client, surface_id, user_input)
+                    .location(loc!())?;
+            },
         }
         Ok(())
     }
@@ -235,19 +515,27 @@ impl WprsClientState {
             return Ok(());
         }
 
+        let key = (request.client, request.surface);
+        if self.refused_windows.contains(&key) || self.suppressed_windows.contains(&key) {
+            return Ok(());
+        }
+
         let client = self.remote_display.client(&request.client);
         // TODO: these properties aren't in the double-buffered state in
         // smithay, but still only take affect on commit. That seems wrong. In
         // the meantime though, we can get these before the initial commit.
         let Ok(surface) = client.surface(&request.surface) else {
-            warn!("received request for unknown surface");
+            // The surface may be destroyed before its toplevel.
+            if request.payload != ToplevelRequestPayload::Destroyed {
+                warn!("received request for unknown surface");
+            }
             return Ok(());
         };
 
         if let Some(Role::XdgToplevel(toplevel)) = &surface.role {
             match request.payload {
                 ToplevelRequestPayload::Destroyed => {
-                    surface.role = None;
+                    surface.clear_role(&mut self.object_bimap);
                 },
                 ToplevelRequestPayload::SetMaximized => {
                     toplevel.local_window.set_maximized();
@@ -295,10 +583,13 @@ impl WprsClientState {
         }
 
         let client = self.remote_display.client(&request.client);
-        let surface = client.surface(&request.surface).location(loc!())?;
+        let Ok(surface) = client.surface(&request.surface) else {
+            // The surface may be destroyed before its popup.
+            return Ok(());
+        };
         match request.payload {
             PopupRequestPayload::Destroyed => {
-                surface.role = None;
+                surface.clear_role(&mut self.object_bimap);
             },
         }
         Ok(())
@@ -306,6 +597,36 @@ impl WprsClientState {
 
     #[instrument(skip(self), level = "debug")]
     fn handle_cursor_image(&mut self, cursor_image: CursorImage) -> Result<()> {
+        self.show_cursor_image(&cursor_image.status)
+            .location(loc!())?;
+        self.cursor_image = Some(cursor_image.status);
+        Ok(())
+    }
+
+    /// Shows the cursor image the server last set when the pointer enters a
+    /// surface, rather than waiting a round trip for the application to set
+    /// it. The application's cursor image replaces it when it arrives.
+    pub(crate) fn predict_cursor_image(&mut self) -> Result<()> {
+        let status = match &self.cursor_image {
+            // The cursor surface may have been destroyed in the meantime.
+            Some(CursorImageStatus::Surface {
+                client_surface: ClientSurface { client, surface },
+                ..
+            }) if !self
+                .remote_display
+                .clients
+                .get(client)
+                .is_some_and(|client| client.surfaces.contains_key(surface)) =>
+            {
+                None
+            },
+            status => status.clone(),
+        };
+        self.show_cursor_image(&status.unwrap_or(CursorImageStatus::Named("default".to_string())))
+            .location(loc!())
+    }
+
+    fn show_cursor_image(&mut self, status: &CursorImageStatus) -> Result<()> {
         // TODO: support multiple seats
         let Some(themed_pointer) = self.seat_objects.last().location(loc!())?.pointer.as_ref()
         else {
@@ -313,7 +634,7 @@ impl WprsClientState {
             return Ok(());
         };
 
-        match cursor_image.status {
+        match status {
             CursorImageStatus::Named(name) => {
                 themed_pointer
                     .set_cursor(
@@ -330,15 +651,15 @@ impl WprsClientState {
                 let client = self
                     .remote_display
                     .clients
-                    .get_mut(&client)
+                    .get_mut(client)
                     .location(loc!())?;
                 let remote_surface = client
                     .surfaces
-                    .entry(surface)
+                    .entry(*surface)
                     .or_insert_with_result(|| {
                         RemoteSurface::new(
                             client.id,
-                            surface,
+                            *surface,
                             &self.compositor_state,
                             &self.qh,
                             &mut self.object_bimap,
@@ -556,18 +877,184 @@ impl WprsClientState {
 
     #[instrument(skip(self), level = "debug")]
     fn handle_client_disconnected(&mut self, client: ClientId) -> Result<()> {
+        // Dropping the client's surfaces destroys the corresponding local
+        // surfaces, and with them any windows and popups.
         self.remote_display.clients.remove(&client);
+        remove_client_objects(&mut self.object_bimap, client);
+        self.refused_windows
+            .retain(|(client_id, _)| *client_id != client);
+        self.suppressed_windows
+            .retain(|(client_id, _)| *client_id != client);
         Ok(())
     }
 
     #[instrument(skip(self), level = "debug")]
     fn handle_capabilities(&mut self, caps: Capabilities) -> Result<()> {
+        if self.resync.is_some() {
+            // The server resends its capabilities after a reconnection.
+            if self.capabilities.get() != Some(&caps) {
+                warn!(
+                    "server capabilities changed to {caps:?} while reconnecting, keeping {:?}",
+                    self.capabilities.get()
+                );
+            }
+            return Ok(());
+        }
         self.capabilities
             .set(caps)
             .map_err(|_| anyhow!("attempted to set capabilities more than once"))
             .location(loc!())
     }
 
+    #[instrument(skip(self), level = "debug")]
+    fn handle_session_lock(&mut self, request: SessionLockRequest) -> Result<()> {
+        match request {
+            SessionLockRequest::Lock => {
+                if let Some(resync) = &mut self.resync {
+                    resync.session_locked = true;
+                }
+                if self.session_lock.is_some() {
+                    return Ok(());
+                }
+                match self.session_lock_state.lock(&self.qh) {
+                    Ok(session_lock) => {
+                        self.session_lock = Some(session_lock);
+                        // Exiting would leave the local session locked
+                        // without any way to unlock it. Instead, keep
+                        // showing the lock surfaces until the server, which
+                        // runs the actual screen locker, is back and unlocks.
+                        self.serializer.set_keep_reconnecting(true);
+                    },
+                    Err(err) => {
+                        warn!("unable to lock the local session: {err:?}");
+                        self.serializer
+                            .writer()
+                            .send(SendType::Object(Event::SessionLock(
+                                SessionLockEvent::Finished,
+                            )));
+                    },
+                }
+            },
+            SessionLockRequest::Unlock => self.unlock_local_session(),
+        }
+        Ok(())
+    }
+
+    fn unlock_local_session(&mut self) {
+        if let Some(session_lock) = self.session_lock.take() {
+            session_lock.unlock();
+        }
+        self.serializer.set_keep_reconnecting(false);
+    }
+
+    #[instrument(skip(self), level = "debug")]
+    fn handle_reconnected(&mut self) -> Result<()> {
+        self.resync = Some(Resync::default());
+        // Pings sent before the reconnection won't be answered.
+        self.pending_pings.clear();
+        self.serializer
+            .writer()
+            .send(SendType::Object(Event::WprsClientConnect(
+                self.client_capabilities(),
+            )));
+        self.announce_outputs();
+        // The local selection may have changed while the client was
+        // disconnected. The server still has its own selection.
+        // TODO: support multiple seats
+        let offer = self
+            .seat_objects
+            .iter()
+            .last()
+            .and_then(|seat_obj| seat_obj.data_device.data().selection_offer())
+            .filter(|offer| !offer.with_mime_types(offered_by_server));
+        if let Some(offer) = offer {
+            self.send_selection(&offer, self.clipboard_push_on_focus);
+            self.selection_offer = Some(offer);
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self), level = "debug")]
+    fn handle_pong(&mut self, nonce: u64) -> Result<()> {
+        // The ping may have timed out, or been sent before a reconnection.
+        let Some((sent, reply)) = self.pending_pings.take(nonce) else {
+            debug!("ignoring pong for unknown or timed out ping {nonce}");
+            return Ok(());
+        };
+        // The control connection may have gone away in the meantime.
+        _ = reply.send(sent.elapsed());
+        Ok(())
+    }
+
+    /// Destroys what the server didn't resend after a reconnection, i.e.,
+    /// what went away while the client was disconnected.
+    #[instrument(skip(self), level = "debug")]
+    fn handle_snapshot_complete(&mut self) -> Result<()> {
+        let Some(resync) = self.resync.take() else {
+            return Ok(());
+        };
+
+        let stale_surfaces =
+            resync.stale_surfaces(self.remote_display.clients.values().flat_map(|client| {
+                client
+                    .surfaces
+                    .keys()
+                    .map(|surface_id| (client.id, *surface_id))
+            }));
+        for (client_id, surface_id) in stale_surfaces {
+            self.handle_surface_destroy(client_id, surface_id)
+                .location(loc!())?;
+        }
+
+        let stale_clients: Vec<ClientId> = self
+            .remote_display
+            .clients
+            .values()
+            .filter(|client| client.surfaces.is_empty())
+            .map(|client| client.id)
+            .collect();
+        for client_id in stale_clients {
+            self.handle_client_disconnected(client_id)
+                .location(loc!())?;
+        }
+
+        if !resync.session_locked {
+            self.unlock_local_session();
+        }
+
+        if self.restore_input_on_reconnect {
+            self.send_input_snapshot();
+        }
+        Ok(())
+    }
+
+    /// Sent once the server's surfaces are known again, so that the focused
+    /// surfaces can be referred to.
+    fn send_input_snapshot(&self) {
+        let surface_id = |surface: &WlSurface| {
+            self.object_bimap
+                .get_wl_surface_id(&surface.id())
+                .map(|(_, surface_id)| surface_id)
+        };
+        let keyboard_focus = self.current_focus.as_ref().and_then(surface_id);
+        let snapshot = InputSnapshot {
+            keyboard_focus,
+            pressed_keys: if keyboard_focus.is_some() {
+                self.pressed_keys.iter().copied().collect()
+            } else {
+                Vec::new()
+            },
+            pointer_focus: self
+                .pointer_focus
+                .as_ref()
+                .and_then(|(surface, position)| Some((surface_id(surface)?, *position))),
+            pressed_buttons: self.pressed_buttons.iter().copied().collect(),
+        };
+        self.serializer
+            .writer()
+            .send(SendType::Object(Event::InputSnapshot(snapshot)));
+    }
+
     #[instrument(skip_all, level = "debug")]
     fn handle_buffer(&mut self, buffer: Vec<u8>) -> Result<()> {
         self.buffer_cache = Some(Arc::new(buffer.into()));
@@ -588,9 +1075,35 @@ impl WprsClientState {
                 self.handle_client_disconnected(client)
             },
             RecvType::Object(Request::Capabilities(caps)) => self.handle_capabilities(caps),
+            RecvType::Object(Request::SessionLock(request)) => self.handle_session_lock(request),
+            RecvType::Object(Request::SnapshotComplete) => self.handle_snapshot_complete(),
+            RecvType::Object(Request::Pong(nonce)) => self.handle_pong(nonce),
+            RecvType::Object(Request::TextInput(request)) => self.handle_text_input(request),
             RecvType::RawBuffer(buffer) => self.handle_buffer(buffer),
+            RecvType::Reconnected => self.handle_reconnected(),
         }
         .log_and_ignore(loc!())
         // TODO: maybe send errors back to the server.
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commit_acks() {
+        assert_eq!(commit_acks(false, false), Vec::new());
+        // A refused window, or a commit which failed before its buffer was
+        // attached, still reports its frame.
+        assert_eq!(
+            commit_acks(true, false),
+            vec![
+                SurfaceEventPayload::PresentationFeedback(PresentationFeedback::Discarded),
+                SurfaceEventPayload::FrameAck,
+            ]
+        );
+        // The local compositor reports the presentation itself.
+        assert_eq!(commit_acks(true, true), vec![SurfaceEventPayload::FrameAck]);
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use smithay_client_toolkit::output::OutputState;
+use smithay_client_toolkit::reexports::client::QueueHandle;
+use smithay_client_toolkit::session_lock::SessionLock;
+use smithay_client_toolkit::session_lock::SessionLockSurface;
+
+use crate::client::RemoteSurface;
+use crate::client::Role;
+use crate::client::WprsClientState;
+use crate::prelude::*;
+use crate::serialization::wayland::SurfaceState;
+use crate::serialization::wayland::WlSurfaceId;
+use crate::serialization::ClientId;
+
+#[derive(Debug)]
+pub struct RemoteSessionLockSurface {
+    pub client: ClientId,
+    pub local_lock_surface: SessionLockSurface,
+    pub configured: bool,
+    pub output: u32,
+}
+
+impl RemoteSessionLockSurface {
+    pub fn set_role(
+        client_id: ClientId,
+        surface_state: &SurfaceState,
+        surface_id: WlSurfaceId,
+        surfaces: &mut HashMap<WlSurfaceId, RemoteSurface>,
+        session_lock: &SessionLock,
+        output_state: &OutputState,
+        qh: &QueueHandle<WprsClientState>,
+    ) -> Result<()> {
+        let surface = surfaces.get_mut(&surface_id).location(loc!())?;
+        if surface.role.is_some() {
+            return Ok(());
+        }
+        let lock_surface_state = surface_state
+            .role
+            .as_ref()
+            .location(loc!())?
+            .as_session_lock_surface()
+            .location(loc!())?;
+
+        let output = output_state
+            .outputs()
+            .find(|output| {
+                output_state
+                    .info(output)
+                    .is_some_and(|info| info.id == lock_surface_state.output)
+            })
+            .context(loc!(), "output of lock surface not found")?;
+
+        let local_surface = surface.local_surface.take().location(loc!())?;
+        let local_lock_surface = session_lock.create_lock_surface(local_surface, &output, qh);
+
+        surface.role = Some(Role::SessionLockSurface(Self {
+            client: client_id,
+            local_lock_surface,
+            configured: false,
+            output: lock_surface_state.output,
+        }));
+        Ok(())
+    }
+}
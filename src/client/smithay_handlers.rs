@@ -13,13 +13,21 @@
 // limitations under the License.
 
 /// Handlers for events from smithay client toolkit.
+use std::io::Read;
+use std::sync::Mutex;
+use std::thread;
+
 use smithay::reexports::wayland_protocols::wp::primary_selection::zv1::client::zwp_primary_selection_device_v1::ZwpPrimarySelectionDeviceV1;
 use smithay::reexports::wayland_protocols::wp::primary_selection::zv1::client::zwp_primary_selection_source_v1::ZwpPrimarySelectionSourceV1;
+use smithay_client_toolkit::activation::ActivationHandler;
+use smithay_client_toolkit::activation::RequestData;
+use smithay_client_toolkit::activation::RequestDataExt;
 use smithay_client_toolkit::compositor::CompositorHandler;
 use smithay_client_toolkit::compositor::SurfaceData;
 use smithay_client_toolkit::data_device_manager::data_device::DataDeviceHandler;
 use smithay_client_toolkit::data_device_manager::data_offer::DataOfferHandler;
 use smithay_client_toolkit::data_device_manager::data_offer::DragOffer;
+use smithay_client_toolkit::data_device_manager::data_offer::SelectionOffer;
 use smithay_client_toolkit::data_device_manager::data_source::DataSourceHandler;
 use smithay_client_toolkit::data_device_manager::WritePipe;
 use smithay_client_toolkit::output::OutputData;
@@ -40,10 +48,39 @@ use smithay_client_toolkit::reexports::client::protocol::wl_subcompositor::WlSub
 use smithay_client_toolkit::reexports::client::protocol::wl_subsurface::Event as WlSubsurfaceEvent;
 use smithay_client_toolkit::reexports::client::protocol::wl_subsurface::WlSubsurface;
 use smithay_client_toolkit::reexports::client::protocol::wl_surface::WlSurface;
+use smithay_client_toolkit::reexports::client::protocol::wl_touch::WlTouch;
 use smithay_client_toolkit::reexports::client::Connection;
 use smithay_client_toolkit::reexports::client::Dispatch;
 use smithay_client_toolkit::reexports::client::Proxy;
 use smithay_client_toolkit::reexports::client::QueueHandle;
+use smithay_client_toolkit::reexports::client::WEnum;
+use smithay_client_toolkit::reexports::protocols::ext::idle_notify::v1::client::ext_idle_notification_v1;
+use smithay_client_toolkit::reexports::protocols::ext::idle_notify::v1::client::ext_idle_notification_v1::ExtIdleNotificationV1;
+use smithay_client_toolkit::reexports::protocols::ext::idle_notify::v1::client::ext_idle_notifier_v1;
+use smithay_client_toolkit::reexports::protocols::ext::idle_notify::v1::client::ext_idle_notifier_v1::ExtIdleNotifierV1;
+use smithay_client_toolkit::reexports::protocols::wp::fractional_scale::v1::client::wp_fractional_scale_manager_v1;
+use smithay_client_toolkit::reexports::protocols::wp::fractional_scale::v1::client::wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::fractional_scale::v1::client::wp_fractional_scale_v1;
+use smithay_client_toolkit::reexports::protocols::wp::fractional_scale::v1::client::wp_fractional_scale_v1::WpFractionalScaleV1;
+use smithay_client_toolkit::reexports::protocols::wp::pointer_constraints::zv1::client::zwp_confined_pointer_v1::ZwpConfinedPointerV1;
+use smithay_client_toolkit::reexports::protocols::wp::pointer_constraints::zv1::client::zwp_locked_pointer_v1::ZwpLockedPointerV1;
+use smithay_client_toolkit::reexports::protocols::wp::idle_inhibit::zv1::client::zwp_idle_inhibit_manager_v1;
+use smithay_client_toolkit::reexports::protocols::wp::idle_inhibit::zv1::client::zwp_idle_inhibit_manager_v1::ZwpIdleInhibitManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::idle_inhibit::zv1::client::zwp_idle_inhibitor_v1;
+use smithay_client_toolkit::reexports::protocols::wp::idle_inhibit::zv1::client::zwp_idle_inhibitor_v1::ZwpIdleInhibitorV1;
+use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibit_manager_v1;
+use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibit_manager_v1::ZwpKeyboardShortcutsInhibitManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibitor_v1;
+use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibitor_v1::ZwpKeyboardShortcutsInhibitorV1;
+use smithay_client_toolkit::reexports::protocols::wp::presentation_time::client::wp_presentation;
+use smithay_client_toolkit::reexports::protocols::wp::presentation_time::client::wp_presentation::WpPresentation;
+use smithay_client_toolkit::reexports::protocols::wp::presentation_time::client::wp_presentation_feedback;
+use smithay_client_toolkit::reexports::protocols::wp::presentation_time::client::wp_presentation_feedback::WpPresentationFeedback;
+use smithay_client_toolkit::reexports::protocols::wp::relative_pointer::zv1::client::zwp_relative_pointer_v1::ZwpRelativePointerV1;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewport;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewport::WpViewport;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewporter;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewporter::WpViewporter;
 use smithay_client_toolkit::registry::ProvidesRegistryState;
 use smithay_client_toolkit::registry::RegistryState;
 use smithay_client_toolkit::registry_handlers;
@@ -57,9 +94,20 @@ use smithay_client_toolkit::seat::pointer::PointerEvent;
 use smithay_client_toolkit::seat::pointer::PointerEventKind;
 use smithay_client_toolkit::seat::pointer::PointerHandler;
 use smithay_client_toolkit::seat::pointer::ThemeSpec;
+use smithay_client_toolkit::seat::pointer_constraints::PointerConstraintsHandler;
+use smithay_client_toolkit::seat::relative_pointer::RelativeMotionEvent;
+use smithay_client_toolkit::seat::relative_pointer::RelativePointerHandler;
+use smithay_client_toolkit::seat::touch::TouchHandler;
 use smithay_client_toolkit::seat::Capability;
 use smithay_client_toolkit::seat::SeatHandler;
 use smithay_client_toolkit::seat::SeatState;
+use smithay_client_toolkit::session_lock::SessionLock;
+use smithay_client_toolkit::session_lock::SessionLockHandler;
+use smithay_client_toolkit::session_lock::SessionLockSurface;
+use smithay_client_toolkit::session_lock::SessionLockSurfaceConfigure as SctkSessionLockSurfaceConfigure;
+use smithay_client_toolkit::shell::wlr_layer::LayerShellHandler;
+use smithay_client_toolkit::shell::wlr_layer::LayerSurface;
+use smithay_client_toolkit::shell::wlr_layer::LayerSurfaceConfigure as SctkLayerSurfaceConfigure;
 use smithay_client_toolkit::shell::xdg::popup;
 use smithay_client_toolkit::shell::xdg::window::Window;
 use smithay_client_toolkit::shell::xdg::window::WindowConfigure;
@@ -70,26 +118,37 @@ use smithay_client_toolkit::shm::ShmHandler;
 use tracing::Span;
 
 use crate::args;
+use crate::client::offered_by_server;
 use crate::client::subsurface;
+use crate::client::text_input::LocalTextInput;
 use crate::client::ObjectBimapExt;
 use crate::client::Role;
 use crate::client::SeatObject;
 use crate::client::WprsClientState;
 use crate::prelude::*;
+use crate::serialization::session_lock::SessionLockEvent;
+use crate::serialization::session_lock::SessionLockSurfaceConfigure;
 use crate::serialization::wayland;
 use crate::serialization::wayland::DataDestinationEvent;
 use crate::serialization::wayland::DataEvent;
 use crate::serialization::wayland::DataSource;
 use crate::serialization::wayland::DataSourceEvent;
+use crate::serialization::wayland::DataToTransfer;
 use crate::serialization::wayland::DragEnter;
+use crate::serialization::wayland::IdleEvent;
 use crate::serialization::wayland::KeyInner;
 use crate::serialization::wayland::KeyState;
 use crate::serialization::wayland::KeyboardEvent;
 use crate::serialization::wayland::Output;
 use crate::serialization::wayland::OutputEvent;
+use crate::serialization::wayland::PresentationFeedback;
 use crate::serialization::wayland::SourceMetadata;
 use crate::serialization::wayland::SurfaceEvent;
+use crate::serialization::wayland::SurfaceEventPayload;
 use crate::serialization::wayland::SurfaceEventPayload::OutputsChanged;
+use crate::serialization::wayland::TouchEvent;
+use crate::serialization::wlr_layer_shell::LayerSurfaceConfigure;
+use crate::serialization::wlr_layer_shell::LayerSurfaceEvent;
 use crate::serialization::xdg_shell::PopupConfigure;
 use crate::serialization::xdg_shell::PopupEvent;
 use crate::serialization::xdg_shell::ToplevelConfigure;
@@ -161,6 +220,13 @@ impl CompositorHandler for WprsClientState {
             // instead?
             return;
         };
+        self.serializer
+            .writer()
+            .send(SendType::Object(Event::Surface(SurfaceEvent {
+                surface_id,
+                payload: SurfaceEventPayload::FramePresented,
+            })));
+
         let client = self.remote_display.client(&client_id);
 
         subsurface::commit_sync_children(surface_id, &mut client.surfaces).log_and_ignore(loc!());
@@ -206,6 +272,9 @@ impl OutputHandler for WprsClientState {
 
     #[instrument(skip(self, _conn, _qh), level = "debug")]
     fn new_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, output: WlOutput) {
+        if !self.outputs_announced {
+            return;
+        }
         let output_info = self.output_state().info(&output).unwrap();
         self.serializer
             .writer()
@@ -216,6 +285,9 @@ impl OutputHandler for WprsClientState {
 
     #[instrument(skip(self, _conn, _qh), level = "debug")]
     fn update_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, output: WlOutput) {
+        if !self.outputs_announced {
+            return;
+        }
         let output_info = self.output_state().info(&output).unwrap();
         self.serializer
             .writer()
@@ -226,6 +298,9 @@ impl OutputHandler for WprsClientState {
 
     #[instrument(skip(self, _conn, _qh), level = "debug")]
     fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, output: WlOutput) {
+        if !self.outputs_announced {
+            return;
+        }
         let output_info = self.output_state().info(&output).unwrap();
         self.serializer
             .writer()
@@ -236,7 +311,22 @@ impl OutputHandler for WprsClientState {
 }
 
 impl WindowHandler for WprsClientState {
-    fn request_close(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &Window) {}
+    #[instrument(skip_all, level = "debug")]
+    fn request_close(&mut self, _: &Connection, _: &QueueHandle<Self>, window: &Window) {
+        // The window is only removed once the application destroys it, see
+        // handle_toplevel.
+        let Some((_, surface_id)) = self
+            .object_bimap
+            .get_wl_surface_id(&window.wl_surface().id())
+        else {
+            return;
+        };
+        self.serializer
+            .writer()
+            .send(SendType::Object(Event::Toplevel(ToplevelEvent::Close(
+                surface_id,
+            ))));
+    }
 
     #[instrument(skip_all, level = "debug")]
     fn configure(
@@ -261,10 +351,12 @@ impl WindowHandler for WprsClientState {
             .as_xdg_toplevel_mut()
             .unwrap();
 
+        toplevel.fullscreen = configure.is_fullscreen();
         if !toplevel.configured {
             toplevel.configured = true;
             surface.draw_buffer_send_frame(qh).log_and_ignore(loc!());
         }
+        surface.update_idle_inhibitor(self.idle_inhibit_manager.as_ref(), qh);
 
         self.serializer
             .writer()
@@ -308,6 +400,127 @@ impl popup::PopupHandler for WprsClientState {
     }
 }
 
+impl LayerShellHandler for WprsClientState {
+    #[instrument(skip_all, level = "debug")]
+    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, layer: &LayerSurface) {
+        let (_, surface_id) = self
+            .object_bimap
+            .get_wl_surface_id(&layer.wl_surface().id())
+            .expect("Object corresponding to client object id {key} not found.");
+
+        // The application is expected to destroy the layer surface in
+        // response, which will destroy the local one.
+        self.serializer
+            .writer()
+            .send(SendType::Object(Event::LayerSurface(
+                LayerSurfaceEvent::Closed(surface_id),
+            )));
+    }
+
+    #[instrument(skip_all, level = "debug")]
+    fn configure(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        layer: &LayerSurface,
+        configure: SctkLayerSurfaceConfigure,
+        _serial: u32,
+    ) {
+        let (client_id, surface_id) = self
+            .object_bimap
+            .get_wl_surface_id(&layer.wl_surface().id())
+            .expect("Object corresponding to client object id {key} not found.");
+
+        let client = self.remote_display.client(&client_id);
+        let surface = client.surface(&surface_id).unwrap();
+        let remote_layer_surface = surface
+            .role
+            .as_mut()
+            .unwrap()
+            .as_layer_surface_mut()
+            .unwrap();
+        if !remote_layer_surface.configured {
+            remote_layer_surface.configured = true;
+            surface.draw_buffer_send_frame(qh).log_and_ignore(loc!());
+        }
+
+        self.serializer
+            .writer()
+            .send(SendType::Object(Event::LayerSurface(
+                LayerSurfaceEvent::Configure(LayerSurfaceConfigure::from_smithay(
+                    &surface_id,
+                    configure,
+                )),
+            )));
+    }
+}
+
+impl SessionLockHandler for WprsClientState {
+    #[instrument(skip_all, level = "debug")]
+    fn locked(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _session_lock: SessionLock) {
+        self.serializer
+            .writer()
+            .send(SendType::Object(Event::SessionLock(
+                SessionLockEvent::Locked,
+            )));
+    }
+
+    #[instrument(skip_all, level = "debug")]
+    fn finished(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _session_lock: SessionLock,
+    ) {
+        // The local compositor refused the lock, e.g. because another
+        // application holds it.
+        self.session_lock = None;
+        self.serializer.set_keep_reconnecting(false);
+        self.serializer
+            .writer()
+            .send(SendType::Object(Event::SessionLock(
+                SessionLockEvent::Finished,
+            )));
+    }
+
+    #[instrument(skip_all, level = "debug")]
+    fn configure(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        session_lock_surface: SessionLockSurface,
+        configure: SctkSessionLockSurfaceConfigure,
+        _serial: u32,
+    ) {
+        let (client_id, surface_id) = self
+            .object_bimap
+            .get_wl_surface_id(&session_lock_surface.wl_surface().id())
+            .expect("Object corresponding to client object id {key} not found.");
+
+        let client = self.remote_display.client(&client_id);
+        let surface = client.surface(&surface_id).unwrap();
+        let remote_lock_surface = surface
+            .role
+            .as_mut()
+            .unwrap()
+            .as_session_lock_surface_mut()
+            .unwrap();
+        if !remote_lock_surface.configured {
+            remote_lock_surface.configured = true;
+            surface.draw_buffer_send_frame(qh).log_and_ignore(loc!());
+        }
+
+        self.serializer
+            .writer()
+            .send(SendType::Object(Event::SessionLock(
+                SessionLockEvent::Configure(SessionLockSurfaceConfigure::from_smithay(
+                    &surface_id,
+                    configure,
+                )),
+            )));
+    }
+}
+
 impl SeatHandler for WprsClientState {
     fn seat_state(&mut self) -> &mut SeatState {
         &mut self.seat_state
@@ -336,10 +549,31 @@ impl SeatHandler for WprsClientState {
                 },
             );
 
+            if let Some(tablet_manager) = &self.tablet_manager {
+                tablet_manager.get_tablet_seat(&seat, qh, ());
+            }
+
+            // Idleness is tracked for the first seat only.
+            if let (Some(idle_notifier), None) = (&self.idle_notifier, &self.idle_notification) {
+                self.idle_notification = Some(idle_notifier.get_idle_notification(
+                    self.idle_timeout.saturating_mul(1000),
+                    &seat,
+                    qh,
+                    (),
+                ));
+            }
+
+            // As is text input.
+            if let (Some(text_input_manager), None) = (&self.text_input_manager, &self.text_input) {
+                self.text_input = Some(LocalTextInput::new(text_input_manager, &seat, qh));
+            }
+
             self.seat_objects.push(SeatObject {
                 seat: seat.clone(),
                 keyboard: None,
                 pointer: None,
+                relative_pointer: None,
+                touch: None,
                 data_device,
                 primary_selection_device,
             });
@@ -367,8 +601,23 @@ impl SeatHandler for WprsClientState {
                     ThemeSpec::default(),
                 )
                 .expect("Failed to create pointer");
+            seat_obj.relative_pointer = self
+                .relative_pointer_state
+                .get_relative_pointer(themed_pointer.pointer(), qh)
+                .context(loc!(), "zwp_relative_pointer_manager_v1 is not available")
+                .warn(loc!())
+                .ok();
             seat_obj.pointer.replace(themed_pointer);
         }
+
+        if capability == Capability::Touch && seat_obj.touch.is_none() {
+            debug!("set touch capability");
+            let touch = self
+                .seat_state
+                .get_touch(qh, &seat)
+                .expect("Failed to create touch");
+            seat_obj.touch.replace(touch);
+        }
     }
 
     fn remove_capability(
@@ -384,8 +633,12 @@ impl SeatHandler for WprsClientState {
                     seat_obj.keyboard.take().map(|k| k.release());
                 },
                 Capability::Pointer => {
+                    seat_obj.relative_pointer.take().map(|p| p.destroy());
                     seat_obj.pointer.take();
                 },
+                Capability::Touch => {
+                    seat_obj.touch.take().map(|t| t.release());
+                },
                 _ => {},
             }
         }
@@ -404,9 +657,10 @@ impl KeyboardHandler for WprsClientState {
         surface: &WlSurface,
         serial: u32,
         raw: &[u32],
-        keysyms: &[Keysym],
+        _keysyms: &[Keysym],
     ) {
         self.current_focus = Some(surface.clone());
+        self.pressed_keys = raw.iter().copied().collect();
         let Some((_, surface_id)) = self.object_bimap.get_wl_surface_id(&surface.id()) else {
             // TODO: unwrap is wrong, we can enter before surface exists.
             // Currently we're just returning in that case, but should we create
@@ -421,7 +675,6 @@ impl KeyboardHandler for WprsClientState {
                     serial,
                     surface_id,
                     keycodes: raw.into(),
-                    keysyms: keysyms.iter().map(|k| k.raw()).collect(),
                 },
             )));
     }
@@ -436,6 +689,7 @@ impl KeyboardHandler for WprsClientState {
         serial: u32,
     ) {
         self.current_focus = None;
+        self.pressed_keys.clear();
         self.serializer
             .writer()
             .send(SendType::Object(Event::KeyboardEvent(
@@ -458,6 +712,7 @@ impl KeyboardHandler for WprsClientState {
         event: KeyEvent,
     ) {
         self.last_implicit_grab_serial = Some(serial);
+        self.pressed_keys.insert(event.raw_code);
         if args::get_log_priv_data() {
             Span::current().record("event", field::debug(&event));
         }
@@ -486,6 +741,7 @@ impl KeyboardHandler for WprsClientState {
         serial: u32,
         event: KeyEvent,
     ) {
+        self.pressed_keys.remove(&event.raw_code);
         if args::get_log_priv_data() {
             Span::current().record("event", field::debug(&event));
         }
@@ -560,22 +816,41 @@ impl PointerHandler for WprsClientState {
         _pointer: &WlPointer,
         events: &[PointerEvent],
     ) {
-        for event in events.iter() {
-            if self
-                .object_bimap
-                .get_by_right(&event.surface.id())
-                .is_none()
-            {
-                // window was distroyed already, TODO handle consistently
-                return;
-            };
+        // Events for windows which were already destroyed are dropped, but the
+        // rest of the frame is still sent as a single frame.
+        let events: Vec<&PointerEvent> = events
+            .iter()
+            .filter(|event| {
+                self.object_bimap
+                    .get_by_right(&event.surface.id())
+                    .is_some()
+            })
+            .collect();
+        if events.is_empty() {
+            return;
+        }
 
+        for event in &events {
+            self.pointer_focus = match event.kind {
+                PointerEventKind::Leave { .. } => None,
+                _ => Some((event.surface.clone(), event.position.into())),
+            };
             match event.kind {
                 PointerEventKind::Enter { serial } => {
                     self.last_enter_serial = serial;
+                    if self.predicted_cursor {
+                        self.predict_cursor_image().log_and_ignore(loc!());
+                    }
                 },
-                PointerEventKind::Press { serial, .. } => {
+                PointerEventKind::Press { serial, button, .. } => {
                     self.last_mouse_down_serial = Some(serial);
+                    self.pressed_buttons.insert(button);
+                },
+                PointerEventKind::Release { button, .. } => {
+                    self.pressed_buttons.remove(&button);
+                },
+                PointerEventKind::Leave { .. } => {
+                    self.pressed_buttons.clear();
                 },
                 _ => {},
             }
@@ -585,7 +860,7 @@ impl PointerHandler for WprsClientState {
             .writer()
             .send(SendType::Object(Event::PointerFrame(
                 events
-                    .iter()
+                    .into_iter()
                     .map(|event| {
                         let (_, surface_id) = self
                             .object_bimap
@@ -599,6 +874,283 @@ impl PointerHandler for WprsClientState {
     }
 }
 
+impl RelativePointerHandler for WprsClientState {
+    #[instrument(skip(self, _conn, _qh, _relative_pointer, _pointer), level = "debug")]
+    fn relative_pointer_motion(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _relative_pointer: &ZwpRelativePointerV1,
+        _pointer: &WlPointer,
+        event: RelativeMotionEvent,
+    ) {
+        let Some((surface, position)) = &self.pointer_focus else {
+            return;
+        };
+        let Some((_, surface_id)) = self.object_bimap.get_wl_surface_id(&surface.id()) else {
+            return;
+        };
+
+        self.serializer
+            .writer()
+            .send(SendType::Object(Event::PointerFrame(vec![
+                wayland::PointerEvent {
+                    surface_id,
+                    position: *position,
+                    kind: wayland::PointerEventKind::RelativeMotion {
+                        dx: event.delta.0,
+                        dy: event.delta.1,
+                        dx_unaccel: event.delta_unaccel.0,
+                        dy_unaccel: event.delta_unaccel.1,
+                    },
+                },
+            ])));
+    }
+}
+
+/// Text mime types whose contents are pushed to the server, in order of
+/// preference. Only one of them is pushed.
+const PUSHED_MIME_TYPES: [&str; 3] = ["text/plain;charset=utf-8", "UTF8_STRING", "text/plain"];
+
+/// Larger selections are left for the server to request.
+const MAX_PUSHED_SELECTION_SIZE: u64 = 1 << 20;
+
+/// The mime type the text of a selection offered in mime_types is pushed in,
+/// if it has text.
+fn pushed_mime_type(mime_types: &[String]) -> Option<String> {
+    PUSHED_MIME_TYPES
+        .into_iter()
+        .find(|pushed| mime_types.iter().any(|mime_type| mime_type == pushed))
+        .map(str::to_string)
+}
+
+/// Reads the contents of the selection of the given generation and pushes
+/// them with push, unless they're too large or a newer selection was offered
+/// in the meantime. The lock on selection_generation is held while pushing.
+fn push_selection_contents(
+    read_pipe: impl Read,
+    generation: u64,
+    selection_generation: &Mutex<u64>,
+    push: impl FnOnce(Vec<u8>),
+) -> Result<()> {
+    let mut buf = Vec::new();
+    read_pipe
+        .take(MAX_PUSHED_SELECTION_SIZE + 1)
+        .read_to_end(&mut buf)
+        .location(loc!())?;
+    if buf.len() as u64 > MAX_PUSHED_SELECTION_SIZE {
+        debug!("not pushing selection larger than {MAX_PUSHED_SELECTION_SIZE} bytes");
+        return Ok(());
+    }
+    let current_generation = selection_generation.lock().unwrap();
+    if *current_generation != generation {
+        debug!("not pushing selection which was replaced while it was read");
+        return Ok(());
+    }
+    push(buf);
+    Ok(())
+}
+
+impl WprsClientState {
+    /// Reads the text in offer and sends it to the server ahead of any paste.
+    fn push_selection(&self, offer: &SelectionOffer) {
+        let Some(mime_type) = offer.with_mime_types(pushed_mime_type) else {
+            return;
+        };
+        let Some(read_pipe) = offer.receive(mime_type.clone()).ok() else {
+            return;
+        };
+        let generation = *self.selection_generation.lock().unwrap();
+        let selection_generation = self.selection_generation.clone();
+        let writer = self.serializer.writer().clone().into_inner();
+        // Like in RequestDataTransfer, the source application writes the data
+        // at its convenience.
+        thread::spawn(move || {
+            push_selection_contents(read_pipe, generation, &selection_generation, |buf| {
+                writer
+                    .send(SendType::Object(Event::Data(DataEvent::SelectionContents(
+                        DataSource::Selection,
+                        mime_type,
+                        DataToTransfer(buf),
+                    ))))
+                    // This should be infallible, see RequestDataTransfer.
+                    .unwrap();
+            })
+        });
+    }
+
+    /// Tells the server about the local selection, and pushes its text if
+    /// push is set.
+    pub(crate) fn send_selection(&self, offer: &SelectionOffer, push: bool) {
+        let mime_types = offer.with_mime_types(<[String]>::to_vec);
+        {
+            // Held while sending, see selection_generation.
+            let mut generation = self.selection_generation.lock().unwrap();
+            *generation += 1;
+            self.serializer.writer().send(SendType::Object(Event::Data(
+                DataEvent::DestinationEvent(DataDestinationEvent::SelectionSet(
+                    DataSource::Selection,
+                    SourceMetadata::from_mime_types(mime_types),
+                )),
+            )));
+        }
+        if push {
+            self.push_selection(offer);
+        }
+    }
+
+    fn send_pointer_constraint_active(&self, surface: &WlSurface, active: bool) {
+        let Some((_, surface_id)) = self.object_bimap.get_wl_surface_id(&surface.id()) else {
+            return;
+        };
+        self.serializer
+            .writer()
+            .send(SendType::Object(Event::Surface(SurfaceEvent {
+                surface_id,
+                payload: SurfaceEventPayload::PointerConstraintActive(active),
+            })));
+    }
+}
+
+impl PointerConstraintsHandler for WprsClientState {
+    fn confined(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _confined_pointer: &ZwpConfinedPointerV1,
+        surface: &WlSurface,
+        _pointer: &WlPointer,
+    ) {
+        self.send_pointer_constraint_active(surface, true);
+    }
+
+    fn unconfined(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _confined_pointer: &ZwpConfinedPointerV1,
+        surface: &WlSurface,
+        _pointer: &WlPointer,
+    ) {
+        self.send_pointer_constraint_active(surface, false);
+    }
+
+    fn locked(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _locked_pointer: &ZwpLockedPointerV1,
+        surface: &WlSurface,
+        _pointer: &WlPointer,
+    ) {
+        self.send_pointer_constraint_active(surface, true);
+    }
+
+    fn unlocked(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _locked_pointer: &ZwpLockedPointerV1,
+        surface: &WlSurface,
+        _pointer: &WlPointer,
+    ) {
+        self.send_pointer_constraint_active(surface, false);
+    }
+}
+
+// SCTK buffers touch events until the wl_touch.frame event and then calls
+// these handlers for each of them, so the events are collected here and sent as
+// a single frame by send_touch_frame after the wayland events are dispatched.
+impl TouchHandler for WprsClientState {
+    #[instrument(skip(self, _conn, _qh, _touch), level = "debug")]
+    fn down(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &WlTouch,
+        serial: u32,
+        _time: u32,
+        surface: WlSurface,
+        id: i32,
+        position: (f64, f64),
+    ) {
+        // Touches on windows which were already destroyed are dropped, the
+        // server ignores the rest of the events for their ids.
+        let Some((_, surface_id)) = self.object_bimap.get_wl_surface_id(&surface.id()) else {
+            return;
+        };
+        self.touch_frame.push(TouchEvent::Down {
+            id,
+            serial,
+            surface_id,
+            position: position.into(),
+        });
+    }
+
+    #[instrument(skip(self, _conn, _qh, _touch), level = "debug")]
+    fn up(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &WlTouch,
+        serial: u32,
+        _time: u32,
+        id: i32,
+    ) {
+        self.touch_frame.push(TouchEvent::Up { id, serial });
+    }
+
+    #[instrument(skip(self, _conn, _qh, _touch), level = "debug")]
+    fn motion(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &WlTouch,
+        _time: u32,
+        id: i32,
+        position: (f64, f64),
+    ) {
+        self.touch_frame.push(TouchEvent::Motion {
+            id,
+            position: position.into(),
+        });
+    }
+
+    #[instrument(skip(self, _conn, _qh, _touch), level = "debug")]
+    fn shape(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &WlTouch,
+        id: i32,
+        major: f64,
+        minor: f64,
+    ) {
+        self.touch_frame
+            .push(TouchEvent::Shape { id, major, minor });
+    }
+
+    #[instrument(skip(self, _conn, _qh, _touch), level = "debug")]
+    fn orientation(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &WlTouch,
+        id: i32,
+        orientation: f64,
+    ) {
+        self.touch_frame
+            .push(TouchEvent::Orientation { id, orientation });
+    }
+
+    #[instrument(skip(self, _conn, _qh, _touch), level = "debug")]
+    fn cancel(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _touch: &WlTouch) {
+        // Events in the same frame as the cancellation are dropped by SCTK,
+        // but ones from earlier frames may not have been sent yet.
+        self.touch_frame.push(TouchEvent::Cancel);
+    }
+}
+
 impl ShmHandler for WprsClientState {
     fn shm_state(&mut self) -> &mut Shm {
         &mut self.shm_state
@@ -629,7 +1181,7 @@ impl DataDeviceHandler for WprsClientState {
             drag_offer.x, drag_offer.y
         );
         let mime_types = drag_offer.with_mime_types(<[String]>::to_vec);
-        if mime_types.contains(&"_wprs_marker".to_string()) {
+        if offered_by_server(&mime_types) {
             return;
         }
         self.dnd_offer = Some(drag_offer.clone());
@@ -703,19 +1255,13 @@ impl DataDeviceHandler for WprsClientState {
         let Some(offer) = data_device.selection_offer() else {
             return;
         };
-        let mime_types = offer.with_mime_types(<[String]>::to_vec);
-        if mime_types.contains(&"_wprs_marker".to_string()) {
+        if offer.with_mime_types(offered_by_server) {
             return;
         }
-        self.selection_offer = Some(offer);
-        self.serializer
-            .writer()
-            .send(SendType::Object(Event::Data(DataEvent::DestinationEvent(
-                DataDestinationEvent::SelectionSet(
-                    DataSource::Selection,
-                    SourceMetadata::from_mime_types(mime_types),
-                ),
-            ))));
+        self.selection_offer = Some(offer.clone());
+        // Compositors offer the selection when one of our surfaces gains
+        // keyboard focus.
+        self.send_selection(&offer, self.clipboard_push_on_focus);
     }
 
     #[instrument(skip_all, level = "debug")]
@@ -895,7 +1441,7 @@ impl PrimarySelectionDeviceHandler for WprsClientState {
             return;
         };
         let mime_types = offer.with_mime_types(<[String]>::to_vec);
-        if mime_types.contains(&"_wprs_marker".to_string()) {
+        if offered_by_server(&mime_types) {
             return;
         }
         self.primary_selection_offer = Some(offer);
@@ -947,19 +1493,37 @@ impl PrimarySelectionSourceHandler for WprsClientState {
     }
 }
 
+impl ActivationHandler for WprsClientState {
+    type RequestData = RequestData;
+
+    fn new_token(&mut self, token: String, data: &Self::RequestData) {
+        let (Some(activation_state), Some(surface)) = (&self.activation_state, data.surface())
+        else {
+            return;
+        };
+        activation_state.activate::<Self>(surface, token);
+    }
+}
+
+smithay_client_toolkit::delegate_activation!(WprsClientState);
 smithay_client_toolkit::delegate_compositor!(WprsClientState);
 smithay_client_toolkit::delegate_data_device!(WprsClientState);
 smithay_client_toolkit::delegate_keyboard!(WprsClientState);
+smithay_client_toolkit::delegate_layer!(WprsClientState);
 smithay_client_toolkit::delegate_output!(WprsClientState);
 smithay_client_toolkit::delegate_pointer!(WprsClientState);
+smithay_client_toolkit::delegate_pointer_constraints!(WprsClientState);
 smithay_client_toolkit::delegate_registry!(WprsClientState);
 smithay_client_toolkit::delegate_seat!(WprsClientState);
+smithay_client_toolkit::delegate_session_lock!(WprsClientState);
 smithay_client_toolkit::delegate_shm!(WprsClientState);
 smithay_client_toolkit::delegate_subcompositor!(WprsClientState);
+smithay_client_toolkit::delegate_touch!(WprsClientState);
 smithay_client_toolkit::delegate_xdg_popup!(WprsClientState);
 smithay_client_toolkit::delegate_xdg_shell!(WprsClientState);
 smithay_client_toolkit::delegate_xdg_window!(WprsClientState);
 smithay_client_toolkit::delegate_primary_selection!(WprsClientState);
+smithay_client_toolkit::delegate_relative_pointer!(WprsClientState);
 
 impl ProvidesRegistryState for WprsClientState {
     fn registry(&mut self) -> &mut RegistryState {
@@ -997,3 +1561,268 @@ impl Dispatch<WlSubsurface, SubSurfaceData> for WprsClientState {
         dbg!("SUBSURFACE DISPATCH");
     }
 }
+
+impl Dispatch<WpViewporter, ()> for WprsClientState {
+    fn event(
+        _state: &mut Self,
+        _viewporter: &WpViewporter,
+        _event: wp_viewporter::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // wp_viewporter has no events.
+    }
+}
+
+impl Dispatch<WpViewport, ()> for WprsClientState {
+    fn event(
+        _state: &mut Self,
+        _viewport: &WpViewport,
+        _event: wp_viewport::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // wp_viewport has no events.
+    }
+}
+
+impl Dispatch<WpFractionalScaleManagerV1, ()> for WprsClientState {
+    fn event(
+        _state: &mut Self,
+        _fractional_scale_manager: &WpFractionalScaleManagerV1,
+        _event: wp_fractional_scale_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // wp_fractional_scale_manager_v1 has no events.
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, WlSurface> for WprsClientState {
+    #[instrument(skip(state, _fractional_scale, _conn, _qh), level = "debug")]
+    fn event(
+        state: &mut Self,
+        _fractional_scale: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        surface: &WlSurface,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let wp_fractional_scale_v1::Event::PreferredScale { scale } = event else {
+            return;
+        };
+        let Some((_, surface_id)) = state.object_bimap.get_wl_surface_id(&surface.id()) else {
+            return;
+        };
+        state
+            .serializer
+            .writer()
+            .send(SendType::Object(Event::Surface(SurfaceEvent {
+                surface_id,
+                payload: SurfaceEventPayload::PreferredFractionalScale(scale),
+            })));
+    }
+}
+
+impl Dispatch<ZwpKeyboardShortcutsInhibitManagerV1, ()> for WprsClientState {
+    fn event(
+        _state: &mut Self,
+        _keyboard_shortcuts_inhibit_manager: &ZwpKeyboardShortcutsInhibitManagerV1,
+        _event: zwp_keyboard_shortcuts_inhibit_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // zwp_keyboard_shortcuts_inhibit_manager_v1 has no events.
+    }
+}
+
+impl Dispatch<ZwpIdleInhibitManagerV1, ()> for WprsClientState {
+    fn event(
+        _state: &mut Self,
+        _idle_inhibit_manager: &ZwpIdleInhibitManagerV1,
+        _event: zwp_idle_inhibit_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // zwp_idle_inhibit_manager_v1 has no events.
+    }
+}
+
+impl Dispatch<ZwpIdleInhibitorV1, ()> for WprsClientState {
+    fn event(
+        _state: &mut Self,
+        _idle_inhibitor: &ZwpIdleInhibitorV1,
+        _event: zwp_idle_inhibitor_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // zwp_idle_inhibitor_v1 has no events.
+    }
+}
+
+impl Dispatch<ZwpKeyboardShortcutsInhibitorV1, WlSurface> for WprsClientState {
+    #[instrument(skip(state, _inhibitor, _conn, _qh), level = "debug")]
+    fn event(
+        state: &mut Self,
+        _inhibitor: &ZwpKeyboardShortcutsInhibitorV1,
+        event: zwp_keyboard_shortcuts_inhibitor_v1::Event,
+        surface: &WlSurface,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let active = match event {
+            zwp_keyboard_shortcuts_inhibitor_v1::Event::Active => true,
+            zwp_keyboard_shortcuts_inhibitor_v1::Event::Inactive => false,
+            _ => return,
+        };
+        let Some((_, surface_id)) = state.object_bimap.get_wl_surface_id(&surface.id()) else {
+            return;
+        };
+        state
+            .serializer
+            .writer()
+            .send(SendType::Object(Event::Surface(SurfaceEvent {
+                surface_id,
+                payload: SurfaceEventPayload::KeyboardShortcutsInhibitorActive(active),
+            })));
+    }
+}
+
+impl Dispatch<WpPresentation, ()> for WprsClientState {
+    fn event(
+        _state: &mut Self,
+        _presentation: &WpPresentation,
+        _event: wp_presentation::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // The clock is irrelevant, the server timestamps feedback itself.
+    }
+}
+
+impl Dispatch<WpPresentationFeedback, WlSurface> for WprsClientState {
+    #[instrument(skip(state, _feedback, _conn, _qh), level = "debug")]
+    fn event(
+        state: &mut Self,
+        _feedback: &WpPresentationFeedback,
+        event: wp_presentation_feedback::Event,
+        surface: &WlSurface,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let feedback = match event {
+            wp_presentation_feedback::Event::Presented {
+                tv_sec_hi,
+                tv_sec_lo,
+                tv_nsec,
+                refresh,
+                seq_hi,
+                seq_lo,
+                flags,
+            } => PresentationFeedback::presented(
+                tv_sec_hi,
+                tv_sec_lo,
+                tv_nsec,
+                refresh,
+                seq_hi,
+                seq_lo,
+                match flags {
+                    WEnum::Value(kind) => kind.bits(),
+                    WEnum::Unknown(bits) => bits,
+                },
+            ),
+            wp_presentation_feedback::Event::Discarded => PresentationFeedback::Discarded,
+            _ => return,
+        };
+        // The surface may have been destroyed in the meantime, in which case
+        // the server has already dropped its feedback.
+        let Some((_, surface_id)) = state.object_bimap.get_wl_surface_id(&surface.id()) else {
+            return;
+        };
+        state
+            .serializer
+            .writer()
+            .send(SendType::Object(Event::Surface(SurfaceEvent {
+                surface_id,
+                payload: SurfaceEventPayload::PresentationFeedback(feedback),
+            })));
+    }
+}
+
+impl Dispatch<ExtIdleNotifierV1, ()> for WprsClientState {
+    fn event(
+        _state: &mut Self,
+        _idle_notifier: &ExtIdleNotifierV1,
+        _event: ext_idle_notifier_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // ext_idle_notifier_v1 has no events.
+    }
+}
+
+impl Dispatch<ExtIdleNotificationV1, ()> for WprsClientState {
+    #[instrument(skip(state, _idle_notification, _conn, _qh), level = "debug")]
+    fn event(
+        state: &mut Self,
+        _idle_notification: &ExtIdleNotificationV1,
+        event: ext_idle_notification_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let idle_event = match event {
+            ext_idle_notification_v1::Event::Idled => IdleEvent::Idle,
+            ext_idle_notification_v1::Event::Resumed => IdleEvent::Active,
+            _ => return,
+        };
+        state
+            .serializer
+            .writer()
+            .send(SendType::Object(Event::Idle(idle_event)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pushed_mime_type() {
+        let mime_types = ["text/plain".to_string(), "UTF8_STRING".to_string()];
+        assert_eq!(
+            pushed_mime_type(&mime_types),
+            Some("UTF8_STRING".to_string())
+        );
+        assert_eq!(pushed_mime_type(&["image/png".to_string()]), None);
+    }
+
+    #[test]
+    fn test_push_selection_contents() {
+        let selection_generation = Mutex::new(2);
+        let push = |contents: &[u8], generation| {
+            let mut pushed = None;
+            push_selection_contents(contents, generation, &selection_generation, |buf| {
+                pushed = Some(buf)
+            })
+            .unwrap();
+            pushed
+        };
+        assert_eq!(push(b"copied", 2), Some(b"copied".to_vec()));
+        // Another selection was offered while this one was read.
+        assert_eq!(push(b"copied", 1), None);
+
+        let largest = vec![0; MAX_PUSHED_SELECTION_SIZE as usize];
+        assert_eq!(push(&largest, 2), Some(largest.clone()));
+        let too_large = vec![0; MAX_PUSHED_SELECTION_SIZE as usize + 1];
+        assert_eq!(push(&too_large, 2), None);
+    }
+}
@@ -0,0 +1,352 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! zwp_tablet_v2, which SCTK doesn't wrap. Tablets and tools are sent to the
+//! server once the local compositor has finished describing them, tool events
+//! are sent a frame at a time. Pads are bound, as the protocol requires, but
+//! not forwarded.
+
+use std::mem;
+
+use smithay_client_toolkit::reexports::client::event_created_child;
+use smithay_client_toolkit::reexports::client::Connection;
+use smithay_client_toolkit::reexports::client::Dispatch;
+use smithay_client_toolkit::reexports::client::Proxy;
+use smithay_client_toolkit::reexports::client::QueueHandle;
+use smithay_client_toolkit::reexports::client::WEnum;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_manager_v2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_manager_v2::ZwpTabletManagerV2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_pad_group_v2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_pad_group_v2::ZwpTabletPadGroupV2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_pad_ring_v2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_pad_ring_v2::ZwpTabletPadRingV2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_pad_strip_v2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_pad_strip_v2::ZwpTabletPadStripV2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_pad_v2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_pad_v2::ZwpTabletPadV2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_seat_v2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_seat_v2::ZwpTabletSeatV2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_tool_v2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_tool_v2::ZwpTabletToolV2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_v2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_v2::ZwpTabletV2;
+
+use crate::client::ObjectBimapExt;
+use crate::client::WprsClientState;
+use crate::prelude::*;
+use crate::serialization;
+use crate::serialization::tablet::Tablet;
+use crate::serialization::tablet::TabletEvent;
+use crate::serialization::tablet::TabletId;
+use crate::serialization::tablet::TabletTool;
+use crate::serialization::tablet::TabletToolEvent;
+use crate::serialization::tablet::TabletToolId;
+use crate::serialization::tablet::TabletToolType;
+use crate::serialization::tablet::UsbId;
+use crate::serialization::Event;
+use crate::serialization::SendType;
+
+/// The range of zwp_tablet_tool_v2 pressure, distance, and slider values.
+const AXIS_MAX: f64 = 65535.0;
+
+#[derive(Debug)]
+pub(crate) struct LocalTabletTool {
+    tool: TabletTool,
+    /// Events received since the last frame event.
+    frame: Vec<TabletToolEvent>,
+}
+
+fn tablet_id(tablet: &ZwpTabletV2) -> TabletId {
+    TabletId(serialization::hash(&tablet.id()))
+}
+
+fn tablet_tool_id(tool: &ZwpTabletToolV2) -> TabletToolId {
+    TabletToolId(serialization::hash(&tool.id()))
+}
+
+fn u64_from_hi_lo(hi: u32, lo: u32) -> u64 {
+    ((hi as u64) << 32) | lo as u64
+}
+
+impl WprsClientState {
+    fn send_tablet_event(&self, tablet_event: TabletEvent) {
+        self.serializer
+            .writer()
+            .send(SendType::Object(Event::Tablet(tablet_event)));
+    }
+}
+
+impl Dispatch<ZwpTabletManagerV2, ()> for WprsClientState {
+    fn event(
+        _state: &mut Self,
+        _tablet_manager: &ZwpTabletManagerV2,
+        _event: zwp_tablet_manager_v2::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // zwp_tablet_manager_v2 has no events.
+    }
+}
+
+impl Dispatch<ZwpTabletSeatV2, ()> for WprsClientState {
+    #[instrument(skip(state, _tablet_seat, _conn, _qh), level = "debug")]
+    fn event(
+        state: &mut Self,
+        _tablet_seat: &ZwpTabletSeatV2,
+        event: zwp_tablet_seat_v2::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwp_tablet_seat_v2::Event::TabletAdded { id } => {
+                state.tablets.insert(id.id(), Tablet::new(tablet_id(&id)));
+            },
+            zwp_tablet_seat_v2::Event::ToolAdded { id } => {
+                state.tablet_tools.insert(
+                    id.id(),
+                    LocalTabletTool {
+                        tool: TabletTool::new(tablet_tool_id(&id)),
+                        frame: Vec::new(),
+                    },
+                );
+            },
+            _ => {},
+        }
+    }
+
+    event_created_child!(WprsClientState, ZwpTabletSeatV2, [
+        zwp_tablet_seat_v2::EVT_TABLET_ADDED_OPCODE => (ZwpTabletV2, ()),
+        zwp_tablet_seat_v2::EVT_TOOL_ADDED_OPCODE => (ZwpTabletToolV2, ()),
+        zwp_tablet_seat_v2::EVT_PAD_ADDED_OPCODE => (ZwpTabletPadV2, ()),
+    ]);
+}
+
+impl Dispatch<ZwpTabletV2, ()> for WprsClientState {
+    #[instrument(skip(state, tablet, _conn, _qh), level = "debug")]
+    fn event(
+        state: &mut Self,
+        tablet: &ZwpTabletV2,
+        event: zwp_tablet_v2::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let zwp_tablet_v2::Event::Removed = event {
+            if state.tablets.remove(&tablet.id()).is_some() {
+                state.send_tablet_event(TabletEvent::TabletRemoved(tablet_id(tablet)));
+            }
+            tablet.destroy();
+            return;
+        }
+
+        let Some(local_tablet) = state.tablets.get_mut(&tablet.id()) else {
+            warn!("event for unknown tablet {tablet:?}");
+            return;
+        };
+        match event {
+            zwp_tablet_v2::Event::Name { name } => local_tablet.name = name,
+            zwp_tablet_v2::Event::Id { vid, pid } => {
+                local_tablet.usb_id = Some(UsbId {
+                    vendor: vid,
+                    product: pid,
+                });
+            },
+            zwp_tablet_v2::Event::Path { path } => local_tablet.path = Some(path),
+            zwp_tablet_v2::Event::Done => {
+                let local_tablet = local_tablet.clone();
+                state.send_tablet_event(TabletEvent::TabletAdded(local_tablet));
+            },
+            _ => {},
+        }
+    }
+}
+
+impl Dispatch<ZwpTabletToolV2, ()> for WprsClientState {
+    #[instrument(skip(state, tool, _conn, _qh), level = "debug")]
+    fn event(
+        state: &mut Self,
+        tool: &ZwpTabletToolV2,
+        event: zwp_tablet_tool_v2::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let zwp_tablet_tool_v2::Event::Removed = event {
+            if state.tablet_tools.remove(&tool.id()).is_some() {
+                state.send_tablet_event(TabletEvent::ToolRemoved(tablet_tool_id(tool)));
+            }
+            tool.destroy();
+            return;
+        }
+
+        let Some(local_tool) = state.tablet_tools.get_mut(&tool.id()) else {
+            warn!("event for unknown tablet tool {tool:?}");
+            return;
+        };
+        let tool_event = match event {
+            zwp_tablet_tool_v2::Event::Type { tool_type } => {
+                match TabletToolType::from_wayland(tool_type) {
+                    Some(tool_type) => local_tool.tool.tool_type = tool_type,
+                    None => warn!("unknown tablet tool type {tool_type:?}"),
+                }
+                return;
+            },
+            zwp_tablet_tool_v2::Event::HardwareSerial {
+                hardware_serial_hi,
+                hardware_serial_lo,
+            } => {
+                local_tool.tool.hardware_serial =
+                    u64_from_hi_lo(hardware_serial_hi, hardware_serial_lo);
+                return;
+            },
+            zwp_tablet_tool_v2::Event::HardwareIdWacom {
+                hardware_id_hi,
+                hardware_id_lo,
+            } => {
+                local_tool.tool.hardware_id_wacom = u64_from_hi_lo(hardware_id_hi, hardware_id_lo);
+                return;
+            },
+            zwp_tablet_tool_v2::Event::Capability { capability } => {
+                local_tool.tool.capabilities.add(capability);
+                return;
+            },
+            zwp_tablet_tool_v2::Event::Done => {
+                let tool = local_tool.tool;
+                state.send_tablet_event(TabletEvent::ToolAdded(tool));
+                return;
+            },
+            zwp_tablet_tool_v2::Event::Frame { .. } => {
+                if local_tool.frame.is_empty() {
+                    return;
+                }
+                let tablet_event = TabletEvent::ToolFrame {
+                    tool: local_tool.tool.id,
+                    events: mem::take(&mut local_tool.frame),
+                };
+                state.send_tablet_event(tablet_event);
+                return;
+            },
+            zwp_tablet_tool_v2::Event::ProximityIn {
+                serial,
+                tablet,
+                surface,
+            } => {
+                // The server ignores the events of tools which aren't in
+                // proximity of any surface.
+                let Some((_, surface_id)) = state.object_bimap.get_wl_surface_id(&surface.id())
+                else {
+                    return;
+                };
+                TabletToolEvent::ProximityIn {
+                    serial,
+                    tablet: tablet_id(&tablet),
+                    surface_id,
+                }
+            },
+            zwp_tablet_tool_v2::Event::ProximityOut => TabletToolEvent::ProximityOut,
+            zwp_tablet_tool_v2::Event::Down { serial } => TabletToolEvent::Down { serial },
+            zwp_tablet_tool_v2::Event::Up => TabletToolEvent::Up,
+            zwp_tablet_tool_v2::Event::Motion { x, y } => TabletToolEvent::Motion((x, y).into()),
+            zwp_tablet_tool_v2::Event::Pressure { pressure } => {
+                TabletToolEvent::Pressure(pressure as f64 / AXIS_MAX)
+            },
+            zwp_tablet_tool_v2::Event::Distance { distance } => {
+                TabletToolEvent::Distance(distance as f64 / AXIS_MAX)
+            },
+            zwp_tablet_tool_v2::Event::Tilt { tilt_x, tilt_y } => {
+                TabletToolEvent::Tilt((tilt_x, tilt_y).into())
+            },
+            zwp_tablet_tool_v2::Event::Rotation { degrees } => TabletToolEvent::Rotation(degrees),
+            zwp_tablet_tool_v2::Event::Slider { position } => {
+                TabletToolEvent::Slider(position as f64 / AXIS_MAX)
+            },
+            zwp_tablet_tool_v2::Event::Wheel { degrees, clicks } => {
+                TabletToolEvent::Wheel { degrees, clicks }
+            },
+            zwp_tablet_tool_v2::Event::Button {
+                serial,
+                button,
+                state: button_state,
+            } => TabletToolEvent::Button {
+                serial,
+                button,
+                pressed: button_state == WEnum::Value(zwp_tablet_tool_v2::ButtonState::Pressed),
+            },
+            _ => return,
+        };
+        local_tool.frame.push(tool_event);
+    }
+}
+
+impl Dispatch<ZwpTabletPadV2, ()> for WprsClientState {
+    fn event(
+        _state: &mut Self,
+        pad: &ZwpTabletPadV2,
+        event: zwp_tablet_pad_v2::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let zwp_tablet_pad_v2::Event::Removed = event {
+            pad.destroy();
+        }
+    }
+
+    event_created_child!(WprsClientState, ZwpTabletPadV2, [
+        zwp_tablet_pad_v2::EVT_GROUP_OPCODE => (ZwpTabletPadGroupV2, ()),
+    ]);
+}
+
+impl Dispatch<ZwpTabletPadGroupV2, ()> for WprsClientState {
+    fn event(
+        _state: &mut Self,
+        _pad_group: &ZwpTabletPadGroupV2,
+        _event: zwp_tablet_pad_group_v2::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+
+    event_created_child!(WprsClientState, ZwpTabletPadGroupV2, [
+        zwp_tablet_pad_group_v2::EVT_RING_OPCODE => (ZwpTabletPadRingV2, ()),
+        zwp_tablet_pad_group_v2::EVT_STRIP_OPCODE => (ZwpTabletPadStripV2, ()),
+    ]);
+}
+
+impl Dispatch<ZwpTabletPadRingV2, ()> for WprsClientState {
+    fn event(
+        _state: &mut Self,
+        _pad_ring: &ZwpTabletPadRingV2,
+        _event: zwp_tablet_pad_ring_v2::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwpTabletPadStripV2, ()> for WprsClientState {
+    fn event(
+        _state: &mut Self,
+        _pad_strip: &ZwpTabletPadStripV2,
+        _event: zwp_tablet_pad_strip_v2::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
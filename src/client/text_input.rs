@@ -0,0 +1,172 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! zwp_text_input_v3, which SCTK doesn't wrap. A single text input, of the
+//! first seat, mirrors the text input of the application with keyboard focus
+//! on the server, and the input method's events on it are sent to the server.
+
+use smithay_client_toolkit::reexports::client::protocol::wl_seat::WlSeat;
+use smithay_client_toolkit::reexports::client::Connection;
+use smithay_client_toolkit::reexports::client::Dispatch;
+use smithay_client_toolkit::reexports::client::QueueHandle;
+use smithay_client_toolkit::reexports::protocols::wp::text_input::zv3::client::zwp_text_input_manager_v3;
+use smithay_client_toolkit::reexports::protocols::wp::text_input::zv3::client::zwp_text_input_manager_v3::ZwpTextInputManagerV3;
+use smithay_client_toolkit::reexports::protocols::wp::text_input::zv3::client::zwp_text_input_v3;
+use smithay_client_toolkit::reexports::protocols::wp::text_input::zv3::client::zwp_text_input_v3::ZwpTextInputV3;
+
+use crate::client::WprsClientState;
+use crate::prelude::*;
+use crate::serialization::text_input::TextInputEvent;
+use crate::serialization::text_input::TextInputRequest;
+use crate::serialization::Event;
+use crate::serialization::SendType;
+
+#[derive(Debug)]
+pub(crate) struct LocalTextInput {
+    text_input: ZwpTextInputV3,
+    enabled: bool,
+}
+
+impl LocalTextInput {
+    pub(crate) fn new(
+        text_input_manager: &ZwpTextInputManagerV3,
+        seat: &WlSeat,
+        qh: &QueueHandle<WprsClientState>,
+    ) -> Self {
+        Self {
+            text_input: text_input_manager.get_text_input(seat, qh, ()),
+            enabled: false,
+        }
+    }
+
+    fn apply(&mut self, request: TextInputRequest) {
+        match request {
+            TextInputRequest::Enabled(state) => {
+                if !self.enabled {
+                    self.text_input.enable();
+                    self.enabled = true;
+                }
+                if let Some(surrounding_text) = state.surrounding_text {
+                    self.text_input.set_surrounding_text(
+                        surrounding_text.text,
+                        surrounding_text.cursor,
+                        surrounding_text.anchor,
+                    );
+                }
+                self.text_input.set_text_change_cause(
+                    zwp_text_input_v3::ChangeCause::try_from(state.text_change_cause)
+                        .unwrap_or(zwp_text_input_v3::ChangeCause::Other),
+                );
+                self.text_input.set_content_type(
+                    zwp_text_input_v3::ContentHint::from_bits_truncate(state.content_hint),
+                    zwp_text_input_v3::ContentPurpose::try_from(state.content_purpose)
+                        .unwrap_or(zwp_text_input_v3::ContentPurpose::Normal),
+                );
+                if let Some(cursor_rectangle) = state.cursor_rectangle {
+                    self.text_input.set_cursor_rectangle(
+                        cursor_rectangle.loc.x,
+                        cursor_rectangle.loc.y,
+                        cursor_rectangle.size.w,
+                        cursor_rectangle.size.h,
+                    );
+                }
+            },
+            TextInputRequest::Disabled => {
+                if !self.enabled {
+                    return;
+                }
+                self.text_input.disable();
+                self.enabled = false;
+            },
+        }
+        self.text_input.commit();
+    }
+}
+
+impl WprsClientState {
+    #[instrument(skip(self), level = "debug")]
+    pub(crate) fn handle_text_input(&mut self, request: TextInputRequest) -> Result<()> {
+        let Some(text_input) = &mut self.text_input else {
+            debug!("zwp_text_input_manager_v3 is not available, ignoring text input");
+            return Ok(());
+        };
+        text_input.apply(request);
+        Ok(())
+    }
+
+    fn send_text_input_event(&self, text_input_event: TextInputEvent) {
+        self.serializer
+            .writer()
+            .send(SendType::Object(Event::TextInput(text_input_event)));
+    }
+}
+
+impl Dispatch<ZwpTextInputManagerV3, ()> for WprsClientState {
+    fn event(
+        _state: &mut Self,
+        _text_input_manager: &ZwpTextInputManagerV3,
+        _event: zwp_text_input_manager_v3::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // zwp_text_input_manager_v3 has no events.
+    }
+}
+
+impl Dispatch<ZwpTextInputV3, ()> for WprsClientState {
+    #[instrument(skip(state, _text_input, _conn, _qh), level = "debug")]
+    fn event(
+        state: &mut Self,
+        _text_input: &ZwpTextInputV3,
+        event: zwp_text_input_v3::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let text_input_event = match event {
+            // Keyboard focus is tracked through wl_keyboard, but the local
+            // input method forgets about the text input on leave, so it has
+            // to be enabled again.
+            zwp_text_input_v3::Event::Leave { .. } => {
+                if let Some(text_input) = &mut state.text_input {
+                    text_input.enabled = false;
+                }
+                return;
+            },
+            zwp_text_input_v3::Event::PreeditString {
+                text,
+                cursor_begin,
+                cursor_end,
+            } => TextInputEvent::PreeditString {
+                text,
+                cursor_begin,
+                cursor_end,
+            },
+            zwp_text_input_v3::Event::CommitString { text } => TextInputEvent::CommitString(text),
+            zwp_text_input_v3::Event::DeleteSurroundingText {
+                before_length,
+                after_length,
+            } => TextInputEvent::DeleteSurroundingText {
+                before_length,
+                after_length,
+            },
+            // The server answers with the serial of the application's text
+            // input.
+            zwp_text_input_v3::Event::Done { serial: _ } => TextInputEvent::Done,
+            _ => return,
+        };
+        state.send_text_input_event(text_input_event);
+    }
+}
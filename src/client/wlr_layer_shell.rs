@@ -0,0 +1,156 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use smithay_client_toolkit::output::OutputState;
+use smithay_client_toolkit::reexports::client::QueueHandle;
+use smithay_client_toolkit::shell::wlr_layer::LayerShell;
+use smithay_client_toolkit::shell::wlr_layer::LayerSurface;
+
+use crate::client::RemoteSurface;
+use crate::client::Role;
+use crate::client::WprsClientState;
+use crate::prelude::*;
+use crate::serialization::wayland::SurfaceState;
+use crate::serialization::wayland::WlSurfaceId;
+use crate::serialization::wlr_layer_shell::LayerSurfaceId;
+use crate::serialization::wlr_layer_shell::LayerSurfaceState;
+use crate::serialization::ClientId;
+
+#[derive(Debug)]
+pub struct RemoteLayerSurface {
+    pub client: ClientId,
+    pub id: LayerSurfaceId,
+    pub local_layer_surface: LayerSurface,
+    pub configured: bool,
+    /// The state last applied to local_layer_surface.
+    pub state: LayerSurfaceState,
+}
+
+impl RemoteLayerSurface {
+    pub fn set_role(
+        client_id: ClientId,
+        surface_state: &SurfaceState,
+        surface_id: WlSurfaceId,
+        surfaces: &mut HashMap<WlSurfaceId, RemoteSurface>,
+        layer_shell: &LayerShell,
+        output_state: &OutputState,
+        qh: &QueueHandle<WprsClientState>,
+    ) -> Result<()> {
+        let surface = surfaces.get_mut(&surface_id).location(loc!())?;
+        if surface.role.is_some() {
+            return Ok(());
+        }
+        let layer_surface_state = surface_state
+            .role
+            .as_ref()
+            .location(loc!())?
+            .as_layer_surface()
+            .location(loc!())?;
+
+        // Fall back to letting the local compositor choose if the requested
+        // output has gone away.
+        let output = layer_surface_state.output.and_then(|id| {
+            output_state
+                .outputs()
+                .find(|output| output_state.info(output).is_some_and(|info| info.id == id))
+        });
+
+        let local_surface = surface.local_surface.take().location(loc!())?;
+        let local_layer_surface = layer_shell.create_layer_surface(
+            qh,
+            local_surface,
+            layer_surface_state.layer.into(),
+            Some(layer_surface_state.namespace.clone()),
+            output.as_ref(),
+        );
+        let mut new_layer_surface = Self {
+            client: client_id,
+            id: layer_surface_state.id,
+            local_layer_surface,
+            configured: false,
+            state: layer_surface_state.clone(),
+        };
+        new_layer_surface.apply_state();
+
+        surface.role = Some(Role::LayerSurface(new_layer_surface));
+        Ok(())
+    }
+
+    /// Sends all of state to the local compositor. The changes take effect
+    /// with the next commit of the surface.
+    fn apply_state(&self) {
+        let state = &self.state;
+        let layer_surface = &self.local_layer_surface;
+        layer_surface.set_layer(state.layer.into());
+        layer_surface.set_anchor(state.sctk_anchor());
+        layer_surface.set_exclusive_zone(state.exclusive_zone);
+        layer_surface.set_margin(
+            state.margin.top,
+            state.margin.right,
+            state.margin.bottom,
+            state.margin.left,
+        );
+        layer_surface.set_keyboard_interactivity(state.keyboard_interactivity.into());
+        layer_surface.set_size(state.size.w, state.size.h);
+    }
+
+    pub fn update(surface_state: SurfaceState, surface: &mut RemoteSurface) -> Result<()> {
+        let remote_layer_surface = surface
+            .role
+            .as_mut()
+            .location(loc!())?
+            .as_layer_surface_mut()
+            .location(loc!())?;
+
+        let layer_surface_state = surface_state
+            .role
+            .location(loc!())?
+            .into_layer_surface()
+            // The error type is the enum. :(
+            .map_err(|_| anyhow!("role wasn't layer surface"))
+            .location(loc!())?;
+
+        // The namespace and output can't be changed after creation.
+        if remote_layer_surface.state != layer_surface_state {
+            remote_layer_surface.state = layer_surface_state;
+            remote_layer_surface.apply_state();
+        }
+        Ok(())
+    }
+
+    pub fn apply(
+        client_id: ClientId,
+        surface_state: SurfaceState,
+        surface_id: WlSurfaceId,
+        surfaces: &mut HashMap<WlSurfaceId, RemoteSurface>,
+        layer_shell: &LayerShell,
+        output_state: &OutputState,
+        qh: &QueueHandle<WprsClientState>,
+    ) -> Result<()> {
+        Self::set_role(
+            client_id,
+            &surface_state,
+            surface_id,
+            surfaces,
+            layer_shell,
+            output_state,
+            qh,
+        )
+        .location(loc!())?;
+        let surface = surfaces.get_mut(&surface_id).location(loc!())?;
+        Self::update(surface_state, surface)
+    }
+}
@@ -53,6 +53,8 @@ pub struct RemoteXdgToplevel {
     pub decoration_mode: Option<DecorationMode>,
     pub max_size: Size<i32>,
     pub min_size: Size<i32>,
+    /// Whether the local compositor last configured the window as fullscreen.
+    pub fullscreen: bool,
 }
 
 impl RemoteXdgToplevel {
@@ -129,6 +131,7 @@ impl RemoteXdgToplevel {
             decoration_mode: None,
             max_size: (0, 0).into(),
             min_size: (0, 0).into(),
+            fullscreen: false,
         };
 
         let surface = surfaces.get_mut(&surface_id).location(loc!())?;
@@ -16,12 +16,16 @@ use smithay_client_toolkit::data_device_manager::data_device::DataDevice;
 use smithay_client_toolkit::primary_selection::device::PrimarySelectionDevice;
 use smithay_client_toolkit::reexports::client::protocol::wl_keyboard::WlKeyboard;
 use smithay_client_toolkit::reexports::client::protocol::wl_seat::WlSeat;
+use smithay_client_toolkit::reexports::client::protocol::wl_touch::WlTouch;
+use smithay_client_toolkit::reexports::protocols::wp::relative_pointer::zv1::client::zwp_relative_pointer_v1::ZwpRelativePointerV1;
 
 #[derive(Debug)]
 pub(crate) struct SeatObject<P> {
     pub(crate) seat: WlSeat,
     pub(crate) keyboard: Option<WlKeyboard>,
     pub(crate) pointer: Option<P>,
+    pub(crate) relative_pointer: Option<ZwpRelativePointerV1>,
+    pub(crate) touch: Option<WlTouch>,
     pub(crate) data_device: DataDevice,
     pub(crate) primary_selection_device: Option<PrimarySelectionDevice>,
 }
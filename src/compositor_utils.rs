@@ -23,6 +23,9 @@ use smithay::reexports::wayland_server::protocol::wl_buffer::WlBuffer;
 use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
 use smithay::reexports::wayland_server::Resource;
 use smithay::utils::user_data::UserDataMap;
+use smithay::utils::Physical;
+use smithay::utils::Size;
+use smithay::utils::Transform;
 use smithay::wayland::compositor::SurfaceAttributes;
 use smithay::wayland::shm;
 use smithay::wayland::shm::BufferAccessError;
@@ -30,6 +33,7 @@ use smithay::wayland::shm::BufferData;
 
 use crate::buffer_pointer::BufferPointer;
 use crate::prelude::*;
+use crate::serialization::wayland::AxisSource;
 use crate::serialization::wayland::OutputInfo;
 
 /// # Panics
@@ -100,6 +104,57 @@ pub fn send_frames(
     Ok(())
 }
 
+/// Axis value, in surface-local coordinates, which compositors typically send
+/// per wheel notch (libinput reports a 15 degree wheel click).
+const PIXELS_PER_WHEEL_NOTCH: f64 = 15.0;
+
+/// Synthesizes v120 values for wheel scrolls which arrive with only a
+/// continuous value, for example from high-resolution wheels on compositors
+/// which don't send axis_value120. Partial notches are carried over to later
+/// events so that slow scrolling still eventually produces whole notches for
+/// applications which only look at discrete steps.
+#[derive(Debug, Default)]
+pub struct ScrollAccumulator {
+    remainder_v120: f64,
+}
+
+impl ScrollAccumulator {
+    pub fn v120(&mut self, absolute: f64, discrete: i32, source: AxisSource, stop: bool) -> i32 {
+        if discrete != 0 || stop || !matches!(source, AxisSource::Wheel | AxisSource::WheelTilt) {
+            self.remainder_v120 = 0.0;
+            return discrete;
+        }
+        if absolute == 0.0 {
+            return 0;
+        }
+        // Don't let a partial notch in one direction cancel out scrolling in
+        // the other.
+        if self.remainder_v120 != 0.0 && absolute.signum() != self.remainder_v120.signum() {
+            self.remainder_v120 = 0.0;
+        }
+
+        self.remainder_v120 += absolute / PIXELS_PER_WHEEL_NOTCH * 120.0;
+        let notches = (self.remainder_v120 / 120.0).trunc();
+        self.remainder_v120 -= notches * 120.0;
+        (notches * 120.0) as i32
+    }
+}
+
+/// The scale of the output, such that the logical size smithay advertises via
+/// xdg-output matches the one the client's compositor reported, while wl_output
+/// still advertises the client's integer scale.
+fn output_scale(output: &OutputInfo) -> Scale {
+    let Some(logical_size) = output.logical_size.filter(|size| size.w > 0) else {
+        return Scale::Integer(output.scale_factor);
+    };
+    let mode_size: Size<i32, Physical> = output.mode.dimensions.into();
+    let transformed_size = Transform::from(output.transform).transform_size(mode_size);
+    Scale::Custom {
+        advertised_integer: output.scale_factor,
+        fractional: f64::from(transformed_size.w) / f64::from(logical_size.w),
+    }
+}
+
 pub fn update_output(local_output: &mut Output, output: OutputInfo) {
     let current_mode = local_output.current_mode().unwrap_or(Mode {
         size: (0, 0).into(),
@@ -116,8 +171,8 @@ pub fn update_output(local_output: &mut Output, output: OutputInfo) {
     local_output.change_current_state(
         Some(received_mode),
         Some(output.transform.into()),
-        Some(Scale::Integer(output.scale_factor)),
-        Some(output.location.into()),
+        Some(output_scale(&output)),
+        Some(output.logical_position.unwrap_or(output.location).into()),
     );
 
     if output.mode.preferred {
@@ -151,3 +206,90 @@ pub fn update_surface_outputs<'a, F>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialization::wayland;
+
+    fn output_info(
+        dimensions: (i32, i32),
+        transform: wayland::Transform,
+        scale_factor: i32,
+        logical_size: Option<(i32, i32)>,
+    ) -> OutputInfo {
+        let mut output = OutputInfo::new_for_test(1);
+        output.transform = transform;
+        output.scale_factor = scale_factor;
+        output.mode.dimensions = dimensions.into();
+        output.logical_size = logical_size.map(Into::into);
+        output
+    }
+
+    #[test]
+    fn test_output_scale_hidpi() {
+        let output = output_info(
+            (3840, 2160),
+            wayland::Transform::Normal,
+            2,
+            Some((1920, 1080)),
+        );
+        let scale = output_scale(&output);
+        assert_eq!(scale.integer_scale(), 2);
+        assert_eq!(scale.fractional_scale(), 2.0);
+        let logical_size = Size::<i32, Physical>::from((3840, 2160))
+            .to_f64()
+            .to_logical(scale.fractional_scale())
+            .to_i32_round::<i32>();
+        assert_eq!(logical_size, (1920, 1080).into());
+    }
+
+    #[test]
+    fn test_output_scale_fractional() {
+        let output = output_info(
+            (2880, 1800),
+            wayland::Transform::Normal,
+            2,
+            Some((1920, 1200)),
+        );
+        let scale = output_scale(&output);
+        assert_eq!(scale.integer_scale(), 2);
+        assert_eq!(scale.fractional_scale(), 1.5);
+    }
+
+    #[test]
+    fn test_output_scale_rotated() {
+        let output = output_info((3840, 2160), wayland::Transform::_90, 2, Some((1080, 1920)));
+        assert_eq!(output_scale(&output).fractional_scale(), 2.0);
+    }
+
+    #[test]
+    fn test_output_scale_without_xdg_output() {
+        let output = output_info((3840, 2160), wayland::Transform::Normal, 2, None);
+        assert_eq!(output_scale(&output), Scale::Integer(2));
+    }
+
+    #[test]
+    fn test_scroll_accumulator() {
+        let mut scroll = ScrollAccumulator::default();
+        // A whole notch.
+        assert_eq!(scroll.v120(15.0, 0, AxisSource::Wheel, false), 120);
+        // Partial notches add up.
+        assert_eq!(scroll.v120(5.0, 0, AxisSource::Wheel, false), 0);
+        assert_eq!(scroll.v120(5.0, 0, AxisSource::Wheel, false), 0);
+        assert_eq!(scroll.v120(5.0, 0, AxisSource::Wheel, false), 120);
+        // A partial notch doesn't cancel out scrolling the other way.
+        assert_eq!(scroll.v120(10.0, 0, AxisSource::Wheel, false), 0);
+        assert_eq!(scroll.v120(-15.0, 0, AxisSource::Wheel, false), -120);
+        // Discrete values from the compositor are passed through and drop the
+        // partial notch.
+        assert_eq!(scroll.v120(-10.0, 0, AxisSource::WheelTilt, false), 0);
+        assert_eq!(scroll.v120(-15.0, -120, AxisSource::Wheel, false), -120);
+        assert_eq!(scroll.v120(-5.0, 0, AxisSource::Wheel, false), 0);
+        // So does stopping.
+        assert_eq!(scroll.v120(-10.0, 0, AxisSource::Wheel, true), 0);
+        assert_eq!(scroll.v120(-10.0, 0, AxisSource::Wheel, false), 0);
+        // Fingers don't scroll in notches.
+        assert_eq!(scroll.v120(30.0, 0, AxisSource::Finger, false), 0);
+    }
+}
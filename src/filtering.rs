@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use itertools::izip;
+
 use crate::buffer_pointer::BufferPointer;
 use crate::prefix_sum;
 use crate::prelude::*;
@@ -38,6 +40,47 @@ pub fn unfilter(data: &mut Vec4u8s, output_buf: &mut [u8]) {
     transpose::vec4u8_soa_to_aos(data, output_buf);
 }
 
+/// Like filter, but only uses the scalar transposition and no unsafe code, as a
+/// reference for the output of filter, which uses SIMD where available.
+#[cfg(test)]
+fn filter_scalar(data: &[u8], output_buf: &mut Vec4u8s) {
+    assert!(data.len() % 4 == 0); // data is a buffer of argb or xrgb pixels.
+    output_buf.resize(data.len() / 4);
+    let (p0, p1, p2, p3) = output_buf.parts_mut();
+    for (pixel, r0, r1, r2, r3) in izip!(data.chunks_exact(4), p0, p1, p2, p3) {
+        *r0 = pixel[0];
+        *r1 = pixel[1];
+        *r2 = pixel[2];
+        *r3 = pixel[3];
+    }
+    filter_argb8888(output_buf);
+}
+
+/// Like unfilter, but only uses the scalar prefix sum and transposition.
+#[cfg(test)]
+fn unfilter_scalar(data: &mut Vec4u8s, output_buf: &mut [u8]) {
+    let (p0, p1, p2, p3) = data.parts_mut();
+    unfilter_colors(p0, p1, p2, p3);
+    for part in [p0, p1, p2, p3] {
+        prefix_sum::prefix_sum_scalar(part, 0);
+    }
+    transpose::vec4u8_soa_to_aos_scalar(data, bytemuck::cast_slice_mut(output_buf));
+}
+
+/// FNV-1a hash of unfiltered argb8888 or xrgb8888 pixels, for checking that
+/// wprsc decoded the same frame that wprsd sent. Alpha is skipped because it is
+/// undefined for xrgb8888 and wprsd may make it opaque before sending.
+#[instrument(skip_all, level = "debug")]
+pub fn frame_checksum(pixels: impl IntoIterator<Item = u8>) -> u64 {
+    pixels
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| i % 4 != 3)
+        .fold(0xcbf29ce484222325, |hash, (_, byte)| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        })
+}
+
 // https://afrantzis.com/pixel-format-guide/wayland_drm.html
 
 #[instrument(skip_all, level = "debug")]
@@ -61,9 +104,34 @@ pub fn filter_argb8888(data: &mut Vec4u8s) {
     }
 }
 
+/// Sets the alpha channel of filtered data to fully opaque. This is equivalent
+/// to, but much cheaper than, unfiltering, setting alpha, and filtering again.
+#[instrument(skip_all, level = "debug")]
+pub fn make_opaque_filtered(data: &mut Vec4u8s) {
+    let (_, _, _, alpha) = data.parts_mut();
+    alpha.fill(0);
+    if let Some(first) = alpha.first_mut() {
+        *first = u8::MAX;
+    }
+}
+
 #[instrument(skip_all, level = "debug")]
 pub fn unfilter_argb8888(data: &mut Vec4u8s) {
-    for vec4 in data.iter_mut() {
+    let (p0, p1, p2, p3) = data.parts_mut();
+    unfilter_colors(p0, p1, p2, p3);
+
+    debug_span!("prefix_sum").in_scope(|| {
+        lagoon::ThreadPool::global().scoped(|s| {
+            s.run(move || prefix_sum::prefix_sum(p0));
+            s.run(move || prefix_sum::prefix_sum(p1));
+            s.run(move || prefix_sum::prefix_sum(p2));
+            s.run(move || prefix_sum::prefix_sum(p3));
+        });
+    });
+}
+
+fn unfilter_colors(p0: &mut [u8], p1: &mut [u8], p2: &mut [u8], p3: &mut [u8]) {
+    for vec4 in izip!(p0, p1, p2, p3) {
         let g = *vec4.0;
         let b = vec4.1.wrapping_add(g);
         let r = vec4.2.wrapping_add(g);
@@ -74,14 +142,72 @@ pub fn unfilter_argb8888(data: &mut Vec4u8s) {
         *vec4.2 = r;
         *vec4.3 = a;
     }
+}
 
-    let (p0, p1, p2, p3) = data.parts_mut();
-    debug_span!("prefix_sum").in_scope(|| {
-        lagoon::ThreadPool::global().scoped(|s| {
-            s.run(move || prefix_sum::prefix_sum(p0));
-            s.run(move || prefix_sum::prefix_sum(p1));
-            s.run(move || prefix_sum::prefix_sum(p2));
-            s.run(move || prefix_sum::prefix_sum(p3));
-        });
-    });
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn test_data(n_pixels: usize) -> Vec<u8> {
+        (0..n_pixels * 4).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_frame_checksum_roundtrip() {
+        let data = test_data(1000);
+        let ptr = data.as_ptr();
+        // SAFETY: ptr was created from a vec, so it is non-null, aligned, and
+        // valid for reads of data.len() elements.
+        let buf = unsafe { BufferPointer::new(&ptr, data.len()) };
+        let mut filtered = Vec4u8s::with_total_size(data.len());
+        filter(buf, &mut filtered);
+        let mut output = vec![0; data.len()];
+        unfilter(&mut filtered, &mut output);
+        assert_eq!(frame_checksum(&buf), frame_checksum(output.iter().copied()));
+
+        // Alpha doesn't matter, color does.
+        output[3] ^= 1;
+        assert_eq!(frame_checksum(&buf), frame_checksum(output.iter().copied()));
+        output[0] ^= 1;
+        assert_ne!(frame_checksum(&buf), frame_checksum(output.iter().copied()));
+    }
+
+    fn pixels() -> impl Strategy<Value = Vec<u8>> {
+        proptest::collection::vec(any::<[u8; 4]>(), 0..100_000)
+            .prop_map(|pixels| pixels.into_iter().flatten().collect())
+    }
+
+    proptest! {
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn proptest_scalar_roundtrip(data in pixels()) {
+            let mut filtered = Vec4u8s::new();
+            filter_scalar(&data, &mut filtered);
+            let mut output = vec![0; data.len()];
+            unfilter_scalar(&mut filtered, &mut output);
+            prop_assert_eq!(output, data);
+        }
+
+        #[test]
+        #[cfg_attr(miri, ignore)]
+        fn proptest_scalar_matches_simd(data in pixels()) {
+            let ptr = data.as_ptr();
+            // SAFETY: ptr was created from a vec, so it is non-null, aligned,
+            // and valid for reads of data.len() elements.
+            let buf = unsafe { BufferPointer::new(&ptr, data.len()) };
+            let mut filtered = Vec4u8s::with_total_size(data.len());
+            filter(buf, &mut filtered);
+            let mut filtered_scalar = Vec4u8s::new();
+            filter_scalar(&data, &mut filtered_scalar);
+            prop_assert_eq!(&filtered, &filtered_scalar);
+
+            let mut output = vec![0; data.len()];
+            unfilter(&mut filtered, &mut output);
+            let mut output_scalar = vec![0; data.len()];
+            unfilter_scalar(&mut filtered_scalar, &mut output_scalar);
+            prop_assert_eq!(output, output_scalar);
+        }
+    }
 }
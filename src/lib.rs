@@ -24,6 +24,7 @@ pub mod control_server;
 pub mod error_utils;
 pub mod fallible_entry;
 pub mod filtering;
+pub mod metrics;
 pub mod prefix_sum;
 pub mod prelude;
 pub mod serialization;
@@ -36,3 +37,11 @@ pub mod xwayland_xdg_shell;
 
 #[cfg(feature = "tracy-allocator")]
 pub mod tracy_allocator;
+
+#[cfg(all(
+    feature = "require-simd",
+    not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64"))
+))]
+compile_error!(
+    "the require-simd feature is enabled, but filtering has no SIMD implementation for this target"
+);
@@ -0,0 +1,673 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Transport metrics and an optional HTTP endpoint which exposes them in the
+//! Prometheus text exposition format.
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::prelude::*;
+use crate::serialization::wayland::WlSurfaceId;
+
+/// Counters for the buffers sent for a single surface.
+#[derive(Debug, Default)]
+struct SurfaceStats {
+    frames_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_sent_uncompressed: AtomicU64,
+}
+
+impl SurfaceStats {
+    fn record_sent(&self, uncompressed_size: usize, compressed_size: usize) {
+        self.frames_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent
+            .fetch_add(compressed_size as u64, Ordering::Relaxed);
+        self.bytes_sent_uncompressed
+            .fetch_add(uncompressed_size as u64, Ordering::Relaxed);
+    }
+}
+
+/// Buffers sent per second, averaged over a sliding window.
+#[derive(Debug)]
+struct FrameRate {
+    window: Duration,
+    /// When each buffer in the window was sent, oldest first.
+    timestamps: Mutex<VecDeque<Instant>>,
+}
+
+impl FrameRate {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            timestamps: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn expire(&self, timestamps: &mut VecDeque<Instant>, now: Instant) {
+        while let Some(timestamp) = timestamps.front() {
+            if now.saturating_duration_since(*timestamp) < self.window {
+                break;
+            }
+            timestamps.pop_front();
+        }
+    }
+
+    fn record(&self, now: Instant) {
+        let mut timestamps = self.timestamps.lock().unwrap();
+        self.expire(&mut timestamps, now);
+        timestamps.push_back(now);
+    }
+
+    fn per_second(&self, now: Instant) -> f64 {
+        let mut timestamps = self.timestamps.lock().unwrap();
+        self.expire(&mut timestamps, now);
+        timestamps.len() as f64 / self.window.as_secs_f64()
+    }
+}
+
+/// Upper bounds of the buckets of the frame decode time histogram, in seconds.
+const FRAME_DECODE_BUCKETS: [f64; 10] = [
+    0.0005, 0.001, 0.002, 0.004, 0.008, 0.016, 0.032, 0.064, 0.128, 0.256,
+];
+
+/// Upper bounds of the buckets of the shard compression time histogram, in
+/// seconds.
+const SHARD_COMPRESSION_BUCKETS: [f64; 11] = [
+    0.0001, 0.0002, 0.0005, 0.001, 0.002, 0.004, 0.008, 0.016, 0.032, 0.064, 0.128,
+];
+
+#[derive(Debug)]
+struct Histogram {
+    bounds: &'static [f64],
+    /// The observations in each bucket, with one more bucket for those above
+    /// the last bound. Unlike in the exposition format, the buckets aren't
+    /// cumulative.
+    counts: Vec<AtomicU64>,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(
+            u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn write(&self, out: &mut String, name: &str, help: &str) {
+        _ = writeln!(out, "# HELP {name} {help}");
+        _ = writeln!(out, "# TYPE {name} histogram");
+        let mut count = 0;
+        for (i, bucket_count) in self.counts.iter().enumerate() {
+            count += bucket_count.load(Ordering::Relaxed);
+            match self.bounds.get(i) {
+                Some(bound) => _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {count}"),
+                None => _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}"),
+            }
+        }
+        let sum = Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed));
+        _ = writeln!(out, "{name}_sum {}", sum.as_secs_f64());
+        _ = writeln!(out, "{name}_count {count}");
+    }
+}
+
+/// The buffers sent for a single surface so far.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub struct StatsSnapshot {
+    pub surface: u64,
+    pub frames_sent: u64,
+    /// After compression.
+    pub bytes_sent: u64,
+    pub bytes_sent_uncompressed: u64,
+    /// Ratio of uncompressed to compressed bytes sent, or 0 if nothing was
+    /// sent yet.
+    pub compression_ratio: f64,
+}
+
+/// Served at /health and /health/ready.
+#[derive(Debug, Clone, PartialEq, serde_derive::Serialize)]
+pub struct Health {
+    /// Always "ok", the server is alive if it answers at all.
+    pub status: &'static str,
+    pub client_connected: bool,
+    /// Surfaces which had a buffer queued for sending and weren't destroyed
+    /// yet.
+    pub surfaces: usize,
+    /// Buffers sent per second over the last 5 seconds.
+    pub frames_per_second: f64,
+}
+
+#[derive(Debug)]
+pub struct TransportStats {
+    connected: Arc<AtomicBool>,
+    bytes_sent: AtomicU64,
+    bytes_sent_uncompressed: AtomicU64,
+    frames_sent: AtomicU64,
+    bytes_received_uncompressed: AtomicU64,
+    frames_received: AtomicU64,
+    /// Compression time of the individual shards frames are split into for
+    /// parallel compression.
+    shard_compression_time: Histogram,
+    /// Buffers the server didn't send because wprsc was too far behind, see
+    /// server::frame_budget.
+    frames_dropped: AtomicU64,
+    /// Only written to by add_surface and remove_surface, so the write thread
+    /// only takes the read lock.
+    surfaces: RwLock<HashMap<WlSurfaceId, SurfaceStats>>,
+    buffer_rate: FrameRate,
+    /// How long wprsc took to write the buffers it received into the buffers
+    /// it shows.
+    frame_decode_time: Histogram,
+}
+
+impl TransportStats {
+    pub fn new(connected: Arc<AtomicBool>) -> Self {
+        Self {
+            connected,
+            bytes_sent: AtomicU64::new(0),
+            bytes_sent_uncompressed: AtomicU64::new(0),
+            frames_sent: AtomicU64::new(0),
+            bytes_received_uncompressed: AtomicU64::new(0),
+            frames_received: AtomicU64::new(0),
+            shard_compression_time: Histogram::new(&SHARD_COMPRESSION_BUCKETS),
+            frames_dropped: AtomicU64::new(0),
+            surfaces: RwLock::new(HashMap::new()),
+            buffer_rate: FrameRate::new(Duration::from_secs(5)),
+            frame_decode_time: Histogram::new(&FRAME_DECODE_BUCKETS),
+        }
+    }
+
+    pub fn record_sent(&self, uncompressed_size: usize, compressed_size: usize) {
+        self.frames_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent
+            .fetch_add(compressed_size as u64, Ordering::Relaxed);
+        self.bytes_sent_uncompressed
+            .fetch_add(uncompressed_size as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_shard_compressed(&self, compression_time: Duration) {
+        self.shard_compression_time.observe(compression_time);
+    }
+
+    /// Starts tracking the buffers sent for surface, if it isn't tracked yet.
+    /// This has to be called before its buffers are queued for sending, from
+    /// the thread which queues them and removes the surface.
+    pub fn add_surface(&self, surface: WlSurfaceId) {
+        if self.surfaces.read().unwrap().contains_key(&surface) {
+            return;
+        }
+        self.surfaces.write().unwrap().entry(surface).or_default();
+    }
+
+    /// Buffers of surfaces which aren't tracked, e.g. because they were still
+    /// queued when the surface was removed, only count towards the totals.
+    pub fn record_surface_sent(
+        &self,
+        surface: WlSurfaceId,
+        uncompressed_size: usize,
+        compressed_size: usize,
+    ) {
+        self.buffer_rate.record(Instant::now());
+        if let Some(surface_stats) = self.surfaces.read().unwrap().get(&surface) {
+            surface_stats.record_sent(uncompressed_size, compressed_size);
+        }
+    }
+
+    pub fn remove_surface(&self, surface: WlSurfaceId) {
+        self.surfaces.write().unwrap().remove(&surface);
+    }
+
+    /// The stats of each surface, the ones with the most bytes sent first.
+    pub fn surface_stats(&self) -> Vec<StatsSnapshot> {
+        let mut snapshots: Vec<StatsSnapshot> = self
+            .surfaces
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(surface, surface_stats)| {
+                let bytes_sent = surface_stats.bytes_sent.load(Ordering::Relaxed);
+                let bytes_sent_uncompressed = surface_stats
+                    .bytes_sent_uncompressed
+                    .load(Ordering::Relaxed);
+                StatsSnapshot {
+                    surface: surface.0,
+                    frames_sent: surface_stats.frames_sent.load(Ordering::Relaxed),
+                    bytes_sent,
+                    bytes_sent_uncompressed,
+                    compression_ratio: if bytes_sent == 0 {
+                        0.0
+                    } else {
+                        bytes_sent_uncompressed as f64 / bytes_sent as f64
+                    },
+                }
+            })
+            .collect();
+        snapshots.sort_by(|a, b| b.bytes_sent.cmp(&a.bytes_sent));
+        snapshots
+    }
+
+    pub fn health(&self) -> Health {
+        Health {
+            status: "ok",
+            client_connected: self.connected(),
+            surfaces: self.surfaces.read().unwrap().len(),
+            frames_per_second: self.buffer_rate.per_second(Instant::now()),
+        }
+    }
+
+    pub fn record_frame_dropped(&self) {
+        self.frames_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_frame_decoded(&self, decode_time: Duration) {
+        self.frame_decode_time.observe(decode_time);
+    }
+
+    pub fn record_received(&self, uncompressed_size: usize) {
+        self.frames_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received_uncompressed
+            .fetch_add(uncompressed_size as u64, Ordering::Relaxed);
+    }
+
+    pub fn connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_sent_uncompressed(&self) -> u64 {
+        self.bytes_sent_uncompressed.load(Ordering::Relaxed)
+    }
+
+    pub fn frames_sent(&self) -> u64 {
+        self.frames_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_received_uncompressed(&self) -> u64 {
+        self.bytes_received_uncompressed.load(Ordering::Relaxed)
+    }
+
+    pub fn frames_received(&self) -> u64 {
+        self.frames_received.load(Ordering::Relaxed)
+    }
+
+    pub fn frames_dropped(&self) -> u64 {
+        self.frames_dropped.load(Ordering::Relaxed)
+    }
+
+    /// Overall compression ratio of all data sent so far, or 0 if nothing was
+    /// sent yet.
+    pub fn compression_ratio(&self) -> f64 {
+        let compressed = self.bytes_sent();
+        if compressed == 0 {
+            return 0.0;
+        }
+        self.bytes_sent_uncompressed() as f64 / compressed as f64
+    }
+}
+
+fn write_metric<T: std::fmt::Display>(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: T,
+) {
+    // Writing to a String never fails.
+    _ = writeln!(out, "# HELP {name} {help}");
+    _ = writeln!(out, "# TYPE {name} {kind}");
+    _ = writeln!(out, "{name} {value}");
+}
+
+/// Renders stats in the Prometheus text exposition format.
+pub fn render(stats: &TransportStats) -> String {
+    let mut out = String::new();
+    write_metric(
+        &mut out,
+        "wprs_bytes_sent_total",
+        "counter",
+        "Bytes sent after compression.",
+        stats.bytes_sent(),
+    );
+    write_metric(
+        &mut out,
+        "wprs_bytes_sent_uncompressed_total",
+        "counter",
+        "Bytes sent before compression.",
+        stats.bytes_sent_uncompressed(),
+    );
+    write_metric(
+        &mut out,
+        "wprs_frames_total",
+        "counter",
+        "Protocol frames sent.",
+        stats.frames_sent(),
+    );
+    write_metric(
+        &mut out,
+        "wprs_bytes_received_uncompressed_total",
+        "counter",
+        "Bytes received after decompression.",
+        stats.bytes_received_uncompressed(),
+    );
+    write_metric(
+        &mut out,
+        "wprs_frames_received_total",
+        "counter",
+        "Protocol frames received.",
+        stats.frames_received(),
+    );
+    write_metric(
+        &mut out,
+        "wprs_frames_dropped_total",
+        "counter",
+        "Buffers dropped because wprsc was too far behind.",
+        stats.frames_dropped(),
+    );
+    write_metric(
+        &mut out,
+        "wprs_compression_ratio",
+        "gauge",
+        "Ratio of uncompressed to compressed bytes sent.",
+        stats.compression_ratio(),
+    );
+    write_metric(
+        &mut out,
+        "wprs_connected",
+        "gauge",
+        "Whether the other end is connected.",
+        u8::from(stats.connected()),
+    );
+    stats.frame_decode_time.write(
+        &mut out,
+        "wprs_frame_decode_duration_seconds",
+        "Time taken to write a received buffer into the buffer shown locally.",
+    );
+    stats.shard_compression_time.write(
+        &mut out,
+        "wprs_shard_compression_duration_seconds",
+        "Time a single shard took to compress. Frames are split into shards for parallel compression.",
+    );
+    _ = writeln!(
+        out,
+        "# HELP wprs_surface_frames_sent_total Buffers sent for each surface."
+    );
+    _ = writeln!(out, "# TYPE wprs_surface_frames_sent_total counter");
+    for surface_stats in stats.surface_stats() {
+        _ = writeln!(
+            out,
+            "wprs_surface_frames_sent_total{{surface=\"{}\"}} {}",
+            surface_stats.surface, surface_stats.frames_sent
+        );
+    }
+    out
+}
+
+fn handle_connection(stream: TcpStream, stats: &TransportStats) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone().location(loc!())?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).location(loc!())?;
+    // Drain the headers, we don't care about any of them.
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).location(loc!())? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", "text/plain; version=0.0.4", render(stats)),
+        (Some("GET"), Some("/health")) => (
+            "200 OK",
+            "application/json",
+            serde_json::to_string(&stats.health()).location(loc!())?,
+        ),
+        // For readiness probes, which should only succeed once a client is
+        // connected.
+        (Some("GET"), Some("/health/ready")) => {
+            let health = stats.health();
+            (
+                if health.client_connected {
+                    "200 OK"
+                } else {
+                    "503 Service Unavailable"
+                },
+                "application/json",
+                serde_json::to_string(&health).location(loc!())?,
+            )
+        },
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n".to_string(),
+        ),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .location(loc!())?;
+    stream.flush().location(loc!())?;
+    Ok(())
+}
+
+/// Serves stats at http://addr/metrics, and a summary of them at
+/// http://addr/health and http://addr/health/ready, on a background thread.
+pub fn start(addr: SocketAddr, stats: Arc<TransportStats>) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .with_context(loc!(), || format!("failed to bind metrics address {addr}"))?;
+    info!("serving metrics on http://{addr}/metrics");
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = log_and_continue!(stream.location(loc!()));
+            handle_connection(stream, &stats).warn_and_ignore(loc!());
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let stats = TransportStats::new(Arc::new(AtomicBool::new(true)));
+        stats.record_sent(400, 100);
+        stats.record_sent(400, 100);
+        stats.record_frame_dropped();
+        let rendered = render(&stats);
+        assert!(rendered.contains("wprs_bytes_sent_total 200\n"));
+        assert!(rendered.contains("wprs_bytes_sent_uncompressed_total 800\n"));
+        assert!(rendered.contains("wprs_frames_total 2\n"));
+        assert!(rendered.contains("wprs_frames_dropped_total 1\n"));
+        assert!(rendered.contains("wprs_compression_ratio 4\n"));
+        assert!(rendered.contains("wprs_connected 1\n"));
+    }
+
+    #[test]
+    fn test_shard_compression_time() {
+        let stats = TransportStats::new(Arc::new(AtomicBool::new(true)));
+        stats.record_shard_compressed(Duration::from_micros(50));
+        stats.record_shard_compressed(Duration::from_millis(1));
+        stats.record_shard_compressed(Duration::from_millis(7));
+        stats.record_shard_compressed(Duration::from_secs(1));
+
+        let rendered = render(&stats);
+        for (bound, count) in [
+            ("0.0001", 1),
+            ("0.001", 2),
+            ("0.008", 3),
+            ("0.128", 3),
+            ("+Inf", 4),
+        ] {
+            assert!(rendered.contains(&format!(
+                "wprs_shard_compression_duration_seconds_bucket{{le=\"{bound}\"}} {count}\n"
+            )));
+        }
+        assert!(rendered.contains("wprs_shard_compression_duration_seconds_sum 1.00805\n"));
+        assert!(rendered.contains("wprs_shard_compression_duration_seconds_count 4\n"));
+
+        // Unlike a min and max, the histogram isn't reset by scraping.
+        let rendered = render(&stats);
+        assert!(rendered.contains("wprs_shard_compression_duration_seconds_count 4\n"));
+    }
+
+    #[test]
+    fn test_surface_stats() {
+        let stats = TransportStats::new(Arc::new(AtomicBool::new(true)));
+        assert_eq!(stats.surface_stats(), Vec::new());
+
+        stats.add_surface(WlSurfaceId(1));
+        stats.add_surface(WlSurfaceId(2));
+        stats.record_surface_sent(WlSurfaceId(1), 400, 100);
+        stats.record_surface_sent(WlSurfaceId(2), 1000, 500);
+        stats.record_surface_sent(WlSurfaceId(1), 400, 100);
+        assert_eq!(
+            stats.surface_stats(),
+            vec![
+                StatsSnapshot {
+                    surface: 2,
+                    frames_sent: 1,
+                    bytes_sent: 500,
+                    bytes_sent_uncompressed: 1000,
+                    compression_ratio: 2.0,
+                },
+                StatsSnapshot {
+                    surface: 1,
+                    frames_sent: 2,
+                    bytes_sent: 200,
+                    bytes_sent_uncompressed: 800,
+                    compression_ratio: 4.0,
+                },
+            ]
+        );
+
+        stats.remove_surface(WlSurfaceId(2));
+        assert_eq!(stats.surface_stats().len(), 1);
+
+        // A buffer which was still queued when its surface was removed doesn't
+        // bring the surface back.
+        stats.record_surface_sent(WlSurfaceId(2), 1000, 500);
+        assert_eq!(stats.surface_stats().len(), 1);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_scrape() {
+        let stats = TransportStats::new(Arc::new(AtomicBool::new(true)));
+        stats.add_surface(WlSurfaceId(0));
+        stats.add_surface(WlSurfaceId(1));
+        for i in 0..100 {
+            stats.record_sent(400, 100);
+            stats.record_surface_sent(WlSurfaceId(i % 2), 400, 100);
+            stats.record_frame_decoded(Duration::from_millis(i % 4));
+        }
+        let response = get(&stats, "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 "));
+        assert!(response.contains("wprs_frames_total 100\n"));
+        assert!(response.contains("wprs_surface_frames_sent_total{surface=\"0\"} 50\n"));
+        assert!(response.contains("wprs_surface_frames_sent_total{surface=\"1\"} 50\n"));
+        // 0 and 1ms.
+        assert!(response.contains("wprs_frame_decode_duration_seconds_bucket{le=\"0.001\"} 50\n"));
+        assert!(response.contains("wprs_frame_decode_duration_seconds_bucket{le=\"+Inf\"} 100\n"));
+        assert!(response.contains("wprs_frame_decode_duration_seconds_sum 0.15\n"));
+        assert!(response.contains("wprs_frame_decode_duration_seconds_count 100\n"));
+    }
+
+    #[test]
+    fn test_frame_rate() {
+        let frame_rate = FrameRate::new(Duration::from_secs(5));
+        let start = Instant::now();
+        for i in 0..10 {
+            frame_rate.record(start + Duration::from_secs(i));
+        }
+        // The first 5 timestamps are out of the window by now.
+        assert_eq!(frame_rate.per_second(start + Duration::from_secs(9)), 1.0);
+        assert_eq!(frame_rate.per_second(start + Duration::from_secs(20)), 0.0);
+    }
+
+    fn get(stats: &TransportStats, path: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        write!(client, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let (server, _) = listener.accept().unwrap();
+        handle_connection(server, stats).unwrap();
+        let mut response = String::new();
+        std::io::Read::read_to_string(&mut client, &mut response).unwrap();
+        response
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_health() {
+        let connected = Arc::new(AtomicBool::new(false));
+        let stats = TransportStats::new(connected.clone());
+        assert!(get(&stats, "/health/ready").starts_with("HTTP/1.1 503 "));
+
+        connected.store(true, Ordering::Relaxed);
+        stats.add_surface(WlSurfaceId(1));
+        stats.add_surface(WlSurfaceId(2));
+        stats.record_surface_sent(WlSurfaceId(1), 400, 100);
+        stats.record_surface_sent(WlSurfaceId(2), 400, 100);
+        let response = get(&stats, "/health");
+        assert!(response.starts_with("HTTP/1.1 200 "));
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let health: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(health["status"], "ok");
+        assert_eq!(health["client_connected"], true);
+        assert_eq!(health["surfaces"], 2);
+        assert!(health["frames_per_second"].as_f64().unwrap() > 0.0);
+        assert!(get(&stats, "/health/ready").starts_with("HTTP/1.1 200 "));
+    }
+}
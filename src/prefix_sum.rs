@@ -14,18 +14,71 @@
 
 /// u8 prefix sum functions, based on
 /// https://en.algorithmica.org/hpc/algorithms/prefix/.
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::uint8x16_t;
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::vaddq_u8;
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::vdupq_laneq_u8;
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::vdupq_n_u8;
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::vextq_u8;
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::vld1q_u8;
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::vst1q_u8;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::__m128i;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::__m256i;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_add_epi8;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_loadu_si256;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_slli_si256;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_storeu_si256;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm_add_epi8;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm_loadu_si128;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm_prefetch;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm_set1_epi8;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm_setzero_si128;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm_storeu_si128;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_MM_HINT_T0;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::__m128i;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::__m256i;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_add_epi8;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_loadu_si256;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_slli_si256;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_storeu_si256;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm_add_epi8;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm_loadu_si128;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm_prefetch;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm_set1_epi8;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm_setzero_si128;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm_storeu_si128;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_MM_HINT_T0;
 
 // SAFETY:
@@ -106,6 +159,55 @@ unsafe fn prefix_sum_avx2<const BS: usize>(arr: &mut [u8]) {
     }
 }
 
+// SAFETY:
+// * neon must be available.
+// * `block` must be valid for reads and writes of 16 bytes.
+#[allow(unsafe_op_in_unsafe_fn)]
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+#[inline]
+unsafe fn prefix_16(block: *mut u8, prev_sum: uint8x16_t) -> uint8x16_t {
+    let zero = vdupq_n_u8(0);
+    let mut x: uint8x16_t = vld1q_u8(block);
+    // vextq_u8::<N>(zero, x) shifts x up by 16 - N lanes, shifting in zeros.
+    x = vaddq_u8(x, vextq_u8::<15>(zero, x));
+    x = vaddq_u8(x, vextq_u8::<14>(zero, x));
+    x = vaddq_u8(x, vextq_u8::<12>(zero, x));
+    x = vaddq_u8(x, vextq_u8::<8>(zero, x));
+    x = vaddq_u8(x, prev_sum);
+    vst1q_u8(block, x);
+    vdupq_laneq_u8::<15>(x)
+}
+
+/// Computes the prefix sum of `arr` in-place using NEON instructions.
+///
+/// # Safety
+/// * neon must be available.
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+#[inline]
+pub unsafe fn prefix_sum_neon(arr: &mut [u8]) {
+    let len = arr.len();
+    let lim = (len / 16) * 16;
+
+    // SAFETY:
+    // * neon is available by precondition.
+    // * i + 16 <= lim <= len, so each block is valid for reads and writes of
+    // 16 bytes.
+    unsafe {
+        let ptr = arr.as_mut_ptr();
+        let mut s = vdupq_n_u8(0);
+        for i in (0..lim).step_by(16) {
+            s = prefix_16(ptr.add(i), s);
+        }
+    }
+
+    if lim != len {
+        let prior_sum = if lim > 0 { arr[lim - 1] } else { 0 };
+        prefix_sum_scalar(&mut arr[lim..len], prior_sum);
+    }
+}
+
 #[inline(always)]
 pub fn prefix_sum_scalar(a: &mut [u8], prior_sum: u8) {
     let len = a.len();
@@ -150,7 +252,7 @@ pub unsafe fn prefix_sum_bs<const BS: usize>(arr: &mut [u8]) {
 }
 
 /// Computes the prefix sum of `arr` in-place. Will use SIMD intrinsics if AVX2
-/// is available. *Significantly* (~4.5x) slower without AVX2.
+/// or NEON is available. *Significantly* (~4.5x) slower without AVX2.
 #[inline(always)]
 pub fn prefix_sum(arr: &mut [u8]) {
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
@@ -162,6 +264,14 @@ pub fn prefix_sum(arr: &mut [u8]) {
         }
     }
 
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            // SAFETY: checked for neon support.
+            return unsafe { prefix_sum_neon(arr) };
+        }
+    }
+
     prefix_sum_scalar(arr, 0)
 }
 
@@ -231,6 +341,19 @@ mod tests {
         assert_eq!(arr, expected_arr);
     }
 
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn test_prefix_sum_neon() {
+        let i: u32 = 1001;
+        let mut arr = (0..i).map(|i| (i % 256) as u8).collect::<Vec<_>>();
+        let mut expected_arr = arr.clone();
+
+        unsafe { prefix_sum_neon(&mut arr) };
+        prefix_sum_scalar(&mut expected_arr, 0);
+
+        assert_eq!(arr, expected_arr);
+    }
+
     proptest! {
         #[test]
         #[cfg_attr(miri, ignore)]
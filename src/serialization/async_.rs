@@ -0,0 +1,445 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An async counterpart of Serializer, for embedding wprs connections in tokio
+//! programs.
+//!
+//! The wire format is the same as Serializer's, so either end of a connection
+//! can be sync or async. Unlike Serializer, an AsyncSerializer is a single
+//! connection: it doesn't reconnect and it doesn't accept further clients.
+//! Compression and decompression still run on the sharding thread pools, from
+//! tokio's blocking threads.
+
+use std::marker::PhantomData;
+use std::num::NonZeroUsize;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use futures::Sink;
+use futures::Stream;
+use rkyv::bytecheck;
+use rkyv::de::deserializers::SharedDeserializeMap;
+use rkyv::validation::validators::DefaultValidator;
+use rkyv::Deserialize;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use tokio::task;
+
+use crate::metrics::TransportStats;
+use crate::prelude::*;
+#[cfg(feature = "tokio-server")]
+use crate::serialization::auth;
+use crate::serialization::auth::AuthSecret;
+#[cfg(feature = "tokio-server")]
+use crate::serialization::enlarge_socket_buffer;
+#[cfg(feature = "tokio-server")]
+use crate::serialization::router;
+#[cfg(feature = "tokio-server")]
+use crate::serialization::router::BackendRequest;
+#[cfg(feature = "tokio-server")]
+use crate::serialization::router::DEFAULT_BACKEND;
+use crate::serialization::write_compression_algorithm;
+use crate::serialization::write_frame;
+use crate::serialization::FrameHeader;
+use crate::serialization::RecvType;
+use crate::serialization::SendType;
+use crate::serialization::Serializable;
+use crate::serialization::SerializerOptions;
+#[cfg(feature = "tokio-server")]
+use crate::serialization::Version;
+use crate::sharding_compression::CompressedShard;
+use crate::sharding_compression::CompressionAlgorithm;
+use crate::sharding_compression::ShardingCompressor;
+use crate::sharding_compression::ShardingDecompressor;
+
+type BoxedRead = Box<dyn AsyncRead + Send + Unpin>;
+type BoxedWrite = Box<dyn AsyncWrite + Send + Unpin>;
+
+pub struct AsyncSerializer<ST, RT>
+where
+    ST: Serializable,
+    ST::Archived:
+        Deserialize<ST, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
+    RT: Serializable,
+    RT::Archived:
+        Deserialize<RT, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
+{
+    read_half: Option<BoxedRead>,
+    write_half: Option<BoxedWrite>,
+    sharding_decompressor: Option<ShardingDecompressor>,
+    sharding_compressor: Option<(Arc<ShardingCompressor>, NonZeroUsize)>,
+    stats: Arc<TransportStats>,
+    options: SerializerOptions,
+    _types: PhantomData<fn(ST) -> RT>,
+}
+
+impl<ST, RT> AsyncSerializer<ST, RT>
+where
+    ST: Serializable,
+    ST::Archived:
+        Deserialize<ST, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
+    RT: Serializable,
+    RT::Archived:
+        Deserialize<RT, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
+{
+    /// The other end of stream must already be past the version exchange,
+    /// authentication and backend selection, if it does them. Use connect or
+    /// accept for connections to or from wprs sockets.
+    pub async fn new<S>(stream: S, options: SerializerOptions) -> Result<Self>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (mut read_half, mut write_half) = tokio::io::split(stream);
+
+        // Same as read_loop and write_loop.
+        let n_compressors = NonZeroUsize::new(16).unwrap();
+        let sharding_compressor =
+            ShardingCompressor::new(n_compressors, options.compression()).location(loc!())?;
+
+        let mut handshake_buf = Vec::new();
+        write_compression_algorithm(&mut handshake_buf, sharding_compressor.algorithm())
+            .location(loc!())?;
+        write_half
+            .write_all(&handshake_buf)
+            .await
+            .location(loc!())?;
+        write_half.flush().await.location(loc!())?;
+        read_compression_algorithm(&mut read_half)
+            .await
+            .location(loc!())?;
+
+        Ok(Self {
+            read_half: Some(Box::new(read_half)),
+            write_half: Some(Box::new(write_half)),
+            sharding_decompressor: Some(
+                ShardingDecompressor::new(NonZeroUsize::new(8).unwrap()).location(loc!())?,
+            ),
+            sharding_compressor: Some((Arc::new(sharding_compressor), n_compressors)),
+            stats: Arc::new(TransportStats::new(Arc::new(AtomicBool::new(true)))),
+            options,
+            _types: PhantomData,
+        })
+    }
+
+    /// Connects to the named backend of the server listening on sock_path,
+    /// see Serializer::new_client_for_backend.
+    #[cfg(feature = "tokio-client")]
+    pub async fn connect<P: AsRef<std::path::Path>>(
+        sock_path: P,
+        backend: &str,
+        options: SerializerOptions,
+        auth_secret: Option<AuthSecret>,
+    ) -> Result<Self> {
+        let sock_path = sock_path.as_ref().to_path_buf();
+        let backend = backend.to_string();
+        // The version exchange, authentication and backend selection are
+        // short, run them with the sync implementation.
+        let stream = task::spawn_blocking(move || {
+            super::connect(&sock_path, &backend, &options, auth_secret.as_ref())
+        })
+        .await
+        .location(loc!())?
+        .location(loc!())?;
+        stream.set_nonblocking(true).location(loc!())?;
+        let stream = tokio::net::UnixStream::from_std(stream).location(loc!())?;
+        Self::new(stream, options).await.location(loc!())
+    }
+
+    /// Accepts a client on listener, which only offers the default backend.
+    /// Clients have to prove that they know auth_secret, if it's set.
+    #[cfg(feature = "tokio-server")]
+    pub async fn accept(
+        listener: &tokio::net::UnixListener,
+        options: SerializerOptions,
+        auth_secret: Option<AuthSecret>,
+    ) -> Result<Self> {
+        let (stream, _) = listener.accept().await.location(loc!())?;
+        let stream = stream.into_std().location(loc!())?;
+        stream.set_nonblocking(false).location(loc!())?;
+        enlarge_socket_buffer(&stream, &options);
+        let stream = task::spawn_blocking(move || {
+            let mut stream = stream;
+            auth::with_timeout(
+                &mut stream,
+                std::time::Duration::from_millis(options.auth_timeout_ms),
+                |stream| {
+                    Version::exchange(stream).location(loc!())?;
+                    auth::challenge(stream, auth_secret.as_ref()).location(loc!())?;
+                    router::select_backend(stream, |request| {
+                        matches!(request, BackendRequest::Connect(name) if name == DEFAULT_BACKEND)
+                            .then_some(())
+                    })
+                    .location(loc!())
+                },
+            )
+            .location(loc!())?;
+            Ok::<_, anyhow::Error>(stream)
+        })
+        .await
+        .location(loc!())?
+        .location(loc!())?;
+        stream.set_nonblocking(true).location(loc!())?;
+        let stream = tokio::net::UnixStream::from_std(stream).location(loc!())?;
+        Self::new(stream, options).await.location(loc!())
+    }
+
+    /// The messages from the other end, until it closes the connection. Like
+    /// Serializer::reader, this can only be taken once.
+    pub fn reader(&mut self) -> Option<impl Stream<Item = Result<RecvType<RT>>>> {
+        let state = ReadState {
+            read_half: self.read_half.take()?,
+            sharding_decompressor: self.sharding_decompressor.take()?,
+            stats: self.stats.clone(),
+            options: self.options,
+        };
+        Some(futures::stream::try_unfold(state, read_next))
+    }
+
+    /// Sends messages to the other end. Each message is flushed once it's
+    /// written. Like reader, this can only be taken once.
+    pub fn writer(&mut self) -> Option<impl Sink<SendType<ST>, Error = anyhow::Error>> {
+        let (sharding_compressor, n_compressors) = self.sharding_compressor.take()?;
+        let state = WriteState {
+            write_half: self.write_half.take()?,
+            sharding_compressor,
+            n_compressors,
+            stats: self.stats.clone(),
+        };
+        Some(futures::sink::unfold(state, write_next))
+    }
+
+    pub fn stats(&self) -> Arc<TransportStats> {
+        self.stats.clone()
+    }
+}
+
+struct ReadState {
+    read_half: BoxedRead,
+    sharding_decompressor: ShardingDecompressor,
+    stats: Arc<TransportStats>,
+    options: SerializerOptions,
+}
+
+/// The async counterpart of an iteration of read_loop, returns None once the
+/// other end closes the connection.
+async fn read_next<RT>(mut state: ReadState) -> Result<Option<(RecvType<RT>, ReadState)>>
+where
+    RT: Serializable,
+    RT::Archived:
+        Deserialize<RT, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
+{
+    loop {
+        let mut header_buf = [0; FrameHeader::SIZE];
+        if !read_exact_or_eof(&mut state.read_half, &mut header_buf)
+            .await
+            .location(loc!())?
+        {
+            debug!("other end closed the connection");
+            return Ok(None);
+        }
+        let header = FrameHeader::parse(&header_buf, &state.options).location(loc!())?;
+        state.stats.record_received(header.uncompressed_size);
+
+        let mut compressed_shards = Vec::with_capacity(header.actual_n_shards());
+        for _ in 0..header.actual_n_shards() {
+            compressed_shards.push(read_shard(&mut state.read_half).await.location(loc!())?);
+        }
+
+        // Decompression blocks on the decompressor threads.
+        let skip_unknown_objects = state.options.skip_unknown_objects;
+        let mut sharding_decompressor = state.sharding_decompressor;
+        let (sharding_decompressor, obj) = task::spawn_blocking(move || {
+            let obj = header.decompress(
+                &mut sharding_decompressor,
+                fallible_iterator::convert(compressed_shards.into_iter().map(Ok)),
+                skip_unknown_objects,
+            );
+            (sharding_decompressor, obj)
+        })
+        .await
+        .location(loc!())?;
+        state.sharding_decompressor = sharding_decompressor;
+
+        if let Some(obj) = obj.location(loc!())? {
+            debug!("read obj: {obj:?}");
+            return Ok(Some((obj, state)));
+        }
+    }
+}
+
+struct WriteState {
+    write_half: BoxedWrite,
+    sharding_compressor: Arc<ShardingCompressor>,
+    n_compressors: NonZeroUsize,
+    stats: Arc<TransportStats>,
+}
+
+/// The async counterpart of an iteration of write_loop, without its
+/// coalescing of queued messages.
+async fn write_next<ST>(mut state: WriteState, obj: SendType<ST>) -> Result<WriteState>
+where
+    ST: Serializable,
+    ST::Archived:
+        Deserialize<ST, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
+{
+    debug!("sending obj: {:?}", obj);
+    // Compression blocks on the compressor threads.
+    let sharding_compressor = state.sharding_compressor.clone();
+    let n_compressors = state.n_compressors;
+    let stats = state.stats.clone();
+    // Nothing removes surfaces here, so buffers can't outlive their surface's
+    // stats.
+    if let SendType::RawBuffer { surface, .. } = &obj {
+        stats.add_surface(*surface);
+    }
+    let (frame, uncompressed_size, compressed_size) = task::spawn_blocking(move || {
+        let mut frame = Vec::new();
+        let (_, uncompressed_size, compressed_size) = write_frame(
+            &mut frame,
+            &sharding_compressor,
+            n_compressors,
+            &obj,
+            &stats,
+        )
+        .location(loc!())?;
+        if let SendType::RawBuffer { surface, .. } = &obj {
+            stats.record_surface_sent(*surface, uncompressed_size, compressed_size);
+        }
+        Ok::<_, anyhow::Error>((frame, uncompressed_size, compressed_size))
+    })
+    .await
+    .location(loc!())?
+    .location(loc!())?;
+
+    state.write_half.write_all(&frame).await.location(loc!())?;
+    state.write_half.flush().await.location(loc!())?;
+    state.stats.record_sent(uncompressed_size, compressed_size);
+    Ok(state)
+}
+
+/// Like serialization::read_compression_algorithm.
+async fn read_compression_algorithm<R: AsyncRead + Unpin>(
+    stream: &mut R,
+) -> Result<CompressionAlgorithm> {
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf).await.location(loc!())?;
+    CompressionAlgorithm::check_supported(u32::from_be_bytes(buf))
+        .context(loc!(), "the other end's compression isn't supported")
+}
+
+/// Like CompressedShard::framed_read.
+async fn read_shard<R: AsyncRead + Unpin>(stream: &mut R) -> Result<CompressedShard> {
+    let idx = stream.read_u32_le().await.location(loc!())?;
+    let compression = stream.read_u32_le().await.location(loc!())?;
+    let len = stream.read_u32_le().await.location(loc!())?;
+    let mut data = vec![0; len as usize];
+    stream.read_exact(&mut data).await.location(loc!())?;
+    Ok(CompressedShard {
+        idx,
+        compression,
+        data,
+    })
+}
+
+/// Like serialization::read_exact_or_eof.
+async fn read_exact_or_eof<R: AsyncRead + Unpin>(stream: &mut R, buf: &mut [u8]) -> Result<bool> {
+    let mut n_read = 0;
+    while n_read < buf.len() {
+        match stream.read(&mut buf[n_read..]).await.location(loc!())? {
+            0 if n_read == 0 => return Ok(false),
+            0 => bail!(
+                "connection closed in the middle of a frame header, after {n_read} of {} bytes",
+                buf.len()
+            ),
+            n => n_read += n,
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+    use std::pin::pin;
+
+    use futures::SinkExt;
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::serialization::wayland::WlSurfaceId;
+    use crate::serialization::Serializer;
+
+    #[tokio::test]
+    async fn test_async_client_sync_server() {
+        let (server_stream, client_stream) = UnixStream::pair().unwrap();
+        let (connections_tx, connections_rx) = crossbeam_channel::unbounded();
+        connections_tx.send(server_stream).unwrap();
+        let mut server: Serializer<String, String> =
+            Serializer::from_connections(connections_rx, SerializerOptions::default());
+
+        client_stream.set_nonblocking(true).unwrap();
+        let client_stream = tokio::net::UnixStream::from_std(client_stream).unwrap();
+        let mut client: AsyncSerializer<String, String> =
+            AsyncSerializer::new(client_stream, SerializerOptions::default())
+                .await
+                .unwrap();
+
+        // Large enough to be split into several shards.
+        let raw_buffer: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let mut writer = pin!(client.writer().unwrap());
+        writer
+            .send(SendType::Object("from the client".to_string()))
+            .await
+            .unwrap();
+        writer
+            .send(SendType::RawBuffer {
+                surface: WlSurfaceId(1),
+                data: Arc::new(raw_buffer.clone()),
+                compress: true,
+            })
+            .await
+            .unwrap();
+
+        let server_reader = server.reader().unwrap();
+        let received = task::spawn_blocking(move || {
+            let mut received = Vec::new();
+            while received.len() < 2 {
+                received.push(server_reader.recv().unwrap());
+            }
+            received
+        })
+        .await
+        .unwrap();
+        assert!(
+            matches!(&received[0], RecvType::Object(obj) if obj == "from the client"),
+            "{received:?}"
+        );
+        assert!(
+            matches!(&received[1], RecvType::RawBuffer(data) if *data == raw_buffer),
+            "{received:?}"
+        );
+
+        server
+            .writer()
+            .send(SendType::Object("from the server".to_string()));
+        let mut reader = pin!(client.reader().unwrap());
+        let obj = reader.next().await.unwrap().unwrap();
+        assert!(
+            matches!(&obj, RecvType::Object(obj) if obj == "from the server"),
+            "{obj:?}"
+        );
+    }
+}
@@ -0,0 +1,277 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Authentication of connections with a shared secret.
+//!
+//! Without a secret, access to wprsd is only protected by the permissions of
+//! its socket. With one, each connection starts with a challenge: wprsd sends
+//! a random nonce and wprsc has to answer with HMAC-SHA256(nonce, secret)
+//! before anything else is exchanged. wprsd tells wprsc whether a secret is
+//! required, so that a missing secret is reported as such.
+//!
+//! The secret is read from the configured secret file if there is one and from
+//! `WPRS_AUTH_SECRET` otherwise.
+//!
+//! The handshake follows the version exchange, so that a peer speaking a
+//! different protocol is reported as such rather than as failing
+//! authentication.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hmac::Hmac;
+use hmac::Mac;
+use sha2::Sha256;
+
+use crate::prelude::*;
+
+pub const SECRET_ENV_VAR: &str = "WPRS_AUTH_SECRET";
+
+const NONCE_SIZE: usize = 32;
+const MAC_SIZE: usize = 32;
+
+const AUTH_NOT_REQUIRED: u8 = 0;
+const AUTH_REQUIRED: u8 = 1;
+const AUTH_REJECTED: u8 = 0;
+const AUTH_ACCEPTED: u8 = 1;
+
+#[derive(Clone, Eq, PartialEq)]
+pub struct AuthSecret(Arc<[u8]>);
+
+impl AuthSecret {
+    pub fn new(secret: &[u8]) -> Self {
+        Self(secret.into())
+    }
+
+    /// Loads the secret from secret_file or, if that isn't set, from
+    /// SECRET_ENV_VAR. Returns None if neither is set, which disables
+    /// authentication. Surrounding whitespace is ignored, so that a secret
+    /// file may end with a newline.
+    pub fn load(secret_file: Option<&Path>) -> Result<Option<Self>> {
+        Self::load_from(secret_file, env::var(SECRET_ENV_VAR).ok())
+    }
+
+    fn load_from(secret_file: Option<&Path>, env_secret: Option<String>) -> Result<Option<Self>> {
+        let secret = match (secret_file, env_secret) {
+            (Some(secret_file), env_secret) => {
+                if env_secret.is_some() {
+                    warn!("{SECRET_ENV_VAR} is set, but using the secret in {secret_file:?}");
+                }
+                fs::read_to_string(secret_file)
+                    .with_context(loc!(), || format!("reading {secret_file:?}"))?
+            },
+            (None, Some(secret)) => secret,
+            (None, None) => return Ok(None),
+        };
+        let secret = secret.trim();
+        if secret.is_empty() {
+            bail!("the authentication secret is empty");
+        }
+        Ok(Some(Self::new(secret.as_bytes())))
+    }
+
+    fn mac(&self, nonce: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC accepts keys of any size");
+        mac.update(nonce);
+        mac
+    }
+}
+
+// Keep the secret out of logs.
+impl fmt::Debug for AuthSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AuthSecret(..)")
+    }
+}
+
+fn read_u8<R: Read>(stream: &mut R) -> Result<u8> {
+    let mut buf = [0; 1];
+    stream.read_exact(&mut buf).location(loc!())?;
+    Ok(buf[0])
+}
+
+fn random_nonce() -> Result<[u8; NONCE_SIZE]> {
+    let mut nonce = [0; NONCE_SIZE];
+    File::open("/dev/urandom")
+        .location(loc!())?
+        .read_exact(&mut nonce)
+        .location(loc!())?;
+    Ok(nonce)
+}
+
+/// The server's side of the handshake. An error means that the client must be
+/// disconnected.
+pub(crate) fn challenge<S: Read + Write>(
+    stream: &mut S,
+    secret: Option<&AuthSecret>,
+) -> Result<()> {
+    let Some(secret) = secret else {
+        stream.write_all(&[AUTH_NOT_REQUIRED]).location(loc!())?;
+        return stream.flush().location(loc!());
+    };
+
+    let nonce = random_nonce().location(loc!())?;
+    stream.write_all(&[AUTH_REQUIRED]).location(loc!())?;
+    stream.write_all(&nonce).location(loc!())?;
+    stream.flush().location(loc!())?;
+
+    let mut response = [0; MAC_SIZE];
+    stream.read_exact(&mut response).location(loc!())?;
+    // verify_slice compares in constant time.
+    let accepted = secret.mac(&nonce).verify_slice(&response).is_ok();
+
+    stream
+        .write_all(&[if accepted {
+            AUTH_ACCEPTED
+        } else {
+            AUTH_REJECTED
+        }])
+        .location(loc!())?;
+    stream.flush().location(loc!())?;
+    if !accepted {
+        bail!("client failed authentication");
+    }
+    Ok(())
+}
+
+/// The client's side of the handshake.
+pub(crate) fn respond<S: Read + Write>(stream: &mut S, secret: Option<&AuthSecret>) -> Result<()> {
+    match read_u8(stream).location(loc!())? {
+        AUTH_NOT_REQUIRED => {
+            if secret.is_some() {
+                warn!("the server doesn't require authentication, not using the secret");
+            }
+            return Ok(());
+        },
+        AUTH_REQUIRED => {},
+        other => bail!("invalid authentication request {other}"),
+    }
+
+    let mut nonce = [0; NONCE_SIZE];
+    stream.read_exact(&mut nonce).location(loc!())?;
+    let Some(secret) = secret else {
+        bail!("the server requires authentication, but no secret is configured (set {SECRET_ENV_VAR} or the secret file)");
+    };
+    stream
+        .write_all(&secret.mac(&nonce).finalize().into_bytes())
+        .location(loc!())?;
+    stream.flush().location(loc!())?;
+
+    match read_u8(stream).location(loc!())? {
+        AUTH_ACCEPTED => Ok(()),
+        _ => bail!("the server rejected the authentication secret"),
+    }
+}
+
+/// Runs handshake on stream, which gives up on the other end after timeout.
+pub(crate) fn with_timeout<F, T>(
+    stream: &mut UnixStream,
+    timeout: Duration,
+    handshake: F,
+) -> Result<T>
+where
+    F: FnOnce(&mut UnixStream) -> Result<T>,
+{
+    stream.set_read_timeout(Some(timeout)).location(loc!())?;
+    let result = handshake(stream).location(loc!())?;
+    stream.set_read_timeout(None).location(loc!())?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn handshake(
+        server_secret: Option<&str>,
+        client_secret: Option<&str>,
+    ) -> (Result<()>, Result<()>) {
+        let (mut server_stream, mut client_stream) = UnixStream::pair().unwrap();
+        let server_secret = server_secret.map(|secret| AuthSecret::new(secret.as_bytes()));
+        let client_secret = client_secret.map(|secret| AuthSecret::new(secret.as_bytes()));
+        let server = thread::spawn(move || challenge(&mut server_stream, server_secret.as_ref()));
+        let client_result = respond(&mut client_stream, client_secret.as_ref());
+        // A client which gives up disconnects, which the server has to see.
+        drop(client_stream);
+        (server.join().unwrap(), client_result)
+    }
+
+    #[test]
+    fn test_matching_secret() {
+        let (server_result, client_result) = handshake(Some("secret"), Some("secret"));
+        server_result.unwrap();
+        client_result.unwrap();
+    }
+
+    #[test]
+    fn test_wrong_secret() {
+        let (server_result, client_result) = handshake(Some("secret"), Some("wrong"));
+        assert!(server_result.is_err());
+        assert!(format!("{:?}", client_result.unwrap_err()).contains("rejected"));
+    }
+
+    #[test]
+    fn test_missing_secret() {
+        let (server_result, client_result) = handshake(Some("secret"), None);
+        assert!(server_result.is_err());
+        assert!(format!("{:?}", client_result.unwrap_err()).contains("no secret"));
+    }
+
+    #[test]
+    fn test_auth_not_required() {
+        let (server_result, client_result) = handshake(None, Some("secret"));
+        server_result.unwrap();
+        client_result.unwrap();
+    }
+
+    #[test]
+    fn test_secret_file_takes_precedence() {
+        let secret_file =
+            std::env::temp_dir().join(format!("wprs-test-auth-secret-{}", std::process::id()));
+        fs::write(&secret_file, "from file\n").unwrap();
+        let secret = AuthSecret::load_from(Some(&secret_file), Some("from env".to_string()));
+        fs::remove_file(&secret_file).unwrap();
+        assert_eq!(secret.unwrap(), Some(AuthSecret::new(b"from file")));
+
+        assert_eq!(
+            AuthSecret::load_from(None, Some("from env".to_string())).unwrap(),
+            Some(AuthSecret::new(b"from env"))
+        );
+        assert_eq!(AuthSecret::load_from(None, None).unwrap(), None);
+        assert!(AuthSecret::load_from(None, Some(" ".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (mut server_stream, _client_stream) = UnixStream::pair().unwrap();
+        let secret = AuthSecret::new(b"secret");
+        assert!(
+            with_timeout(&mut server_stream, Duration::from_millis(10), |stream| {
+                challenge(stream, Some(&secret))
+            })
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,257 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Simulated network link for testing.
+//!
+//! Running wprsc and wprsd on the same machine hides most of the behaviour
+//! that shows up over a real (ssh-forwarded) link. When enabled, the client's
+//! connection to the server is proxied through a pair of threads per direction
+//! which delay and rate-limit the data, so that a local session behaves as if
+//! it were remote.
+//!
+//! Enabled with the following environment variables on the client side:
+//! * `WPRS_SIM_LATENCY`: one-way latency, e.g. `100ms`, `1s`, `500us`.
+//! * `WPRS_SIM_JITTER`: maximum additional random one-way latency.
+//! * `WPRS_SIM_BANDWIDTH`: per-direction bandwidth cap, e.g. `5Mbit`, `1MB`.
+
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::io::Read;
+use std::io::Write;
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use crossbeam_channel::Receiver;
+use crossbeam_channel::Sender;
+
+use crate::prelude::*;
+
+pub const LATENCY_ENV_VAR: &str = "WPRS_SIM_LATENCY";
+pub const JITTER_ENV_VAR: &str = "WPRS_SIM_JITTER";
+pub const BANDWIDTH_ENV_VAR: &str = "WPRS_SIM_BANDWIDTH";
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct LinkSimConfig {
+    pub latency: Duration,
+    pub jitter: Duration,
+    /// Bytes per second, or None for no cap.
+    pub bandwidth: Option<u64>,
+}
+
+impl LinkSimConfig {
+    /// Returns None if none of the simulation environment variables are set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let latency = env::var(LATENCY_ENV_VAR).ok();
+        let jitter = env::var(JITTER_ENV_VAR).ok();
+        let bandwidth = env::var(BANDWIDTH_ENV_VAR).ok();
+        if latency.is_none() && jitter.is_none() && bandwidth.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            latency: latency
+                .map(|s| parse_duration(&s))
+                .transpose()
+                .context(loc!(), LATENCY_ENV_VAR)?
+                .unwrap_or_default(),
+            jitter: jitter
+                .map(|s| parse_duration(&s))
+                .transpose()
+                .context(loc!(), JITTER_ENV_VAR)?
+                .unwrap_or_default(),
+            bandwidth: bandwidth
+                .map(|s| parse_bandwidth(&s))
+                .transpose()
+                .context(loc!(), BANDWIDTH_ENV_VAR)?,
+        }))
+    }
+}
+
+fn split_suffix(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    let idx = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    (&s[..idx], s[idx..].trim())
+}
+
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let (value, unit) = split_suffix(s);
+    let value: f64 = value
+        .parse()
+        .with_context(loc!(), || format!("invalid duration {s:?}"))?;
+    let secs = match unit {
+        "us" => value / 1_000_000.0,
+        "ms" | "" => value / 1_000.0,
+        "s" => value,
+        _ => bail!("unknown duration unit {unit:?} in {s:?}"),
+    };
+    Ok(Duration::from_secs_f64(secs))
+}
+
+/// Parses a bandwidth into bytes per second. Units ending in "bit" are bits per
+/// second, units ending in "B" are bytes per second.
+pub fn parse_bandwidth(s: &str) -> Result<u64> {
+    let (value, unit) = split_suffix(s);
+    let value: f64 = value
+        .parse()
+        .with_context(loc!(), || format!("invalid bandwidth {s:?}"))?;
+    let bytes_per_sec = match unit {
+        "bit" => value / 8.0,
+        "kbit" | "Kbit" => value * 1e3 / 8.0,
+        "Mbit" => value * 1e6 / 8.0,
+        "Gbit" => value * 1e9 / 8.0,
+        "B" | "" => value,
+        "kB" | "KB" => value * 1e3,
+        "MB" => value * 1e6,
+        "GB" => value * 1e9,
+        _ => bail!("unknown bandwidth unit {unit:?} in {s:?}"),
+    };
+    if bytes_per_sec < 1.0 {
+        bail!("bandwidth {s:?} is too small");
+    }
+    Ok(bytes_per_sec as u64)
+}
+
+struct Jitter {
+    max: Duration,
+    hasher_builder: RandomState,
+    counter: u64,
+}
+
+impl Jitter {
+    fn new(max: Duration) -> Self {
+        Self {
+            max,
+            hasher_builder: RandomState::new(),
+            counter: 0,
+        }
+    }
+
+    fn sample(&mut self) -> Duration {
+        if self.max.is_zero() {
+            return Duration::ZERO;
+        }
+        // Good enough randomness for this purpose without pulling in a crate.
+        let mut hasher = self.hasher_builder.build_hasher();
+        hasher.write_u64(self.counter);
+        self.counter += 1;
+        let frac = (hasher.finish() as f64) / (u64::MAX as f64);
+        self.max.mul_f64(frac)
+    }
+}
+
+fn delay_loop<R: Read>(
+    mut src: R,
+    config: LinkSimConfig,
+    output: Sender<(Instant, Vec<u8>)>,
+) -> Result<()> {
+    let mut jitter = Jitter::new(config.jitter);
+    let mut last_deadline = Instant::now();
+    loop {
+        let mut buf = vec![0; READ_CHUNK_SIZE];
+        let n = src.read(&mut buf).location(loc!())?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.truncate(n);
+        // Jitter must not reorder data; a real link would deliver it in order
+        // too.
+        let deadline = (Instant::now() + config.latency + jitter.sample()).max(last_deadline);
+        last_deadline = deadline;
+        output.send((deadline, buf)).location(loc!())?;
+    }
+}
+
+fn throttle_loop<W: Write>(
+    mut dst: W,
+    config: LinkSimConfig,
+    input: Receiver<(Instant, Vec<u8>)>,
+) -> Result<()> {
+    let mut link_free_at = Instant::now();
+    for (deadline, buf) in input {
+        let start = deadline.max(link_free_at);
+        let done = match config.bandwidth {
+            Some(bandwidth) => start + Duration::from_secs_f64(buf.len() as f64 / bandwidth as f64),
+            None => start,
+        };
+        link_free_at = done;
+        thread::sleep(done.saturating_duration_since(Instant::now()));
+        dst.write_all(&buf).location(loc!())?;
+    }
+    Ok(())
+}
+
+fn spawn_direction(src: UnixStream, dst: UnixStream, config: LinkSimConfig, name: &'static str) {
+    let (tx, rx) = crossbeam_channel::unbounded();
+    thread::spawn(move || {
+        delay_loop(&src, config, tx)
+            .context(loc!(), name)
+            .debug_and_ignore(loc!());
+    });
+    thread::spawn(move || {
+        throttle_loop(&dst, config, rx)
+            .context(loc!(), name)
+            .debug_and_ignore(loc!());
+        // Propagate the disconnection to the other end.
+        _ = dst.shutdown(Shutdown::Write);
+    });
+}
+
+/// Proxies stream through a simulated link and returns the local end of the
+/// proxy, which can be used in place of stream.
+pub fn wrap(stream: UnixStream, config: LinkSimConfig) -> Result<UnixStream> {
+    warn!("simulating network link: {config:?}");
+    let (local, proxy) = UnixStream::pair().location(loc!())?;
+
+    spawn_direction(
+        stream.try_clone().location(loc!())?,
+        proxy.try_clone().location(loc!())?,
+        config,
+        "link_sim_recv",
+    );
+    spawn_direction(proxy, stream, config, "link_sim_send");
+
+    Ok(local)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("100ms").unwrap(), Duration::from_millis(100));
+        assert_eq!(parse_duration("1s").unwrap(), Duration::from_secs(1));
+        assert_eq!(parse_duration("500us").unwrap(), Duration::from_micros(500));
+        assert_eq!(parse_duration("20").unwrap(), Duration::from_millis(20));
+        assert!(parse_duration("5 parsecs").is_err());
+    }
+
+    #[test]
+    fn test_parse_bandwidth() {
+        assert_eq!(parse_bandwidth("5Mbit").unwrap(), 625_000);
+        assert_eq!(parse_bandwidth("1MB").unwrap(), 1_000_000);
+        assert_eq!(parse_bandwidth("800bit").unwrap(), 100);
+        assert!(parse_bandwidth("0.1bit").is_err());
+        assert!(parse_bandwidth("fast").is_err());
+    }
+}
@@ -17,29 +17,36 @@ use std::fmt;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::hash::Hasher;
+use std::io;
 use std::io::BufWriter;
 use std::io::Read;
 use std::io::Write;
 use std::net::Shutdown;
 use std::num::NonZeroUsize;
 use std::os::fd::AsFd;
-use std::os::unix::net::UnixListener;
 use std::os::unix::net::UnixStream;
+use std::panic;
 use std::path::Path;
+use std::path::PathBuf;
 use std::process;
 use std::str;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::thread;
+use std::thread::JoinHandle;
 use std::thread::Scope;
 use std::thread::ScopedJoinHandle;
 use std::time::Duration;
+use std::time::Instant;
 
+use anyhow::Error;
 use arrayref::array_ref;
 use crossbeam_channel::Receiver;
 use crossbeam_channel::RecvTimeoutError;
 use crossbeam_channel::Sender;
+use fallible_iterator::FallibleIterator;
 use nix::sys::socket;
 use nix::sys::socket::sockopt::RcvBuf;
 use nix::sys::socket::sockopt::SndBuf;
@@ -62,16 +69,31 @@ use sysctl::Sysctl;
 use crate::arc_slice::ArcSlice;
 use crate::channel_utils::DiscardingSender;
 use crate::channel_utils::InfallibleSender;
+use crate::metrics::TransportStats;
 use crate::prelude::*;
+use crate::serialization::auth::AuthSecret;
+use crate::serialization::router::Router;
+use crate::serialization::router::DEFAULT_BACKEND;
 use crate::sharding_compression::CompressedShard;
+use crate::sharding_compression::CompressionAlgorithm;
+use crate::sharding_compression::CompressionConfig;
 use crate::sharding_compression::ShardingCompressor;
 use crate::sharding_compression::ShardingDecompressor;
 use crate::sharding_compression::MIN_SIZE_TO_COMPRESS;
 use crate::utils;
 
+#[cfg(any(feature = "tokio-client", feature = "tokio-server"))]
+pub mod async_;
+pub mod auth;
 pub mod geometry;
+pub mod link_sim;
+pub mod router;
+pub mod session_lock;
+pub mod tablet;
+pub mod text_input;
 pub mod tuple;
 pub mod wayland;
+pub mod wlr_layer_shell;
 pub mod xdg_shell;
 
 #[derive(Archive, Deserialize, Serialize, Debug, Copy, Clone, Hash, Eq, PartialEq)]
@@ -111,6 +133,30 @@ pub struct Capabilities {
     pub xwayland: bool,
 }
 
+#[derive(Debug, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct ClientCapabilities {
+    /// Buffer formats the client can display. The server converts buffers in
+    /// other formats to one of these.
+    pub buffer_formats: Vec<wayland::BufferFormat>,
+    /// Identifies the client (e.g., user@host) in the server's logs and
+    /// control server.
+    pub identity: String,
+}
+
+impl Default for ClientCapabilities {
+    fn default() -> Self {
+        Self {
+            // wl_shm requires all compositors to support both.
+            buffer_formats: vec![
+                wayland::BufferFormat::Argb8888,
+                wayland::BufferFormat::Xrgb8888,
+            ],
+            identity: String::new(),
+        }
+    }
+}
+
 // TODO: https://github.com/rust-lang/rfcs/pull/2593 - simplify all the enums.
 
 #[derive(Debug, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
@@ -123,19 +169,36 @@ pub enum Request {
     Data(wayland::DataRequest),
     ClientDisconnected(ClientId),
     Capabilities(Capabilities),
+    SessionLock(session_lock::SessionLockRequest),
+    /// Sent after the surfaces which existed when the client connected have
+    /// been sent to it.
+    SnapshotComplete,
+    /// The reply to Event::Ping, with its nonce.
+    Pong(u64),
+    TextInput(text_input::TextInputRequest),
 }
 
 #[derive(Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
 #[archive_attr(derive(bytecheck::CheckBytes, Debug))]
 pub enum Event {
-    WprsClientConnect,
+    WprsClientConnect(ClientCapabilities),
     Output(wayland::OutputEvent),
     PointerFrame(Vec<wayland::PointerEvent>),
+    TouchFrame(Vec<wayland::TouchEvent>),
     KeyboardEvent(wayland::KeyboardEvent),
     Toplevel(xdg_shell::ToplevelEvent),
     Popup(xdg_shell::PopupEvent),
     Data(wayland::DataEvent),
     Surface(wayland::SurfaceEvent),
+    Idle(wayland::IdleEvent),
+    LayerSurface(wlr_layer_shell::LayerSurfaceEvent),
+    SessionLock(session_lock::SessionLockEvent),
+    Tablet(tablet::TabletEvent),
+    InputSnapshot(wayland::InputSnapshot),
+    /// Asks the server for a Request::Pong with the same nonce, for measuring
+    /// the round-trip time.
+    Ping(u64),
+    TextInput(text_input::TextInputEvent),
 }
 
 // TODO: test that object ids with same value from different clients hash
@@ -146,7 +209,7 @@ pub fn hash<T: Hash>(t: &T) -> u64 {
     s.finish()
 }
 
-const SERIALIZE_SCRATCH_SPACE: usize = 1024 * 1024;
+pub(crate) const SERIALIZE_SCRATCH_SPACE: usize = 1024 * 1024;
 const CHANNEL_SIZE: usize = 1024;
 
 pub trait Serializable:
@@ -185,11 +248,135 @@ fn socket_buffer_limits() -> Result<(usize, usize)> {
     Ok((rmem_max, wmem_max))
 }
 
-fn enlarge_socket_buffer<F: AsFd>(fd: &F) {
-    let (rmem_max, wmem_max) = warn_and_return!(socket_buffer_limits());
+/// Tuning for a connection, which may differ between hosts.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, serde_derive::Deserialize, serde_derive::Serialize)]
+#[serde(default)]
+pub struct SerializerOptions {
+    /// Compression of data sent by this end of the connection, see
+    /// compression(). The other end has to support the algorithm too, which
+    /// the handshake checks. None if the config doesn't set it, so that
+    /// migrate_compression_level can tell.
+    #[serde(
+        deserialize_with = "deserialize_some",
+        serialize_with = "serialize_some",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub compression: Option<CompressionConfig>,
+    /// Socket receive and send buffer sizes. None uses the largest sizes
+    /// allowed by net.core.rmem_max and net.core.wmem_max.
+    pub socket_buffer_size: Option<usize>,
+    /// Skip, with a warning, objects which fail to deserialize instead of
+    /// dropping the connection. This lets a peer running a newer version
+    /// send message kinds which this end doesn't know about yet, as long as
+    /// the new variants don't change the archived size of the enum.
+    ///
+    /// Off by default: a skipped object can leave the two ends out of sync
+    /// (e.g., a surface whose commit was skipped), and a corrupted stream is
+    /// better caught early. Enable it only when wprsd and wprsc are
+    /// deliberately run at different versions, e.g. during a staged upgrade.
+    pub skip_unknown_objects: bool,
+    /// The largest uncompressed object, in bytes, which will be accepted from
+    /// the other end. Larger objects drop the connection before anything is
+    /// allocated for them. Raw buffers (i.e., surface contents) aren't
+    /// subject to this limit, see max_raw_buffer_size.
+    pub max_object_size: usize,
+    /// The largest uncompressed raw buffer, in bytes, which will be accepted
+    /// from the other end. The declared size is allocated before the
+    /// compressed data is read, so without a limit a few bytes of compressed
+    /// data could make this end allocate up to 4GiB.
+    pub max_raw_buffer_size: usize,
+    /// How long, in microseconds, to hold back a raw buffer (i.e., surface
+    /// contents) for more messages to be written with it in a single flush.
+    /// 0 flushes it as soon as nothing else is queued, like other messages.
+    pub raw_buffer_flush_delay_us: u64,
+    /// How many times a client tries to reconnect, with exponential backoff,
+    /// after losing the connection to the server. 0 exits instead.
+    pub max_reconnect_attempts: u32,
+    /// How long, in milliseconds, the server waits for a client to answer
+    /// the authentication challenge, and vice versa, see auth.
+    pub auth_timeout_ms: u64,
+    /// The zstd level configs set before the compression algorithm became
+    /// configurable. Only read from existing configs, see
+    /// migrate_compression_level.
+    #[serde(skip_serializing, deserialize_with = "deserialize_some")]
+    pub compression_level: Option<i32>,
+}
+
+impl Default for SerializerOptions {
+    fn default() -> Self {
+        Self {
+            compression: None,
+            socket_buffer_size: None,
+            skip_unknown_objects: false,
+            max_object_size: 256 * 1024 * 1024,
+            // Enough for an 8K surface (~130MB) with room to spare.
+            max_raw_buffer_size: 512 * 1024 * 1024,
+            raw_buffer_flush_delay_us: 0,
+            max_reconnect_attempts: 0,
+            auth_timeout_ms: 5000,
+            compression_level: None,
+        }
+    }
+}
+
+/// Configs set optional fields to a bare value rather than Some(value).
+fn deserialize_some<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::Deserialize<'de>,
+{
+    serde::Deserialize::deserialize(deserializer).map(Some)
+}
 
-    socket::setsockopt(fd, RcvBuf, &rmem_max).warn_and_ignore(loc!());
-    socket::setsockopt(fd, SndBuf, &wmem_max).warn_and_ignore(loc!());
+/// The counterpart of deserialize_some.
+fn serialize_some<S, T>(value: &Option<T>, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    T: serde::Serialize,
+{
+    match value {
+        Some(value) => serde::Serialize::serialize(value, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+impl SerializerOptions {
+    pub fn compression(&self) -> CompressionConfig {
+        self.compression.unwrap_or_default()
+    }
+
+    /// Maps the compression_level of configs written before compression was
+    /// configurable onto compression, with a warning. compression takes
+    /// precedence if both are set.
+    pub fn migrate_compression_level(mut self) -> Self {
+        let Some(level) = self.compression_level.take() else {
+            return self;
+        };
+        if self.compression.is_none() {
+            warn!("compression_level is deprecated, use compression: (algorithm: Zstd, level: {level}) instead");
+            self.compression = Some(CompressionConfig {
+                algorithm: CompressionAlgorithm::Zstd,
+                level,
+            });
+        } else {
+            warn!("compression_level is deprecated and ignored in favor of compression, remove it");
+        }
+        self
+    }
+}
+
+fn socket_buffer_sizes(options: &SerializerOptions) -> Result<(usize, usize)> {
+    match options.socket_buffer_size {
+        Some(size) => Ok((size, size)),
+        None => socket_buffer_limits().location(loc!()),
+    }
+}
+
+fn enlarge_socket_buffer<F: AsFd>(fd: &F, options: &SerializerOptions) {
+    let (rmem, wmem) = warn_and_return!(socket_buffer_sizes(options));
+
+    socket::setsockopt(fd, RcvBuf, &rmem).warn_and_ignore(loc!());
+    socket::setsockopt(fd, SndBuf, &wmem).warn_and_ignore(loc!());
 }
 
 fn write_usize_as_u32_be<W: Write>(stream: &mut W, u: usize) -> Result<()> {
@@ -238,6 +425,38 @@ impl Version {
             warn!("Self version is {:?}, while other version is {:?}. These versions may be incompatible; if you experience bugs (especially hanging or crashes), restart the server.", self, other);
         }
     }
+
+    /// Both ends send their version first thing, before authenticating, so
+    /// that a mismatch is reported even if the rest of the handshake fails.
+    fn exchange<S: Read + Write>(stream: &mut S) -> Result<()> {
+        let version = Self::new();
+        version.framed_write(stream).location(loc!())?;
+        version.compare_and_warn(&Self::framed_read(stream).location(loc!())?);
+        Ok(())
+    }
+}
+
+/// Sent before the first frame, so that data compressed with an algorithm the
+/// other end doesn't support fails the handshake rather than the first
+/// frames.
+fn write_compression_algorithm<W: Write>(
+    stream: &mut W,
+    algorithm: CompressionAlgorithm,
+) -> Result<()> {
+    stream
+        .write_all(&u32::from(algorithm).to_be_bytes())
+        .location(loc!())?;
+    stream.flush().location(loc!())?;
+    Ok(())
+}
+
+fn read_compression_algorithm<R: Read>(stream: &mut R) -> Result<CompressionAlgorithm> {
+    let mut buf: [u8; 4] = [0; 4];
+    stream.read_exact(&mut buf).location(loc!())?;
+    let algorithm = CompressionAlgorithm::check_supported(u32::from_be_bytes(buf))
+        .context(loc!(), "the other end's compression isn't supported")?;
+    debug!("other end compresses with {:?}", algorithm);
+    Ok(algorithm)
 }
 
 // TODO: figure out how to shorten the T::Archived bound. This may require
@@ -250,7 +469,13 @@ where
         Deserialize<ST, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
 {
     Object(ST),
-    RawBuffer(Arc<dyn AsRef<[u8]> + Send + Sync>),
+    /// The contents of a surface, sent before its commit.
+    RawBuffer {
+        surface: wayland::WlSurfaceId,
+        data: Arc<dyn AsRef<[u8]> + Send + Sync>,
+        /// False for contents which aren't worth the CPU time of compressing.
+        compress: bool,
+    },
 }
 
 impl<ST> fmt::Debug for SendType<ST>
@@ -262,7 +487,17 @@ where
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Object(obj) => write!(f, "Object({:?})", obj),
-            Self::RawBuffer(vec) => write!(f, "RawBuffer(<len {:?}>)", (**vec).as_ref().len()),
+            Self::RawBuffer {
+                surface,
+                data,
+                compress,
+            } => write!(
+                f,
+                "RawBuffer({:?}, <len {:?}>, compress: {:?})",
+                surface,
+                (**data).as_ref().len(),
+                compress
+            ),
         }
     }
 }
@@ -275,6 +510,9 @@ where
 {
     Object(RT),
     RawBuffer(Vec<u8>),
+    /// The connection to the server was lost and then reestablished. Only
+    /// received by clients.
+    Reconnected,
 }
 
 impl<RT> fmt::Debug for RecvType<RT>
@@ -287,88 +525,209 @@ where
         match self {
             Self::Object(obj) => write!(f, "Object({:?})", obj),
             Self::RawBuffer(vec) => write!(f, "RawBuffer(<len {:?}>)", vec.len()),
+            Self::Reconnected => write!(f, "Reconnected"),
         }
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, IntoPrimitive, TryFromPrimitive)]
 #[repr(u32)]
 pub enum MessageType {
     Object,
     RawBuffer,
 }
 
-fn read_loop<R, RT>(mut stream: R, output_channel: channel::SyncSender<RecvType<RT>>) -> Result<()>
+/// Like read_exact, but returns Ok(false) instead of an error if the stream
+/// ends before any of buf was read, i.e., if the other end closed the
+/// connection between frames rather than in the middle of one.
+fn read_exact_or_eof<R: Read>(stream: &mut R, buf: &mut [u8]) -> Result<bool> {
+    let mut n_read = 0;
+    while n_read < buf.len() {
+        match stream.read(&mut buf[n_read..]) {
+            Ok(0) if n_read == 0 => return Ok(false),
+            Ok(0) => bail!(
+                "connection closed in the middle of a frame header, after {n_read} of {} bytes",
+                buf.len()
+            ),
+            Ok(n) => n_read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e).location(loc!()),
+        }
+    }
+    Ok(true)
+}
+
+/// Deserializes an object. If `skip_unknown` is set, objects which fail to
+/// deserialize (for example because the peer sent an enum variant added in a
+/// newer version) are logged and None is returned instead of an error. The
+/// frame has already been fully read at this point, so the stream stays in
+/// sync.
+fn deserialize_object<RT>(buf: &[u8], skip_unknown: bool) -> Result<Option<RT>>
 where
-    R: Read,
     RT: Serializable,
     RT::Archived:
         Deserialize<RT, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
 {
-    // TODO: try tuning this based on the number of cpus the machine has.
-    let n_decompressors = NonZeroUsize::new(8).unwrap();
-    let mut sharding_decompressor = ShardingDecompressor::new(n_decompressors).location(loc!())?;
+    match debug_span!("deserialize").in_scope(|| rkyv::from_bytes(buf)) {
+        Ok(obj) => Ok(Some(obj)),
+        Err(e) if skip_unknown => {
+            warn!(
+                "skipping object which failed to deserialize, the other end may be running a newer version: {e}"
+            );
+            Ok(None)
+        },
+        // The error type is not Send + Sync, which anyhow requires.
+        Err(e) => Err(anyhow!("{e}")).location(loc!()),
+    }
+}
 
-    Version::new().compare_and_warn(&Version::framed_read(&mut stream).location(loc!())?);
+fn check_raw_buffer_size(size: usize, max_raw_buffer_size: usize) -> Result<()> {
+    if size > max_raw_buffer_size {
+        bail!(
+            "raw buffer of {size} bytes exceeds max_raw_buffer_size ({max_raw_buffer_size} bytes)"
+        );
+    }
+    Ok(())
+}
 
-    loop {
-        let mut u32_buf: [u8; 12] = [0; 12];
-        stream.read_exact(&mut u32_buf).location(loc!())?;
+fn check_object_size(size: usize, max_object_size: usize) -> Result<()> {
+    if size > max_object_size {
+        bail!("object of {size} bytes exceeds max_object_size ({max_object_size} bytes)");
+    }
+    Ok(())
+}
 
-        // read_exact blocks waiting for data, so start the span afterward.
-        let _span = debug_span!("serializer_read_loop").entered();
+/// The header which precedes the shards of each message.
+#[derive(Debug, Copy, Clone)]
+struct FrameHeader {
+    n_shards: NonZeroUsize,
+    uncompressed_size: usize,
+    message_type: MessageType,
+}
+
+impl FrameHeader {
+    const SIZE: usize = 12;
 
-        // read frame header
-        let n_shards = non_zero_usize_from_u32_as_u8_4(array_ref!(u32_buf, 0, 4))
+    /// Parses a header and checks the message's size against options.
+    fn parse(buf: &[u8; Self::SIZE], options: &SerializerOptions) -> Result<Self> {
+        let n_shards = non_zero_usize_from_u32_as_u8_4(array_ref!(buf, 0, 4))
             .inspect_err(|_| error!("n_shards was 0"))
             .location(loc!())?;
         debug!("read n_shards: {}", n_shards);
-        let uncompressed_size = usize_from_u32_as_u8_4(array_ref!(u32_buf, 4, 4));
+        let uncompressed_size = usize_from_u32_as_u8_4(array_ref!(buf, 4, 4));
         debug!("read uncompressed_size: {}", uncompressed_size);
 
-        let message_type = MessageType::try_from(u32::from_be_bytes(*array_ref!(u32_buf, 8, 4)))
-            .location(loc!())?;
+        let message_type =
+            MessageType::try_from(u32::from_be_bytes(*array_ref!(buf, 8, 4))).location(loc!())?;
         debug!("read message_type: {:?}", message_type);
 
-        let chunk_size = uncompressed_size / n_shards;
-        let actual_n_shards = utils::n_chunks(uncompressed_size, chunk_size);
-        let compressed_shard_iter = fallible_iterator::convert(
-            (0..actual_n_shards).map(|_| CompressedShard::framed_read(&mut stream)),
-        );
-
         match message_type {
             MessageType::Object => {
-                sharding_decompressor
-                    .decompress_with(n_shards, uncompressed_size, compressed_shard_iter, |buf| {
-                        let obj = RecvType::Object(
-                            debug_span!("deserialize").in_scope(
-                            || rkyv::from_bytes(buf))
-                                         // The error type is not Send + Sync, which anyhow requires.
-                                         .map_err(|e| anyhow!("{e}"))
-                                         .location(loc!())?,
-                        );
-                        debug!("read obj: {obj:?}");
-                        output_channel.send(obj)
-                        // The error type is not Send + Sync, which anyhow requires.
-                            .map_err(|e| anyhow!("{e}"))
-                            .location(loc!())?;
-                        Ok(())
-                    })
-                    .location(loc!())?;
+                check_object_size(uncompressed_size, options.max_object_size).location(loc!())?;
             },
             MessageType::RawBuffer => {
-                let obj = RecvType::RawBuffer(
-                    sharding_decompressor
-                        .decompress_to_owned(n_shards, uncompressed_size, compressed_shard_iter)
-                        .location(loc!())?,
-                );
-                debug!("read obj: {obj:?}");
-                output_channel.send(obj)
-                // The error type is not Send + Sync, which anyhow requires.
-                    .map_err(|e| anyhow!("{e}"))
+                check_raw_buffer_size(uncompressed_size, options.max_raw_buffer_size)
                     .location(loc!())?;
             },
         }
+
+        Ok(Self {
+            n_shards,
+            uncompressed_size,
+            message_type,
+        })
+    }
+
+    /// The number of shards which follow the header.
+    fn actual_n_shards(&self) -> usize {
+        let chunk_size = self.uncompressed_size / self.n_shards;
+        utils::n_chunks(self.uncompressed_size, chunk_size)
+    }
+
+    /// Decompresses the message's shards. Returns None for objects which were
+    /// skipped, see deserialize_object.
+    fn decompress<RT>(
+        &self,
+        sharding_decompressor: &mut ShardingDecompressor,
+        compressed_shards: impl FallibleIterator<Item = CompressedShard, Error = Error>,
+        skip_unknown_objects: bool,
+    ) -> Result<Option<RecvType<RT>>>
+    where
+        RT: Serializable,
+        RT::Archived: Deserialize<RT, SharedDeserializeMap>
+            + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
+    {
+        match self.message_type {
+            MessageType::Object => sharding_decompressor
+                .decompress_with(
+                    self.n_shards,
+                    self.uncompressed_size,
+                    compressed_shards,
+                    |buf| {
+                        Ok(deserialize_object(buf, skip_unknown_objects)
+                            .location(loc!())?
+                            .map(RecvType::Object))
+                    },
+                )
+                .location(loc!()),
+            MessageType::RawBuffer => Ok(Some(RecvType::RawBuffer(
+                sharding_decompressor
+                    .decompress_to_owned(self.n_shards, self.uncompressed_size, compressed_shards)
+                    .location(loc!())?,
+            ))),
+        }
+    }
+}
+
+fn read_loop<R, RT>(
+    mut stream: R,
+    output_channel: channel::SyncSender<RecvType<RT>>,
+    stats: Arc<TransportStats>,
+    options: SerializerOptions,
+) -> Result<()>
+where
+    R: Read,
+    RT: Serializable,
+    RT::Archived:
+        Deserialize<RT, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
+{
+    // TODO: try tuning this based on the number of cpus the machine has.
+    let n_decompressors = NonZeroUsize::new(8).unwrap();
+    let mut sharding_decompressor = ShardingDecompressor::new(n_decompressors).location(loc!())?;
+
+    read_compression_algorithm(&mut stream).location(loc!())?;
+
+    loop {
+        let mut header_buf = [0; FrameHeader::SIZE];
+        if !read_exact_or_eof(&mut stream, &mut header_buf).location(loc!())? {
+            debug!("other end closed the connection");
+            return Ok(());
+        }
+
+        // read_exact blocks waiting for data, so start the span afterward.
+        let _span = debug_span!("serializer_read_loop").entered();
+
+        let header = FrameHeader::parse(&header_buf, &options).location(loc!())?;
+        stats.record_received(header.uncompressed_size);
+
+        let compressed_shard_iter = fallible_iterator::convert(
+            (0..header.actual_n_shards()).map(|_| CompressedShard::framed_read(&mut stream)),
+        );
+        let Some(obj) = header
+            .decompress(
+                &mut sharding_decompressor,
+                compressed_shard_iter,
+                options.skip_unknown_objects,
+            )
+            .location(loc!())?
+        else {
+            continue;
+        };
+        debug!("read obj: {obj:?}");
+        output_channel.send(obj)
+            // The error type is not Send + Sync, which anyhow requires.
+            .map_err(|e| anyhow!("{e}"))
+            .location(loc!())?;
     }
 }
 
@@ -376,6 +735,8 @@ fn write_loop<W, ST>(
     stream: W,
     input_channel: Receiver<SendType<ST>>,
     other_end_connected: Arc<AtomicBool>,
+    stats: Arc<TransportStats>,
+    options: SerializerOptions,
 ) -> Result<()>
 where
     W: Write,
@@ -383,22 +744,34 @@ where
     ST::Archived:
         Deserialize<ST, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
 {
-    let (_, wmem_max) = socket_buffer_limits().location(loc!())?;
+    let (_, wmem) = socket_buffer_sizes(&options).location(loc!())?;
     let mut stream = BufWriter::with_capacity(
-        wmem_max, // match the socket's buffer size
+        wmem, // match the socket's buffer size
         stream,
     );
 
     // TODO: try tuning this based on the number of cpus the machine has.
     let n_compressors = NonZeroUsize::new(16).unwrap();
-    let sharding_compressor = ShardingCompressor::new(n_compressors, 1).location(loc!())?;
+    let sharding_compressor =
+        ShardingCompressor::new(n_compressors, options.compression()).location(loc!())?;
+
+    write_compression_algorithm(&mut stream, sharding_compressor.algorithm()).location(loc!())?;
 
-    Version::new().framed_write(&mut stream).location(loc!())?;
+    let raw_buffer_flush_delay = Duration::from_micros(options.raw_buffer_flush_delay_us);
+    // Set while a raw buffer is being held back, see the end of the loop.
+    let mut flush_deadline: Option<Instant> = None;
 
     loop {
-        let obj = match input_channel.recv_timeout(Duration::from_secs(1)) {
+        let timeout = flush_deadline.map_or(Duration::from_secs(1), |flush_deadline| {
+            flush_deadline.saturating_duration_since(Instant::now())
+        });
+        let obj = match input_channel.recv_timeout(timeout) {
             Ok(obj) => obj,
             Err(RecvTimeoutError::Timeout) => {
+                if flush_deadline.take().is_some() {
+                    stream.flush().location(loc!())?;
+                    continue;
+                }
                 if !other_end_connected.load(Ordering::Acquire) {
                     break;
                 } else {
@@ -419,51 +792,33 @@ where
             compression_ratio = field::Empty
         )
         .entered();
-        let (data, message_type): (ArcSlice<u8>, u32) = match &obj {
-            SendType::Object(obj) => (
-                ArcSlice::new(
-                    debug_span!("serialize")
-                        .in_scope(|| rkyv::to_bytes::<_, SERIALIZE_SCRATCH_SPACE>(obj))
-                        .location(loc!())?,
-                ),
-                MessageType::Object.into(),
-            ),
-            SendType::RawBuffer(vec) => (
-                ArcSlice::new_from_arc(vec.clone()),
-                MessageType::RawBuffer.into(),
-            ),
-        };
-
-        let uncompressed_size = data.len();
-        let n_shards = if uncompressed_size > MIN_SIZE_TO_COMPRESS {
-            // There is a lot of variability between how long each thread takes
-            // to compress each shard (4x has been observed), so having more
-            // chunks lets threads which finish early start working on other
-            // chunks and thus reduces tail latency.
-            NonZeroUsize::new(2 * n_compressors.get()).unwrap()
-        } else {
-            NonZeroUsize::new(1).unwrap()
-        };
-
-        // write frame header
-        {
-            write_usize_as_u32_be(&mut stream, n_shards.get()).location(loc!())?;
-            write_usize_as_u32_be(&mut stream, uncompressed_size).location(loc!())?;
-            stream
-                .write_all(&message_type.to_be_bytes())
-                .location(loc!())?;
-        }
+        let (message_type, uncompressed_size, compressed_size) = write_frame(
+            &mut stream,
+            &sharding_compressor,
+            n_compressors,
+            &obj,
+            &stats,
+        )
+        .location(loc!())?;
 
-        let mut compressed_size = 0;
-        for shard in sharding_compressor.compress(n_shards, data) {
-            compressed_size += shard.data.len();
-            debug_span!("write")
-                .in_scope(|| shard.framed_write(&mut stream))
-                .location(loc!())?;
+        // Other messages are flushed once nothing else is queued, so that
+        // bursts of small messages (e.g., input events) go out in a single
+        // write without waiting for messages which haven't been sent yet.
+        if input_channel.is_empty() {
+            if message_type == MessageType::RawBuffer && !raw_buffer_flush_delay.is_zero() {
+                flush_deadline.get_or_insert_with(|| Instant::now() + raw_buffer_flush_delay);
+            } else {
+                stream.flush().location(loc!())?;
+                flush_deadline = None;
+            }
         }
 
         // metrics
         {
+            stats.record_sent(uncompressed_size, compressed_size);
+            if let SendType::RawBuffer { surface, .. } = &obj {
+                stats.record_surface_sent(*surface, uncompressed_size, compressed_size);
+            }
             let compression_ratio = uncompressed_size as f64 / compressed_size as f64;
             span.record("uncompressed_size", field::debug(uncompressed_size));
             span.record("compressed_size", compressed_size);
@@ -491,12 +846,95 @@ where
     Ok(())
 }
 
+/// Writes obj as a frame header followed by its shards. Returns the message
+/// type and the uncompressed and compressed sizes of the message.
+fn write_frame<W, ST>(
+    stream: &mut W,
+    sharding_compressor: &ShardingCompressor,
+    n_compressors: NonZeroUsize,
+    obj: &SendType<ST>,
+    stats: &TransportStats,
+) -> Result<(MessageType, usize, usize)>
+where
+    W: Write,
+    ST: Serializable,
+    ST::Archived:
+        Deserialize<ST, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
+{
+    let (data, message_type, compress): (ArcSlice<u8>, MessageType, bool) = match obj {
+        SendType::Object(obj) => (
+            ArcSlice::new(
+                debug_span!("serialize")
+                    .in_scope(|| rkyv::to_bytes::<_, SERIALIZE_SCRATCH_SPACE>(obj))
+                    .location(loc!())?,
+            ),
+            MessageType::Object,
+            true,
+        ),
+        SendType::RawBuffer { data, compress, .. } => (
+            ArcSlice::new_from_arc(data.clone()),
+            MessageType::RawBuffer,
+            *compress,
+        ),
+    };
+
+    let uncompressed_size = data.len();
+    let n_shards = if uncompressed_size > MIN_SIZE_TO_COMPRESS {
+        // There is a lot of variability between how long each thread takes
+        // to compress each shard (4x has been observed), so having more
+        // chunks lets threads which finish early start working on other
+        // chunks and thus reduces tail latency.
+        NonZeroUsize::new(2 * n_compressors.get()).unwrap()
+    } else {
+        NonZeroUsize::new(1).unwrap()
+    };
+
+    // write frame header
+    {
+        write_usize_as_u32_be(stream, n_shards.get()).location(loc!())?;
+        write_usize_as_u32_be(stream, uncompressed_size).location(loc!())?;
+        stream
+            .write_all(&u32::from(message_type).to_be_bytes())
+            .location(loc!())?;
+    }
+
+    let mut compressed_size = 0;
+    for (shard, compression_time) in
+        sharding_compressor.maybe_compress_timed(n_shards, data, compress)
+    {
+        compressed_size += shard.data.len();
+        if compress {
+            stats.record_shard_compressed(compression_time);
+        }
+        #[cfg(feature = "tracy")]
+        if let Some(tracy_client) = tracy_client::Client::running() {
+            tracy_client.plot(
+                tracy_client::plot_name!("shard_compression_time_us"),
+                compression_time.as_secs_f64() * 1e6,
+            );
+        }
+        debug_span!("write")
+            .in_scope(|| shard.framed_write(stream))
+            .location(loc!())?;
+        // Flush each shard of large messages instead of after writing all
+        // of them so that the other end can start decompressing the shards
+        // sooner.
+        if n_shards.get() > 1 {
+            stream.flush().location(loc!())?;
+        }
+    }
+
+    Ok((message_type, uncompressed_size, compressed_size))
+}
+
 fn spawn_rw_loops<'scope, ST, RT>(
     scope: &'scope Scope<'scope, '_>,
     stream: UnixStream,
     read_channel_tx: channel::SyncSender<RecvType<RT>>,
     write_channel_rx: Receiver<SendType<ST>>,
     other_end_connected: Arc<AtomicBool>,
+    stats: Arc<TransportStats>,
+    options: SerializerOptions,
 ) -> Result<(
     ScopedJoinHandle<'scope, Result<()>>,
     ScopedJoinHandle<'scope, Result<()>>,
@@ -510,20 +948,33 @@ where
         Deserialize<RT, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
 {
     let read_stream = stream.try_clone().location(loc!())?;
-    let read_thread = scope.spawn(move || read_loop(read_stream, read_channel_tx));
+    let read_thread = {
+        let stats = stats.clone();
+        scope.spawn(move || read_loop(read_stream, read_channel_tx, stats, options))
+    };
 
     let write_stream = stream.try_clone().location(loc!())?;
-    let write_thread =
-        scope.spawn(move || write_loop(write_stream, write_channel_rx, other_end_connected));
+    let write_thread = scope.spawn(move || {
+        write_loop(
+            write_stream,
+            write_channel_rx,
+            other_end_connected,
+            stats,
+            options,
+        )
+    });
 
     Ok((read_thread, write_thread))
 }
 
-fn accept_loop<ST, RT>(
-    listener: UnixListener,
+/// Serves the connections which the router hands over, one at a time.
+fn serve_loop<ST, RT>(
+    connections: Receiver<UnixStream>,
     read_channel_tx: channel::SyncSender<RecvType<RT>>,
     write_channel_rx: Receiver<SendType<ST>>,
     other_end_connected: Arc<AtomicBool>,
+    stats: Arc<TransportStats>,
+    options: SerializerOptions,
 ) where
     ST: Serializable,
     ST::Archived:
@@ -533,9 +984,7 @@ fn accept_loop<ST, RT>(
         Deserialize<RT, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
 {
     thread::scope(|scope| {
-        loop {
-            debug!("waiting for client connection");
-            let (stream, _) = listener.accept().unwrap();
+        for stream in connections {
             info!("wprs client connected");
             let (read_thread, write_thread) = spawn_rw_loops(
                 scope,
@@ -543,6 +992,8 @@ fn accept_loop<ST, RT>(
                 read_channel_tx.clone(),
                 write_channel_rx.clone(),
                 other_end_connected.clone(),
+                stats.clone(),
+                options,
             )
             .unwrap();
             let read_thread_result = utils::join_unwrap(read_thread);
@@ -562,11 +1013,83 @@ fn accept_loop<ST, RT>(
     });
 }
 
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// The delay before the given (0-based) reconnection attempt.
+fn reconnect_delay(attempt: u32) -> Duration {
+    Duration::from_millis(100)
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_RECONNECT_DELAY)
+}
+
+fn connect(
+    sock_path: &Path,
+    backend: &str,
+    options: &SerializerOptions,
+    auth_secret: Option<&AuthSecret>,
+) -> Result<UnixStream> {
+    let mut stream = UnixStream::connect(sock_path).location(loc!())?;
+    enlarge_socket_buffer(&stream, options);
+    if let Some(link_sim_config) = link_sim::LinkSimConfig::from_env().location(loc!())? {
+        stream = link_sim::wrap(stream, link_sim_config).location(loc!())?;
+        enlarge_socket_buffer(&stream, options);
+    }
+    auth::with_timeout(
+        &mut stream,
+        Duration::from_millis(options.auth_timeout_ms),
+        |stream| {
+            Version::exchange(stream).location(loc!())?;
+            auth::respond(stream, auth_secret).location(loc!())?;
+            router::request_backend(stream, backend).location(loc!())
+        },
+    )
+    .location(loc!())?;
+    Ok(stream)
+}
+
+/// Tries to connect to the server until it succeeds, the attempts allowed by
+/// options.max_reconnect_attempts run out while keep_trying isn't set, or the
+/// client starts shutting down.
+fn reconnect(
+    sock_path: &Path,
+    backend: &str,
+    options: &SerializerOptions,
+    auth_secret: Option<&AuthSecret>,
+    keep_trying: &AtomicBool,
+    shutting_down: &AtomicBool,
+) -> Option<UnixStream> {
+    let mut attempt = 0;
+    while attempt < options.max_reconnect_attempts || keep_trying.load(Ordering::Acquire) {
+        thread::sleep(reconnect_delay(attempt));
+        if shutting_down.load(Ordering::Acquire) {
+            return None;
+        }
+        attempt = attempt.saturating_add(1);
+        match connect(sock_path, backend, options, auth_secret) {
+            Ok(stream) => return Some(stream),
+            Err(err) => warn!(
+                "reconnection attempt {attempt} of {} failed: {err:?}",
+                options.max_reconnect_attempts
+            ),
+        }
+    }
+    None
+}
+
+#[allow(clippy::too_many_arguments)]
 fn client_loop<ST, RT>(
-    stream: UnixStream,
+    sock_path: PathBuf,
+    backend: String,
+    mut stream: UnixStream,
+    current_stream: Arc<Mutex<UnixStream>>,
     read_channel_tx: channel::SyncSender<RecvType<RT>>,
     write_channel_rx: Receiver<SendType<ST>>,
     other_end_connected: Arc<AtomicBool>,
+    stats: Arc<TransportStats>,
+    options: SerializerOptions,
+    auth_secret: Option<AuthSecret>,
+    keep_reconnecting: Arc<AtomicBool>,
+    shutting_down: Arc<AtomicBool>,
 ) -> Result<()>
 where
     ST: Serializable,
@@ -576,23 +1099,68 @@ where
     RT::Archived:
         Deserialize<RT, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
 {
-    thread::scope(|scope| {
-        let (read_thread, _) = spawn_rw_loops(
-            scope,
-            stream,
-            read_channel_tx,
-            write_channel_rx,
-            other_end_connected,
-        )
+    loop {
+        let result = thread::scope(|scope| -> Result<Result<()>> {
+            let (read_thread, write_thread) = spawn_rw_loops(
+                scope,
+                stream,
+                read_channel_tx.clone(),
+                write_channel_rx.clone(),
+                other_end_connected.clone(),
+                stats.clone(),
+                options,
+            )
+            .location(loc!())?;
+
+            let result = utils::join_unwrap(read_thread);
+            debug!("read thread joined: {:?}", result);
+            // The write thread exits once it notices that the other end is no
+            // longer connected.
+            other_end_connected.store(false, Ordering::Release);
+            let write_thread_result = utils::join_unwrap(write_thread);
+            debug!("write thread joined: {write_thread_result:?}");
+            Ok(result)
+        })
         .location(loc!())?;
+        if shutting_down.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        match &result {
+            Ok(()) => eprintln!("server closed connection"),
+            Err(err) => eprintln!("server disconnected: {err:?}"),
+        }
 
-        // TODO: consider actually look at the error and not printing the reason
-        // if was actually just a disconnection and not some other error.
-        let result = utils::join_unwrap(read_thread);
-        debug!("read thread joined: {:?}", result);
-        eprintln!("server disconnected: {:?}", result);
-        process::exit(1);
-    })
+        let Some(new_stream) = reconnect(
+            &sock_path,
+            &backend,
+            &options,
+            auth_secret.as_ref(),
+            &keep_reconnecting,
+            &shutting_down,
+        ) else {
+            if shutting_down.load(Ordering::Acquire) {
+                return Ok(());
+            }
+            process::exit(if result.is_ok() { 0 } else { 1 });
+        };
+        {
+            // Checked while holding the lock so that shutdown can't miss the
+            // new stream.
+            let mut current_stream = current_stream.lock().unwrap();
+            if shutting_down.load(Ordering::Acquire) {
+                return Ok(());
+            }
+            *current_stream = new_stream.try_clone().location(loc!())?;
+        }
+        eprintln!("reconnected to server");
+        stream = new_stream;
+        other_end_connected.store(true, Ordering::Release);
+        read_channel_tx
+            .send(RecvType::Reconnected)
+            // The error type is not Send + Sync, which anyhow requires.
+            .map_err(|e| anyhow!("{e}"))
+            .location(loc!())?;
+    }
 }
 
 // TODO: can we create a separate thread to handle serialization/deserialization
@@ -611,6 +1179,17 @@ where
     read_handle: Option<Channel<RecvType<RT>>>,
     write_handle: DiscardingSender<Sender<SendType<ST>>>,
     other_end_connected: Arc<AtomicBool>,
+    stats: Arc<TransportStats>,
+    /// Only set for clients, see shutdown.
+    client_connection: Option<ClientConnection>,
+}
+
+struct ClientConnection {
+    /// Replaced by client_loop when it reconnects.
+    stream: Arc<Mutex<UnixStream>>,
+    thread: JoinHandle<Result<()>>,
+    keep_reconnecting: Arc<AtomicBool>,
+    shutting_down: Arc<AtomicBool>,
 }
 
 impl<ST, RT> Serializer<ST, RT>
@@ -623,18 +1202,60 @@ where
         Deserialize<RT, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
 {
     pub fn new_server<P: AsRef<Path>>(sock_path: P) -> Result<Self> {
-        let listener = utils::bind_user_socket(sock_path).location(loc!())?;
-        enlarge_socket_buffer(&listener);
+        Self::new_server_with_options(sock_path, SerializerOptions::default(), None)
+    }
+
+    /// Clients have to prove that they know auth_secret, if it's set.
+    pub fn new_server_with_options<P: AsRef<Path>>(
+        sock_path: P,
+        options: SerializerOptions,
+        auth_secret: Option<AuthSecret>,
+    ) -> Result<Self> {
+        Self::new_server_for_backend(sock_path, DEFAULT_BACKEND, options, auth_secret)
+    }
 
+    /// Serves the clients which ask for the named backend, see router. If
+    /// another process already listens on sock_path, the backend registers
+    /// with its router instead.
+    pub fn new_server_for_backend<P: AsRef<Path>>(
+        sock_path: P,
+        backend: &str,
+        options: SerializerOptions,
+        auth_secret: Option<AuthSecret>,
+    ) -> Result<Self> {
+        if let Ok(stream) = UnixStream::connect(&sock_path) {
+            return router::register(stream, backend, options, auth_secret.as_ref())
+                .location(loc!());
+        }
+        Router::new(sock_path, options, auth_secret)
+            .location(loc!())?
+            .serializer(backend, options)
+            .location(loc!())
+    }
+
+    /// A server-side serializer for the connections which the router hands
+    /// over, see router::Router::serializer.
+    fn from_connections(connections: Receiver<UnixStream>, options: SerializerOptions) -> Self {
         let (reader_tx, reader_rx): (channel::SyncSender<RecvType<RT>>, Channel<RecvType<RT>>) =
             channel::sync_channel(CHANNEL_SIZE);
         let (writer_tx, writer_rx): (Sender<SendType<ST>>, Receiver<SendType<ST>>) =
             crossbeam_channel::unbounded();
         let other_end_connected = Arc::new(AtomicBool::new(false));
+        let stats = Arc::new(TransportStats::new(other_end_connected.clone()));
 
         {
             let other_end_connected = other_end_connected.clone();
-            thread::spawn(move || accept_loop(listener, reader_tx, writer_rx, other_end_connected));
+            let stats = stats.clone();
+            thread::spawn(move || {
+                serve_loop(
+                    connections,
+                    reader_tx,
+                    writer_rx,
+                    other_end_connected,
+                    stats,
+                    options,
+                )
+            });
         }
 
         let writer_tx = DiscardingSender {
@@ -642,27 +1263,99 @@ where
             actually_send: other_end_connected.clone(),
         };
 
-        Ok(Self {
+        Self {
             read_handle: Some(reader_rx),
             write_handle: writer_tx,
             other_end_connected,
-        })
+            stats,
+            client_connection: None,
+        }
+    }
+
+    /// A connected serializer without a transport, for testing the code which
+    /// sends objects. The receiver gets everything which is sent.
+    #[cfg(test)]
+    pub(crate) fn new_for_test() -> (Self, Receiver<SendType<ST>>) {
+        let (_, reader_rx): (channel::SyncSender<RecvType<RT>>, Channel<RecvType<RT>>) =
+            channel::sync_channel(CHANNEL_SIZE);
+        let (writer_tx, writer_rx): (Sender<SendType<ST>>, Receiver<SendType<ST>>) =
+            crossbeam_channel::unbounded();
+        let other_end_connected = Arc::new(AtomicBool::new(true));
+        let stats = Arc::new(TransportStats::new(other_end_connected.clone()));
+
+        let writer_tx = DiscardingSender {
+            sender: writer_tx,
+            actually_send: other_end_connected.clone(),
+        };
+
+        let serializer = Self {
+            read_handle: Some(reader_rx),
+            write_handle: writer_tx,
+            other_end_connected,
+            stats,
+            client_connection: None,
+        };
+        (serializer, writer_rx)
     }
 
     pub fn new_client<P: AsRef<Path>>(sock_path: P) -> Result<Self> {
-        let stream = UnixStream::connect(sock_path).location(loc!())?;
-        enlarge_socket_buffer(&stream);
+        Self::new_client_with_options(sock_path, SerializerOptions::default(), None)
+    }
+
+    /// auth_secret is used if the server requires authentication.
+    pub fn new_client_with_options<P: AsRef<Path>>(
+        sock_path: P,
+        options: SerializerOptions,
+        auth_secret: Option<AuthSecret>,
+    ) -> Result<Self> {
+        Self::new_client_for_backend(sock_path, DEFAULT_BACKEND, options, auth_secret)
+    }
+
+    /// Connects to the named backend of a server with several, see router.
+    pub fn new_client_for_backend<P: AsRef<Path>>(
+        sock_path: P,
+        backend: &str,
+        options: SerializerOptions,
+        auth_secret: Option<AuthSecret>,
+    ) -> Result<Self> {
+        let sock_path = sock_path.as_ref().to_path_buf();
+        let stream =
+            connect(&sock_path, backend, &options, auth_secret.as_ref()).location(loc!())?;
+        let current_stream = Arc::new(Mutex::new(stream.try_clone().location(loc!())?));
 
         let (reader_tx, reader_rx): (channel::SyncSender<RecvType<RT>>, Channel<RecvType<RT>>) =
             channel::sync_channel(CHANNEL_SIZE);
         let (writer_tx, writer_rx): (Sender<SendType<ST>>, Receiver<SendType<ST>>) =
             crossbeam_channel::unbounded();
         let other_end_connected = Arc::new(AtomicBool::new(true));
+        let stats = Arc::new(TransportStats::new(other_end_connected.clone()));
+        let keep_reconnecting = Arc::new(AtomicBool::new(false));
+        let shutting_down = Arc::new(AtomicBool::new(false));
 
-        {
+        let thread = {
+            let backend = backend.to_string();
+            let current_stream = current_stream.clone();
             let other_end_connected = other_end_connected.clone();
-            thread::spawn(move || client_loop(stream, reader_tx, writer_rx, other_end_connected));
-        }
+            let stats = stats.clone();
+            let keep_reconnecting = keep_reconnecting.clone();
+            let shutting_down = shutting_down.clone();
+            thread::spawn(move || {
+                client_loop(
+                    sock_path,
+                    backend,
+                    stream,
+                    current_stream,
+                    reader_tx,
+                    writer_rx,
+                    other_end_connected,
+                    stats,
+                    options,
+                    auth_secret,
+                    keep_reconnecting,
+                    shutting_down,
+                )
+            })
+        };
 
         let writer_tx = DiscardingSender {
             sender: writer_tx,
@@ -673,6 +1366,13 @@ where
             read_handle: Some(reader_rx),
             write_handle: writer_tx,
             other_end_connected,
+            stats,
+            client_connection: Some(ClientConnection {
+                stream: current_stream,
+                thread,
+                keep_reconnecting,
+                shutting_down,
+            }),
         })
     }
 
@@ -697,4 +1397,550 @@ where
     pub fn set_other_end_connected(&mut self, state: bool) {
         self.other_end_connected.store(state, Ordering::Relaxed);
     }
+
+    pub fn stats(&self) -> Arc<TransportStats> {
+        self.stats.clone()
+    }
+
+    /// Whether a client keeps trying to reconnect to the server after
+    /// options.max_reconnect_attempts run out, rather than exiting. Does
+    /// nothing for servers.
+    pub fn set_keep_reconnecting(&self, keep_reconnecting: bool) {
+        if let Some(connection) = &self.client_connection {
+            connection
+                .keep_reconnecting
+                .store(keep_reconnecting, Ordering::Release);
+        }
+    }
+
+    /// Disconnects from the server and joins the serializer's threads. Without
+    /// this, the client's serializer threads run until the process exits (and
+    /// a server disconnection which isn't followed by a successful reconnection
+    /// exits the process). Does nothing for servers.
+    pub fn shutdown(&mut self) -> Result<()> {
+        let Some(connection) = self.client_connection.take() else {
+            return Ok(());
+        };
+        connection.shutting_down.store(true, Ordering::Release);
+        // Unblocks the read thread, which is waiting for data from the server.
+        connection
+            .stream
+            .lock()
+            .unwrap()
+            .shutdown(Shutdown::Both)
+            .location(loc!())?;
+        match connection.thread.join() {
+            Ok(result) => result,
+            Err(e) => panic::resume_unwind(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_compression_level() {
+        let options: SerializerOptions = ron::from_str("(compression_level: 9)").unwrap();
+        assert_eq!(
+            options.migrate_compression_level(),
+            SerializerOptions {
+                compression: Some(CompressionConfig {
+                    algorithm: CompressionAlgorithm::Zstd,
+                    level: 9,
+                }),
+                ..Default::default()
+            }
+        );
+
+        let options: SerializerOptions =
+            ron::from_str("(compression: (algorithm: None, level: 0), compression_level: 9)")
+                .unwrap();
+        assert_eq!(
+            options.migrate_compression_level().compression(),
+            CompressionConfig {
+                algorithm: CompressionAlgorithm::None,
+                level: 0,
+            }
+        );
+
+        // Setting compression to the default still takes precedence.
+        let options: SerializerOptions =
+            ron::from_str("(compression: (algorithm: Zstd, level: 1), compression_level: 9)")
+                .unwrap();
+        assert_eq!(
+            options.migrate_compression_level().compression(),
+            CompressionConfig::default()
+        );
+
+        let options = SerializerOptions::default();
+        assert_eq!(options.migrate_compression_level(), options);
+    }
+
+    #[test]
+    fn test_read_exact_or_eof() {
+        let mut buf = [0; 4];
+
+        let mut stream: &[u8] = &[1, 2, 3, 4, 5];
+        assert!(read_exact_or_eof(&mut stream, &mut buf).unwrap());
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        let mut stream: &[u8] = &[];
+        assert!(!read_exact_or_eof(&mut stream, &mut buf).unwrap());
+
+        let mut stream: &[u8] = &[1, 2];
+        assert!(read_exact_or_eof(&mut stream, &mut buf).is_err());
+    }
+
+    #[derive(Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
+    #[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+    enum OldMessage {
+        A(u32),
+        B(String),
+    }
+
+    #[derive(Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
+    #[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+    enum NewMessage {
+        A(u32),
+        B(String),
+        C(u32),
+    }
+
+    #[test]
+    fn test_deserialize_object_unknown_variant() {
+        let known = rkyv::to_bytes::<_, SERIALIZE_SCRATCH_SPACE>(&NewMessage::A(7)).unwrap();
+        let unknown = rkyv::to_bytes::<_, SERIALIZE_SCRATCH_SPACE>(&NewMessage::C(7)).unwrap();
+
+        assert_eq!(
+            deserialize_object::<OldMessage>(&known, true).unwrap(),
+            Some(OldMessage::A(7))
+        );
+        assert_eq!(
+            deserialize_object::<OldMessage>(&unknown, true).unwrap(),
+            None
+        );
+        assert!(deserialize_object::<OldMessage>(&unknown, false).is_err());
+    }
+
+    #[test]
+    fn test_reconnect_delay() {
+        assert_eq!(reconnect_delay(0), Duration::from_millis(100));
+        assert_eq!(reconnect_delay(3), Duration::from_millis(800));
+        assert_eq!(reconnect_delay(100), MAX_RECONNECT_DELAY);
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let sock_path = std::env::temp_dir().join(format!("wprs-test-reconnect-{}", process::id()));
+        let options = SerializerOptions {
+            socket_buffer_size: Some(4096),
+            max_reconnect_attempts: 2,
+            ..Default::default()
+        };
+        let keep_trying = AtomicBool::new(false);
+        let shutting_down = AtomicBool::new(false);
+        assert!(reconnect(
+            &sock_path,
+            DEFAULT_BACKEND,
+            &options,
+            None,
+            &keep_trying,
+            &shutting_down
+        )
+        .is_none());
+
+        let router = Router::new(&sock_path, options, None).unwrap();
+        let _server = router
+            .serializer::<OldMessage, OldMessage>(DEFAULT_BACKEND, options)
+            .unwrap();
+        let stream = reconnect(
+            &sock_path,
+            DEFAULT_BACKEND,
+            &options,
+            None,
+            &keep_trying,
+            &shutting_down,
+        );
+        assert!(stream.is_some());
+
+        // Attempts don't run out while keep_trying is set.
+        let no_attempts = SerializerOptions {
+            max_reconnect_attempts: 0,
+            ..options
+        };
+        keep_trying.store(true, Ordering::Release);
+        let stream = reconnect(
+            &sock_path,
+            DEFAULT_BACKEND,
+            &no_attempts,
+            None,
+            &keep_trying,
+            &shutting_down,
+        );
+        std::fs::remove_file(&sock_path).unwrap();
+        assert!(stream.is_some());
+
+        shutting_down.store(true, Ordering::Release);
+        assert!(reconnect(
+            &sock_path,
+            DEFAULT_BACKEND,
+            &options,
+            None,
+            &keep_trying,
+            &shutting_down
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_router() {
+        let sock_path = std::env::temp_dir().join(format!("wprs-test-router-{}", process::id()));
+        let options = SerializerOptions {
+            socket_buffer_size: Some(4096),
+            ..Default::default()
+        };
+        let router = Router::new(&sock_path, options, None).unwrap();
+        let mock = router
+            .serializer::<OldMessage, OldMessage>("mock", options)
+            .unwrap();
+        let desktop = router
+            .serializer::<OldMessage, OldMessage>("desktop", options)
+            .unwrap();
+        assert!(router
+            .serializer::<OldMessage, OldMessage>("mock", options)
+            .is_err());
+
+        let mut client = Serializer::<OldMessage, OldMessage>::new_client_for_backend(
+            &sock_path, "mock", options, None,
+        )
+        .unwrap();
+        let err = Serializer::<OldMessage, OldMessage>::new_client_for_backend(
+            &sock_path, "other", options, None,
+        )
+        .err()
+        .unwrap();
+        assert!(format!("{err:?}").contains("no backend"));
+        std::fs::remove_file(&sock_path).unwrap();
+
+        client.writer().send(SendType::Object(OldMessage::A(1)));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while mock.stats().frames_received() == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(mock.stats().frames_received(), 1);
+        assert_eq!(desktop.stats().frames_received(), 0);
+        client.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_registered_backend() {
+        let sock_path = std::env::temp_dir().join(format!("wprs-test-register-{}", process::id()));
+        let options = SerializerOptions {
+            socket_buffer_size: Some(4096),
+            ..Default::default()
+        };
+        let desktop = Serializer::<OldMessage, OldMessage>::new_server_for_backend(
+            &sock_path, "desktop", options, None,
+        )
+        .unwrap();
+        // The socket is in use, so these register with desktop's router.
+        let mock = Serializer::<OldMessage, OldMessage>::new_server_for_backend(
+            &sock_path, "mock", options, None,
+        )
+        .unwrap();
+        assert!(
+            Serializer::<OldMessage, OldMessage>::new_server_for_backend(
+                &sock_path, "desktop", options, None,
+            )
+            .is_err()
+        );
+
+        let mut client = Serializer::<OldMessage, OldMessage>::new_client_for_backend(
+            &sock_path, "mock", options, None,
+        )
+        .unwrap();
+        std::fs::remove_file(&sock_path).unwrap();
+
+        client.writer().send(SendType::Object(OldMessage::A(1)));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while mock.stats().frames_received() == 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(mock.stats().frames_received(), 1);
+        assert_eq!(desktop.stats().frames_received(), 0);
+        client.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_client_with_wrong_secret_is_rejected() {
+        let sock_path = std::env::temp_dir().join(format!("wprs-test-auth-{}", process::id()));
+        let options = SerializerOptions {
+            socket_buffer_size: Some(4096),
+            ..Default::default()
+        };
+        let server = Serializer::<OldMessage, OldMessage>::new_server_with_options(
+            &sock_path,
+            options,
+            Some(AuthSecret::new(b"secret")),
+        )
+        .unwrap();
+
+        let err = Serializer::<OldMessage, OldMessage>::new_client_with_options(
+            &sock_path,
+            options,
+            Some(AuthSecret::new(b"wrong")),
+        )
+        .err()
+        .unwrap();
+        assert!(format!("{err:?}").contains("rejected"));
+        assert!(
+            Serializer::<OldMessage, OldMessage>::new_client_with_options(
+                &sock_path, options, None
+            )
+            .is_err()
+        );
+        std::fs::remove_file(&sock_path).unwrap();
+        assert_eq!(server.stats().frames_received(), 0);
+    }
+
+    #[test]
+    fn test_check_object_size() {
+        assert!(check_object_size(0, 16).is_ok());
+        assert!(check_object_size(16, 16).is_ok());
+        assert!(check_object_size(17, 16).is_err());
+    }
+
+    #[test]
+    fn test_read_loop_rejects_oversized_object() {
+        let mut stream = Vec::new();
+        write_compression_algorithm(&mut stream, CompressionAlgorithm::Zstd).unwrap();
+        write_usize_as_u32_be(&mut stream, 1).unwrap();
+        write_usize_as_u32_be(&mut stream, 1024).unwrap();
+        stream.extend_from_slice(&u32::from(MessageType::Object).to_be_bytes());
+
+        let (tx, _rx) = channel::sync_channel::<RecvType<OldMessage>>(1);
+        let stats = Arc::new(TransportStats::new(Arc::new(AtomicBool::new(true))));
+        let options = SerializerOptions {
+            max_object_size: 512,
+            ..Default::default()
+        };
+        let err = read_loop(stream.as_slice(), tx, stats, options).unwrap_err();
+        assert!(format!("{err:?}").contains("exceeds max_object_size"));
+    }
+
+    #[test]
+    fn test_read_loop_rejects_unknown_compression() {
+        let mut stream = Vec::new();
+        stream.extend_from_slice(&1000_u32.to_be_bytes());
+
+        let (tx, _rx) = channel::sync_channel::<RecvType<OldMessage>>(1);
+        let stats = Arc::new(TransportStats::new(Arc::new(AtomicBool::new(true))));
+        let err =
+            read_loop(stream.as_slice(), tx, stats, SerializerOptions::default()).unwrap_err();
+        assert!(format!("{err:?}").contains("unknown compression algorithm"));
+    }
+
+    #[test]
+    fn test_read_loop_rejects_decompression_bomb() {
+        // A raw buffer claiming to decompress to 2GiB, with a single tiny
+        // compressed shard.
+        let mut stream = Vec::new();
+        write_compression_algorithm(&mut stream, CompressionAlgorithm::Zstd).unwrap();
+        write_usize_as_u32_be(&mut stream, 1).unwrap();
+        write_usize_as_u32_be(&mut stream, 1 << 31).unwrap();
+        stream.extend_from_slice(&u32::from(MessageType::RawBuffer).to_be_bytes());
+        CompressedShard {
+            idx: 0,
+            compression: 1,
+            data: zstd::bulk::compress(&[0; 1024], 1).unwrap(),
+        }
+        .framed_write(&mut stream)
+        .unwrap();
+
+        let (tx, _rx) = channel::sync_channel::<RecvType<OldMessage>>(1);
+        let stats = Arc::new(TransportStats::new(Arc::new(AtomicBool::new(true))));
+        let err =
+            read_loop(stream.as_slice(), tx, stats, SerializerOptions::default()).unwrap_err();
+        assert!(format!("{err:?}").contains("exceeds max_raw_buffer_size"));
+    }
+
+    /// Counts the writes which reach the underlying stream, which for a socket
+    /// are syscalls.
+    #[derive(Default)]
+    struct CountingWriter {
+        writes: usize,
+    }
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_loop_coalesces_queued_messages() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        for i in 0..100 {
+            tx.send(SendType::Object(OldMessage::A(i))).unwrap();
+        }
+        drop(tx);
+
+        let mut writer = CountingWriter::default();
+        let connected = Arc::new(AtomicBool::new(true));
+        let stats = Arc::new(TransportStats::new(connected.clone()));
+        let options = SerializerOptions {
+            socket_buffer_size: Some(1024 * 1024),
+            ..Default::default()
+        };
+        write_loop(&mut writer, rx, connected, stats, options).unwrap();
+
+        // One write for the compression algorithm and one for all of the
+        // messages, instead of one per message.
+        assert_eq!(writer.writes, 2);
+    }
+
+    #[test]
+    fn test_selection_contents_are_forwarded() {
+        let event = Event::Data(wayland::DataEvent::SelectionContents(
+            wayland::DataSource::Selection,
+            "text/plain".to_string(),
+            wayland::DataToTransfer(b"copied on the client".to_vec()),
+        ));
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx.send(SendType::Object(event.clone())).unwrap();
+        drop(tx);
+
+        let mut stream = Vec::new();
+        let connected = Arc::new(AtomicBool::new(true));
+        let stats = Arc::new(TransportStats::new(connected.clone()));
+        write_loop(
+            &mut stream,
+            rx,
+            connected,
+            stats.clone(),
+            SerializerOptions::default(),
+        )
+        .unwrap();
+
+        let (tx, rx) = channel::sync_channel::<RecvType<Event>>(1);
+        read_loop(stream.as_slice(), tx, stats, SerializerOptions::default()).unwrap();
+        match rx.recv() {
+            Ok(RecvType::Object(received)) => assert_eq!(received, event),
+            _ => panic!("the selection contents weren't received"),
+        }
+    }
+
+    #[test]
+    fn test_write_loop_records_surface_stats() {
+        let (tx, rx) = crossbeam_channel::unbounded::<SendType<OldMessage>>();
+        for _ in 0..3 {
+            tx.send(SendType::RawBuffer {
+                surface: wayland::WlSurfaceId(7),
+                data: Arc::new(vec![0u8; 4096]),
+                compress: true,
+            })
+            .unwrap();
+        }
+        tx.send(SendType::Object(OldMessage::A(0))).unwrap();
+        drop(tx);
+
+        let connected = Arc::new(AtomicBool::new(true));
+        let stats = Arc::new(TransportStats::new(connected.clone()));
+        stats.add_surface(wayland::WlSurfaceId(7));
+        let options = SerializerOptions {
+            socket_buffer_size: Some(1024 * 1024),
+            ..Default::default()
+        };
+        write_loop(io::sink(), rx, connected, stats.clone(), options).unwrap();
+
+        let surface_stats = stats.surface_stats();
+        assert_eq!(surface_stats.len(), 1);
+        assert_eq!(surface_stats[0].surface, 7);
+        assert_eq!(surface_stats[0].frames_sent, 3);
+        assert_eq!(surface_stats[0].bytes_sent_uncompressed, 3 * 4096);
+        assert!(surface_stats[0].bytes_sent > 0);
+        assert_eq!(stats.frames_sent(), 4);
+    }
+
+    #[test]
+    fn test_write_loop_skips_compression() {
+        let (tx, rx) = crossbeam_channel::unbounded::<SendType<OldMessage>>();
+        for (surface, compress) in [(1, true), (2, false)] {
+            tx.send(SendType::RawBuffer {
+                surface: wayland::WlSurfaceId(surface),
+                data: Arc::new(vec![0u8; 64 * 1024]),
+                compress,
+            })
+            .unwrap();
+        }
+        drop(tx);
+
+        let connected = Arc::new(AtomicBool::new(true));
+        let stats = Arc::new(TransportStats::new(connected.clone()));
+        stats.add_surface(wayland::WlSurfaceId(1));
+        stats.add_surface(wayland::WlSurfaceId(2));
+        let options = SerializerOptions {
+            socket_buffer_size: Some(1024 * 1024),
+            ..Default::default()
+        };
+        write_loop(io::sink(), rx, connected, stats.clone(), options).unwrap();
+
+        let bytes_sent = |surface| {
+            stats
+                .surface_stats()
+                .into_iter()
+                .find(|snapshot| snapshot.surface == surface)
+                .unwrap()
+                .bytes_sent
+        };
+        assert!(bytes_sent(1) < 64 * 1024);
+        assert_eq!(bytes_sent(2), 64 * 1024);
+    }
+
+    #[test]
+    fn test_read_loop_skip_unknown_objects() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        tx.send(SendType::Object(NewMessage::C(7))).unwrap();
+        tx.send(SendType::Object(NewMessage::A(8))).unwrap();
+        drop(tx);
+
+        let mut stream = Vec::new();
+        let connected = Arc::new(AtomicBool::new(true));
+        let stats = Arc::new(TransportStats::new(connected.clone()));
+        write_loop(
+            &mut stream,
+            rx,
+            connected,
+            stats.clone(),
+            SerializerOptions::default(),
+        )
+        .unwrap();
+
+        let (tx, _rx) = channel::sync_channel::<RecvType<OldMessage>>(2);
+        assert!(read_loop(
+            stream.as_slice(),
+            tx,
+            stats.clone(),
+            SerializerOptions::default()
+        )
+        .is_err());
+
+        let (tx, rx) = channel::sync_channel::<RecvType<OldMessage>>(2);
+        let options = SerializerOptions {
+            skip_unknown_objects: true,
+            ..Default::default()
+        };
+        read_loop(stream.as_slice(), tx, stats, options).unwrap();
+        match rx.try_iter().collect::<Vec<_>>().as_slice() {
+            [RecvType::Object(OldMessage::A(8))] => {},
+            received => panic!("unexpected objects received: {received:?}"),
+        }
+    }
 }
@@ -0,0 +1,508 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Serving several server-side serializers, e.g. one per desktop, from a single
+//! socket.
+//!
+//! After the version exchange and authentication, a client names the backend it wants to talk to and
+//! the router hands the connection over to that backend's serializer. A plain
+//! server is a router with a single backend, named DEFAULT_BACKEND.
+//!
+//! Backends in other processes register with the router over the same socket,
+//! and the router passes their clients' connections to them as file
+//! descriptors. Such backends stop getting new clients when the router's
+//! process exits.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::IoSlice;
+use std::io::IoSliceMut;
+use std::io::Read;
+use std::io::Write;
+use std::net::Shutdown;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::RawFd;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel::Receiver;
+use crossbeam_channel::Sender;
+use nix::sys::socket;
+use nix::sys::socket::ControlMessage;
+use nix::sys::socket::ControlMessageOwned;
+use nix::sys::socket::MsgFlags;
+use nix::sys::socket::UnixAddr;
+use rkyv::bytecheck;
+use rkyv::de::deserializers::SharedDeserializeMap;
+use rkyv::validation::validators::DefaultValidator;
+use rkyv::Deserialize;
+
+use crate::prelude::*;
+use crate::serialization::auth;
+use crate::serialization::auth::AuthSecret;
+use crate::serialization::enlarge_socket_buffer;
+use crate::serialization::Serializable;
+use crate::serialization::Serializer;
+use crate::serialization::SerializerOptions;
+use crate::serialization::Version;
+use crate::utils;
+
+/// The backend of clients which don't ask for a specific one.
+pub const DEFAULT_BACKEND: &str = "";
+
+const MAX_BACKEND_NAME_LEN: usize = 254;
+
+/// Sent instead of a name's length by backends which register with the router.
+const REGISTER_BACKEND: u8 = u8::MAX;
+
+const BACKEND_UNKNOWN: u8 = 0;
+const BACKEND_FOUND: u8 = 1;
+
+/// Connections which are still in the handshake get a thread each, so a flood
+/// of connections which never complete it mustn't spawn threads without bound
+/// until they time out.
+const MAX_PENDING_HANDSHAKES: usize = 64;
+
+type Backends = Arc<Mutex<HashMap<String, Sender<UnixStream>>>>;
+
+#[derive(Debug)]
+pub struct Router {
+    backends: Backends,
+}
+
+impl Router {
+    /// Clients have to prove that they know auth_secret, if it's set, before
+    /// they can connect to any backend.
+    pub fn new<P: AsRef<Path>>(
+        sock_path: P,
+        options: SerializerOptions,
+        auth_secret: Option<AuthSecret>,
+    ) -> Result<Self> {
+        let listener = utils::bind_user_socket(sock_path).location(loc!())?;
+        enlarge_socket_buffer(&listener, &options);
+
+        let backends = Backends::default();
+        {
+            let backends = backends.clone();
+            thread::spawn(move || route_loop(listener, backends, options, auth_secret));
+        }
+        Ok(Self { backends })
+    }
+
+    /// Creates the serializer for the clients which ask for the backend name.
+    pub fn serializer<ST, RT>(
+        &self,
+        name: &str,
+        options: SerializerOptions,
+    ) -> Result<Serializer<ST, RT>>
+    where
+        ST: Serializable,
+        ST::Archived: Deserialize<ST, SharedDeserializeMap>
+            + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
+        RT: Serializable,
+        RT::Archived: Deserialize<RT, SharedDeserializeMap>
+            + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
+    {
+        check_backend_name(name).location(loc!())?;
+        let (connections_tx, connections_rx) = crossbeam_channel::unbounded();
+        match self.backends.lock().unwrap().entry(name.to_string()) {
+            Entry::Occupied(_) => bail!("backend {name:?} already exists"),
+            Entry::Vacant(entry) => entry.insert(connections_tx),
+        };
+        Ok(Serializer::from_connections(connections_rx, options))
+    }
+}
+
+/// Serves the named backend for the router which listens on stream's other
+/// end, in another process.
+pub(crate) fn register<ST, RT>(
+    mut stream: UnixStream,
+    name: &str,
+    options: SerializerOptions,
+    auth_secret: Option<&AuthSecret>,
+) -> Result<Serializer<ST, RT>>
+where
+    ST: Serializable,
+    ST::Archived:
+        Deserialize<ST, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
+    RT: Serializable,
+    RT::Archived:
+        Deserialize<RT, SharedDeserializeMap> + for<'a> bytecheck::CheckBytes<DefaultValidator<'a>>,
+{
+    check_backend_name(name).location(loc!())?;
+    auth::with_timeout(
+        &mut stream,
+        Duration::from_millis(options.auth_timeout_ms),
+        |stream| {
+            Version::exchange(stream).location(loc!())?;
+            auth::respond(stream, auth_secret).location(loc!())?;
+            request_registration(stream, name).location(loc!())
+        },
+    )
+    .location(loc!())?;
+
+    let (connections_tx, connections_rx) = crossbeam_channel::unbounded();
+    {
+        let name = name.to_string();
+        thread::spawn(move || {
+            receive_connections(&stream, &connections_tx).warn_and_ignore(loc!());
+            warn!("the router stopped serving backend {name:?}");
+        });
+    }
+    Ok(Serializer::from_connections(connections_rx, options))
+}
+
+fn check_backend_name(name: &str) -> Result<()> {
+    if name.len() > MAX_BACKEND_NAME_LEN {
+        bail!("backend name {name:?} is longer than {MAX_BACKEND_NAME_LEN} bytes");
+    }
+    Ok(())
+}
+
+/// Counts a connection as pending until the handshake is over, i.e. until it's
+/// dropped.
+struct PendingHandshake(Arc<AtomicUsize>);
+
+impl PendingHandshake {
+    /// Returns None if MAX_PENDING_HANDSHAKES are already pending.
+    fn start(pending: &Arc<AtomicUsize>) -> Option<Self> {
+        pending
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < MAX_PENDING_HANDSHAKES).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| Self(pending.clone()))
+    }
+}
+
+impl Drop for PendingHandshake {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+fn route_loop(
+    listener: UnixListener,
+    backends: Backends,
+    options: SerializerOptions,
+    auth_secret: Option<AuthSecret>,
+) {
+    let pending = Arc::new(AtomicUsize::new(0));
+    loop {
+        debug!("waiting for client connection");
+        let (stream, _) = listener.accept().unwrap();
+        let Some(handshake) = PendingHandshake::start(&pending) else {
+            warn!("rejecting wprs client: {MAX_PENDING_HANDSHAKES} handshakes are pending");
+            stream.shutdown(Shutdown::Both).warn_and_ignore(loc!());
+            continue;
+        };
+        // A client which is slow to complete the handshake mustn't hold up the
+        // others.
+        let backends = backends.clone();
+        let auth_secret = auth_secret.clone();
+        thread::spawn(move || route(stream, handshake, &backends, &options, auth_secret.as_ref()));
+    }
+}
+
+/// What the router does with a connection once the handshake is over.
+enum Route {
+    /// Hand it over to a backend.
+    Client(Sender<UnixStream>),
+    /// Pass the named backend's connections to it.
+    Backend(String, Sender<UnixStream>, Receiver<UnixStream>),
+}
+
+fn route(
+    mut stream: UnixStream,
+    handshake: PendingHandshake,
+    backends: &Backends,
+    options: &SerializerOptions,
+    auth_secret: Option<&AuthSecret>,
+) {
+    let route = auth::with_timeout(
+        &mut stream,
+        Duration::from_millis(options.auth_timeout_ms),
+        |stream| {
+            Version::exchange(stream).location(loc!())?;
+            auth::challenge(stream, auth_secret).location(loc!())?;
+            select_backend(stream, |request| match request {
+                BackendRequest::Connect(name) => backends
+                    .lock()
+                    .unwrap()
+                    .get(name)
+                    .cloned()
+                    .map(Route::Client),
+                BackendRequest::Register(name) => {
+                    match backends.lock().unwrap().entry(name.clone()) {
+                        Entry::Occupied(_) => None,
+                        Entry::Vacant(entry) => {
+                            let (connections_tx, connections_rx) = crossbeam_channel::unbounded();
+                            entry.insert(connections_tx.clone());
+                            Some(Route::Backend(name.clone(), connections_tx, connections_rx))
+                        },
+                    }
+                },
+            })
+            .location(loc!())
+        },
+    );
+    drop(handshake);
+    match route {
+        Ok(Route::Client(backend)) => {
+            // The backend's serializer was dropped, which disconnects the
+            // client.
+            backend.send(stream).warn_and_ignore(loc!());
+        },
+        Ok(Route::Backend(name, connections_tx, connections_rx)) => {
+            debug!("backend {name:?} registered");
+            serve_backend(stream, &name, connections_tx, connections_rx, backends);
+        },
+        Err(err) => {
+            warn!("rejecting wprs client: {err:?}");
+            stream.shutdown(Shutdown::Both).warn_and_ignore(loc!());
+        },
+    }
+}
+
+/// Passes the connections for a backend which registered over stream to it,
+/// until it goes away.
+fn serve_backend(
+    stream: UnixStream,
+    name: &str,
+    connections_tx: Sender<UnixStream>,
+    connections_rx: Receiver<UnixStream>,
+    backends: &Backends,
+) {
+    // The backend never writes to stream, so a read only returns once it
+    // closes the connection, or fails. Unregistering it then ends the loop
+    // below, as nothing else holds on to its sender for long.
+    let watcher = stream.try_clone().location(loc!()).map(|mut stream| {
+        let backends = backends.clone();
+        let name = name.to_string();
+        let connections_tx = connections_tx.clone();
+        thread::spawn(move || {
+            let _ = stream.read(&mut [0; 1]);
+            unregister(&backends, &name, &connections_tx);
+        })
+    });
+    if let Err(err) = watcher {
+        warn!("unregistering backend {name:?}: {err:?}");
+        unregister(backends, name, &connections_tx);
+        return;
+    }
+    drop(connections_tx);
+
+    for connection in connections_rx {
+        if let Err(err) = send_connection(&stream, connection) {
+            // The watcher unregisters the backend once it notices as well.
+            warn!("passing a connection to backend {name:?} failed: {err:?}");
+            break;
+        }
+    }
+}
+
+/// Removes the named backend, unless it has been registered again since
+/// connections_tx was its sender.
+fn unregister(backends: &Backends, name: &str, connections_tx: &Sender<UnixStream>) {
+    let mut backends = backends.lock().unwrap();
+    if backends
+        .get(name)
+        .is_some_and(|backend| backend.same_channel(connections_tx))
+    {
+        backends.remove(name);
+        debug!("backend {name:?} unregistered");
+    }
+}
+
+/// Sends connection's file descriptor over stream, along with a single byte.
+fn send_connection(stream: &UnixStream, connection: UnixStream) -> Result<()> {
+    let fds = [connection.as_raw_fd()];
+    socket::sendmsg::<UnixAddr>(
+        stream.as_raw_fd(),
+        &[IoSlice::new(&[0])],
+        &[ControlMessage::ScmRights(&fds)],
+        MsgFlags::empty(),
+        None,
+    )
+    .location(loc!())?;
+    Ok(())
+}
+
+/// Receives the connections sent by send_connection until stream is closed or
+/// connections_tx's receiver is dropped.
+fn receive_connections(stream: &UnixStream, connections_tx: &Sender<UnixStream>) -> Result<()> {
+    loop {
+        let mut buf = [0; 1];
+        let mut iov = [IoSliceMut::new(&mut buf)];
+        let mut cmsg_buf = nix::cmsg_space!(RawFd);
+        let msg = socket::recvmsg::<UnixAddr>(
+            stream.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg_buf),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )
+        .location(loc!())?;
+        if msg.bytes == 0 {
+            return Ok(());
+        }
+
+        let mut connections = Vec::new();
+        for cmsg in msg.cmsgs().location(loc!())? {
+            if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                for fd in fds {
+                    // SAFETY: the kernel installed fd in this process for us,
+                    // and nothing else refers to it.
+                    connections.push(unsafe { UnixStream::from_raw_fd(fd) });
+                }
+            }
+        }
+        for connection in connections {
+            if connections_tx.send(connection).is_err() {
+                // The backend's serializer was dropped.
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// What a peer asks for after the version exchange and authentication.
+pub(crate) enum BackendRequest {
+    /// A client wants to connect to the named backend.
+    Connect(String),
+    /// A backend in another process wants to serve the name.
+    Register(String),
+}
+
+/// The server's side of selecting a backend. accept looks up the backend the
+/// client asked for, or registers the backend.
+pub(crate) fn select_backend<T, F>(stream: &mut UnixStream, accept: F) -> Result<T>
+where
+    F: FnOnce(&BackendRequest) -> Option<T>,
+{
+    let request = match read_u8(stream).location(loc!())? {
+        REGISTER_BACKEND => BackendRequest::Register(read_name(stream).location(loc!())?),
+        len => BackendRequest::Connect(read_name_with_len(stream, len).location(loc!())?),
+    };
+
+    let result = accept(&request);
+    let status = if result.is_some() {
+        BACKEND_FOUND
+    } else {
+        BACKEND_UNKNOWN
+    };
+    stream.write_all(&[status]).location(loc!())?;
+    stream.flush().location(loc!())?;
+    result.with_context(loc!(), || match &request {
+        BackendRequest::Connect(name) => format!("client asked for unknown backend {name:?}"),
+        BackendRequest::Register(name) => format!("can't register backend {name:?}"),
+    })
+}
+
+/// The client's side of selecting a backend.
+pub(crate) fn request_backend(stream: &mut UnixStream, name: &str) -> Result<()> {
+    write_name(stream, name).location(loc!())?;
+    if read_u8(stream).location(loc!())? != BACKEND_FOUND {
+        bail!("the server has no backend named {name:?}");
+    }
+    Ok(())
+}
+
+/// The registering backend's side of selecting a backend.
+fn request_registration(stream: &mut UnixStream, name: &str) -> Result<()> {
+    stream.write_all(&[REGISTER_BACKEND]).location(loc!())?;
+    write_name(stream, name).location(loc!())?;
+    if read_u8(stream).location(loc!())? != BACKEND_FOUND {
+        bail!("the server already has a backend named {name:?}, or doesn't accept backends");
+    }
+    Ok(())
+}
+
+fn read_u8(stream: &mut UnixStream) -> Result<u8> {
+    let mut buf = [0; 1];
+    stream.read_exact(&mut buf).location(loc!())?;
+    Ok(buf[0])
+}
+
+fn read_name(stream: &mut UnixStream) -> Result<String> {
+    let len = read_u8(stream).location(loc!())?;
+    read_name_with_len(stream, len).location(loc!())
+}
+
+fn read_name_with_len(stream: &mut UnixStream, len: u8) -> Result<String> {
+    let mut name_buf = vec![0; len.into()];
+    stream.read_exact(&mut name_buf).location(loc!())?;
+    String::from_utf8(name_buf).location(loc!())
+}
+
+fn write_name(stream: &mut UnixStream, name: &str) -> Result<()> {
+    // Longer names' lengths would be read as REGISTER_BACKEND.
+    check_backend_name(name).location(loc!())?;
+    let len: u8 = name.len().try_into().location(loc!())?;
+    stream.write_all(&[len]).location(loc!())?;
+    stream.write_all(name.as_bytes()).location(loc!())?;
+    stream.flush().location(loc!())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_handshakes_are_bounded() {
+        let pending = Arc::new(AtomicUsize::new(0));
+        let mut handshakes: Vec<_> = (0..MAX_PENDING_HANDSHAKES)
+            .map(|_| PendingHandshake::start(&pending).unwrap())
+            .collect();
+        assert!(PendingHandshake::start(&pending).is_none());
+
+        handshakes.pop();
+        assert!(PendingHandshake::start(&pending).is_some());
+        drop(handshakes);
+        assert_eq!(pending.load(Ordering::Acquire), 0);
+    }
+
+    #[test]
+    fn test_select_backend() {
+        let (mut server, mut client) = UnixStream::pair().unwrap();
+        let registration = thread::spawn(move || request_registration(&mut client, "mock"));
+        let name = select_backend(&mut server, |request| match request {
+            BackendRequest::Register(name) => Some(name.clone()),
+            BackendRequest::Connect(_) => None,
+        })
+        .unwrap();
+        assert_eq!(name, "mock");
+        registration.join().unwrap().unwrap();
+
+        let (mut server, mut client) = UnixStream::pair().unwrap();
+        let connection = thread::spawn(move || request_backend(&mut client, "mock"));
+        assert!(select_backend(&mut server, |request| match request {
+            BackendRequest::Register(_) => Some(()),
+            BackendRequest::Connect(_) => None,
+        })
+        .is_err());
+        assert!(connection.join().unwrap().is_err());
+
+        let (_, mut client) = UnixStream::pair().unwrap();
+        assert!(request_backend(&mut client, &"a".repeat(MAX_BACKEND_NAME_LEN + 1)).is_err());
+    }
+}
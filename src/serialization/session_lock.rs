@@ -0,0 +1,67 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rkyv::bytecheck;
+use rkyv::Archive;
+use rkyv::Deserialize;
+use rkyv::Serialize;
+use smithay_client_toolkit::session_lock::SessionLockSurfaceConfigure as SctkSessionLockSurfaceConfigure;
+
+use crate::serialization::geometry::Size;
+use crate::serialization::wayland::WlSurfaceId;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub enum SessionLockRequest {
+    /// An application asked to lock the session. The lock isn't confirmed to
+    /// the application until the local session is locked.
+    Lock,
+    Unlock,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct SessionLockSurfaceState {
+    /// The id of the output the lock surface covers, as in OutputInfo.
+    pub output: u32,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct SessionLockSurfaceConfigure {
+    pub surface_id: WlSurfaceId,
+    pub size: Size<u32>,
+}
+
+impl SessionLockSurfaceConfigure {
+    pub fn from_smithay(
+        surface_id: &WlSurfaceId,
+        configure: SctkSessionLockSurfaceConfigure,
+    ) -> Self {
+        Self {
+            surface_id: *surface_id,
+            size: configure.new_size.into(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub enum SessionLockEvent {
+    /// The local session is locked.
+    Locked,
+    /// The local compositor refused the lock or ended it.
+    Finished,
+    Configure(SessionLockSurfaceConfigure),
+}
@@ -0,0 +1,278 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Graphics tablets (zwp_tablet_v2) of the client's compositor, forwarded to
+//! the server. Tablet pads aren't forwarded.
+
+use rkyv::bytecheck;
+use rkyv::Archive;
+use rkyv::Deserialize;
+use rkyv::Serialize;
+use smithay::backend::input::TabletToolCapabilities as SmithayTabletToolCapabilities;
+use smithay::backend::input::TabletToolDescriptor as SmithayTabletToolDescriptor;
+use smithay::backend::input::TabletToolType as SmithayTabletToolType;
+use smithay::backend::input::UsbId as SmithayUsbId;
+use smithay::wayland::tablet_manager::TabletDescriptor as SmithayTabletDescriptor;
+use smithay_client_toolkit::reexports::client::WEnum;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_tool_v2;
+
+use crate::serialization::geometry::Point;
+use crate::serialization::wayland::WlSurfaceId;
+
+/// The hash of the client's zwp_tablet_v2 object id.
+#[derive(Archive, Deserialize, Serialize, Debug, Copy, Clone, Hash, Eq, PartialEq)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct TabletId(pub u64);
+
+/// The hash of the client's zwp_tablet_tool_v2 object id.
+#[derive(Archive, Deserialize, Serialize, Debug, Copy, Clone, Hash, Eq, PartialEq)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct TabletToolId(pub u64);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct UsbId {
+    pub vendor: u32,
+    pub product: u32,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct Tablet {
+    pub id: TabletId,
+    pub name: String,
+    pub usb_id: Option<UsbId>,
+    pub path: Option<String>,
+}
+
+impl Tablet {
+    pub fn new(id: TabletId) -> Self {
+        Self {
+            id,
+            name: String::new(),
+            usb_id: None,
+            path: None,
+        }
+    }
+
+    pub fn smithay_descriptor(&self) -> SmithayTabletDescriptor {
+        SmithayTabletDescriptor {
+            name: self.name.clone(),
+            usb_id: self.usb_id.map(|usb_id| SmithayUsbId {
+                vendor: usb_id.vendor,
+                product: usb_id.product,
+            }),
+            syspath: self.path.clone().map(Into::into),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub enum TabletToolType {
+    Pen,
+    Eraser,
+    Brush,
+    Pencil,
+    Airbrush,
+    Finger,
+    Mouse,
+    Lens,
+}
+
+impl TabletToolType {
+    pub fn from_wayland(tool_type: WEnum<zwp_tablet_tool_v2::Type>) -> Option<Self> {
+        match tool_type {
+            WEnum::Value(zwp_tablet_tool_v2::Type::Pen) => Some(Self::Pen),
+            WEnum::Value(zwp_tablet_tool_v2::Type::Eraser) => Some(Self::Eraser),
+            WEnum::Value(zwp_tablet_tool_v2::Type::Brush) => Some(Self::Brush),
+            WEnum::Value(zwp_tablet_tool_v2::Type::Pencil) => Some(Self::Pencil),
+            WEnum::Value(zwp_tablet_tool_v2::Type::Airbrush) => Some(Self::Airbrush),
+            WEnum::Value(zwp_tablet_tool_v2::Type::Finger) => Some(Self::Finger),
+            WEnum::Value(zwp_tablet_tool_v2::Type::Mouse) => Some(Self::Mouse),
+            WEnum::Value(zwp_tablet_tool_v2::Type::Lens) => Some(Self::Lens),
+            _ => None,
+        }
+    }
+}
+
+impl From<TabletToolType> for SmithayTabletToolType {
+    fn from(tool_type: TabletToolType) -> Self {
+        match tool_type {
+            TabletToolType::Pen => Self::Pen,
+            TabletToolType::Eraser => Self::Eraser,
+            TabletToolType::Brush => Self::Brush,
+            TabletToolType::Pencil => Self::Pencil,
+            TabletToolType::Airbrush => Self::Airbrush,
+            // Smithay has no finger tools, they're the closest thing to a pen.
+            TabletToolType::Finger => Self::Pen,
+            TabletToolType::Mouse => Self::Mouse,
+            TabletToolType::Lens => Self::Lens,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct TabletToolCapabilities {
+    pub tilt: bool,
+    pub pressure: bool,
+    pub distance: bool,
+    pub rotation: bool,
+    pub slider: bool,
+    pub wheel: bool,
+}
+
+impl TabletToolCapabilities {
+    pub fn add(&mut self, capability: WEnum<zwp_tablet_tool_v2::Capability>) {
+        match capability {
+            WEnum::Value(zwp_tablet_tool_v2::Capability::Tilt) => self.tilt = true,
+            WEnum::Value(zwp_tablet_tool_v2::Capability::Pressure) => self.pressure = true,
+            WEnum::Value(zwp_tablet_tool_v2::Capability::Distance) => self.distance = true,
+            WEnum::Value(zwp_tablet_tool_v2::Capability::Rotation) => self.rotation = true,
+            WEnum::Value(zwp_tablet_tool_v2::Capability::Slider) => self.slider = true,
+            WEnum::Value(zwp_tablet_tool_v2::Capability::Wheel) => self.wheel = true,
+            _ => {},
+        }
+    }
+}
+
+impl From<TabletToolCapabilities> for SmithayTabletToolCapabilities {
+    fn from(capabilities: TabletToolCapabilities) -> Self {
+        let mut smithay_capabilities = Self::empty();
+        for (has, capability) in [
+            (capabilities.tilt, Self::TILT),
+            (capabilities.pressure, Self::PRESSURE),
+            (capabilities.distance, Self::DISTANCE),
+            (capabilities.rotation, Self::ROTATION),
+            (capabilities.slider, Self::SLIDER),
+            (capabilities.wheel, Self::WHEEL),
+        ] {
+            smithay_capabilities.set(capability, has);
+        }
+        smithay_capabilities
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct TabletTool {
+    pub id: TabletToolId,
+    pub tool_type: TabletToolType,
+    pub hardware_serial: u64,
+    pub hardware_id_wacom: u64,
+    pub capabilities: TabletToolCapabilities,
+}
+
+impl TabletTool {
+    pub fn new(id: TabletToolId) -> Self {
+        Self {
+            id,
+            tool_type: TabletToolType::Pen,
+            hardware_serial: 0,
+            hardware_id_wacom: 0,
+            capabilities: TabletToolCapabilities::default(),
+        }
+    }
+
+    pub fn smithay_descriptor(&self) -> SmithayTabletToolDescriptor {
+        SmithayTabletToolDescriptor {
+            tool_type: self.tool_type.into(),
+            hardware_serial: self.hardware_serial,
+            hardware_id_wacom: self.hardware_id_wacom,
+            capabilities: self.capabilities.into(),
+        }
+    }
+}
+
+/// Axis values are normalized: pressure and distance to [0, 1] and the slider
+/// to [-1, 1]. Tilt and rotation are in degrees.
+#[derive(Debug, Copy, Clone, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub enum TabletToolEvent {
+    ProximityIn {
+        serial: u32,
+        tablet: TabletId,
+        surface_id: WlSurfaceId,
+    },
+    ProximityOut,
+    Down {
+        serial: u32,
+    },
+    Up,
+    /// Relative to the surface the tool is in proximity of.
+    Motion(Point<f64>),
+    Pressure(f64),
+    Distance(f64),
+    Tilt(Point<f64>),
+    Rotation(f64),
+    Slider(f64),
+    Wheel {
+        degrees: f64,
+        clicks: i32,
+    },
+    Button {
+        serial: u32,
+        button: u32,
+        pressed: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub enum TabletEvent {
+    TabletAdded(Tablet),
+    TabletRemoved(TabletId),
+    ToolAdded(TabletTool),
+    ToolRemoved(TabletToolId),
+    /// The events of a tool between two zwp_tablet_tool_v2.frame events.
+    ToolFrame {
+        tool: TabletToolId,
+        events: Vec<TabletToolEvent>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tool_type_from_wayland() {
+        assert_eq!(
+            TabletToolType::from_wayland(WEnum::Value(zwp_tablet_tool_v2::Type::Eraser)),
+            Some(TabletToolType::Eraser)
+        );
+        assert_eq!(TabletToolType::from_wayland(WEnum::Unknown(7)), None);
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let mut capabilities = TabletToolCapabilities::default();
+        capabilities.add(WEnum::Value(zwp_tablet_tool_v2::Capability::Pressure));
+        capabilities.add(WEnum::Value(zwp_tablet_tool_v2::Capability::Tilt));
+        capabilities.add(WEnum::Unknown(100));
+        assert_eq!(
+            capabilities,
+            TabletToolCapabilities {
+                tilt: true,
+                pressure: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            SmithayTabletToolCapabilities::from(capabilities),
+            SmithayTabletToolCapabilities::TILT | SmithayTabletToolCapabilities::PRESSURE
+        );
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Text input (zwp_text_input_v3), so that the input method of the client's
+//! compositor can be used with applications on the server. The text input
+//! state of the application with keyboard focus is mirrored by a text input
+//! of the client, and the input method's events on the latter are replayed on
+//! the application's text input.
+
+use rkyv::bytecheck;
+use rkyv::Archive;
+use rkyv::Deserialize;
+use rkyv::Serialize;
+
+use crate::serialization::geometry::Rectangle;
+
+#[derive(Debug, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct SurroundingText {
+    pub text: String,
+    /// Byte offsets into text.
+    pub cursor: i32,
+    pub anchor: i32,
+}
+
+/// The double-buffered state of an enabled text input, as of its last commit.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct TextInputState {
+    pub surrounding_text: Option<SurroundingText>,
+    /// zwp_text_input_v3::ChangeCause.
+    pub text_change_cause: u32,
+    /// zwp_text_input_v3::ContentHint and ContentPurpose.
+    pub content_hint: u32,
+    pub content_purpose: u32,
+    /// Relative to the surface with keyboard focus.
+    pub cursor_rectangle: Option<Rectangle<i32>>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub enum TextInputRequest {
+    /// The application with keyboard focus committed its text input while it
+    /// was enabled.
+    Enabled(TextInputState),
+    /// The application with keyboard focus disabled its text input, or
+    /// keyboard focus moved away from it.
+    Disabled,
+}
+
+/// The events of the client's text input, see zwp_text_input_v3. The events
+/// before Done are only applied with Done.
+#[derive(Debug, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub enum TextInputEvent {
+    PreeditString {
+        text: Option<String>,
+        cursor_begin: i32,
+        cursor_end: i32,
+    },
+    CommitString(Option<String>),
+    DeleteSurroundingText {
+        before_length: u32,
+        after_length: u32,
+    },
+    Done,
+}
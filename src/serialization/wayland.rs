@@ -37,6 +37,7 @@ use smithay::wayland::compositor::RectangleKind as SmithayRectangleKind;
 use smithay::wayland::compositor::RegionAttributes;
 use smithay::wayland::selection::data_device::SourceMetadata as SmithaySourceMetadata;
 use smithay::wayland::shm::BufferData;
+use smithay::wayland::viewporter::ViewportCachedState;
 use smithay_client_toolkit::compositor::CompositorState;
 use smithay_client_toolkit::compositor::Region as SctkRegion;
 use smithay_client_toolkit::output::Mode as SctkMode;
@@ -61,6 +62,8 @@ use crate::serialization;
 use crate::serialization::geometry::Point;
 use crate::serialization::geometry::Rectangle;
 use crate::serialization::geometry::Size;
+use crate::serialization::session_lock;
+use crate::serialization::wlr_layer_shell;
 use crate::serialization::xdg_shell;
 use crate::serialization::ClientId;
 use crate::vec4u8::Vec4u8s;
@@ -124,6 +127,26 @@ pub struct BufferMetadata {
     pub format: BufferFormat,
 }
 
+impl BufferFormat {
+    /// Returns the format in supported_formats which a buffer in this format
+    /// should be converted to: itself if it's supported, otherwise the format
+    /// differing only in whether the alpha channel is used.
+    pub fn closest_supported(self, supported_formats: &[Self]) -> Result<Self> {
+        if supported_formats.contains(&self) {
+            return Ok(self);
+        }
+        let alternative = match self {
+            Self::Argb8888 => Self::Xrgb8888,
+            Self::Xrgb8888 => Self::Argb8888,
+        };
+        if supported_formats.contains(&alternative) {
+            Ok(alternative)
+        } else {
+            bail!("buffer format {self:?} can't be converted to any of {supported_formats:?}")
+        }
+    }
+}
+
 impl TryFrom<SmithayBufferFormat> for BufferFormat {
     type Error = Error;
     fn try_from(format: SmithayBufferFormat) -> Result<Self> {
@@ -188,6 +211,9 @@ impl BufferMetadata {
 pub struct Buffer {
     pub metadata: BufferMetadata,
     pub data: Arc<Vec4u8s>,
+    /// filtering::frame_checksum of the buffer before filtering, when wprsd
+    /// is configured to send them.
+    pub checksum: Option<u64>,
 }
 
 impl Buffer {
@@ -205,6 +231,7 @@ impl Buffer {
         Ok(Self {
             metadata,
             data: Arc::new(buf),
+            checksum: None,
         })
     }
 
@@ -231,6 +258,49 @@ impl Buffer {
         filtering::filter(data, self_data);
         Ok(())
     }
+
+    /// Fills in the data of a buffer received in a commit. The data is normally
+    /// sent in a separate RawBuffer message immediately before the commit, but
+    /// is still sent inline when surface state is resent on connection.
+    pub fn fill_data(&mut self, raw_buffer: Option<Arc<Vec4u8s>>) -> Result<()> {
+        if let Some(raw_buffer) = raw_buffer {
+            self.data = raw_buffer;
+        }
+
+        if self.data.is_empty() {
+            bail!("Received buffer commit with empty data. This can happen if wprsc reattaches between wprsd sending a buffer message and a commit message.");
+        }
+        if self.data.len() * 4 != self.metadata.len() {
+            bail!(
+                "Buffer data size {} doesn't match the size {} implied by its metadata {:?}.",
+                self.data.len() * 4,
+                self.metadata.len(),
+                self.metadata
+            );
+        }
+        Ok(())
+    }
+
+    /// Converts the buffer to one of supported_formats if its format isn't
+    /// already one of them.
+    pub fn convert_to_supported(&mut self, supported_formats: &[BufferFormat]) -> Result<()> {
+        let format = self
+            .metadata
+            .format
+            .closest_supported(supported_formats)
+            .location(loc!())?;
+        match (self.metadata.format, format) {
+            // Dropping the alpha channel is lossy, but the best we can do.
+            (BufferFormat::Argb8888, BufferFormat::Xrgb8888) => {},
+            // The alpha channel of xrgb buffers is undefined, make it opaque.
+            (BufferFormat::Xrgb8888, BufferFormat::Argb8888) => {
+                filtering::make_opaque_filtered(Arc::make_mut(&mut self.data));
+            },
+            _ => {},
+        }
+        self.metadata.format = format;
+        Ok(())
+    }
 }
 
 impl fmt::Debug for Buffer {
@@ -238,6 +308,7 @@ impl fmt::Debug for Buffer {
         f.debug_struct("Buffer")
             .field("metadata", &self.metadata)
             .field("data", &format_args!("Vec4u8s[{}]", &self.data.len()))
+            .field("checksum", &self.checksum)
             .finish()
     }
 }
@@ -354,11 +425,13 @@ impl From<SmithayModifiers> for ModifierState {
 #[derive(Debug, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
 #[archive_attr(derive(bytecheck::CheckBytes, Debug))]
 pub enum KeyboardEvent {
+    /// Only the raw keycodes of the held keys are sent. The server's keymap is
+    /// the client's keymap (see Keymap), so the server derives the same
+    /// keysyms from the keycodes that the client's compositor did.
     Enter {
         serial: u32,
         surface_id: WlSurfaceId,
         keycodes: Vec<u32>,
-        keysyms: Vec<u32>,
     },
     Leave {
         serial: u32,
@@ -445,6 +518,14 @@ pub enum PointerEventKind {
         vertical: AxisScroll,
         source: AxisSource,
     },
+    /// From zwp_relative_pointer_v1, in surface-local coordinates. The
+    /// position of the event is the last known position of the pointer.
+    RelativeMotion {
+        dx: f64,
+        dy: f64,
+        dx_unaccel: f64,
+        dy_unaccel: f64,
+    },
 }
 
 impl From<SctkPointerEventKind> for PointerEventKind {
@@ -495,6 +576,63 @@ impl PointerEvent {
     }
 }
 
+/// The client's input state, sent after it reconnects. Input events are lost
+/// while the client is disconnected, so the server may still think that keys
+/// or buttons are held which were released in the meantime.
+#[derive(Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct InputSnapshot {
+    pub keyboard_focus: Option<WlSurfaceId>,
+    /// Raw keycodes, as in KeyboardEvent::Enter.
+    pub pressed_keys: Vec<u32>,
+    /// The surface the pointer is over and its position on it.
+    pub pointer_focus: Option<(WlSurfaceId, Point<f64>)>,
+    pub pressed_buttons: Vec<u32>,
+}
+
+/// Whether the user of the client is idle, as determined by the client's
+/// compositor.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub enum IdleEvent {
+    Idle,
+    Active,
+}
+
+/// Touch points are identified by the id assigned by the client's compositor,
+/// which is unique among the touch points currently in contact with the
+/// surface. Positions are relative to the surface the touch point went down
+/// on.
+#[derive(Debug, Copy, Clone, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub enum TouchEvent {
+    Down {
+        id: i32,
+        serial: u32,
+        surface_id: WlSurfaceId,
+        position: Point<f64>,
+    },
+    Up {
+        id: i32,
+        serial: u32,
+    },
+    Motion {
+        id: i32,
+        position: Point<f64>,
+    },
+    Shape {
+        id: i32,
+        major: f64,
+        minor: f64,
+    },
+    Orientation {
+        id: i32,
+        orientation: f64,
+    },
+    /// All active touch points were cancelled.
+    Cancel,
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
 #[archive_attr(derive(bytecheck::CheckBytes, Debug))]
 pub struct SubSurfaceState {
@@ -520,6 +658,8 @@ pub enum Role {
     SubSurface(SubSurfaceState),
     XdgToplevel(xdg_shell::XdgToplevelState),
     XdgPopup(xdg_shell::XdgPopupState),
+    LayerSurface(wlr_layer_shell::LayerSurfaceState),
+    SessionLockSurface(session_lock::SessionLockSurfaceState),
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
@@ -584,6 +724,32 @@ impl Default for Region {
     }
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub enum PointerConstraintKind {
+    Lock,
+    Confine,
+}
+
+/// A zwp_pointer_constraints_v1 lock or confinement an application requested
+/// for a surface.
+#[derive(Debug, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct PointerConstraintState {
+    pub kind: PointerConstraintKind,
+    /// None means the whole surface.
+    pub region: Option<Region>,
+}
+
+impl PointerConstraintState {
+    pub fn new(kind: PointerConstraintKind, region: Option<&RegionAttributes>) -> Self {
+        Self {
+            kind,
+            region: region.map(Into::into),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
 #[archive_attr(derive(bytecheck::CheckBytes, Debug))]
 pub enum Transform {
@@ -680,6 +846,46 @@ impl From<Transform> for SmithayTransform {
     }
 }
 
+/// wp_viewport state of a surface. The source rectangle is stored as wl_fixed
+/// values (1/256ths of a surface coordinate), which is how it was originally
+/// sent, so that states can be compared exactly.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct ViewportState {
+    pub src: Option<Rectangle<i32>>,
+    pub dst: Option<Size<i32>>,
+}
+
+impl ViewportState {
+    pub fn src_f64(&self) -> Option<(f64, f64, f64, f64)> {
+        self.src.map(|src| {
+            (
+                f64::from(src.loc.x) / 256.0,
+                f64::from(src.loc.y) / 256.0,
+                f64::from(src.size.w) / 256.0,
+                f64::from(src.size.h) / 256.0,
+            )
+        })
+    }
+}
+
+impl From<&ViewportCachedState> for ViewportState {
+    fn from(viewport: &ViewportCachedState) -> Self {
+        let to_fixed = |v: f64| (v * 256.0).round() as i32;
+        Self {
+            src: viewport.src.map(|src| {
+                Rectangle::new(
+                    to_fixed(src.loc.x),
+                    to_fixed(src.loc.y),
+                    to_fixed(src.size.w),
+                    to_fixed(src.size.h),
+                )
+            }),
+            dst: viewport.dst.map(Into::into),
+        }
+    }
+}
+
 /// An entry for a vector of child surfaces. The (x, y) position is stored
 /// explicitly, the z position (stacking order) is stored implicitly based on
 /// the index of the item in the vector.
@@ -704,6 +910,14 @@ pub struct SurfaceState {
     pub input_region: Option<Region>,
     pub z_ordered_children: Vec<SubsurfacePosition>,
     pub damage: Option<Vec<Rectangle<i32>>>,
+    pub viewport: Option<ViewportState>,
+    pub pointer_constraint: Option<PointerConstraintState>,
+    /// The application asked for the compositor not to go idle, e.g. blank the
+    /// screen, while the surface is visible.
+    pub idle_inhibit: bool,
+    /// The application asked for the compositor's keyboard shortcuts to be
+    /// inhibited while the surface has keyboard focus.
+    pub keyboard_shortcuts_inhibit: bool,
     // server-side only
     pub output_ids: Vec<u32>,
 
@@ -726,27 +940,51 @@ impl SurfaceState {
             // client isolation.
             z_ordered_children: Vec::new(),
             damage: None,
+            viewport: None,
+            pointer_constraint: None,
+            idle_inhibit: false,
+            keyboard_shortcuts_inhibit: false,
             output_ids: Vec::new(),
             xdg_surface_state: None,
         })
     }
 
+    /// Also computes the frame checksum of data if checksum is true.
     #[instrument(skip(data), level = "debug")]
-    pub fn set_buffer(&mut self, metadata: &BufferData, data: BufferPointer<u8>) -> Result<()> {
+    pub fn set_buffer(
+        &mut self,
+        metadata: &BufferData,
+        data: BufferPointer<u8>,
+        checksum: bool,
+    ) -> Result<()> {
+        let checksum = checksum.then(|| filtering::frame_checksum(&data));
         match &mut self.buffer {
             // Only buffer data was updated, we can reuse the buffer.
             Some(BufferAssignment::New(buffer)) => {
                 buffer.update(metadata, data).location(loc!())?;
+                buffer.checksum = checksum;
             },
             Some(BufferAssignment::Removed) | None => {
-                self.buffer = Some(BufferAssignment::New(
-                    Buffer::new(metadata, data).location(loc!())?,
-                ));
+                let mut buffer = Buffer::new(metadata, data).location(loc!())?;
+                buffer.checksum = checksum;
+                self.buffer = Some(BufferAssignment::New(buffer));
             },
         }
         Ok(())
     }
 
+    pub fn convert_buffer_to_supported(
+        &mut self,
+        supported_formats: &[BufferFormat],
+    ) -> Result<()> {
+        if let Some(BufferAssignment::New(buffer)) = &mut self.buffer {
+            buffer
+                .convert_to_supported(supported_formats)
+                .location(loc!())?;
+        }
+        Ok(())
+    }
+
     #[instrument(skip_all, level = "debug")]
     pub fn clone_without_buffer(&self) -> Self {
         let mut clone = self.clone();
@@ -849,6 +1087,10 @@ pub struct OutputInfo {
     pub mode: Mode,
     pub name: Option<String>,
     pub description: Option<String>,
+    /// From zxdg_output_v1, if the client's compositor supports it. Unlike the
+    /// integer scale_factor, the logical size reflects fractional scaling.
+    pub logical_position: Option<Point<i32>>,
+    pub logical_size: Option<Size<i32>>,
 }
 
 impl From<SctkOutputInfo> for OutputInfo {
@@ -871,6 +1113,35 @@ impl From<SctkOutputInfo> for OutputInfo {
                 .into(),
             name: output.name.clone(),
             description: output.description.clone(),
+            logical_position: output.logical_position.map(Into::into),
+            logical_size: output.logical_size.map(Into::into),
+        }
+    }
+}
+
+#[cfg(test)]
+impl OutputInfo {
+    /// A 1920x1080 output at scale 1.
+    pub(crate) fn new_for_test(id: u32) -> Self {
+        Self {
+            id,
+            model: String::new(),
+            make: String::new(),
+            location: (0, 0).into(),
+            physical_size: (600, 340).into(),
+            subpixel: Subpixel::Unknown,
+            transform: Transform::Normal,
+            scale_factor: 1,
+            mode: Mode {
+                dimensions: (1920, 1080).into(),
+                refresh_rate: 60000,
+                current: true,
+                preferred: true,
+            },
+            name: None,
+            description: None,
+            logical_position: None,
+            logical_size: None,
         }
     }
 }
@@ -880,6 +1151,12 @@ impl From<SctkOutputInfo> for OutputInfo {
 pub enum SurfaceRequestPayload {
     Commit(SurfaceState),
     Destroyed,
+    // The application's xdg_activation_v1 token is only meaningful to wprsd,
+    // so wprsc requests a new token from its own compositor instead of
+    // forwarding it. user_input is whether the application's token was
+    // requested with the serial of recent input, in which case wprsc requests
+    // its token with the serial of its own.
+    Activate { user_input: bool },
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
@@ -1069,11 +1346,19 @@ pub enum DataEvent {
     // // E.g.: accept mime type, request data transfer.
     // DestinationRequest(DataDestinationRequest),
     TransferData(DataSource, DataToTransfer),
+    /// The contents of the current selection for one mime type, sent without
+    /// the server asking for them so that remote applications can paste
+    /// without a round trip to the client.
+    SelectionContents(DataSource, String, DataToTransfer),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Archive, Deserialize, Serialize)]
 #[archive_attr(derive(bytecheck::CheckBytes, Debug))]
 pub enum OutputEvent {
+    /// The complete set of outputs, sent once on connection so that
+    /// applications see all outputs at once rather than one at a time.
+    /// Outputs not in the set are removed.
+    Set(Vec<OutputInfo>),
     New(OutputInfo),
     Update(OutputInfo),
     Destroy(OutputInfo),
@@ -1089,6 +1374,71 @@ pub struct Output {
 #[archive_attr(derive(bytecheck::CheckBytes, Debug))]
 pub enum SurfaceEventPayload {
     OutputsChanged(Vec<Output>),
+    /// The local compositor sent a frame callback for the surface, i.e. it's
+    /// a good time for the application to draw a new frame.
+    FramePresented,
+    /// The scale the local compositor would like the surface to be drawn at,
+    /// from wp_fractional_scale_v1, in 120ths. See fractional_scale_to_f64.
+    PreferredFractionalScale(u32),
+    /// The local compositor activated (true) or deactivated (false) the
+    /// pointer constraint for the surface, see PointerConstraintState.
+    PointerConstraintActive(bool),
+    /// The local compositor's wp_presentation_feedback for the oldest commit
+    /// of the surface which carried a new buffer and hasn't had feedback yet.
+    PresentationFeedback(PresentationFeedback),
+    /// The local compositor activated (true) or deactivated (false) the
+    /// keyboard shortcuts inhibitor for the surface.
+    KeyboardShortcutsInhibitorActive(bool),
+    /// wprsc applied the oldest commit of the surface which carried a new
+    /// buffer and hasn't been acknowledged yet, see server::frame_budget.
+    FrameAck,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub enum PresentationFeedback {
+    /// The timestamp is in the clock domain of the local compositor, which
+    /// need not be comparable to the server's clocks.
+    Presented {
+        tv_sec: u64,
+        tv_nsec: u32,
+        /// 0 if the refresh rate is unknown.
+        refresh_ns: u32,
+        seq: u64,
+        /// wp_presentation_feedback.kind
+        flags: u32,
+    },
+    /// The content was never shown, or the local compositor doesn't support
+    /// wp_presentation.
+    Discarded,
+}
+
+impl PresentationFeedback {
+    /// From the arguments of wp_presentation_feedback.presented, which splits
+    /// the 64-bit values into 32-bit halves.
+    pub fn presented(
+        tv_sec_hi: u32,
+        tv_sec_lo: u32,
+        tv_nsec: u32,
+        refresh_ns: u32,
+        seq_hi: u32,
+        seq_lo: u32,
+        flags: u32,
+    ) -> Self {
+        Self::Presented {
+            tv_sec: (u64::from(tv_sec_hi) << 32) | u64::from(tv_sec_lo),
+            tv_nsec,
+            refresh_ns,
+            seq: (u64::from(seq_hi) << 32) | u64::from(seq_lo),
+            flags,
+        }
+    }
+}
+
+/// Converts a scale from wp_fractional_scale_v1, where it's the numerator of a
+/// fraction with a denominator of 120.
+pub fn fractional_scale_to_f64(scale: u32) -> f64 {
+    f64::from(scale) / 120.0
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Archive, Deserialize, Serialize)]
@@ -1097,3 +1447,193 @@ pub struct SurfaceEvent {
     pub surface_id: WlSurfaceId,
     pub payload: SurfaceEventPayload,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialization::SERIALIZE_SCRATCH_SPACE;
+
+    fn buffer_with_data(data: Vec4u8s) -> Buffer {
+        Buffer {
+            metadata: BufferMetadata {
+                width: 2,
+                height: 2,
+                stride: 8,
+                format: BufferFormat::Argb8888,
+            },
+            data: Arc::new(data),
+            checksum: None,
+        }
+    }
+
+    #[test]
+    fn test_fill_data_from_raw_buffer() {
+        let mut buffer = buffer_with_data(Vec4u8s::new());
+        let raw_buffer = Arc::new(Vec4u8s::with_total_size(16));
+        buffer.fill_data(Some(raw_buffer.clone())).unwrap();
+        assert!(Arc::ptr_eq(&buffer.data, &raw_buffer));
+    }
+
+    #[test]
+    fn test_fill_data_inline() {
+        let mut buffer = buffer_with_data(Vec4u8s::with_total_size(16));
+        buffer.fill_data(None).unwrap();
+        assert_eq!(buffer.data.len(), 4);
+    }
+
+    #[test]
+    fn test_fill_data_missing() {
+        let mut buffer = buffer_with_data(Vec4u8s::new());
+        assert!(buffer.fill_data(None).is_err());
+    }
+
+    #[test]
+    fn test_fill_data_wrong_size() {
+        let mut buffer = buffer_with_data(Vec4u8s::new());
+        assert!(buffer
+            .fill_data(Some(Arc::new(Vec4u8s::with_total_size(8))))
+            .is_err());
+    }
+
+    #[test]
+    fn test_viewport_state_fixed_point_roundtrip() {
+        let mut cached = ViewportCachedState::default();
+        cached.src = Some(smithay::utils::Rectangle::from_loc_and_size(
+            (0.5, 10.0),
+            (100.25, 50.0),
+        ));
+        cached.dst = Some((200, 100).into());
+
+        let viewport = ViewportState::from(&cached);
+        assert_eq!(viewport.src, Some(Rectangle::new(128, 2560, 25664, 12800)));
+        assert_eq!(viewport.dst, Some(Size { w: 200, h: 100 }));
+        assert_eq!(viewport.src_f64(), Some((0.5, 10.0, 100.25, 50.0)));
+    }
+
+    #[test]
+    fn test_viewport_state_unset() {
+        let viewport = ViewportState::from(&ViewportCachedState::default());
+        assert_eq!(viewport.src, None);
+        assert_eq!(viewport.dst, None);
+        assert_eq!(viewport.src_f64(), None);
+    }
+
+    #[test]
+    fn test_pointer_constraint_state_region() {
+        let mut region = RegionAttributes::default();
+        region.rects.push((
+            SmithayRectangleKind::Add,
+            smithay::utils::Rectangle::from_loc_and_size((10, 20), (30, 40)),
+        ));
+
+        let confined = PointerConstraintState::new(PointerConstraintKind::Confine, Some(&region));
+        assert_eq!(
+            confined.region,
+            Some(Region {
+                rects: vec![(RectangleKind::Add, Rectangle::new(10, 20, 30, 40)).into()],
+            })
+        );
+
+        let locked = PointerConstraintState::new(PointerConstraintKind::Lock, None);
+        assert_eq!(locked.region, None);
+        assert_ne!(confined, locked);
+    }
+
+    #[test]
+    fn test_region_round_trip() {
+        let mut region = RegionAttributes::default();
+        region.rects.push((
+            SmithayRectangleKind::Add,
+            smithay::utils::Rectangle::from_loc_and_size((0, 0), (640, 480)),
+        ));
+        region.rects.push((
+            SmithayRectangleKind::Subtract,
+            smithay::utils::Rectangle::from_loc_and_size((0, 0), (8, 8)),
+        ));
+
+        let region = Region::from(&region);
+        let bytes = rkyv::to_bytes::<_, SERIALIZE_SCRATCH_SPACE>(&region).unwrap();
+        let decoded: Region = rkyv::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            decoded.rects,
+            vec![
+                (RectangleKind::Add, Rectangle::new(0, 0, 640, 480)).into(),
+                (RectangleKind::Subtract, Rectangle::new(0, 0, 8, 8)).into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_idle_inhibit_round_trip() {
+        let surface_state = SurfaceState {
+            client: ClientId(1),
+            id: WlSurfaceId(2),
+            buffer: None,
+            role: None,
+            buffer_scale: 1,
+            buffer_transform: None,
+            opaque_region: None,
+            input_region: None,
+            z_ordered_children: Vec::new(),
+            damage: None,
+            viewport: None,
+            pointer_constraint: None,
+            idle_inhibit: true,
+            keyboard_shortcuts_inhibit: false,
+            output_ids: Vec::new(),
+            xdg_surface_state: None,
+        };
+        let bytes = rkyv::to_bytes::<_, SERIALIZE_SCRATCH_SPACE>(&surface_state).unwrap();
+        let decoded: SurfaceState = rkyv::from_bytes(&bytes).unwrap();
+        assert!(decoded.idle_inhibit);
+        assert_eq!(decoded, surface_state);
+    }
+
+    #[test]
+    fn test_keyboard_shortcuts_inhibit_round_trip() {
+        let surface_state = SurfaceState {
+            client: ClientId(1),
+            id: WlSurfaceId(2),
+            buffer: None,
+            role: None,
+            buffer_scale: 1,
+            buffer_transform: None,
+            opaque_region: None,
+            input_region: None,
+            z_ordered_children: Vec::new(),
+            damage: None,
+            viewport: None,
+            pointer_constraint: None,
+            idle_inhibit: false,
+            keyboard_shortcuts_inhibit: true,
+            output_ids: Vec::new(),
+            xdg_surface_state: None,
+        };
+        let bytes = rkyv::to_bytes::<_, SERIALIZE_SCRATCH_SPACE>(&surface_state).unwrap();
+        let decoded: SurfaceState = rkyv::from_bytes(&bytes).unwrap();
+        assert!(decoded.keyboard_shortcuts_inhibit);
+        assert_eq!(decoded, surface_state);
+    }
+
+    #[test]
+    fn test_presentation_feedback_presented() {
+        assert_eq!(
+            PresentationFeedback::presented(1, 2, 3, 16_666_666, 4, 5, 0x1),
+            PresentationFeedback::Presented {
+                tv_sec: (1 << 32) | 2,
+                tv_nsec: 3,
+                refresh_ns: 16_666_666,
+                seq: (4 << 32) | 5,
+                flags: 0x1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_fractional_scale_to_f64() {
+        assert_eq!(fractional_scale_to_f64(120), 1.0);
+        assert_eq!(fractional_scale_to_f64(150), 1.25);
+        assert_eq!(fractional_scale_to_f64(180), 1.5);
+        assert_eq!(fractional_scale_to_f64(240), 2.0);
+    }
+}
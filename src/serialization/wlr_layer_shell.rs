@@ -0,0 +1,285 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rkyv::bytecheck;
+use rkyv::Archive;
+use rkyv::Deserialize;
+use rkyv::Serialize;
+use smithay::reexports::wayland_server::Resource;
+use smithay::wayland::shell::wlr_layer::ExclusiveZone;
+use smithay::wayland::shell::wlr_layer::KeyboardInteractivity as SmithayKeyboardInteractivity;
+use smithay::wayland::shell::wlr_layer::Layer as SmithayLayer;
+use smithay::wayland::shell::wlr_layer::LayerSurface as SmithayLayerSurface;
+use smithay::wayland::shell::wlr_layer::LayerSurfaceCachedState;
+use smithay_client_toolkit::shell::wlr_layer::Anchor as SctkAnchor;
+use smithay_client_toolkit::shell::wlr_layer::KeyboardInteractivity as SctkKeyboardInteractivity;
+use smithay_client_toolkit::shell::wlr_layer::Layer as SctkLayer;
+use smithay_client_toolkit::shell::wlr_layer::LayerSurfaceConfigure as SctkLayerSurfaceConfigure;
+
+use crate::serialization;
+use crate::serialization::geometry::Size;
+use crate::serialization::wayland::WlSurfaceId;
+
+#[derive(Archive, Deserialize, Serialize, Debug, Copy, Clone, Hash, Eq, PartialEq)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct LayerSurfaceId(pub u64);
+
+impl LayerSurfaceId {
+    pub fn new(layer_surface: &SmithayLayerSurface) -> Self {
+        Self(serialization::hash(&layer_surface.layer_surface().id()))
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub enum Layer {
+    Background,
+    Bottom,
+    Top,
+    Overlay,
+}
+
+impl From<SmithayLayer> for Layer {
+    fn from(layer: SmithayLayer) -> Self {
+        match layer {
+            SmithayLayer::Background => Self::Background,
+            SmithayLayer::Bottom => Self::Bottom,
+            SmithayLayer::Top => Self::Top,
+            SmithayLayer::Overlay => Self::Overlay,
+        }
+    }
+}
+
+impl From<Layer> for SctkLayer {
+    fn from(layer: Layer) -> Self {
+        match layer {
+            Layer::Background => Self::Background,
+            Layer::Bottom => Self::Bottom,
+            Layer::Top => Self::Top,
+            Layer::Overlay => Self::Overlay,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub enum KeyboardInteractivity {
+    None,
+    Exclusive,
+    OnDemand,
+}
+
+impl From<SmithayKeyboardInteractivity> for KeyboardInteractivity {
+    fn from(keyboard_interactivity: SmithayKeyboardInteractivity) -> Self {
+        match keyboard_interactivity {
+            SmithayKeyboardInteractivity::None => Self::None,
+            SmithayKeyboardInteractivity::Exclusive => Self::Exclusive,
+            SmithayKeyboardInteractivity::OnDemand => Self::OnDemand,
+        }
+    }
+}
+
+impl From<KeyboardInteractivity> for SctkKeyboardInteractivity {
+    fn from(keyboard_interactivity: KeyboardInteractivity) -> Self {
+        match keyboard_interactivity {
+            KeyboardInteractivity::None => Self::None,
+            KeyboardInteractivity::Exclusive => Self::Exclusive,
+            KeyboardInteractivity::OnDemand => Self::OnDemand,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct Margins {
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    pub left: i32,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct LayerSurfaceState {
+    pub id: LayerSurfaceId,
+    pub namespace: String,
+    /// The id of the output the application asked for, as in OutputInfo, or
+    /// None to let the compositor choose.
+    pub output: Option<u32>,
+    pub layer: Layer,
+    /// zwlr_layer_surface_v1 anchor bits.
+    pub anchor: u32,
+    /// As in zwlr_layer_surface_v1.set_exclusive_zone: positive values are
+    /// the size of the exclusive zone, 0 means the surface should be moved to
+    /// avoid other surfaces' exclusive zones, and -1 means it shouldn't.
+    pub exclusive_zone: i32,
+    pub margin: Margins,
+    pub keyboard_interactivity: KeyboardInteractivity,
+    /// 0 in either dimension means the compositor should decide.
+    pub size: Size<u32>,
+}
+
+impl LayerSurfaceState {
+    pub fn new(
+        layer_surface: &SmithayLayerSurface,
+        output: Option<u32>,
+        layer: SmithayLayer,
+        namespace: String,
+    ) -> Self {
+        Self {
+            id: LayerSurfaceId::new(layer_surface),
+            namespace,
+            output,
+            layer: layer.into(),
+            anchor: 0,
+            exclusive_zone: 0,
+            margin: Margins::default(),
+            keyboard_interactivity: KeyboardInteractivity::None,
+            size: (0, 0).into(),
+        }
+    }
+
+    pub fn update(&mut self, cached_state: &LayerSurfaceCachedState) {
+        self.layer = cached_state.layer.into();
+        self.anchor = cached_state.anchor.bits();
+        self.exclusive_zone = match cached_state.exclusive_zone {
+            ExclusiveZone::Exclusive(size) => size as i32,
+            ExclusiveZone::Neutral => 0,
+            ExclusiveZone::DontCare => -1,
+        };
+        self.margin = Margins {
+            top: cached_state.margin.top,
+            right: cached_state.margin.right,
+            bottom: cached_state.margin.bottom,
+            left: cached_state.margin.left,
+        };
+        self.keyboard_interactivity = cached_state.keyboard_interactivity.into();
+        self.size = (
+            cached_state.size.w.max(0) as u32,
+            cached_state.size.h.max(0) as u32,
+        )
+            .into();
+    }
+
+    pub fn sctk_anchor(&self) -> SctkAnchor {
+        SctkAnchor::from_bits_truncate(self.anchor)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct LayerSurfaceConfigure {
+    pub surface_id: WlSurfaceId,
+    pub size: Size<u32>,
+}
+
+impl LayerSurfaceConfigure {
+    pub fn from_smithay(surface_id: &WlSurfaceId, configure: SctkLayerSurfaceConfigure) -> Self {
+        Self {
+            surface_id: *surface_id,
+            size: configure.new_size.into(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub enum LayerSurfaceEvent {
+    Configure(LayerSurfaceConfigure),
+    /// The local compositor closed the layer surface, e.g. because its output
+    /// went away.
+    Closed(WlSurfaceId),
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay::utils::Size as SmithaySize;
+    use smithay::wayland::shell::wlr_layer::Anchor as SmithayAnchor;
+    use smithay::wayland::shell::wlr_layer::Margins as SmithayMargins;
+
+    use super::*;
+
+    fn state_with(cached_state: &LayerSurfaceCachedState) -> LayerSurfaceState {
+        let mut state = LayerSurfaceState {
+            id: LayerSurfaceId(0),
+            namespace: "panel".to_string(),
+            output: None,
+            layer: Layer::Background,
+            anchor: 0,
+            exclusive_zone: 0,
+            margin: Margins::default(),
+            keyboard_interactivity: KeyboardInteractivity::None,
+            size: (0, 0).into(),
+        };
+        state.update(cached_state);
+        state
+    }
+
+    #[test]
+    fn test_update_from_cached_state() {
+        let cached_state = LayerSurfaceCachedState {
+            layer: SmithayLayer::Top,
+            anchor: SmithayAnchor::TOP | SmithayAnchor::LEFT | SmithayAnchor::RIGHT,
+            exclusive_zone: ExclusiveZone::Exclusive(30),
+            margin: SmithayMargins {
+                top: 5,
+                ..Default::default()
+            },
+            keyboard_interactivity: SmithayKeyboardInteractivity::OnDemand,
+            size: SmithaySize::from((0, 30)),
+            ..Default::default()
+        };
+
+        let state = state_with(&cached_state);
+        assert_eq!(state.layer, Layer::Top);
+        assert_eq!(
+            state.sctk_anchor(),
+            SctkAnchor::TOP | SctkAnchor::LEFT | SctkAnchor::RIGHT
+        );
+        assert_eq!(state.exclusive_zone, 30);
+        assert_eq!(state.margin.top, 5);
+        assert_eq!(
+            state.keyboard_interactivity,
+            KeyboardInteractivity::OnDemand
+        );
+        assert_eq!(state.size, Size { w: 0, h: 30 });
+    }
+
+    #[test]
+    fn test_exclusive_zone() {
+        for (exclusive_zone, expected) in [
+            (ExclusiveZone::DontCare, -1),
+            (ExclusiveZone::Neutral, 0),
+            (ExclusiveZone::Exclusive(10), 10),
+        ] {
+            let cached_state = LayerSurfaceCachedState {
+                exclusive_zone,
+                ..Default::default()
+            };
+            assert_eq!(state_with(&cached_state).exclusive_zone, expected);
+        }
+    }
+
+    #[test]
+    fn test_layers() {
+        for (smithay_layer, sctk_layer) in [
+            (SmithayLayer::Background, SctkLayer::Background),
+            (SmithayLayer::Bottom, SctkLayer::Bottom),
+            (SmithayLayer::Top, SctkLayer::Top),
+            (SmithayLayer::Overlay, SctkLayer::Overlay),
+        ] {
+            assert_eq!(SctkLayer::from(Layer::from(smithay_layer)), sctk_layer);
+        }
+    }
+}
@@ -401,6 +401,10 @@ pub struct ToplevelRequest {
 #[archive_attr(derive(bytecheck::CheckBytes, Debug))]
 pub enum ToplevelEvent {
     Configure(ToplevelConfigure),
+    /// The user asked the local compositor to close the window. The
+    /// application decides whether to actually close it, in which case the
+    /// server sends ToplevelRequestPayload::Destroyed.
+    Close(WlSurfaceId),
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Archive, Deserialize, Serialize)]
@@ -18,65 +18,100 @@ use std::collections::HashSet;
 use std::fs::File;
 use std::io::Read;
 use std::io::Write;
+use std::mem;
 use std::os::fd::AsFd;
+use std::sync::atomic::Ordering;
 use std::thread;
+use std::time::Duration;
 
 use nix::fcntl::OFlag;
 use nix::unistd;
 use smithay::backend::input::Axis;
 use smithay::backend::input::ButtonState;
 use smithay::backend::input::KeyState;
+use smithay::backend::input::TouchSlot;
 use smithay::input::keyboard::FilterResult;
+use smithay::input::keyboard::KeyboardHandle;
 use smithay::input::keyboard::Layout;
 use smithay::input::keyboard::XkbContext;
 use smithay::input::pointer::AxisFrame;
 use smithay::input::pointer::ButtonEvent;
 use smithay::input::pointer::Focus;
 use smithay::input::pointer::MotionEvent;
+use smithay::input::pointer::RelativeMotionEvent;
+use smithay::input::touch::DownEvent;
+use smithay::input::touch::MotionEvent as TouchMotionEvent;
+use smithay::input::touch::OrientationEvent;
+use smithay::input::touch::ShapeEvent;
+use smithay::input::touch::UpEvent;
 use smithay::output::Output;
 use smithay::output::PhysicalProperties;
+use smithay::reexports::wayland_protocols::wp::presentation_time::server::wp_presentation_feedback;
 use smithay::reexports::wayland_server::backend::ObjectId;
 use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
 use smithay::reexports::wayland_server::Client;
+use smithay::reexports::wayland_server::Resource;
 use smithay::utils::Rectangle;
 use smithay::utils::Serial;
 use smithay::utils::SERIAL_COUNTER;
 use smithay::wayland::compositor;
+use smithay::wayland::compositor::TraversalAction;
+use smithay::wayland::keyboard_shortcuts_inhibit::KeyboardShortcutsInhibitorSeat;
+use smithay::wayland::pointer_constraints;
+use smithay::wayland::presentation::PresentationFeedbackCallback;
+use smithay::wayland::presentation::Refresh;
 use smithay::wayland::selection::data_device;
 use smithay::wayland::selection::data_device::SourceMetadata;
 use smithay::wayland::selection::primary_selection;
+use smithay::wayland::tablet_manager::TabletSeatTrait;
 
 use crate::args;
 use crate::compositor_utils;
 use crate::prelude::*;
+use crate::serialization::session_lock::SessionLockEvent;
+use crate::serialization::session_lock::SessionLockRequest;
+use crate::serialization::tablet::TabletEvent;
+use crate::serialization::tablet::TabletTool;
+use crate::serialization::tablet::TabletToolEvent;
+use crate::serialization::tablet::TabletToolId;
 use crate::serialization::wayland::DataDestinationEvent;
 use crate::serialization::wayland::DataEvent;
 use crate::serialization::wayland::DataRequest;
 use crate::serialization::wayland::DataSource;
 use crate::serialization::wayland::DataSourceEvent;
 use crate::serialization::wayland::DataToTransfer;
+use crate::serialization::wayland::IdleEvent;
+use crate::serialization::wayland::InputSnapshot;
 use crate::serialization::wayland::KeyInner;
 use crate::serialization::wayland::KeyboardEvent;
 use crate::serialization::wayland::OutputEvent;
+use crate::serialization::wayland::OutputInfo;
 use crate::serialization::wayland::PointerEvent;
 use crate::serialization::wayland::PointerEventKind;
+use crate::serialization::wayland::PresentationFeedback;
 use crate::serialization::wayland::RepeatInfo;
 use crate::serialization::wayland::SurfaceEvent;
 use crate::serialization::wayland::SurfaceEventPayload;
 use crate::serialization::wayland::SurfaceRequest;
 use crate::serialization::wayland::SurfaceRequestPayload;
+use crate::serialization::wayland::TouchEvent;
 use crate::serialization::wayland::WlSurfaceId;
+use crate::serialization::wlr_layer_shell::LayerSurfaceEvent;
 use crate::serialization::xdg_shell::PopupConfigure;
 use crate::serialization::xdg_shell::PopupEvent;
 use crate::serialization::xdg_shell::ToplevelConfigure;
 use crate::serialization::xdg_shell::ToplevelEvent;
 use crate::serialization::Capabilities;
+use crate::serialization::ClientCapabilities;
 use crate::serialization::Event;
 use crate::serialization::RecvType;
 use crate::serialization::Request;
 use crate::serialization::SendType;
+use crate::server::smithay_handlers;
 use crate::server::smithay_handlers::DndGrab;
+use crate::server::KeyboardFocusLossBehavior;
 use crate::server::LockedSurfaceState;
+use crate::server::TabletToolState;
 use crate::server::WprsServerState;
 
 enum UnknownSurfaceErr {
@@ -85,6 +120,12 @@ enum UnknownSurfaceErr {
     Surface(Client),
 }
 
+/// The layout for the layout index the client reported, or None if the keymap
+/// doesn't have that many layouts.
+fn requested_layout(layout_index: u32, num_layouts: usize) -> Option<Layout> {
+    (usize::try_from(layout_index).ok()? < num_layouts).then_some(Layout(layout_index))
+}
+
 impl WprsServerState {
     fn object_client_surface_from_id(
         &self,
@@ -112,7 +153,9 @@ impl WprsServerState {
         let pointer = self.seat.get_pointer().location(loc!())?;
 
         for event in events {
-            let (_, _, surface) = self
+            // Skip just this event so that the rest of the frame, and the frame
+            // event itself, still get delivered.
+            let Ok((_, _, surface)) = self
                 .object_client_surface_from_id(&event.surface_id)
                 .map_err(|err| match err {
                     UnknownSurfaceErr::ObjectId(surface_id) => {
@@ -125,7 +168,10 @@ impl WprsServerState {
                         anyhow!("Ignoring pointer event for unknown surface {:?}", client)
                     },
                 })
-                .warn(loc!())?;
+                .warn(loc!())
+            else {
+                continue;
+            };
 
             let time = self.start_time.elapsed().as_millis() as u32;
 
@@ -222,12 +268,24 @@ impl WprsServerState {
                     source,
                 } => {
                     debug!("axis event: horizontal {horizontal:?}, vertical {vertical:?}, source {source:?}");
+                    let horizontal_v120 = self.horizontal_scroll.v120(
+                        horizontal.absolute,
+                        horizontal.discrete,
+                        source,
+                        horizontal.stop,
+                    );
+                    let vertical_v120 = self.vertical_scroll.v120(
+                        vertical.absolute,
+                        vertical.discrete,
+                        source,
+                        vertical.stop,
+                    );
                     let mut axis_frame = AxisFrame::new(time)
                         .source(source.into())
                         .value(Axis::Horizontal, horizontal.absolute)
                         .value(Axis::Vertical, vertical.absolute)
-                        .v120(Axis::Horizontal, horizontal.discrete)
-                        .v120(Axis::Vertical, vertical.discrete);
+                        .v120(Axis::Horizontal, horizontal_v120)
+                        .v120(Axis::Vertical, vertical_v120);
                     if horizontal.stop {
                         axis_frame = axis_frame.stop(Axis::Horizontal);
                     }
@@ -236,6 +294,23 @@ impl WprsServerState {
                     }
                     pointer.axis(self, axis_frame);
                 },
+                PointerEventKind::RelativeMotion {
+                    dx,
+                    dy,
+                    dx_unaccel,
+                    dy_unaccel,
+                } => {
+                    debug!("pointer moved by ({dx}, {dy})");
+                    pointer.relative_motion(
+                        self,
+                        Some((surface, (0, 0).into())),
+                        &RelativeMotionEvent {
+                            delta: (dx, dy).into(),
+                            delta_unaccel: (dx_unaccel, dy_unaccel).into(),
+                            utime: self.start_time.elapsed().as_micros() as u64,
+                        },
+                    );
+                },
             }
         }
         pointer.frame(self);
@@ -243,6 +318,297 @@ impl WprsServerState {
         Ok(())
     }
 
+    #[instrument(skip_all, level = "debug")]
+    fn handle_touch_frame(&mut self, events: Vec<TouchEvent>) -> Result<()> {
+        let touch = self
+            .seat
+            .get_touch()
+            .context(loc!(), "seat has no touch capability")?;
+        let slot = |id: i32| TouchSlot::from(Some(id as u32));
+
+        for event in events {
+            let time = self.start_time.elapsed().as_millis() as u32;
+
+            // Events for touch points which went down on unknown surfaces are
+            // skipped, the rest of the frame is still delivered.
+            match event {
+                TouchEvent::Down {
+                    id,
+                    serial,
+                    surface_id,
+                    position,
+                } => {
+                    let Ok((_, _, surface)) = self.object_client_surface_from_id(&surface_id)
+                    else {
+                        warn!("Ignoring touch down on unknown surface {surface_id:?}");
+                        continue;
+                    };
+                    debug!("touch {id} down at {position:?}");
+                    self.touch_points.down(id, surface.clone());
+                    let serial = self.serial_map.insert(serial);
+                    touch.down(
+                        self,
+                        Some((surface, (0, 0).into())),
+                        &DownEvent {
+                            slot: slot(id),
+                            location: position.into(),
+                            serial,
+                            time,
+                        },
+                    );
+                },
+                TouchEvent::Up { id, serial } => {
+                    if !self.touch_points.up(id) {
+                        continue;
+                    }
+                    debug!("touch {id} up");
+                    let serial = self.serial_map.insert(serial);
+                    touch.up(
+                        self,
+                        &UpEvent {
+                            slot: slot(id),
+                            serial,
+                            time,
+                        },
+                    );
+                },
+                TouchEvent::Motion { id, position } => {
+                    let Some(surface) = self.touch_points.surface(id).cloned() else {
+                        continue;
+                    };
+                    touch.motion(
+                        self,
+                        Some((surface, (0, 0).into())),
+                        &TouchMotionEvent {
+                            slot: slot(id),
+                            location: position.into(),
+                            time,
+                        },
+                    );
+                },
+                TouchEvent::Shape { id, major, minor } => {
+                    if self.touch_points.surface(id).is_none() {
+                        continue;
+                    }
+                    touch.shape(
+                        self,
+                        &ShapeEvent {
+                            slot: slot(id),
+                            major,
+                            minor,
+                        },
+                    );
+                },
+                TouchEvent::Orientation { id, orientation } => {
+                    if self.touch_points.surface(id).is_none() {
+                        continue;
+                    }
+                    touch.orientation(
+                        self,
+                        &OrientationEvent {
+                            slot: slot(id),
+                            orientation,
+                        },
+                    );
+                },
+                TouchEvent::Cancel => {
+                    debug!("touch cancelled");
+                    self.touch_points.cancel();
+                    touch.cancel(self);
+                },
+            }
+        }
+        touch.frame(self);
+
+        Ok(())
+    }
+
+    #[instrument(skip(self), level = "debug")]
+    fn handle_tablet(&mut self, tablet_event: TabletEvent) -> Result<()> {
+        let tablet_seat = self.seat.tablet_seat();
+        match tablet_event {
+            TabletEvent::TabletAdded(tablet) => {
+                tablet_seat.add_tablet::<Self>(&self.dh, &tablet.smithay_descriptor());
+                self.tablets.insert(tablet.id, tablet);
+            },
+            TabletEvent::TabletRemoved(tablet_id) => {
+                if let Some(tablet) = self.tablets.remove(&tablet_id) {
+                    tablet_seat.remove_tablet(&tablet.smithay_descriptor());
+                }
+            },
+            TabletEvent::ToolAdded(mut tool) => {
+                make_tool_distinct(
+                    &mut tool,
+                    self.tablet_tools
+                        .values()
+                        .map(|tool_state| &tool_state.tool),
+                );
+                tablet_seat.add_tool::<Self>(&self.dh, &tool.smithay_descriptor());
+                self.tablet_tools.insert(
+                    tool.id,
+                    TabletToolState {
+                        tool,
+                        proximity: None,
+                        down: false,
+                    },
+                );
+            },
+            TabletEvent::ToolRemoved(tool_id) => {
+                if let Some(tool_state) = self.tablet_tools.remove(&tool_id) {
+                    tablet_seat.remove_tool(&tool_state.tool.smithay_descriptor());
+                }
+            },
+            TabletEvent::ToolFrame { tool, events } => {
+                self.handle_tablet_tool_frame(tool, events)
+                    .location(loc!())?;
+            },
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all, level = "debug")]
+    fn handle_tablet_tool_frame(
+        &mut self,
+        tool_id: TabletToolId,
+        events: Vec<TabletToolEvent>,
+    ) -> Result<()> {
+        let Some(tool_state) = self.tablet_tools.get(&tool_id) else {
+            warn!("Ignoring events for unknown tablet tool {tool_id:?}");
+            return Ok(());
+        };
+        let tool = self
+            .seat
+            .tablet_seat()
+            .get_tool(&tool_state.tool.smithay_descriptor())
+            .location(loc!())?;
+        let mut proximity = tool_state.proximity.clone();
+        let mut down = tool_state.down;
+        let time = self.start_time.elapsed().as_millis() as u32;
+
+        // The position of a tool entering proximity comes in a motion event
+        // later in the same frame.
+        let proximity_position = events
+            .iter()
+            .find_map(|event| match event {
+                TabletToolEvent::Motion(position) => Some(*position),
+                _ => None,
+            })
+            .unwrap_or((0.0, 0.0).into());
+
+        // Events for tools which aren't in proximity of a known surface are
+        // dropped.
+        for event in events {
+            match event {
+                TabletToolEvent::ProximityIn {
+                    serial,
+                    tablet: tablet_id,
+                    surface_id,
+                } => {
+                    let Ok((_, _, surface)) = self.object_client_surface_from_id(&surface_id)
+                    else {
+                        warn!("Ignoring tablet tool proximity on unknown surface {surface_id:?}");
+                        continue;
+                    };
+                    let Some(tablet) = self.tablets.get(&tablet_id).and_then(|tablet| {
+                        self.seat
+                            .tablet_seat()
+                            .get_tablet(&tablet.smithay_descriptor())
+                    }) else {
+                        warn!("Ignoring tablet tool proximity on unknown tablet {tablet_id:?}");
+                        continue;
+                    };
+                    let serial = self.serial_map.insert(serial);
+                    tool.proximity_in(
+                        proximity_position.into(),
+                        (surface.clone(), (0.0, 0.0).into()),
+                        &tablet,
+                        serial,
+                        time,
+                    );
+                    proximity = Some((tablet, surface));
+                },
+                TabletToolEvent::ProximityOut => {
+                    if proximity.take().is_some() {
+                        tool.proximity_out(time);
+                    }
+                    down = false;
+                },
+                TabletToolEvent::Down { serial } => {
+                    if proximity.is_some() {
+                        let serial = self.serial_map.insert(serial);
+                        tool.tip_down(serial, time);
+                        down = true;
+                    }
+                },
+                TabletToolEvent::Up => {
+                    if down {
+                        tool.tip_up(time);
+                        down = false;
+                    }
+                },
+                TabletToolEvent::Motion(position) => {
+                    if let Some((tablet, surface)) = &proximity {
+                        tool.motion(
+                            position.into(),
+                            Some((surface.clone(), (0.0, 0.0).into())),
+                            tablet,
+                            SERIAL_COUNTER.next_serial(),
+                            time,
+                        );
+                    }
+                },
+                TabletToolEvent::Pressure(pressure) => tool.pressure(pressure),
+                TabletToolEvent::Distance(distance) => tool.distance(distance),
+                TabletToolEvent::Tilt(tilt) => tool.tilt((tilt.x, tilt.y)),
+                TabletToolEvent::Rotation(degrees) => tool.rotation(degrees),
+                TabletToolEvent::Slider(position) => tool.slider_position(position),
+                TabletToolEvent::Wheel { degrees, clicks } => tool.wheel(degrees, clicks),
+                TabletToolEvent::Button {
+                    serial,
+                    button,
+                    pressed,
+                } => {
+                    let serial = self.serial_map.insert(serial);
+                    let state = if pressed {
+                        ButtonState::Pressed
+                    } else {
+                        ButtonState::Released
+                    };
+                    tool.button(button, state, serial, time);
+                },
+            }
+        }
+
+        let tool_state = self.tablet_tools.get_mut(&tool_id).location(loc!())?;
+        tool_state.proximity = proximity;
+        tool_state.down = down;
+        Ok(())
+    }
+
+    /// Lifts and takes out of proximity the tools of the previous wprsc, so
+    /// that applications don't see a stroke which never ends, and forgets its
+    /// tablets. The new wprsc announces its own.
+    fn reset_tablets(&mut self) {
+        let tablet_seat = self.seat.tablet_seat();
+        let time = self.start_time.elapsed().as_millis() as u32;
+        for tool_state in self.tablet_tools.values_mut() {
+            let (was_down, was_in_proximity) = tool_state.release();
+            let Some(tool) = tablet_seat.get_tool(&tool_state.tool.smithay_descriptor()) else {
+                continue;
+            };
+            if was_down {
+                tool.tip_up(time);
+            }
+            if was_in_proximity {
+                tool.proximity_out(time);
+            }
+        }
+        self.tablet_tools.clear();
+        self.tablets.clear();
+        tablet_seat.clear_tools();
+        tablet_seat.clear_tablets();
+    }
+
     #[instrument(
         skip(self, keycode, state),
         fields(keycode = "<redacted>", state = "<redacted>"),
@@ -280,6 +646,137 @@ impl WprsServerState {
         Ok(())
     }
 
+    /// Gives keyboard focus (and selection focus) to a surface.
+    pub fn set_keyboard_focus(&mut self, surface_id: &WlSurfaceId, serial: Serial) -> Result<()> {
+        let keyboard = self.seat.get_keyboard().location(loc!())?;
+        let (_, client, surface) = self
+            .object_client_surface_from_id(surface_id)
+            .map_err(|err| match err {
+                UnknownSurfaceErr::ObjectId(surface_id) => anyhow!(
+                    "Ignoring keyboard focus for unknown object {:?}",
+                    surface_id
+                ),
+                UnknownSurfaceErr::Client(object_id) => {
+                    anyhow!("Ignoring keyboard focus for unknown client {:?}", object_id)
+                },
+                UnknownSurfaceErr::Surface(client) => {
+                    anyhow!("Ignoring keyboard focus for unknown surface {:?}", client)
+                },
+            })
+            .location(loc!())?;
+
+        debug!("setting keyboard focus to surface {surface:?}");
+        keyboard.set_focus(self, Some(surface), serial);
+        data_device::set_data_device_focus(&self.dh, &self.seat, Some(client.clone()));
+        primary_selection::set_primary_focus(&self.dh, &self.seat, Some(client));
+        Ok(())
+    }
+
+    /// The surface which currently has keyboard focus.
+    pub fn keyboard_focus(&self) -> Option<WlSurfaceId> {
+        let surface = self.seat.get_keyboard()?.current_focus()?;
+        Some(WlSurfaceId::new(&surface))
+    }
+
+    /// Switches the keymap to the layout group the client last reported.
+    fn apply_layout_index(&mut self, keyboard: &KeyboardHandle<Self>) {
+        let layout_index = self.layout_index;
+        keyboard.with_xkb_state(self, |mut context: XkbContext| {
+            match requested_layout(layout_index, context.layouts().count()) {
+                Some(layout) if layout != context.active_layout() => context.set_layout(layout),
+                Some(_) => {},
+                // This is checked on every Modifiers event, so it would flood
+                // the log at a louder level.
+                None => {
+                    debug!("ignoring layout index {layout_index}, the keymap has fewer layouts")
+                },
+            }
+        });
+    }
+
+    /// Releases the keys which we think are held but the client says aren't and
+    /// presses the ones the client says are held.
+    fn sync_pressed_keys(&mut self, keycodes: Vec<u32>) -> Result<()> {
+        let (released, pressed) = pressed_key_changes(&self.pressed_keys, &keycodes);
+        for keycode in released {
+            self.set_key_state(keycode, KeyState::Released, SERIAL_COUNTER.next_serial())
+                .location(loc!())?;
+        }
+        // We simulate keycodes before focusing since that is what a normal wayland application would see.
+        for keycode in pressed {
+            self.set_key_state(keycode, KeyState::Pressed, SERIAL_COUNTER.next_serial())
+                .location(loc!())?;
+        }
+        Ok(())
+    }
+
+    /// Brings the seat in line with the input state of a client which
+    /// reconnected. Buttons which the client says are held but we don't think
+    /// are aren't pressed, since a click which started before the disconnect
+    /// can't meaningfully be resumed.
+    #[instrument(skip_all, level = "debug")]
+    fn handle_input_snapshot(&mut self, snapshot: InputSnapshot) -> Result<()> {
+        let keyboard = self.seat.get_keyboard().location(loc!())?;
+        match snapshot.keyboard_focus {
+            Some(surface_id) => {
+                self.sync_pressed_keys(snapshot.pressed_keys)
+                    .location(loc!())?;
+                self.set_keyboard_focus(&surface_id, SERIAL_COUNTER.next_serial())
+                    .warn_and_ignore(loc!());
+            },
+            None => {
+                self.sync_pressed_keys(Vec::new()).location(loc!())?;
+                keyboard.set_focus(self, None, SERIAL_COUNTER.next_serial());
+                data_device::set_data_device_focus(&self.dh, &self.seat, None);
+                primary_selection::set_primary_focus(&self.dh, &self.seat, None);
+            },
+        }
+
+        let pointer = self.seat.get_pointer().location(loc!())?;
+        let time = self.start_time.elapsed().as_millis() as u32;
+        let stale_buttons: Vec<u32> = self
+            .pressed_buttons
+            .iter()
+            .filter(|button| !snapshot.pressed_buttons.contains(button))
+            .copied()
+            .collect();
+        for button in stale_buttons {
+            debug!("releasing button {}", button);
+            pointer.button(
+                self,
+                &ButtonEvent {
+                    time,
+                    button,
+                    serial: SERIAL_COUNTER.next_serial(),
+                    state: ButtonState::Released,
+                },
+            );
+            self.pressed_buttons.remove(&button);
+        }
+
+        let (focus, location) = match snapshot.pointer_focus {
+            Some((surface_id, position)) => match self.object_client_surface_from_id(&surface_id) {
+                Ok((_, _, surface)) => (Some((surface, (0, 0).into())), position.into()),
+                Err(_) => {
+                    debug!("pointer focus {surface_id:?} is gone");
+                    (None, (0.0, 0.0).into())
+                },
+            },
+            None => (None, (0.0, 0.0).into()),
+        };
+        pointer.motion(
+            self,
+            focus,
+            &MotionEvent {
+                location,
+                serial: SERIAL_COUNTER.next_serial(),
+                time,
+            },
+        );
+        pointer.frame(self);
+        Ok(())
+    }
+
     #[instrument(skip_all, level = "debug")]
     fn handle_keyboard_event(&mut self, event: KeyboardEvent) -> Result<()> {
         let keyboard = self.seat.get_keyboard().location(loc!())?;
@@ -288,58 +785,11 @@ impl WprsServerState {
                 serial,
                 surface_id,
                 keycodes,
-                keysyms: _,
             } => {
-                // see linux/input-event-codes.h for keycodes
-                let modifier_keycodes = HashSet::from([
-                    /* KEY_LEFTCTRL */ 29, /* KEY_RIGHTCTRL */ 97,
-                    /* KEY_LEFTALT */ 56, /* KEY_RIGHTALT */ 100,
-                    /* KEY_LEFTMETA	*/ 125, /* KEY_RIGHTMETA */ 126,
-                    /* KEY_LEFTSHIFT */ 42, /* KEY_RIGHTSHIFT */ 54,
-                ]);
-
-                // We simulate keycodes before focusing since that is what a normal wayland application would see.
-                // Process modifier keys first so that they apply to other held keys.
-                let mut delayed_keycodes = Vec::new();
-                for keycode in keycodes {
-                    if modifier_keycodes.contains(&keycode) {
-                        self.set_key_state(
-                            keycode,
-                            KeyState::Pressed,
-                            SERIAL_COUNTER.next_serial(),
-                        )
-                        .location(loc!())?;
-                    } else {
-                        delayed_keycodes.push(keycode);
-                    }
-                }
-                for keycode in delayed_keycodes {
-                    self.set_key_state(keycode, KeyState::Pressed, SERIAL_COUNTER.next_serial())
-                        .location(loc!())?;
-                }
+                self.sync_pressed_keys(keycodes).location(loc!())?;
 
                 let serial = self.serial_map.insert(serial);
-
-                let (_, client, surface) = self
-                    .object_client_surface_from_id(&surface_id)
-                    .map_err(|err| match err {
-                        UnknownSurfaceErr::ObjectId(surface_id) => anyhow!(
-                            "Ignoring keyboard event for unknown object {:?}",
-                            surface_id
-                        ),
-                        UnknownSurfaceErr::Client(object_id) => {
-                            anyhow!("Ignoring keyboard event for unknown client {:?}", object_id)
-                        },
-                        UnknownSurfaceErr::Surface(client) => {
-                            anyhow!("Ignoring keyboard event for unknown surface {:?}", client)
-                        },
-                    })
-                    .warn(loc!())?;
-
-                debug!("setting keyboard focus to surface {surface:?}");
-                keyboard.set_focus(self, Some(surface), serial);
-                data_device::set_data_device_focus(&self.dh, &self.seat, Some(client.clone()));
-                primary_selection::set_primary_focus(&self.dh, &self.seat, Some(client));
+                self.set_keyboard_focus(&surface_id, serial).warn(loc!())?;
             },
             KeyboardEvent::Leave { serial } => {
                 let serial = self.serial_map.insert(serial);
@@ -347,9 +797,17 @@ impl WprsServerState {
                 data_device::set_data_device_focus(&self.dh, &self.seat, None);
                 primary_selection::set_primary_focus(&self.dh, &self.seat, None);
 
-                for keycode in self.pressed_keys.clone() {
-                    self.set_key_state(keycode, KeyState::Released, SERIAL_COUNTER.next_serial())
+                // With Reconcile, keys stay pressed until the next Enter, which
+                // releases any that were released while unfocused.
+                if self.keyboard_focus_loss_behavior == KeyboardFocusLossBehavior::Release {
+                    for keycode in self.pressed_keys.clone() {
+                        self.set_key_state(
+                            keycode,
+                            KeyState::Released,
+                            SERIAL_COUNTER.next_serial(),
+                        )
                         .location(loc!())?;
+                    }
                 }
             },
             KeyboardEvent::Key(KeyInner {
@@ -371,16 +829,20 @@ impl WprsServerState {
                 },
                 RepeatInfo::Disable => {},
             },
-            KeyboardEvent::Keymap(keymap) => keyboard
-                .set_keymap_from_string(self, keymap)
-                .location(loc!())?,
+            KeyboardEvent::Keymap(keymap) => {
+                keyboard
+                    .set_keymap_from_string(self, keymap)
+                    .location(loc!())?;
+                // A new keymap starts out with the first layout active, while
+                // the client's compositor keeps the group it had.
+                self.apply_layout_index(&keyboard);
+            },
             KeyboardEvent::Modifiers {
                 modifier_state,
                 layout_index,
             } => {
-                keyboard.with_xkb_state(self, |mut context: XkbContext| {
-                    context.set_layout(Layout(layout_index));
-                });
+                self.layout_index = layout_index;
+                self.apply_layout_index(&keyboard);
 
                 // see linux/input-event-codes.h for keycodes
                 let mod_state = keyboard.modifier_state();
@@ -456,12 +918,29 @@ impl WprsServerState {
         Ok(())
     }
 
+    #[instrument(skip_all, level = "debug")]
+    fn handle_toplevel_close(&self, surface_id: WlSurfaceId) {
+        let surfaces = self.xdg_shell_state.toplevel_surfaces();
+        match surfaces
+            .iter()
+            .find(|surface| WlSurfaceId::new(surface.wl_surface()) == surface_id)
+        {
+            Some(surface) => surface.send_close(),
+            // The application destroyed the window before the close request
+            // arrived.
+            None => debug!("ignoring close for unknown toplevel {surface_id:?}"),
+        }
+    }
+
     #[instrument(skip_all, level = "debug")]
     fn handle_toplevel(&mut self, toplevel: ToplevelEvent) -> Result<()> {
         match &toplevel {
             ToplevelEvent::Configure(configure) => {
                 self.handle_toplevel_configure(configure).location(loc!())?;
             },
+            ToplevelEvent::Close(surface_id) => {
+                self.handle_toplevel_close(*surface_id);
+            },
         }
         Ok(())
     }
@@ -505,29 +984,107 @@ impl WprsServerState {
         Ok(())
     }
 
+    #[instrument(skip_all, level = "debug")]
+    fn handle_layer_surface(&mut self, layer_surface_event: LayerSurfaceEvent) -> Result<()> {
+        let surface_id = match &layer_surface_event {
+            LayerSurfaceEvent::Configure(configure) => configure.surface_id,
+            LayerSurfaceEvent::Closed(surface_id) => *surface_id,
+        };
+        let layer_surface = self
+            .wlr_layer_shell_state
+            .layer_surfaces()
+            .into_iter()
+            .find(|layer_surface| WlSurfaceId::new(layer_surface.wl_surface()) == surface_id)
+            .location(loc!())?;
+
+        match layer_surface_event {
+            LayerSurfaceEvent::Configure(configure) => {
+                layer_surface.with_pending_state(|state| {
+                    state.size = Some((configure.size.w as i32, configure.size.h as i32).into());
+                });
+                layer_surface.send_configure();
+            },
+            LayerSurfaceEvent::Closed(_) => layer_surface.send_close(),
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self), level = "debug")]
+    fn handle_session_lock(&mut self, session_lock_event: SessionLockEvent) -> Result<()> {
+        let Some(session_lock) = &mut self.session_lock else {
+            debug!("ignoring {session_lock_event:?}, the session isn't locked");
+            return Ok(());
+        };
+        match session_lock_event {
+            SessionLockEvent::Locked => {
+                if let Some(locker) = session_lock.locker.take() {
+                    locker.lock();
+                }
+            },
+            SessionLockEvent::Finished => {
+                if let Some(locker) = session_lock.locker.take() {
+                    // The local session couldn't be locked, so neither can
+                    // this one.
+                    locker.ext_session_lock().finished();
+                    self.end_session_lock();
+                } else {
+                    // The application's lock was already confirmed and only
+                    // the application can end it.
+                    warn!("the local session lock ended while the session is still locked");
+                }
+            },
+            SessionLockEvent::Configure(configure) => {
+                let lock_surface = session_lock
+                    .lock_surfaces
+                    .iter()
+                    .find(|lock_surface| {
+                        WlSurfaceId::new(lock_surface.wl_surface()) == configure.surface_id
+                    })
+                    .location(loc!())?;
+                lock_surface.with_pending_state(|state| {
+                    state.size = Some((configure.size.w, configure.size.h).into());
+                });
+                lock_surface.send_configure();
+            },
+        }
+        Ok(())
+    }
+
+    fn add_output(&mut self, output: OutputInfo) {
+        let (local_output, _) = self.outputs.entry(output.id).or_insert_with_key(|id| {
+            let new_output = Output::new(
+                format!(
+                    "{}_{}",
+                    id,
+                    output.name.clone().unwrap_or("None".to_string())
+                ),
+                PhysicalProperties {
+                    size: output.physical_size.into(),
+                    subpixel: output.subpixel.into(),
+                    make: output.make.clone(),
+                    model: output.model.clone(),
+                },
+            );
+            let global_id = new_output.create_global::<Self>(&self.dh);
+            (new_output, global_id)
+        });
+
+        compositor_utils::update_output(local_output, output);
+    }
+
     #[instrument(skip_all, level = "debug")]
     fn handle_output(&mut self, output_event: OutputEvent) -> Result<()> {
         match output_event {
+            OutputEvent::Set(outputs) => {
+                for id in removed_output_ids(self.outputs.keys().copied(), &outputs) {
+                    self.remove_output(id);
+                }
+                for output in outputs {
+                    self.add_output(output);
+                }
+            },
             OutputEvent::New(output) => {
-                let (local_output, _) = self.outputs.entry(output.id).or_insert_with_key(|id| {
-                    let new_output = Output::new(
-                        format!(
-                            "{}_{}",
-                            id,
-                            output.name.clone().unwrap_or("None".to_string())
-                        ),
-                        PhysicalProperties {
-                            size: output.physical_size.into(),
-                            subpixel: output.subpixel.into(),
-                            make: output.make.clone(),
-                            model: output.model.clone(),
-                        },
-                    );
-                    let global_id = new_output.create_global::<Self>(&self.dh);
-                    (new_output, global_id)
-                });
-
-                compositor_utils::update_output(local_output, output);
+                self.add_output(output);
             },
             OutputEvent::Update(output) => {
                 let (local_output, _) = match self.outputs.entry(output.id) {
@@ -541,19 +1098,69 @@ impl WprsServerState {
                 compositor_utils::update_output(local_output, output);
             },
             OutputEvent::Destroy(output) => {
-                if let Some((_, (_, global_id))) = self.outputs.remove_entry(&output.id) {
-                    self.dh.remove_global::<Self>(global_id);
-                }
+                self.remove_output(output.id);
             },
         };
 
         Ok(())
     }
 
+    /// Removes the output, after sending leave for it to the surfaces which
+    /// were on it.
+    fn remove_output(&mut self, id: u32) {
+        let Some((output, global_id)) = self.outputs.remove(&id) else {
+            return;
+        };
+        self.for_each_surface(|surface, surface_data| {
+            let Some(surface_state) = surface_data.data_map.get::<LockedSurfaceState>() else {
+                return;
+            };
+            let mut surface_state = surface_state.0.lock().unwrap();
+            let n_output_ids = surface_state.output_ids.len();
+            surface_state
+                .output_ids
+                .retain(|output_id| *output_id != id);
+            if surface_state.output_ids.len() != n_output_ids {
+                output.leave(surface);
+            }
+        });
+        self.dh.remove_global::<Self>(global_id);
+    }
+
     #[instrument(skip_all, level = "debug")]
-    fn handle_connect(&mut self) -> Result<()> {
+    fn handle_connect(&mut self, client_capabilities: ClientCapabilities) -> Result<()> {
         // TODO: sync client outputs
         self.serializer.set_other_end_connected(true);
+        info!("client connected: client: {}", client_capabilities.identity);
+        for (surface, callbacks) in mem::take(&mut self.parked_frame_callbacks) {
+            if surface.is_alive() {
+                self.send_frame_callbacks(&surface, callbacks);
+            }
+        }
+        self.client_identity
+            .lock()
+            .unwrap()
+            .clone_from(&client_capabilities.identity);
+        self.client_idle.store(false, Ordering::Relaxed);
+        self.client_capabilities = client_capabilities;
+        // Activation tokens handed out while another client was connected
+        // shouldn't be able to steal focus in this one.
+        self.xdg_activation_state.retain_tokens(|_, _| false);
+        self.reset_tablets();
+        // The previous wprsc won't lift its touch points.
+        if self.touch_points.cancel() {
+            if let Some(touch) = self.seat.get_touch() {
+                touch.cancel(self);
+            }
+        }
+        // The previous wprsc won't report the presentation of the buffers it
+        // was sent.
+        let surfaces: Vec<ObjectId> = self.presentation_feedback.keys().cloned().collect();
+        for surface in surfaces {
+            self.discard_presentation_feedback(&surface);
+        }
+        // Nor acknowledge them.
+        self.frame_budget.clear();
 
         self.serializer
             .writer()
@@ -561,8 +1168,25 @@ impl WprsServerState {
                 xwayland: self.xwayland_enabled,
             })));
 
-        self.for_each_surface(|_, surface_data| {
-            let surface_state = surface_data
+        // The local session has to be locked before any lock surfaces can be
+        // shown. This also relocks the local session if the previous wprsc
+        // went away while it was locked.
+        if self.session_lock.is_some() {
+            self.serializer
+                .writer()
+                .send(SendType::Object(Request::SessionLock(
+                    SessionLockRequest::Lock,
+                )));
+        }
+
+        let mut hidden_surfaces = Vec::new();
+        let mut sent_surfaces = Vec::new();
+        self.for_each_surface(|surface, surface_data| {
+            if self.hidden_by_session_lock(surface) {
+                hidden_surfaces.push(surface.clone());
+                return;
+            }
+            let mut surface_state = surface_data
                 .data_map
                 .get::<LockedSurfaceState>()
                 .unwrap()
@@ -570,6 +1194,9 @@ impl WprsServerState {
                 .lock()
                 .unwrap()
                 .clone();
+            surface_state
+                .convert_buffer_to_supported(&self.client_capabilities.buffer_formats)
+                .log_and_ignore(loc!());
 
             self.serializer
                 .writer()
@@ -578,8 +1205,71 @@ impl WprsServerState {
                     surface: surface_state.id,
                     payload: SurfaceRequestPayload::Commit(surface_state),
                 })));
+            sent_surfaces.push(surface.clone());
         });
 
+        // The inhibitors stay inactive until the new wprsc's compositor
+        // activates the local ones, which it creates from the commits above.
+        for surface in sent_surfaces {
+            if let Some(inhibitor) = self.seat.keyboard_shortcuts_inhibitor_for_surface(&surface) {
+                inhibitor.inactivate();
+            }
+        }
+
+        // Surfaces were sent parents first, so the client received the
+        // buffers of synchronized subsurfaces after their parent's commit and
+        // would only show them on the parent's next commit, leaving parts of
+        // windows blank until the application redraws. Commit the top-level
+        // surfaces again, without buffers, to apply them.
+        let roots = self
+            .xdg_shell_state
+            .toplevel_surfaces()
+            .iter()
+            .map(|toplevel| toplevel.wl_surface().clone())
+            .chain(
+                self.xdg_shell_state
+                    .popup_surfaces()
+                    .iter()
+                    .map(|popup| popup.wl_surface().clone()),
+            )
+            .chain(
+                self.wlr_layer_shell_state
+                    .layer_surfaces()
+                    .iter()
+                    .map(|layer_surface| layer_surface.wl_surface().clone()),
+            )
+            .filter(|surface| !self.hidden_by_session_lock(surface))
+            .collect::<Vec<_>>();
+        if let Some(session_lock) = &mut self.session_lock {
+            for surface in hidden_surfaces {
+                if !session_lock.held_surfaces.contains(&surface) {
+                    session_lock.held_surfaces.push(surface);
+                }
+            }
+        }
+        for surface in roots {
+            let surface_state = compositor::with_states(&surface, |surface_data| {
+                surface_data
+                    .data_map
+                    .get::<LockedSurfaceState>()
+                    .map(|state| state.0.lock().unwrap().clone_without_buffer())
+            });
+            let Some(surface_state) = surface_state else {
+                continue;
+            };
+            self.serializer
+                .writer()
+                .send(SendType::Object(Request::Surface(SurfaceRequest {
+                    client: surface_state.client,
+                    surface: surface_state.id,
+                    payload: SurfaceRequestPayload::Commit(surface_state),
+                })));
+        }
+
+        self.serializer
+            .writer()
+            .send(SendType::Object(Request::SnapshotComplete));
+
         Ok(())
     }
 
@@ -794,12 +1484,16 @@ impl WprsServerState {
             },
             DataEvent::DestinationEvent(DataDestinationEvent::SelectionSet(source, metadata)) => {
                 match source {
-                    DataSource::Selection => data_device::set_data_device_selection(
-                        &self.dh,
-                        &self.seat,
-                        metadata.mime_types,
-                        (),
-                    ),
+                    DataSource::Selection => {
+                        self.clipboard_cache
+                            .set_selection(metadata.mime_types.clone());
+                        data_device::set_data_device_selection(
+                            &self.dh,
+                            &self.seat,
+                            metadata.mime_types,
+                            (),
+                        )
+                    },
                     DataSource::Primary => primary_selection::set_primary_selection(
                         &self.dh,
                         &self.seat,
@@ -811,7 +1505,11 @@ impl WprsServerState {
             },
             DataEvent::TransferData(source, data) => {
                 let fd = match source {
-                    DataSource::Selection => self.selection_pipe.take().location(loc!())?,
+                    DataSource::Selection => {
+                        let (mime_type, fd) = self.selection_pipe.take().location(loc!())?;
+                        self.clipboard_cache.insert(&mime_type, &data.0);
+                        fd
+                    },
                     DataSource::Primary => self.primary_selection_pipe.take().location(loc!())?,
                     DataSource::DnD => self.dnd_pipe.take().location(loc!())?,
                 };
@@ -824,6 +1522,12 @@ impl WprsServerState {
                     f.write_all(&data.0).log_and_ignore(loc!());
                 });
             },
+            DataEvent::SelectionContents(DataSource::Selection, mime_type, data) => {
+                self.clipboard_cache.insert(&mime_type, &data.0);
+            },
+            DataEvent::SelectionContents(source, _, _) => {
+                bail!("selection contents pushed for unsupported source {source:?}");
+            },
         }
 
         Ok(())
@@ -857,6 +1561,79 @@ impl WprsServerState {
             .warn(loc!())?;
 
         match surface_event.payload {
+            SurfaceEventPayload::FramePresented => {
+                // Subsurfaces are presented along with the surface, so
+                // release their callbacks too.
+                let mut presented = Vec::new();
+                compositor::with_surface_tree_downward(
+                    &surface,
+                    (),
+                    |_, _, _| TraversalAction::DoChildren(()),
+                    |surface, _, _| {
+                        let callbacks = self.client_paced_frame_callbacks.take_all(&surface.id());
+                        if !callbacks.is_empty() {
+                            presented.push((surface.clone(), callbacks));
+                        }
+                    },
+                    |_, _, _| true,
+                );
+                for (surface, callbacks) in presented {
+                    self.send_frame_callbacks(&surface, callbacks);
+                }
+            },
+            SurfaceEventPayload::PresentationFeedback(feedback) => {
+                let Some(callbacks) = self
+                    .presentation_feedback
+                    .get_mut(&surface.id())
+                    .and_then(|pending| pending.pop_front())
+                else {
+                    debug!("no presentation feedback pending for {:?}", surface.id());
+                    return Ok(());
+                };
+                let output = compositor::with_states(&surface, |surface_data| {
+                    let surface_state = surface_data
+                        .data_map
+                        .get::<LockedSurfaceState>()
+                        .unwrap()
+                        .0
+                        .lock()
+                        .unwrap();
+                    surface_state
+                        .output_ids
+                        .iter()
+                        .find_map(|id| self.outputs.get(id))
+                        .map(|(output, _)| output.clone())
+                });
+                match (feedback, output) {
+                    (
+                        PresentationFeedback::Presented {
+                            refresh_ns,
+                            seq,
+                            flags,
+                            ..
+                        },
+                        Some(output),
+                    ) => {
+                        let refresh = match refresh_ns {
+                            0 => Refresh::Unknown,
+                            refresh_ns => Refresh::Fixed(Duration::from_nanos(refresh_ns.into())),
+                        };
+                        // The local compositor's timestamp is from another
+                        // machine's clock, so use the time wprsc's report
+                        // arrived instead. That isn't a hardware timestamp and
+                        // the buffer was copied, so only vsync still applies.
+                        let flags = wp_presentation_feedback::Kind::from_bits_truncate(flags)
+                            & wp_presentation_feedback::Kind::Vsync;
+                        let time = self.clock.now();
+                        for callback in callbacks {
+                            callback.presented(&output, time, refresh, seq, flags);
+                        }
+                    },
+                    _ => callbacks
+                        .into_iter()
+                        .for_each(PresentationFeedbackCallback::discarded),
+                }
+            },
             SurfaceEventPayload::OutputsChanged(outputs) => {
                 compositor::with_states(&surface, |surface_data| {
                     let surface_state = &mut surface_data
@@ -878,27 +1655,644 @@ impl WprsServerState {
                     surface_state.output_ids = new_ids.iter().cloned().collect();
                 });
             },
+            SurfaceEventPayload::PreferredFractionalScale(scale) => {
+                compositor::with_states(&surface, |surface_data| {
+                    smithay_handlers::update_preferred_fractional_scale(
+                        surface_data,
+                        |preferred| preferred.report(scale),
+                    );
+                });
+            },
+            SurfaceEventPayload::PointerConstraintActive(active) => {
+                let pointer = self.seat.get_pointer().location(loc!())?;
+                pointer_constraints::with_pointer_constraint(&surface, &pointer, |constraint| {
+                    match constraint {
+                        Some(constraint) if active => constraint.activate(),
+                        Some(constraint) => constraint.deactivate(),
+                        None => debug!("pointer constraint was already destroyed"),
+                    }
+                });
+            },
+            SurfaceEventPayload::KeyboardShortcutsInhibitorActive(active) => {
+                match self.seat.keyboard_shortcuts_inhibitor_for_surface(&surface) {
+                    Some(inhibitor) if active => inhibitor.activate(),
+                    Some(inhibitor) => inhibitor.inactivate(),
+                    None => debug!("keyboard shortcuts inhibitor was already destroyed"),
+                }
+            },
+            SurfaceEventPayload::FrameAck => {
+                if !self.frame_budget.ack(&surface.id()) {
+                    return Ok(());
+                }
+                // Buffers were dropped while wprsc was behind, send the latest
+                // one so that wprsc doesn't stay on a stale frame. It was
+                // converted to a supported format when it was committed.
+                if !self.resend_buffer(&surface) {
+                    // The buffer was removed since, nothing will be
+                    // acknowledged.
+                    self.frame_budget.cancel(&surface.id());
+                    return Ok(());
+                }
+                // wprsc reports the presentation of this buffer too, but no
+                // application is waiting for it.
+                self.presentation_feedback
+                    .entry(surface.id())
+                    .or_default()
+                    .push_back(Vec::new());
+            },
         }
 
         Ok(())
     }
 
+    #[instrument(skip(self), level = "debug")]
+    fn handle_idle(&mut self, idle_event: IdleEvent) -> Result<()> {
+        let idle = idle_event == IdleEvent::Idle;
+        info!("client is {}", if idle { "idle" } else { "active" });
+        self.client_idle.store(idle, Ordering::Relaxed);
+        Ok(())
+    }
+
     #[instrument(skip(self), level = "debug")]
     pub fn handle_event(&mut self, event: RecvType<Event>) {
         match event {
-            RecvType::Object(Event::WprsClientConnect) => self.handle_connect(),
+            RecvType::Object(Event::WprsClientConnect(caps)) => self.handle_connect(caps),
             RecvType::Object(Event::Toplevel(toplevel)) => self.handle_toplevel(toplevel),
             RecvType::Object(Event::Popup(popup)) => self.handle_popup(popup),
             RecvType::Object(Event::KeyboardEvent(event)) => self.handle_keyboard_event(event),
             RecvType::Object(Event::PointerFrame(events)) => self.handle_pointer_frame(events),
+            RecvType::Object(Event::TouchFrame(events)) => self.handle_touch_frame(events),
             RecvType::Object(Event::Output(output_event)) => self.handle_output(output_event),
             RecvType::Object(Event::Data(data_event)) => self.handle_data_event(data_event),
             RecvType::Object(Event::Surface(surface_event)) => {
                 self.handle_surface_event(surface_event)
             },
-            RecvType::RawBuffer(_) => unreachable!(),
+            RecvType::Object(Event::Idle(idle_event)) => self.handle_idle(idle_event),
+            RecvType::Object(Event::LayerSurface(layer_surface_event)) => {
+                self.handle_layer_surface(layer_surface_event)
+            },
+            RecvType::Object(Event::SessionLock(session_lock_event)) => {
+                self.handle_session_lock(session_lock_event)
+            },
+            RecvType::Object(Event::Tablet(tablet_event)) => self.handle_tablet(tablet_event),
+            RecvType::Object(Event::InputSnapshot(snapshot)) => {
+                self.handle_input_snapshot(snapshot)
+            },
+            RecvType::Object(Event::Ping(nonce)) => {
+                self.serializer
+                    .writer()
+                    .send(SendType::Object(Request::Pong(nonce)));
+                Ok(())
+            },
+            RecvType::Object(Event::TextInput(event)) => self.handle_text_input(event),
+            RecvType::RawBuffer(_) | RecvType::Reconnected => unreachable!(),
         }
         .log_and_ignore(loc!());
         // TODO: maybe send errors back to the client.
     }
 }
+
+/// The outputs in current_ids which aren't in outputs, sorted.
+fn removed_output_ids(current_ids: impl Iterator<Item = u32>, outputs: &[OutputInfo]) -> Vec<u32> {
+    let ids: HashSet<u32> = outputs.iter().map(|output| output.id).collect();
+    let mut removed: Vec<u32> = current_ids.filter(|id| !ids.contains(id)).collect();
+    removed.sort_unstable();
+    removed
+}
+
+/// Changes the hardware serial of tool until its descriptor, by which smithay
+/// tells tools apart, differs from those of the known tools. Otherwise tools
+/// without serial numbers, e.g. the same kind of pen on two tablets, would be a
+/// single tool to applications, and removing one would remove both.
+fn make_tool_distinct<'a>(tool: &mut TabletTool, known: impl Iterator<Item = &'a TabletTool>) {
+    let descriptors: Vec<_> = known.map(TabletTool::smithay_descriptor).collect();
+    while descriptors.contains(&tool.smithay_descriptor()) {
+        tool.hardware_serial = tool.hardware_serial.wrapping_add(1);
+    }
+}
+
+/// The keys to release and the keys to press, in that order, so that the keys
+/// we think are held, held, match the ones the client says are held, keycodes.
+fn pressed_key_changes(held: &HashSet<u32>, keycodes: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    // Keys which we think are still held but which the client says
+    // aren't, for example if we missed a Leave because the client
+    // disconnected while a key was held. Leaving them pressed
+    // results in stuck modifiers.
+    let mut released: Vec<u32> = held
+        .iter()
+        .filter(|keycode| !keycodes.contains(keycode))
+        .copied()
+        .collect();
+    released.sort_unstable();
+
+    // see linux/input-event-codes.h for keycodes
+    let modifier_keycodes = HashSet::from([
+        /* KEY_LEFTCTRL */ 29, /* KEY_RIGHTCTRL */ 97, /* KEY_LEFTALT */ 56,
+        /* KEY_RIGHTALT */ 100, /* KEY_LEFTMETA	*/ 125, /* KEY_RIGHTMETA */ 126,
+        /* KEY_LEFTSHIFT */ 42, /* KEY_RIGHTSHIFT */ 54,
+    ]);
+
+    // Process modifier keys first so that they apply to other held keys.
+    let (mut pressed, delayed): (Vec<u32>, Vec<u32>) = keycodes
+        .iter()
+        .copied()
+        .filter(|keycode| !held.contains(keycode))
+        .partition(|keycode| modifier_keycodes.contains(keycode));
+    pressed.extend(delayed);
+    (released, pressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use smithay_client_toolkit::reexports::client::protocol::wl_output::WlOutput;
+    use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1::Layer as ClientLayer;
+
+    use super::*;
+    use crate::serialization::geometry::Size;
+    use crate::serialization::tablet::Tablet;
+    use crate::serialization::tablet::TabletId;
+    use crate::serialization::wayland;
+    use crate::serialization::wayland::DataDestinationRequest;
+    use crate::serialization::wayland::SurfaceState;
+    use crate::serialization::wlr_layer_shell::Layer;
+    use crate::serialization::wlr_layer_shell::LayerSurfaceConfigure;
+    use crate::server::testing::TestClient;
+    use crate::server::testing::TestServer;
+
+    const LAYERS: [(ClientLayer, Layer); 4] = [
+        (ClientLayer::Background, Layer::Background),
+        (ClientLayer::Bottom, Layer::Bottom),
+        (ClientLayer::Top, Layer::Top),
+        (ClientLayer::Overlay, Layer::Overlay),
+    ];
+
+    fn committed_surface_states(server: &TestServer) -> Vec<SurfaceState> {
+        server
+            .take_requests()
+            .into_iter()
+            .filter_map(|request| match request {
+                Request::Surface(SurfaceRequest {
+                    payload: SurfaceRequestPayload::Commit(surface_state),
+                    ..
+                }) => Some(surface_state),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// wprsc telling the server which outputs the surface is on.
+    fn outputs_changed(server: &mut TestServer, surface_id: WlSurfaceId, ids: &[u32]) {
+        server
+            .state
+            .handle_surface_event(SurfaceEvent {
+                surface_id,
+                payload: SurfaceEventPayload::OutputsChanged(
+                    ids.iter().map(|id| wayland::Output { id: *id }).collect(),
+                ),
+            })
+            .unwrap();
+    }
+
+    /// wprsc's tablet 1 and the given pens, which have no serial numbers.
+    fn add_tablet_tools(server: &mut TestServer, tools: &[TabletToolId]) {
+        server
+            .state
+            .handle_tablet(TabletEvent::TabletAdded(Tablet::new(TabletId(1))))
+            .unwrap();
+        for tool in tools {
+            server
+                .state
+                .handle_tablet(TabletEvent::ToolAdded(TabletTool::new(*tool)))
+                .unwrap();
+        }
+    }
+
+    /// Connects an application with a toplevel which has keyboard focus, so
+    /// that it's offered the selection.
+    fn connect_focused(server: &mut TestServer) -> TestClient {
+        let mut client = server.connect();
+        client.get_data_device();
+        client.create_toplevel();
+        server.roundtrip(&mut client);
+        let surface_id = committed_surface_states(server).pop().unwrap().id;
+        server
+            .state
+            .set_keyboard_focus(&surface_id, SERIAL_COUNTER.next_serial())
+            .unwrap();
+        client
+    }
+
+    /// Something was copied on wprsc's side.
+    fn set_wprsc_selection(server: &mut TestServer, mime_types: &[&str]) {
+        server
+            .state
+            .handle_data_event(DataEvent::DestinationEvent(
+                DataDestinationEvent::SelectionSet(
+                    DataSource::Selection,
+                    wayland::SourceMetadata::from_mime_types(
+                        mime_types.iter().map(ToString::to_string).collect(),
+                    ),
+                ),
+            ))
+            .unwrap();
+    }
+
+    fn push_selection_contents(server: &mut TestServer, mime_type: &str, data: &[u8]) {
+        server
+            .state
+            .handle_data_event(DataEvent::SelectionContents(
+                DataSource::Selection,
+                mime_type.to_string(),
+                DataToTransfer(data.to_vec()),
+            ))
+            .unwrap();
+    }
+
+    fn request_data_transfer(mime_type: &str) -> Request {
+        Request::Data(DataRequest::DestinationRequest(
+            DataDestinationRequest::RequestDataTransfer(
+                DataSource::Selection,
+                mime_type.to_string(),
+            ),
+        ))
+    }
+
+    #[test]
+    fn test_requested_layout() {
+        assert_eq!(requested_layout(0, 1), Some(Layout(0)));
+        assert_eq!(requested_layout(2, 3), Some(Layout(2)));
+        assert_eq!(requested_layout(1, 1), None);
+        assert_eq!(requested_layout(0, 0), None);
+    }
+
+    #[test]
+    fn test_removed_output_ids() {
+        // Two monitors, then one is unplugged while wprsc is disconnected.
+        assert_eq!(
+            removed_output_ids(
+                [1, 2].into_iter(),
+                &[OutputInfo::new_for_test(1), OutputInfo::new_for_test(2)]
+            ),
+            Vec::<u32>::new()
+        );
+        assert_eq!(
+            removed_output_ids([1, 2].into_iter(), &[OutputInfo::new_for_test(1)]),
+            vec![2]
+        );
+        assert_eq!(removed_output_ids([2, 1].into_iter(), &[]), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_remove_output() {
+        let mut server = TestServer::new();
+        server
+            .state
+            .handle_output(OutputEvent::Set(vec![
+                OutputInfo::new_for_test(1),
+                OutputInfo::new_for_test(2),
+            ]))
+            .unwrap();
+        let mut client = server.connect();
+        let outputs: Vec<WlOutput> = client.bind_all(1);
+        let (_surface, _xdg_surface, _toplevel) = client.create_toplevel();
+        server.roundtrip(&mut client);
+        let surface_id = committed_surface_states(&server).pop().unwrap().id;
+
+        outputs_changed(&mut server, surface_id, &[1]);
+        outputs_changed(&mut server, surface_id, &[1, 2]);
+        server.roundtrip(&mut client);
+        assert_eq!(client.state.surface_enters, outputs);
+
+        // Output 2 was unplugged while wprsc was disconnected.
+        server
+            .state
+            .handle_output(OutputEvent::Set(vec![OutputInfo::new_for_test(1)]))
+            .unwrap();
+        server.roundtrip(&mut client);
+        assert_eq!(client.state.surface_leaves, vec![outputs[1].clone()]);
+
+        server
+            .state
+            .handle_output(OutputEvent::Destroy(OutputInfo::new_for_test(1)))
+            .unwrap();
+        server.roundtrip(&mut client);
+        assert_eq!(
+            client.state.surface_leaves,
+            vec![outputs[1].clone(), outputs[0].clone()]
+        );
+
+        // The surface left output 2 along with the output, so it enters the
+        // output plugged in again with the same id.
+        server
+            .state
+            .handle_output(OutputEvent::New(OutputInfo::new_for_test(2)))
+            .unwrap();
+        server.roundtrip(&mut client);
+        let new_outputs: Vec<WlOutput> = client.bind_all(1);
+        server.roundtrip(&mut client);
+        outputs_changed(&mut server, surface_id, &[2]);
+        server.roundtrip(&mut client);
+        assert_eq!(client.state.surface_enters[2..], new_outputs[..]);
+    }
+
+    #[test]
+    fn test_serve_pushed_selection() {
+        let mut server = TestServer::new();
+        let mut client = connect_focused(&mut server);
+        set_wprsc_selection(&mut server, &["text/plain", "text/html"]);
+        push_selection_contents(&mut server, "text/plain", b"pushed");
+        server.roundtrip(&mut client);
+        server.take_requests();
+        assert_eq!(server.paste(&mut client, "text/plain"), b"pushed");
+
+        // What wasn't pushed is requested from wprsc, and kept for later
+        // pastes.
+        let mut pipe = server.request_paste(&mut client, "text/html");
+        assert_eq!(
+            server.take_requests(),
+            vec![request_data_transfer("text/html")]
+        );
+        server
+            .state
+            .handle_data_event(DataEvent::TransferData(
+                DataSource::Selection,
+                DataToTransfer(b"<p>".to_vec()),
+            ))
+            .unwrap();
+        let mut data = Vec::new();
+        pipe.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"<p>");
+        assert_eq!(server.paste(&mut client, "text/html"), b"<p>");
+
+        // The contents pushed for the previous selection aren't served for
+        // the next one.
+        set_wprsc_selection(&mut server, &["text/plain"]);
+        server.roundtrip(&mut client);
+        server.take_requests();
+        server.request_paste(&mut client, "text/plain");
+        assert_eq!(
+            server.take_requests(),
+            vec![request_data_transfer("text/plain")]
+        );
+    }
+
+    #[test]
+    fn test_selection_survives_reconnect() {
+        let mut server = TestServer::new();
+        let mut client = connect_focused(&mut server);
+
+        // Something was copied on wprsc's side, and wprsc pushed it.
+        set_wprsc_selection(&mut server, &["text/plain"]);
+        push_selection_contents(&mut server, "text/plain", b"copied");
+        server.roundtrip(&mut client);
+        server.take_requests();
+
+        // The connection drops...
+        server.state.serializer.set_other_end_connected(false);
+        assert_eq!(server.paste(&mut client, "text/plain"), b"copied");
+
+        // ...and wprsc reconnects.
+        server
+            .state
+            .handle_connect(ClientCapabilities {
+                buffer_formats: Vec::new(),
+                identity: "test".to_string(),
+            })
+            .unwrap();
+        server.take_requests();
+        assert_eq!(server.paste(&mut client, "text/plain"), b"copied");
+    }
+
+    #[test]
+    fn test_pressed_key_changes() {
+        // Nothing changed while the client was disconnected.
+        assert_eq!(
+            pressed_key_changes(&HashSet::from([29, 30]), &[29, 30]),
+            (Vec::new(), Vec::new())
+        );
+        // Ctrl and A were released while the client was disconnected, so
+        // they mustn't stay stuck.
+        assert_eq!(
+            pressed_key_changes(&HashSet::from([30, 29]), &[]),
+            (vec![29, 30], Vec::new())
+        );
+        // B and then Shift were pressed: Shift goes first so that it applies
+        // to B.
+        assert_eq!(
+            pressed_key_changes(&HashSet::from([30]), &[30, 48, 42]),
+            (Vec::new(), vec![42, 48])
+        );
+    }
+
+    #[test]
+    fn test_tablet_tool_transitions() {
+        let mut server = TestServer::new();
+        let mut client = server.connect();
+        let _tablet_seat = client.get_tablet_seat();
+        let (_surface, _xdg_surface, _toplevel) = client.create_toplevel();
+        server.roundtrip(&mut client);
+        let surface_id = committed_surface_states(&server).pop().unwrap().id;
+        add_tablet_tools(&mut server, &[TabletToolId(1)]);
+        let mut tool_frame = |server: &mut TestServer, events| {
+            server
+                .state
+                .handle_tablet(TabletEvent::ToolFrame {
+                    tool: TabletToolId(1),
+                    events,
+                })
+                .unwrap();
+            server.roundtrip(&mut client);
+            mem::take(&mut client.state.tablet_tool_events)
+        };
+        let proximity_in = TabletToolEvent::ProximityIn {
+            serial: 1,
+            tablet: TabletId(1),
+            surface_id,
+        };
+
+        // Nothing is under the tool.
+        assert!(tool_frame(&mut server, vec![TabletToolEvent::Down { serial: 2 }]).is_empty());
+
+        assert_eq!(
+            tool_frame(
+                &mut server,
+                vec![proximity_in, TabletToolEvent::Down { serial: 3 }]
+            ),
+            ["proximity_in", "down"]
+        );
+        assert_eq!(
+            tool_frame(&mut server, vec![TabletToolEvent::Up, TabletToolEvent::Up]),
+            ["up"]
+        );
+        assert_eq!(
+            tool_frame(&mut server, vec![TabletToolEvent::ProximityOut]),
+            ["proximity_out"]
+        );
+        assert!(tool_frame(&mut server, vec![TabletToolEvent::Up]).is_empty());
+
+        // wprsc reconnects in the middle of a stroke, the stroke ends.
+        tool_frame(
+            &mut server,
+            vec![proximity_in, TabletToolEvent::Down { serial: 4 }],
+        );
+        server
+            .state
+            .handle_connect(ClientCapabilities {
+                buffer_formats: Vec::new(),
+                identity: "test".to_string(),
+            })
+            .unwrap();
+        assert_eq!(tool_frame(&mut server, Vec::new()), ["up", "proximity_out"]);
+    }
+
+    #[test]
+    fn test_tablet_tools_with_identical_descriptors() {
+        let mut server = TestServer::new();
+        let mut client = server.connect();
+        let _tablet_seat = client.get_tablet_seat();
+        let (_surface, _xdg_surface, _toplevel) = client.create_toplevel();
+        server.roundtrip(&mut client);
+        let surface_id = committed_surface_states(&server).pop().unwrap().id;
+
+        // Two pens without serial numbers are still two tools...
+        add_tablet_tools(&mut server, &[TabletToolId(1), TabletToolId(2)]);
+        server.roundtrip(&mut client);
+        assert_eq!(client.state.tablet_tools_added, 2);
+
+        // ...and removing one leaves the other.
+        server
+            .state
+            .handle_tablet(TabletEvent::ToolRemoved(TabletToolId(1)))
+            .unwrap();
+        server
+            .state
+            .handle_tablet(TabletEvent::ToolFrame {
+                tool: TabletToolId(2),
+                events: vec![TabletToolEvent::ProximityIn {
+                    serial: 1,
+                    tablet: TabletId(1),
+                    surface_id,
+                }],
+            })
+            .unwrap();
+        server.roundtrip(&mut client);
+        assert_eq!(client.state.tablet_tool_events, ["proximity_in"]);
+    }
+
+    #[test]
+    fn test_layer_surface_configure() {
+        for (client_layer, layer) in LAYERS {
+            let mut server = TestServer::new();
+            let mut client = server.connect();
+            client.create_layer_surface(client_layer, 200, 30);
+            server.roundtrip(&mut client);
+
+            // The layer surface is configured by wprsc's compositor, not
+            // before.
+            let surface_state = committed_surface_states(&server).pop().unwrap();
+            let layer_surface_state = surface_state
+                .role
+                .clone()
+                .unwrap()
+                .into_layer_surface()
+                .unwrap();
+            assert_eq!(layer_surface_state.layer, layer);
+            assert_eq!(layer_surface_state.size, Size { w: 200, h: 30 });
+            assert!(client.state.layer_surface_configures.is_empty());
+
+            server
+                .state
+                .handle_layer_surface(LayerSurfaceEvent::Configure(LayerSurfaceConfigure {
+                    surface_id: surface_state.id,
+                    size: (1920, 30).into(),
+                }))
+                .unwrap();
+            server.roundtrip(&mut client);
+            assert_eq!(client.state.layer_surface_configures, vec![(1920, 30)]);
+
+            server
+                .state
+                .handle_layer_surface(LayerSurfaceEvent::Closed(surface_state.id))
+                .unwrap();
+            server.roundtrip(&mut client);
+            assert_eq!(client.state.layer_surface_closes, 1);
+        }
+    }
+
+    #[test]
+    fn test_layer_surface_configure_without_client() {
+        for (client_layer, _) in LAYERS {
+            let mut server = TestServer::new();
+            server.state.serializer.set_other_end_connected(false);
+            let mut client = server.connect();
+            let (surface, _) = client.create_layer_surface(client_layer, 200, 30);
+            server.roundtrip(&mut client);
+
+            // The application gets the size it asked for...
+            assert_eq!(client.state.layer_surface_configures, vec![(200, 30)]);
+
+            // ...only once.
+            surface.commit();
+            server.roundtrip(&mut client);
+            assert_eq!(client.state.layer_surface_configures, vec![(200, 30)]);
+        }
+    }
+
+    #[test]
+    fn test_session_lock_holds_commits() {
+        let mut server = TestServer::new();
+        let mut application = server.connect();
+        let (surface, _, _) = application.create_toplevel();
+        server.roundtrip(&mut application);
+        let mut locker = server.connect();
+        let session_lock = locker.lock_session();
+        server.roundtrip(&mut locker);
+        assert!(server
+            .take_requests()
+            .contains(&Request::SessionLock(SessionLockRequest::Lock)));
+
+        // The lock is confirmed once wprsc's compositor locked the local
+        // session.
+        assert!(!locker.state.session_locked);
+        server
+            .state
+            .handle_session_lock(SessionLockEvent::Locked)
+            .unwrap();
+        server.roundtrip(&mut locker);
+        assert!(locker.state.session_locked);
+
+        // Other applications' commits are held while the session is locked...
+        surface.commit();
+        server.roundtrip(&mut application);
+        assert!(committed_surface_states(&server).is_empty());
+
+        // ...and sent once it's unlocked.
+        session_lock.unlock_and_destroy();
+        server.roundtrip(&mut locker);
+        let requests = server.take_requests();
+        assert!(requests.contains(&Request::SessionLock(SessionLockRequest::Unlock)));
+        assert!(requests.iter().any(|request| matches!(
+            request,
+            Request::Surface(SurfaceRequest {
+                payload: SurfaceRequestPayload::Commit(_),
+                ..
+            })
+        )));
+    }
+
+    #[test]
+    fn test_session_lock_refused_while_locked() {
+        let mut server = TestServer::new();
+        let mut locker = server.connect();
+        locker.lock_session();
+        server.roundtrip(&mut locker);
+
+        // Another application can't take over the lock.
+        let mut other_locker = server.connect();
+        other_locker.lock_session();
+        server.roundtrip(&mut other_locker);
+        assert!(other_locker.state.session_lock_finished);
+        server.roundtrip(&mut locker);
+        assert!(!locker.state.session_lock_finished);
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The contents of wprsc's selection, so that applications can paste what was
+//! copied on the client without a round trip to wprsc, and while wprsc is
+//! disconnected, e.g. because the network dropped.
+//!
+//! Contents are cached when wprsc pushes them (see
+//! WprsClientOptions::clipboard_push_on_focus) and when an application pastes
+//! them. Contents which would take the cache over its size limit aren't cached
+//! and are requested from wprsc on every paste.
+
+use std::collections::HashMap;
+
+#[derive(Debug)]
+pub struct ClipboardCache {
+    max_bytes: usize,
+    /// The mime types wprsc's selection is offered in, empty if the selection
+    /// isn't wprsc's.
+    mime_types: Vec<String>,
+    contents: HashMap<String, Vec<u8>>,
+    size: usize,
+}
+
+impl ClipboardCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            mime_types: Vec::new(),
+            contents: HashMap::new(),
+            size: 0,
+        }
+    }
+
+    /// Forgets the previous selection. wprsc's new selection is offered in
+    /// mime_types.
+    pub fn set_selection(&mut self, mime_types: Vec<String>) {
+        self.clear();
+        self.mime_types = mime_types;
+    }
+
+    /// Forgets the selection, e.g. because an application set it.
+    pub fn clear(&mut self) {
+        self.mime_types.clear();
+        self.contents.clear();
+        self.size = 0;
+    }
+
+    /// Returns whether data was cached.
+    pub fn insert(&mut self, mime_type: &str, data: &[u8]) -> bool {
+        if !self.mime_types.iter().any(|offered| offered == mime_type) {
+            return false;
+        }
+        let replaced = self.contents.get(mime_type).map_or(0, Vec::len);
+        let size = self.size - replaced + data.len();
+        if size > self.max_bytes {
+            return false;
+        }
+        self.size = size;
+        self.contents.insert(mime_type.to_string(), data.to_vec());
+        true
+    }
+
+    pub fn get(&self, mime_type: &str) -> Option<&[u8]> {
+        self.contents.get(mime_type).map(Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mime_types() -> Vec<String> {
+        vec!["text/plain".to_string(), "image/png".to_string()]
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut cache = ClipboardCache::new(8);
+        // There's no selection yet.
+        assert!(!cache.insert("text/plain", b"abc"));
+
+        cache.set_selection(mime_types());
+        assert!(cache.insert("text/plain", b"abc"));
+        assert!(!cache.insert("text/html", b"abc"));
+        // Over the limit.
+        assert!(!cache.insert("image/png", b"123456"));
+        assert!(cache.insert("image/png", b"12345"));
+        assert_eq!(cache.get("text/plain"), Some(&b"abc"[..]));
+        assert_eq!(cache.get("image/png"), Some(&b"12345"[..]));
+
+        // Replacing contents only counts their new size.
+        assert!(cache.insert("text/plain", b"ab"));
+        assert!(cache.insert("image/png", b"123456"));
+        assert_eq!(cache.get("text/plain"), Some(&b"ab"[..]));
+    }
+
+    #[test]
+    fn test_set_selection() {
+        let mut cache = ClipboardCache::new(8);
+        cache.set_selection(mime_types());
+        assert!(cache.insert("text/plain", b"abcdefgh"));
+
+        cache.set_selection(mime_types());
+        assert_eq!(cache.get("text/plain"), None);
+        assert!(cache.insert("image/png", b"12345678"));
+
+        cache.clear();
+        assert_eq!(cache.get("image/png"), None);
+        assert!(!cache.insert("image/png", b"1"));
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Limits the buffers in flight to wprsc for each surface, so that a wprsc
+//! which can't keep up with an application doesn't make buffers pile up in the
+//! serializer's write queue.
+//!
+//! A buffer is in flight from when it's queued until wprsc acknowledges it
+//! with SurfaceEventPayload::FrameAck. While a surface's budget is used up,
+//! its buffers are dropped, and its latest buffer is sent once wprsc catches
+//! up, so that wprsc doesn't stay on a stale frame.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::hash::Hash;
+
+#[derive(Debug)]
+pub struct FrameBudget<K> {
+    /// 0 disables the limit.
+    max_in_flight_frames: u32,
+    in_flight: HashMap<K, u32>,
+    /// Surfaces which had a buffer dropped since their last buffer was sent.
+    dropped: HashSet<K>,
+}
+
+impl<K: Hash + Eq + Clone> FrameBudget<K> {
+    pub fn new(max_in_flight_frames: u32) -> Self {
+        Self {
+            max_in_flight_frames,
+            in_flight: HashMap::new(),
+            dropped: HashSet::new(),
+        }
+    }
+
+    /// Whether a buffer for surface may be sent now. If it may, it's counted
+    /// as in flight, otherwise it's remembered as dropped.
+    pub fn try_send(&mut self, surface: &K) -> bool {
+        let in_flight = self.in_flight.entry(surface.clone()).or_default();
+        if self.max_in_flight_frames != 0 && *in_flight >= self.max_in_flight_frames {
+            self.dropped.insert(surface.clone());
+            return false;
+        }
+        *in_flight += 1;
+        self.dropped.remove(surface);
+        true
+    }
+
+    /// Records wprsc's acknowledgement of a buffer for surface. Returns
+    /// whether a buffer was dropped since the last one was sent, in which case
+    /// the latest buffer must be sent and is counted as in flight.
+    pub fn ack(&mut self, surface: &K) -> bool {
+        if let Some(in_flight) = self.in_flight.get_mut(surface) {
+            *in_flight = in_flight.saturating_sub(1);
+        }
+        self.dropped.contains(surface) && self.try_send(surface)
+    }
+
+    /// Undoes try_send, or an ack which returned true, for a buffer which
+    /// wasn't sent after all.
+    pub fn cancel(&mut self, surface: &K) {
+        if let Some(in_flight) = self.in_flight.get_mut(surface) {
+            *in_flight = in_flight.saturating_sub(1);
+        }
+    }
+
+    pub fn in_flight(&self, surface: &K) -> u32 {
+        self.in_flight.get(surface).copied().unwrap_or(0)
+    }
+
+    pub fn remove(&mut self, surface: &K) {
+        self.in_flight.remove(surface);
+        self.dropped.remove(surface);
+    }
+
+    /// Forgets all buffers in flight, e.g. because wprsc reconnected and won't
+    /// acknowledge the buffers sent to the previous one.
+    pub fn clear(&mut self) {
+        self.in_flight.clear();
+        self.dropped.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    #[test]
+    fn test_unlimited() {
+        let mut budget = FrameBudget::new(0);
+        for _ in 0..100 {
+            assert!(budget.try_send(&1));
+        }
+        assert_eq!(budget.in_flight(&1), 100);
+    }
+
+    #[test]
+    fn test_slow_client() {
+        let max_in_flight_frames = 3;
+        let mut budget = FrameBudget::new(max_in_flight_frames);
+        // Frames queued for the client, by the frame number the application
+        // drew.
+        let mut queue = VecDeque::new();
+        let mut dropped = 0;
+
+        // The application draws a frame every tick, the client only takes 10
+        // ticks to decode each frame.
+        for frame in 0..1000 {
+            if budget.try_send(&1) {
+                queue.push_back(frame);
+            } else {
+                dropped += 1;
+            }
+            if frame % 10 == 9 {
+                queue.pop_front().unwrap();
+                if budget.ack(&1) {
+                    // The latest frame is resent.
+                    queue.push_back(frame);
+                }
+            }
+            assert!(queue.len() <= max_in_flight_frames as usize);
+            assert_eq!(queue.len(), budget.in_flight(&1) as usize);
+        }
+        assert!(dropped > 0);
+
+        // Once the application stops drawing, the client ends up with its
+        // last frame.
+        let mut last_decoded = None;
+        while let Some(frame) = queue.pop_front() {
+            last_decoded = Some(frame);
+            if budget.ack(&1) {
+                queue.push_back(999);
+            }
+        }
+        assert_eq!(last_decoded, Some(999));
+        assert_eq!(budget.in_flight(&1), 0);
+    }
+
+    #[test]
+    fn test_latest_frame_resent() {
+        let mut budget = FrameBudget::new(1);
+        assert!(budget.try_send(&1));
+        assert!(!budget.try_send(&1));
+        assert!(budget.ack(&1));
+        assert_eq!(budget.in_flight(&1), 1);
+        assert!(!budget.ack(&1));
+        assert_eq!(budget.in_flight(&1), 0);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut budget = FrameBudget::new(1);
+        assert!(budget.try_send(&1));
+        assert!(!budget.try_send(&1));
+        assert!(budget.ack(&1));
+        budget.cancel(&1);
+        assert_eq!(budget.in_flight(&1), 0);
+        assert!(budget.try_send(&1));
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut budget = FrameBudget::new(1);
+        assert!(budget.try_send(&1));
+        assert!(!budget.try_send(&1));
+        budget.clear();
+        assert_eq!(budget.in_flight(&1), 0);
+        assert!(!budget.ack(&1));
+        assert!(budget.try_send(&1));
+    }
+}
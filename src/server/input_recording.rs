@@ -0,0 +1,266 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording of the input events wprsd receives from wprsc, and replay of
+//! such a recording into the server at the original cadence, for
+//! reproducible benchmarks and bug reports.
+//!
+//! A recording is a sequence of records, each of which is a big-endian u32
+//! length followed by an archived RecordedEvent. Surfaces are referred to by
+//! the same ids as on the wire, so a replay only targets the same surfaces if
+//! the applications are started the same way as during the recording.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
+
+use rkyv::bytecheck;
+use rkyv::AlignedVec;
+use rkyv::Archive;
+use rkyv::Deserialize;
+use rkyv::Serialize;
+use smithay::reexports::calloop::timer::TimeoutAction;
+use smithay::reexports::calloop::timer::Timer;
+use smithay::reexports::calloop::LoopHandle;
+
+use crate::prelude::*;
+use crate::serialization::Event;
+use crate::serialization::RecvType;
+use crate::serialization::SERIALIZE_SCRATCH_SPACE;
+use crate::server::WprsServerState;
+
+#[derive(Debug, Clone, PartialEq, Archive, Deserialize, Serialize)]
+#[archive_attr(derive(bytecheck::CheckBytes, Debug))]
+pub struct RecordedEvent {
+    /// Time since the start of the recording.
+    pub offset_micros: u64,
+    pub event: Event,
+}
+
+impl RecordedEvent {
+    fn offset(&self) -> Duration {
+        Duration::from_micros(self.offset_micros)
+    }
+}
+
+fn is_input(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::KeyboardEvent(_) | Event::PointerFrame(_) | Event::TouchFrame(_) | Event::Tablet(_)
+    )
+}
+
+#[derive(Debug)]
+pub struct InputRecorder<W: Write> {
+    stream: W,
+    start: Instant,
+}
+
+impl InputRecorder<File> {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(File::create(path).location(loc!())?))
+    }
+}
+
+impl<W: Write> InputRecorder<W> {
+    pub fn new(stream: W) -> Self {
+        Self {
+            stream,
+            start: Instant::now(),
+        }
+    }
+
+    /// Appends event to the recording if it is an input event. Each record is
+    /// written out in full so that the recording is usable even if wprsd is
+    /// killed.
+    pub fn record(&mut self, event: &Event) -> Result<()> {
+        if !is_input(event) {
+            return Ok(());
+        }
+        let recorded_event = RecordedEvent {
+            offset_micros: self.start.elapsed().as_micros() as u64,
+            event: event.clone(),
+        };
+        self.write(&recorded_event).location(loc!())
+    }
+
+    fn write(&mut self, recorded_event: &RecordedEvent) -> Result<()> {
+        let bytes = rkyv::to_bytes::<_, SERIALIZE_SCRATCH_SPACE>(recorded_event)
+            // The error type is not Send + Sync, which anyhow requires.
+            .map_err(|e| anyhow!("{e}"))
+            .location(loc!())?;
+        let len: u32 = bytes.len().try_into().location(loc!())?;
+        let mut record = Vec::with_capacity(4 + bytes.len());
+        record.extend_from_slice(&len.to_be_bytes());
+        record.extend_from_slice(&bytes);
+        self.stream.write_all(&record).location(loc!())?;
+        self.stream.flush().location(loc!())
+    }
+}
+
+pub fn read_recording<R: Read>(mut stream: R) -> Result<Vec<RecordedEvent>> {
+    let mut recorded_events = Vec::new();
+    loop {
+        let mut len_buf = [0; 4];
+        match stream.read_exact(&mut len_buf) {
+            Ok(()) => {},
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e).location(loc!()),
+        }
+        let mut buf = vec![0; u32::from_be_bytes(len_buf) as usize];
+        stream.read_exact(&mut buf).location(loc!())?;
+
+        // rkyv requires the buffer to be aligned.
+        let mut aligned_buf = AlignedVec::with_capacity(buf.len());
+        aligned_buf.extend_from_slice(&buf);
+        let recorded_event: RecordedEvent = rkyv::from_bytes(&aligned_buf)
+            // The error type is not Send + Sync, which anyhow requires.
+            .map_err(|e| anyhow!("{e}"))
+            .location(loc!())?;
+        recorded_events.push(recorded_event);
+    }
+    Ok(recorded_events)
+}
+
+#[derive(Debug)]
+pub struct InputReplay {
+    recorded_events: VecDeque<RecordedEvent>,
+}
+
+impl InputReplay {
+    pub fn new(recorded_events: Vec<RecordedEvent>) -> Self {
+        Self {
+            recorded_events: recorded_events.into(),
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = BufReader::new(File::open(path).location(loc!())?);
+        Ok(Self::new(read_recording(file).location(loc!())?))
+    }
+
+    /// The offset of the next event to be replayed, or None if the replay is
+    /// finished.
+    pub fn next_offset(&self) -> Option<Duration> {
+        self.recorded_events.front().map(RecordedEvent::offset)
+    }
+
+    /// Removes and returns the events, in order, which were recorded at or
+    /// before elapsed.
+    pub fn take_due(&mut self, elapsed: Duration) -> Vec<Event> {
+        let mut events = Vec::new();
+        while self.next_offset().is_some_and(|offset| offset <= elapsed) {
+            events.push(self.recorded_events.pop_front().unwrap().event);
+        }
+        events
+    }
+
+    /// Replays the events into state's event loop, starting now.
+    pub fn start(mut self, lh: &LoopHandle<'static, WprsServerState>) -> Result<()> {
+        let start = Instant::now();
+        let Some(first_offset) = self.next_offset() else {
+            info!("input recording is empty");
+            return Ok(());
+        };
+        lh.insert_source(
+            Timer::from_deadline(start + first_offset),
+            move |_, _, state| {
+                for event in self.take_due(start.elapsed()) {
+                    state.handle_event(RecvType::Object(event));
+                }
+                match self.next_offset() {
+                    Some(offset) => TimeoutAction::ToInstant(start + offset),
+                    None => {
+                        info!("finished replaying input");
+                        TimeoutAction::Drop
+                    },
+                }
+            },
+        )
+        .location(loc!())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialization::wayland::IdleEvent;
+    use crate::serialization::wayland::KeyboardEvent;
+
+    fn keyboard_leave(serial: u32) -> Event {
+        Event::KeyboardEvent(KeyboardEvent::Leave { serial })
+    }
+
+    #[test]
+    fn test_record_and_read() {
+        let mut buf = Vec::new();
+        let mut recorder = InputRecorder::new(&mut buf);
+        recorder.record(&keyboard_leave(1)).unwrap();
+        recorder.record(&Event::Idle(IdleEvent::Idle)).unwrap();
+        recorder.record(&Event::PointerFrame(Vec::new())).unwrap();
+
+        let recorded_events = read_recording(buf.as_slice()).unwrap();
+        let events: Vec<Event> = recorded_events.iter().map(|r| r.event.clone()).collect();
+        assert_eq!(
+            events,
+            vec![keyboard_leave(1), Event::PointerFrame(Vec::new())]
+        );
+        assert!(recorded_events[0].offset_micros <= recorded_events[1].offset_micros);
+    }
+
+    #[test]
+    fn test_read_truncated_recording() {
+        let mut buf = Vec::new();
+        InputRecorder::new(&mut buf)
+            .record(&keyboard_leave(1))
+            .unwrap();
+        buf.pop();
+        assert!(read_recording(buf.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_take_due() {
+        let mut replay = InputReplay::new(
+            [(0, 1), (10, 2), (10, 3), (30, 4)]
+                .into_iter()
+                .map(|(offset_micros, serial)| RecordedEvent {
+                    offset_micros,
+                    event: keyboard_leave(serial),
+                })
+                .collect(),
+        );
+
+        assert_eq!(
+            replay.take_due(Duration::from_micros(0)),
+            vec![keyboard_leave(1)]
+        );
+        assert_eq!(replay.next_offset(), Some(Duration::from_micros(10)));
+        assert_eq!(replay.take_due(Duration::from_micros(5)), Vec::new());
+        assert_eq!(
+            replay.take_due(Duration::from_micros(20)),
+            vec![keyboard_leave(2), keyboard_leave(3)]
+        );
+        assert_eq!(
+            replay.take_due(Duration::from_micros(100)),
+            vec![keyboard_leave(4)]
+        );
+        assert_eq!(replay.next_offset(), None);
+    }
+}
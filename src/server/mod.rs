@@ -14,46 +14,92 @@
 
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::mem;
 use std::os::fd::OwnedFd;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::Duration;
 use std::time::Instant;
 
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
 use smithay::input::Seat;
 use smithay::input::SeatState;
 use smithay::output::Output;
 use smithay::reexports::calloop::LoopHandle;
+use smithay::reexports::wayland_server::backend::ClientId;
 use smithay::reexports::wayland_server::backend::GlobalId;
 use smithay::reexports::wayland_server::backend::ObjectId;
+use smithay::reexports::wayland_server::protocol::wl_callback::WlCallback;
 use smithay::reexports::wayland_server::protocol::wl_data_source::WlDataSource;
 use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
 use smithay::reexports::wayland_server::DisplayHandle;
 use smithay::reexports::wayland_server::Resource;
+use smithay::utils::Clock;
+use smithay::utils::Monotonic;
 use smithay::wayland::compositor;
 use smithay::wayland::compositor::CompositorState;
 use smithay::wayland::compositor::SurfaceData;
 use smithay::wayland::compositor::TraversalAction;
+use smithay::wayland::content_type::ContentTypeState;
+use smithay::wayland::fractional_scale::FractionalScaleManagerState;
+use smithay::wayland::idle_inhibit::IdleInhibitManagerState;
+use smithay::wayland::keyboard_shortcuts_inhibit::KeyboardShortcutsInhibitState;
+use smithay::wayland::output::OutputManagerState;
+use smithay::wayland::pointer_constraints::PointerConstraintsState;
+use smithay::wayland::presentation::PresentationFeedbackCallback;
+use smithay::wayland::presentation::PresentationState;
+use smithay::wayland::relative_pointer::RelativePointerManagerState;
 use smithay::wayland::selection::data_device::DataDeviceState;
 use smithay::wayland::selection::primary_selection::PrimarySelectionState;
+use smithay::wayland::session_lock::LockSurface;
+use smithay::wayland::session_lock::SessionLockManagerState;
+use smithay::wayland::session_lock::SessionLocker;
 use smithay::wayland::shell::kde::decoration::KdeDecorationState;
+use smithay::wayland::shell::wlr_layer::WlrLayerShellState;
 use smithay::wayland::shell::xdg::XdgShellState;
 use smithay::wayland::shell::xdg::decoration::XdgDecorationState;
 use smithay::wayland::shm::ShmState;
+use smithay::wayland::tablet_manager::TabletHandle;
+use smithay::wayland::tablet_manager::TabletManagerState;
+use smithay::wayland::viewporter::ViewporterState;
+use smithay::wayland::xdg_activation::XdgActivationState;
 use smithay::reexports::wayland_protocols_misc::server_decoration::server::org_kde_kwin_server_decoration_manager::Mode as KdeDecorationMode;
 
+use crate::compositor_utils::ScrollAccumulator;
 use crate::prelude::*;
+use crate::serialization::tablet::Tablet;
+use crate::serialization::tablet::TabletId;
+use crate::serialization::tablet::TabletTool;
+use crate::serialization::tablet::TabletToolId;
 use crate::serialization::wayland::SurfaceRequest;
 use crate::serialization::wayland::SurfaceRequestPayload;
 use crate::serialization::wayland::SurfaceState;
 use crate::serialization::wayland::WlSurfaceId;
+use crate::serialization::ClientCapabilities;
 use crate::serialization::Event;
 use crate::serialization::Request;
 use crate::serialization::SendType;
 use crate::serialization::Serializer;
+use crate::server::clipboard_cache::ClipboardCache;
+use crate::server::frame_budget::FrameBudget;
+use crate::server::pending_frame_callbacks::PendingFrameCallbacks;
+use crate::server::text_input::TextInputManagerState;
+use crate::server::touch_points::TouchPoints;
 use crate::utils::SerialMap;
 
 pub mod client_handlers;
+pub mod clipboard_cache;
+pub mod frame_budget;
+pub mod input_recording;
+pub mod pending_frame_callbacks;
 pub mod smithay_handlers;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod text_input;
+pub mod touch_points;
 
 struct LockedSurfaceState(Mutex<SurfaceState>);
 
@@ -76,9 +122,89 @@ fn surface_destruction_callback(state: &mut WprsServerState, surface: &WlSurface
         })));
 
         state.object_map.remove(&surface_state.id);
+        state.serializer.stats().remove_surface(surface_state.id);
     });
+    state.discard_presentation_feedback(&surface.id());
+    state.frame_budget.remove(&surface.id());
 }
 
+/// What to do with keys which are held when wprsc's keyboard focus leaves a
+/// surface.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub enum KeyboardFocusLossBehavior {
+    /// Release all held keys, which is what a wayland compositor normally does
+    /// on leave.
+    #[default]
+    Release,
+    /// Keep held keys pressed and reconcile them against the held keys
+    /// reported by the next enter. Keys released while unfocused are released
+    /// then.
+    Reconcile,
+}
+
+/// When applications are told, via wl_surface.frame callbacks, to draw their
+/// next frame.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub enum FramePacing {
+    /// Send frame callbacks on a timer at the configured framerate.
+    #[default]
+    Timer,
+    /// Send frame callbacks when wprsc gets a frame callback for the surface
+    /// from the local compositor, so that applications draw at the rate at
+    /// which frames are actually presented. Callbacks are still sent after
+    /// CLIENT_PACED_FRAME_TIMEOUT so that applications don't stall on
+    /// surfaces which wprsc never presents.
+    Client,
+}
+
+/// What to do with commits which carry damage but neither a new buffer nor any
+/// other change, e.g. from applications which redraw into the buffer they
+/// already attached.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub enum DamageOnlyCommits {
+    /// Send them to wprsc, which presents the surface's current buffer again
+    /// with the damage.
+    #[default]
+    Forward,
+    /// Drop them. The surface is only updated by the next commit which
+    /// changes something.
+    Ignore,
+}
+
+/// An application's lock of the session, e.g. a screen locker's.
+struct SessionLock {
+    /// The application holding the lock. While the session is locked, only
+    /// its surfaces are sent to wprsc.
+    client: ClientId,
+    /// The lock's confirmation, held until wprsc reports that the local
+    /// session is locked.
+    locker: Option<SessionLocker>,
+    lock_surfaces: Vec<LockSurface>,
+    /// Surfaces of other applications which were committed while the session
+    /// was locked. They're committed again when it's unlocked.
+    held_surfaces: Vec<WlSurface>,
+}
+
+/// A tablet tool of wprsc's compositor.
+struct TabletToolState {
+    tool: TabletTool,
+    /// The tablet and surface the tool is in proximity of.
+    proximity: Option<(TabletHandle, WlSurface)>,
+    /// Whether the tip of the tool is down.
+    down: bool,
+}
+
+impl TabletToolState {
+    /// Lifts the tool and takes it out of proximity. Returns whether it was
+    /// down and whether it was in proximity, i.e., what applications have to
+    /// be told.
+    fn release(&mut self) -> (bool, bool) {
+        (mem::take(&mut self.down), self.proximity.take().is_some())
+    }
+}
+
+pub(crate) const CLIENT_PACED_FRAME_TIMEOUT: Duration = Duration::from_millis(250);
+
 pub struct WprsServerState {
     pub dh: DisplayHandle,
     pub lh: LoopHandle<'static, Self>,
@@ -86,8 +212,18 @@ pub struct WprsServerState {
     pub start_time: Instant,
     pub frame_interval: Duration,
     pub xwayland_enabled: bool,
+    pub keyboard_focus_loss_behavior: KeyboardFocusLossBehavior,
+    pub frame_pacing: FramePacing,
+    pub damage_only_commits: DamageOnlyCommits,
+    /// Whether to compress the contents of surfaces whose content type is
+    /// video.
+    pub compress_video: bool,
+    /// Whether to send a checksum of each frame, for wprsc to compare with the
+    /// frame it decoded.
+    pub frame_checksums: bool,
     pub xdg_shell_state: XdgShellState,
     pub xdg_decoration_state: XdgDecorationState,
+    pub wlr_layer_shell_state: WlrLayerShellState,
     // TODO(https://gitlab.gnome.org/GNOME/gtk/-/merge_requests/6398): rip this
     // out once GTK switches to xdg-decoration-protocol and applications/distros
     // move to GTK4.
@@ -96,10 +232,30 @@ pub struct WprsServerState {
     pub seat_state: SeatState<Self>,
     pub data_device_state: DataDeviceState,
     pub primary_selection_state: PrimarySelectionState,
+    pub viewporter_state: ViewporterState,
+    pub fractional_scale_manager_state: FractionalScaleManagerState,
+    pub output_manager_state: OutputManagerState,
+    pub xdg_activation_state: XdgActivationState,
+    pub relative_pointer_manager_state: RelativePointerManagerState,
+    pub pointer_constraints_state: PointerConstraintsState,
+    pub session_lock_state: SessionLockManagerState,
+    pub tablet_manager_state: TabletManagerState,
+    pub content_type_state: ContentTypeState,
+    pub presentation_state: PresentationState,
+    pub keyboard_shortcuts_inhibit_state: KeyboardShortcutsInhibitState,
+    pub idle_inhibit_manager_state: IdleInhibitManagerState,
+    pub text_input_manager_state: TextInputManagerState,
 
     pub seat: Seat<Self>,
 
     pub serializer: Serializer<Request, Event>,
+    pub client_capabilities: ClientCapabilities,
+    /// Identity of the most recently connected client, shared with the
+    /// control server.
+    pub client_identity: Arc<Mutex<String>>,
+    /// Whether the user of the connected client is idle, shared with the
+    /// control server.
+    pub client_idle: Arc<AtomicBool>,
     /// Reverse map from WlSurfaceId, which is the hash of ObjectId, back to its
     /// source ObjectId. We can't put this in SurfaceState because is
     /// serializable, while this only has meaning locally. We need this for
@@ -112,12 +268,38 @@ pub struct WprsServerState {
     pub outputs: HashMap<u32, (Output, GlobalId)>,
     serial_map: SerialMap,
     pressed_keys: HashSet<u32>,
+    /// The keyboard layout group the client last reported.
+    layout_index: u32,
     pressed_buttons: HashSet<u32>,
+    /// Surfaces which the active touch points went down on, by touch id.
+    touch_points: TouchPoints<WlSurface>,
+    tablets: HashMap<TabletId, Tablet>,
+    tablet_tools: HashMap<TabletToolId, TabletToolState>,
+    horizontal_scroll: ScrollAccumulator,
+    vertical_scroll: ScrollAccumulator,
+    /// Frame callbacks withheld while no client is connected, so that
+    /// applications stop drawing frames that no one will see. They're sent
+    /// when a client connects.
+    parked_frame_callbacks: Vec<(WlSurface, Vec<WlCallback>)>,
+    /// Frame callbacks waiting for wprsc to present their surface, when
+    /// frame_pacing is Client.
+    client_paced_frame_callbacks: PendingFrameCallbacks<ObjectId, WlCallback>,
+    /// Presentation feedback of the commits whose buffers were sent to wprsc,
+    /// oldest first, waiting for wprsc to report their presentation.
+    presentation_feedback: HashMap<ObjectId, VecDeque<Vec<PresentationFeedbackCallback>>>,
+    /// The buffers sent to wprsc which it hasn't acknowledged yet, by surface.
+    frame_budget: FrameBudget<ObjectId>,
+    /// The clock of the presentation timestamps sent to applications.
+    clock: Clock<Monotonic>,
 
-    selection_pipe: Option<OwnedFd>,
+    /// With the mime type requested, whose contents are cached once wprsc
+    /// sends them.
+    selection_pipe: Option<(String, OwnedFd)>,
+    clipboard_cache: ClipboardCache,
     dnd_source: Option<WlDataSource>,
     dnd_pipe: Option<OwnedFd>,
     primary_selection_pipe: Option<OwnedFd>,
+    session_lock: Option<SessionLock>,
 }
 
 impl WprsServerState {
@@ -128,9 +310,17 @@ impl WprsServerState {
         xwayland_enabled: bool,
         frame_interval: Duration,
         kde_server_side_decorations: bool,
+        keyboard_focus_loss_behavior: KeyboardFocusLossBehavior,
+        frame_pacing: FramePacing,
+        damage_only_commits: DamageOnlyCommits,
+        compress_video: bool,
+        frame_checksums: bool,
+        max_in_flight_frames: u32,
+        max_clipboard_cache_bytes: usize,
     ) -> Self {
         let mut seat_state = SeatState::new();
         let seat = seat_state.new_wl_seat(&dh, "wprs");
+        let clock = Clock::new();
         let kde_default_decoration_mode = if kde_server_side_decorations {
             KdeDecorationMode::Server
         } else {
@@ -144,27 +334,116 @@ impl WprsServerState {
             start_time: Instant::now(),
             xwayland_enabled,
             frame_interval,
+            keyboard_focus_loss_behavior,
+            frame_pacing,
+            damage_only_commits,
+            compress_video,
+            frame_checksums,
             xdg_shell_state: XdgShellState::new::<Self>(&dh),
             xdg_decoration_state: XdgDecorationState::new::<Self>(&dh),
+            wlr_layer_shell_state: WlrLayerShellState::new::<Self>(&dh),
             kde_decoration_state: KdeDecorationState::new::<Self>(&dh, kde_default_decoration_mode),
             shm_state: ShmState::new::<Self>(&dh, Vec::new()),
             seat_state,
             data_device_state: DataDeviceState::new::<Self>(&dh),
             primary_selection_state: PrimarySelectionState::new::<Self>(&dh),
+            viewporter_state: ViewporterState::new::<Self>(&dh),
+            fractional_scale_manager_state: FractionalScaleManagerState::new::<Self>(&dh),
+            // Some clients, e.g. for night light, need the logical geometry of
+            // outputs from xdg-output.
+            output_manager_state: OutputManagerState::new_with_xdg_output::<Self>(&dh),
+            xdg_activation_state: XdgActivationState::new::<Self>(&dh),
+            relative_pointer_manager_state: RelativePointerManagerState::new::<Self>(&dh),
+            pointer_constraints_state: PointerConstraintsState::new::<Self>(&dh),
+            session_lock_state: SessionLockManagerState::new::<Self, _>(&dh, |_| true),
+            tablet_manager_state: TabletManagerState::new::<Self>(&dh),
+            content_type_state: ContentTypeState::new::<Self>(&dh),
+            presentation_state: PresentationState::new::<Self>(&dh, clock.id() as u32),
+            keyboard_shortcuts_inhibit_state: KeyboardShortcutsInhibitState::new::<Self>(&dh),
+            idle_inhibit_manager_state: IdleInhibitManagerState::new::<Self>(&dh),
+            text_input_manager_state: TextInputManagerState::new(&dh),
             seat,
             serializer,
+            client_capabilities: ClientCapabilities::default(),
+            client_identity: Arc::new(Mutex::new(String::new())),
+            client_idle: Arc::new(AtomicBool::new(false)),
             object_map: HashMap::new(),
             outputs: HashMap::new(),
             serial_map: SerialMap::new(),
             pressed_keys: HashSet::new(),
+            layout_index: 0,
             pressed_buttons: HashSet::new(),
+            touch_points: TouchPoints::default(),
+            tablets: HashMap::new(),
+            tablet_tools: HashMap::new(),
+            horizontal_scroll: ScrollAccumulator::default(),
+            vertical_scroll: ScrollAccumulator::default(),
+            parked_frame_callbacks: Vec::new(),
+            client_paced_frame_callbacks: PendingFrameCallbacks::default(),
+            presentation_feedback: HashMap::new(),
+            frame_budget: FrameBudget::new(max_in_flight_frames),
+            clock,
             selection_pipe: None,
+            clipboard_cache: ClipboardCache::new(max_clipboard_cache_bytes),
             dnd_source: None,
             dnd_pipe: None,
             primary_selection_pipe: None,
+            session_lock: None,
         }
     }
 
+    /// Discards the presentation feedback which is waiting for wprsc to
+    /// present surface.
+    pub(crate) fn discard_presentation_feedback(&mut self, surface: &ObjectId) {
+        for callback in self
+            .presentation_feedback
+            .remove(surface)
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            callback.discarded();
+        }
+    }
+
+    pub(crate) fn send_frame_callbacks(&self, surface: &WlSurface, callbacks: Vec<WlCallback>) {
+        for callback in callbacks {
+            debug!(
+                "Sending callback for surface {:?}: {:?}",
+                surface.id(),
+                callback.id()
+            );
+            callback.done(self.start_time.elapsed().as_millis() as u32);
+        }
+    }
+
+    /// Tells the client to destroy its window for the surface and, if the
+    /// surface is a toplevel, asks the application to close it, since the
+    /// window would otherwise be recreated by the application's next commit.
+    pub fn close_surface(&mut self, surface_id: WlSurfaceId) -> Result<()> {
+        let object_id = self.object_map.get(&surface_id).location(loc!())?;
+        let client = self.dh.get_client(object_id.clone()).location(loc!())?;
+        let surface: WlSurface = client
+            .object_from_protocol_id(&self.dh, object_id.protocol_id())
+            .location(loc!())?;
+
+        if let Some(toplevel) = self
+            .xdg_shell_state
+            .toplevel_surfaces()
+            .iter()
+            .find(|toplevel| toplevel.wl_surface() == &surface)
+        {
+            toplevel.send_close();
+        }
+
+        let request =
+            SurfaceRequest::new(&surface, SurfaceRequestPayload::Destroyed).location(loc!())?;
+        self.serializer
+            .writer()
+            .send(SendType::Object(Request::Surface(request)));
+        Ok(())
+    }
+
     #[instrument(skip(self), level = "debug")]
     pub fn insert_surface(&mut self, surface: &WlSurface) -> Result<()> {
         self.object_map
@@ -183,6 +462,14 @@ impl WprsServerState {
         Ok(())
     }
 
+    /// Whether surface belongs to an application other than the one which
+    /// locked the session, and so mustn't be shown.
+    pub(crate) fn hidden_by_session_lock(&self, surface: &WlSurface) -> bool {
+        self.session_lock.as_ref().is_some_and(|session_lock| {
+            surface.client().map(|client| client.id()).as_ref() != Some(&session_lock.client)
+        })
+    }
+
     pub fn for_each_surface<F>(&self, mut processor: F)
     where
         F: FnMut(&WlSurface, &SurfaceData),
@@ -206,5 +493,64 @@ impl WprsServerState {
                 |_, _, _| true,
             )
         }
+
+        for surface in self.wlr_layer_shell_state.layer_surfaces() {
+            compositor::with_surface_tree_downward(
+                surface.wl_surface(),
+                (),
+                |_, _, _| TraversalAction::DoChildren(()),
+                |surface, surface_data, _| processor(surface, surface_data),
+                |_, _, _| true,
+            )
+        }
+
+        for surface in self
+            .session_lock
+            .iter()
+            .flat_map(|session_lock| &session_lock.lock_surfaces)
+            .filter(|surface| surface.wl_surface().is_alive())
+        {
+            compositor::with_surface_tree_downward(
+                surface.wl_surface(),
+                (),
+                |_, _, _| TraversalAction::DoChildren(()),
+                |surface, surface_data, _| processor(surface, surface_data),
+                |_, _, _| true,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::testing::TestServer;
+
+    #[test]
+    fn test_close_surface() {
+        let mut server = TestServer::new();
+        let mut client = server.connect();
+        client.create_toplevel();
+        server.roundtrip(&mut client);
+        server.take_requests();
+
+        let surface_id = *server.state.object_map.keys().next().unwrap();
+        server.state.close_surface(surface_id).unwrap();
+        server.roundtrip(&mut client);
+
+        // The application is asked to close the toplevel, so that it doesn't
+        // recreate the window with its next commit...
+        assert_eq!(client.state.toplevel_closes, 1);
+        // ...and wprsc is told to destroy the window right away.
+        assert!(server.take_requests().iter().any(|request| matches!(
+            request,
+            Request::Surface(SurfaceRequest {
+                surface,
+                payload: SurfaceRequestPayload::Destroyed,
+                ..
+            }) if *surface == surface_id
+        )));
+
+        assert!(server.state.close_surface(WlSurfaceId(0)).is_err());
     }
 }
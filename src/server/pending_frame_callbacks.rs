@@ -0,0 +1,116 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Frame callbacks waiting for wprsc to present their surface, when
+//! frame_pacing is Client.
+//!
+//! The callbacks of each commit are sent when wprsc presents the surface, or
+//! when the commit's fallback timer expires, whichever comes first. A timer
+//! only releases its own commit's callbacks: those of later commits still get
+//! their full timeout.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+#[derive(Debug)]
+pub struct PendingFrameCallbacks<K, C> {
+    next_commit: u64,
+    /// The callbacks of each surface's commits, oldest first.
+    pending: HashMap<K, Vec<(u64, Vec<C>)>>,
+}
+
+impl<K, C> Default for PendingFrameCallbacks<K, C> {
+    fn default() -> Self {
+        Self {
+            next_commit: 0,
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq, C> PendingFrameCallbacks<K, C> {
+    /// Adds the callbacks of a commit of surface. Returns the commit's key, for
+    /// take_commit.
+    pub fn push(&mut self, surface: K, callbacks: Vec<C>) -> u64 {
+        let commit = self.next_commit;
+        self.next_commit += 1;
+        self.pending
+            .entry(surface)
+            .or_default()
+            .push((commit, callbacks));
+        commit
+    }
+
+    /// Takes the callbacks of one commit of surface, e.g. because its timer
+    /// expired. Empty if they were already taken.
+    pub fn take_commit(&mut self, surface: &K, commit: u64) -> Vec<C> {
+        let Some(commits) = self.pending.get_mut(surface) else {
+            return Vec::new();
+        };
+        let Some(idx) = commits.iter().position(|(key, _)| *key == commit) else {
+            return Vec::new();
+        };
+        let (_, callbacks) = commits.remove(idx);
+        if commits.is_empty() {
+            self.pending.remove(surface);
+        }
+        callbacks
+    }
+
+    /// Takes the callbacks of all of surface's commits, e.g. because wprsc
+    /// presented it.
+    pub fn take_all(&mut self, surface: &K) -> Vec<C> {
+        self.pending
+            .remove(surface)
+            .into_iter()
+            .flatten()
+            .flat_map(|(_, callbacks)| callbacks)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeout_takes_own_commit() {
+        let mut pending = PendingFrameCallbacks::default();
+        let first = pending.push(1, vec!["a", "b"]);
+        let second = pending.push(1, vec!["c"]);
+        let other = pending.push(2, vec!["d"]);
+
+        assert_eq!(pending.take_commit(&1, first), vec!["a", "b"]);
+        // Already taken.
+        assert_eq!(pending.take_commit(&1, first), Vec::<&str>::new());
+        assert_eq!(pending.take_commit(&1, second), vec!["c"]);
+        assert_eq!(pending.take_commit(&2, other), vec!["d"]);
+        assert!(pending.pending.is_empty());
+    }
+
+    #[test]
+    fn test_presented_takes_all_commits() {
+        let mut pending = PendingFrameCallbacks::default();
+        let first = pending.push(1, vec!["a"]);
+        let second = pending.push(1, vec!["b"]);
+        let other = pending.push(2, vec!["c"]);
+
+        assert_eq!(pending.take_all(&1), vec!["a", "b"]);
+        assert_eq!(pending.take_all(&1), Vec::<&str>::new());
+        // The timers of the presented commits find nothing left.
+        assert_eq!(pending.take_commit(&1, first), Vec::<&str>::new());
+        assert_eq!(pending.take_commit(&1, second), Vec::<&str>::new());
+        assert_eq!(pending.take_commit(&2, other), vec!["c"]);
+    }
+}
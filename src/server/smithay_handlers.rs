@@ -13,9 +13,13 @@
 // limitations under the License.
 
 /// Handlers for events from Smithay.
+use std::fs::File;
+use std::io::Write;
 use std::mem;
 use std::os::fd::OwnedFd;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
 use std::time::Duration;
 
 use crossbeam_channel::Sender;
@@ -35,13 +39,16 @@ use smithay::input::pointer::GestureSwipeUpdateEvent;
 use smithay::input::pointer::GrabStartData;
 use smithay::input::pointer::MotionEvent;
 use smithay::input::pointer::PointerGrab;
+use smithay::input::pointer::PointerHandle;
 use smithay::input::pointer::PointerInnerHandle;
 use smithay::input::pointer::RelativeMotionEvent;
 use smithay::input::Seat;
 use smithay::input::SeatHandler;
 use smithay::input::SeatState;
+use smithay::output::Output;
 use smithay::reexports::calloop::timer::TimeoutAction;
 use smithay::reexports::calloop::timer::Timer;
+use smithay::reexports::wayland_protocols::wp::content_type::v1::server::wp_content_type_v1;
 use smithay::reexports::wayland_protocols::xdg::decoration::zv1::server::zxdg_toplevel_decoration_v1::Mode as XdgDecorationMode;
 use smithay::reexports::wayland_protocols_misc::server_decoration::server::org_kde_kwin_server_decoration::Mode as KdeDecorationMode;
 use smithay::reexports::wayland_protocols_misc::server_decoration::server::org_kde_kwin_server_decoration::OrgKdeKwinServerDecoration;
@@ -59,9 +66,12 @@ use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
 use smithay::reexports::wayland_server::Client;
 use smithay::reexports::wayland_server::Resource;
 use smithay::reexports::wayland_server::WEnum;
+use smithay::utils::Buffer as BufferCoords;
 use smithay::utils::Logical;
 use smithay::utils::Point;
+use smithay::utils::Rectangle;
 use smithay::utils::Serial;
+use smithay::utils::Size;
 use smithay::wayland::buffer::BufferHandler;
 use smithay::wayland::compositor;
 use smithay::wayland::compositor::BufferAssignment as SmithayBufferAssignment;
@@ -72,7 +82,19 @@ use smithay::wayland::compositor::Damage;
 use smithay::wayland::compositor::SubsurfaceCachedState;
 use smithay::wayland::compositor::SurfaceAttributes;
 use smithay::wayland::compositor::SurfaceData;
+use smithay::wayland::content_type::ContentTypeSurfaceCachedState;
+use smithay::wayland::fractional_scale;
+use smithay::wayland::fractional_scale::FractionalScaleHandler;
+use smithay::wayland::idle_inhibit::IdleInhibitHandler;
+use smithay::wayland::keyboard_shortcuts_inhibit::KeyboardShortcutsInhibitHandler;
+use smithay::wayland::keyboard_shortcuts_inhibit::KeyboardShortcutsInhibitState;
+use smithay::wayland::keyboard_shortcuts_inhibit::KeyboardShortcutsInhibitor;
 use smithay::wayland::output::OutputHandler;
+use smithay::wayland::pointer_constraints;
+use smithay::wayland::pointer_constraints::PointerConstraint;
+use smithay::wayland::pointer_constraints::PointerConstraintsHandler;
+use smithay::wayland::presentation::PresentationFeedbackCachedState;
+use smithay::wayland::presentation::PresentationFeedbackCallback;
 use smithay::wayland::selection::data_device::with_source_metadata;
 use smithay::wayland::selection::data_device::ClientDndGrabHandler;
 use smithay::wayland::selection::data_device::DataDeviceHandler;
@@ -83,8 +105,18 @@ use smithay::wayland::selection::SelectionSource;
 use smithay::wayland::selection::SelectionTarget;
 use smithay::wayland::selection::primary_selection::PrimarySelectionHandler;
 use smithay::wayland::selection::primary_selection::PrimarySelectionState;
+use smithay::wayland::session_lock::LockSurface;
+use smithay::wayland::session_lock::SessionLockHandler;
+use smithay::wayland::session_lock::SessionLockManagerState;
+use smithay::wayland::session_lock::SessionLocker;
 use smithay::wayland::shell::kde::decoration::KdeDecorationHandler;
 use smithay::wayland::shell::kde::decoration::KdeDecorationState;
+use smithay::wayland::shell::wlr_layer::Layer;
+use smithay::wayland::shell::wlr_layer::LayerSurface;
+use smithay::wayland::shell::wlr_layer::LayerSurfaceCachedState;
+use smithay::wayland::shell::wlr_layer::LayerSurfaceData;
+use smithay::wayland::shell::wlr_layer::WlrLayerShellHandler;
+use smithay::wayland::shell::wlr_layer::WlrLayerShellState;
 use smithay::wayland::shell::xdg::Configure;
 use smithay::wayland::shell::xdg::PopupSurface;
 use smithay::wayland::shell::xdg::PositionerState;
@@ -96,11 +128,19 @@ use smithay::wayland::shell::xdg::XdgToplevelSurfaceData;
 use smithay::wayland::shell::xdg::decoration::XdgDecorationHandler;
 use smithay::wayland::shm::ShmHandler;
 use smithay::wayland::shm::ShmState;
+use smithay::wayland::tablet_manager::TabletSeatHandler;
+use smithay::wayland::viewporter::ViewportCachedState;
+use smithay::wayland::xdg_activation::XdgActivationHandler;
+use smithay::wayland::xdg_activation::XdgActivationState;
+use smithay::wayland::xdg_activation::XdgActivationToken;
+use smithay::wayland::xdg_activation::XdgActivationTokenData;
 
 use crate::channel_utils::DiscardingSender;
 use crate::compositor_utils;
 use crate::prelude::*;
 use crate::serialization;
+use crate::serialization::session_lock::SessionLockRequest;
+use crate::serialization::session_lock::SessionLockSurfaceState;
 use crate::serialization::tuple::Tuple2;
 use crate::serialization::wayland::BufferAssignment;
 use crate::serialization::wayland::ClientSurface;
@@ -110,6 +150,8 @@ use crate::serialization::wayland::DataDestinationRequest;
 use crate::serialization::wayland::DataRequest;
 use crate::serialization::wayland::DataSource;
 use crate::serialization::wayland::DataSourceRequest;
+use crate::serialization::wayland::PointerConstraintKind;
+use crate::serialization::wayland::PointerConstraintState;
 use crate::serialization::wayland::Role;
 use crate::serialization::wayland::SourceMetadata;
 use crate::serialization::wayland::SubSurfaceState;
@@ -119,6 +161,7 @@ use crate::serialization::wayland::SurfaceRequestPayload;
 use crate::serialization::wayland::SurfaceState;
 use crate::serialization::wayland::Transform;
 use crate::serialization::wayland::WlSurfaceId;
+use crate::serialization::wlr_layer_shell::LayerSurfaceState;
 use crate::serialization::xdg_shell::DecorationMode;
 use crate::serialization::xdg_shell::Move;
 use crate::serialization::xdg_shell::PopupRequest;
@@ -132,10 +175,22 @@ use crate::serialization::xdg_shell::XdgSurfaceState;
 use crate::serialization::xdg_shell::XdgToplevelState;
 use crate::serialization::Request;
 use crate::serialization::SendType;
+use crate::server::DamageOnlyCommits;
+use crate::server::FramePacing;
 use crate::server::LockedSurfaceState;
+use crate::server::SessionLock;
 use crate::server::WprsServerState;
+use crate::server::CLIENT_PACED_FRAME_TIMEOUT;
 use crate::vec4u8::Vec4u8s;
 
+/// Activation tokens older than this are ignored, so that an application
+/// can't hold on to a token and steal focus much later.
+const ACTIVATION_TOKEN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// In the user data of activation tokens which were requested with the serial
+/// of input on our seat since the last keyboard enter, see token_created.
+struct UserInputToken;
+
 impl BufferHandler for WprsServerState {
     #[instrument(skip(self), level = "debug")]
     fn buffer_destroyed(&mut self, buffer: &wl_buffer::WlBuffer) {}
@@ -153,6 +208,32 @@ impl WprsServerState {
             })))
     }
 
+    /// The id of output in self.outputs.
+    fn output_id(&self, output: &wl_output::WlOutput) -> Option<u32> {
+        let output = Output::from_resource(output)?;
+        self.outputs
+            .iter()
+            .find(|(_, (local_output, _))| local_output == &output)
+            .map(|(id, _)| *id)
+    }
+
+    /// Ends the session lock and shows the surfaces which were hidden by it.
+    pub(crate) fn end_session_lock(&mut self) {
+        let Some(session_lock) = self.session_lock.take() else {
+            return;
+        };
+        self.serializer
+            .writer()
+            .send(SendType::Object(Request::SessionLock(
+                SessionLockRequest::Unlock,
+            )));
+        for surface in session_lock.held_surfaces {
+            if surface.is_alive() {
+                CompositorHandler::commit(self, &surface);
+            }
+        }
+    }
+
     #[allow(clippy::missing_panics_doc)]
     pub fn update_state_and_send_toplevel_request<F>(
         &mut self,
@@ -399,6 +480,9 @@ impl SelectionHandler for WprsServerState {
         source: Option<SelectionSource>,
         _seat: Seat<Self>,
     ) {
+        if matches!(ty, SelectionTarget::Clipboard) {
+            self.clipboard_cache.clear();
+        }
         if let Some(source) = source {
             self.serializer
                 .writer()
@@ -423,9 +507,28 @@ impl SelectionHandler for WprsServerState {
         _seat: Seat<Self>,
         _user_data: &Self::SelectionUserData,
     ) {
+        if matches!(ty, SelectionTarget::Clipboard) {
+            if let Some(data) = self.clipboard_cache.get(&mime_type) {
+                let mut f = File::from(fd);
+                let data = data.to_vec();
+                // See DataEvent::TransferData.
+                thread::spawn(move || {
+                    f.write_all(&data).log_and_ignore(loc!());
+                });
+                return;
+            }
+        }
+
+        if !self.serializer.other_end_connected() {
+            // Closing fd makes the paste fail instead of waiting for a wprsc
+            // which may never come back.
+            debug!("wprsc is disconnected, can't transfer {mime_type} selection");
+            return;
+        }
+
         let data_source = match ty {
             SelectionTarget::Clipboard => {
-                self.selection_pipe = Some(fd);
+                self.selection_pipe = Some((mime_type.clone(), fd));
 
                 DataSource::Selection
             },
@@ -556,10 +659,22 @@ impl CompositorHandler for WprsServerState {
 
     #[instrument(skip(self), level = "debug")]
     fn commit(&mut self, surface: &WlSurface) {
+        if self.hidden_by_session_lock(surface) {
+            // The commit is applied when the session is unlocked. Until then,
+            // the application doesn't get frame callbacks, so it shouldn't
+            // keep drawing.
+            let session_lock = self.session_lock.as_mut().unwrap();
+            if !session_lock.held_surfaces.contains(surface) {
+                session_lock.held_surfaces.push(surface.clone());
+            }
+            return;
+        }
+
         // Send over the updated buffers from the children first so that the
         // client already has them when the parent is comitted.
         let children_dirty = commit_sync_children(self, surface, &commit).unwrap();
         commit(surface, self, children_dirty, false).log_and_ignore(loc!());
+        self.configure_unshown_layer_surface(surface);
     }
 }
 
@@ -604,6 +719,25 @@ pub fn get_child_positions(surface: &WlSurface) -> Vec<SubsurfacePosition> {
         .collect()
 }
 
+// This can't be done in commit_impl, with_pointer_constraint locks the surface
+// data.
+#[instrument(skip(state), ret, level = "debug")]
+pub fn get_pointer_constraint(
+    surface: &WlSurface,
+    state: &WprsServerState,
+) -> Option<PointerConstraintState> {
+    let pointer = state.seat.get_pointer()?;
+    pointer_constraints::with_pointer_constraint(surface, &pointer, |constraint| {
+        constraint.map(|constraint| {
+            let kind = match &*constraint {
+                PointerConstraint::Locked(_) => PointerConstraintKind::Lock,
+                PointerConstraint::Confined(_) => PointerConstraintKind::Confine,
+            };
+            PointerConstraintState::new(kind, constraint.region())
+        })
+    })
+}
+
 #[instrument(skip_all, level = "debug")]
 pub fn commit(
     surface: &WlSurface,
@@ -612,6 +746,7 @@ pub fn commit(
     skip_buffer: bool,
 ) -> Result<bool> {
     let surface_order = get_child_positions(surface);
+    let pointer_constraint = get_pointer_constraint(surface, state);
 
     // TODO: https://github.com/Smithay/smithay/issues/538 - move into commit.
     let sync = compositor::is_sync_subsurface(surface);
@@ -627,6 +762,7 @@ pub fn commit(
             sync,
             parent,
             surface_order,
+            pointer_constraint,
             children_dirty,
             skip_buffer,
         )
@@ -653,6 +789,28 @@ pub fn set_transformation(
     surface_state.buffer_transform = Some(surface_attributes.buffer_transform.into());
 }
 
+/// Rotated and flipped damage depends on the size of the surface (before
+/// its viewport is applied), so buffer_size is needed too. It's None without
+/// a buffer, in which case the damage doesn't matter.
+fn surface_damage_to_buffer(
+    rect: &Rectangle<i32, Logical>,
+    scale: i32,
+    transform: Transform,
+    buffer_size: Option<Size<i32, BufferCoords>>,
+) -> Rectangle<i32, BufferCoords> {
+    let surface_size =
+        buffer_size.map_or(rect.size, |size| size.to_logical(scale, transform.into()));
+    rect.to_buffer(scale, transform.into(), &surface_size)
+}
+
+#[instrument(skip_all, level = "debug")]
+pub fn set_viewport(surface_data: &SurfaceData, surface_state: &mut SurfaceState) {
+    if surface_data.cached_state.has::<ViewportCachedState>() {
+        let viewport = surface_data.cached_state.current::<ViewportCachedState>();
+        surface_state.viewport = Some((&*viewport).into());
+    }
+}
+
 #[instrument(skip_all, level = "debug")]
 pub fn set_xdg_surface_attributes(surface_data: &SurfaceData, surface_state: &mut SurfaceState) {
     if surface_data.cached_state.has::<SurfaceCachedState>() {
@@ -701,6 +859,89 @@ pub fn set_xdg_toplevel_attributes(
     Ok(())
 }
 
+/// The last buffer attached to a surface, which stays attached until the next
+/// one is.
+#[derive(Debug, Default)]
+struct AttachedBuffer(Mutex<Option<wl_buffer::WlBuffer>>);
+
+/// The buffer whose contents a commit sends: the buffer it attached or, if it
+/// only damaged the surface, the buffer which is still attached, which the
+/// application drew into again.
+fn buffer_to_read<B>(
+    new_buffer: Option<B>,
+    damage_only: bool,
+    attached_buffer: impl FnOnce() -> Option<B>,
+) -> Option<B> {
+    new_buffer.or_else(|| damage_only.then(attached_buffer).flatten())
+}
+
+fn is_video(surface_data: &SurfaceData) -> bool {
+    *surface_data
+        .cached_state
+        .current::<ContentTypeSurfaceCachedState>()
+        .content_type()
+        == wp_content_type_v1::Type::Video
+}
+
+/// Sends the data of surface_state's new buffer as a RawBuffer, which wprsc
+/// expects before the commit with the buffer, and puts the buffer into
+/// surface_state_to_send with its data zeroed out, see the comment on
+/// wayland.rs::Buffer. Returns false if surface_state has no new buffer.
+fn send_buffer(
+    state: &WprsServerState,
+    surface_state: &SurfaceState,
+    surface_state_to_send: &mut SurfaceState,
+    compress: bool,
+) -> bool {
+    let Some(buffer) = surface_state
+        .buffer
+        .as_ref()
+        .and_then(BufferAssignment::as_new)
+    else {
+        return false;
+    };
+    state.serializer.stats().add_surface(surface_state.id);
+    state.serializer.writer().send(SendType::RawBuffer {
+        surface: surface_state.id,
+        data: buffer.data.clone(),
+        compress,
+    });
+    let mut buffer_to_send = buffer.clone();
+    buffer_to_send.data = Arc::new(Vec4u8s::new());
+    surface_state_to_send.buffer = Some(BufferAssignment::New(buffer_to_send));
+    true
+}
+
+impl WprsServerState {
+    /// Sends the latest buffer of surface again, in a commit without any other
+    /// changes. Returns false if surface has no buffer to send.
+    pub(crate) fn resend_buffer(&self, surface: &WlSurface) -> bool {
+        compositor::with_states(surface, |surface_data| {
+            let surface_state = surface_data
+                .data_map
+                .get::<LockedSurfaceState>()
+                .unwrap()
+                .0
+                .lock()
+                .unwrap();
+            // See the comment on clone_without_buffer in commit_impl.
+            let mut surface_state_to_send = surface_state.clone_without_buffer();
+            let compress = self.compress_video || !is_video(surface_data);
+            if !send_buffer(self, &surface_state, &mut surface_state_to_send, compress) {
+                return false;
+            }
+            self.serializer
+                .writer()
+                .send(SendType::Object(Request::Surface(SurfaceRequest {
+                    client: surface_state_to_send.client,
+                    surface: surface_state_to_send.id,
+                    payload: SurfaceRequestPayload::Commit(surface_state_to_send),
+                })));
+            true
+        })
+    }
+}
+
 #[allow(clippy::iter_with_drain)]
 #[instrument(skip(state), level = "debug")]
 pub fn commit_impl(
@@ -710,6 +951,7 @@ pub fn commit_impl(
     sync: bool,
     parent: Option<WlSurface>,
     surface_order: Vec<SubsurfacePosition>,
+    pointer_constraint: Option<PointerConstraintState>,
     children_dirty: bool,
     // TODO: This is a hack to stop sending the same buffer over twice. The
     // subsurface logic needs another pass overall, we shouldn be able to avoid
@@ -746,48 +988,74 @@ pub fn commit_impl(
 
     let mut surface_attributes = surface_data.cached_state.current::<SurfaceAttributes>();
     let mut frame_callbacks = mem::take(&mut surface_attributes.frame_callbacks);
+    let presentation_callbacks = mem::take(
+        &mut surface_data
+            .cached_state
+            .current::<PresentationFeedbackCachedState>()
+            .callbacks,
+    );
 
     if !frame_callbacks.is_empty() {
         let surface = surface.clone();
+        let (timeout, client_paced_commit) = match state.frame_pacing {
+            FramePacing::Timer => (
+                state
+                    .frame_interval
+                    // "The server should give some time for the client to
+                    // draw and commit after sending the frame callback events
+                    // to let it hit the next output refresh."
+                    .saturating_sub(Duration::from_millis(2)),
+                None,
+            ),
+            FramePacing::Client => {
+                // Normally sent from handle_surface_event when wprsc presents
+                // the surface, the timer is only a fallback.
+                let commit = state
+                    .client_paced_frame_callbacks
+                    .push(surface.id(), mem::take(&mut frame_callbacks));
+                (CLIENT_PACED_FRAME_TIMEOUT, Some(commit))
+            },
+        };
         state
             .lh
-            .insert_source(
-                Timer::from_duration(
+            .insert_source(Timer::from_duration(timeout), move |_, _, state| {
+                let client_paced_frame_callbacks = client_paced_commit.map(|commit| {
                     state
-                        .frame_interval
-                        // "The server should give some time for the client to
-                        // draw and commit after sending the frame callback
-                        // events to let it hit the next output refresh."
-                        .saturating_sub(Duration::from_millis(2)),
-                ),
-                move |_, _, state| {
-                    if !surface.is_alive() {
-                        return TimeoutAction::Drop;
-                    }
-
-                    if state.serializer.other_end_connected() {
-                        // We can't use into_iter() because we can't move
-                        // frame_callbacks because this is a FnMut. However, this
-                        // works because this branch will only ever be taken once.
-                        for callback in frame_callbacks.drain(..) {
-                            debug!(
-                                "Sending callback for surface {:?}: {:?}",
-                                surface.id(),
-                                callback.id()
-                            );
-                            callback.done(state.start_time.elapsed().as_millis() as u32);
-                        }
-                        TimeoutAction::Drop
-                    } else {
-                        TimeoutAction::ToDuration(state.frame_interval)
-                    }
-                },
-            )
+                        .client_paced_frame_callbacks
+                        .take_commit(&surface.id(), commit)
+                });
+                if !surface.is_alive() {
+                    return TimeoutAction::Drop;
+                }
+
+                // We can't move frame_callbacks because this is a FnMut.
+                // However, this is fine because the timer is always
+                // dropped after the first timeout.
+                let mut frame_callbacks = mem::take(&mut frame_callbacks);
+                frame_callbacks.extend(client_paced_frame_callbacks.into_iter().flatten());
+                if frame_callbacks.is_empty() {
+                    return TimeoutAction::Drop;
+                }
+
+                if state.serializer.other_end_connected() {
+                    state.send_frame_callbacks(&surface, frame_callbacks);
+                } else {
+                    // Rather than waking up every frame until a client
+                    // connects, hold on to the callbacks and send them
+                    // from handle_connect.
+                    state
+                        .parked_frame_callbacks
+                        .push((surface.clone(), frame_callbacks));
+                }
+                TimeoutAction::Drop
+            })
             .expect("timer registration should never fail");
     }
 
     set_regions(&surface_attributes, surface_state);
     set_transformation(&surface_attributes, surface_state);
+    set_viewport(surface_data, surface_state);
+    surface_state.pointer_constraint = pointer_constraint;
     set_xdg_surface_attributes(surface_data, surface_state);
 
     match &mut surface_state.role {
@@ -804,6 +1072,14 @@ pub fn commit_impl(
             set_xdg_toplevel_attributes(surface_data, toplevel_state).location(loc!())?;
         },
         Some(Role::XdgPopup(_)) => {},
+        Some(Role::SessionLockSurface(_)) => {},
+        Some(Role::LayerSurface(layer_surface_state)) => {
+            layer_surface_state.update(
+                &surface_data
+                    .cached_state
+                    .current::<LayerSurfaceCachedState>(),
+            );
+        },
         None => {},
     }
 
@@ -811,49 +1087,79 @@ pub fn commit_impl(
     // data arc will cause a deadlock otherwise.
     let mut surface_state_to_send = surface_state.clone_without_buffer();
 
+    let forward_damage = state.damage_only_commits == DamageOnlyCommits::Forward
+        && surface_attributes.buffer.is_none()
+        && !surface_attributes.damage.is_empty();
+
+    let mut attached_buffer = surface_data
+        .data_map
+        .get_or_insert_threadsafe(AttachedBuffer::default)
+        .0
+        .lock()
+        .unwrap();
+    match &surface_attributes.buffer {
+        Some(SmithayBufferAssignment::NewBuffer(buffer)) => {
+            *attached_buffer = Some(buffer.clone());
+        },
+        Some(SmithayBufferAssignment::Removed) => *attached_buffer = None,
+        None => {},
+    }
+    let new_buffer = match &surface_attributes.buffer {
+        Some(SmithayBufferAssignment::NewBuffer(buffer)) if !skip_buffer => Some(buffer),
+        _ => None,
+    };
+    let buffer = buffer_to_read(new_buffer, forward_damage && !skip_buffer, || {
+        attached_buffer.as_ref().filter(|buffer| buffer.is_alive())
+    });
+
     // TODO: make a function and dedupe with compositor.rs.
     debug!("buffer assignment: {:?}", &surface_attributes.buffer);
-    match &surface_attributes.buffer {
-        Some(SmithayBufferAssignment::NewBuffer(buffer)) if !skip_buffer => {
+    match (&surface_attributes.buffer, buffer) {
+        (_, Some(buffer)) => {
             compositor_utils::with_buffer_contents(buffer, |data, spec| {
-                surface_state.set_buffer(&spec, data)
+                surface_state.set_buffer(&spec, data, state.frame_checksums)
             })
             .location(loc!())?
             .location(loc!())?;
-
-            surface_state_to_send
-                .buffer
-                .clone_from(&surface_state.buffer);
-            // surface_state.set_buffer (called above) sets buffer to
-            // Some(BufferAssignment::New(...)), so the 4 unwraps below should
-            // never fail.
-
-            // zero-out data, see comment on wayland.rs::Buffer.
-            surface_state_to_send
-                .buffer
-                .as_mut()
-                .unwrap()
-                .as_new_mut()
-                .unwrap()
-                .data = Arc::new(Vec4u8s::new());
-
-            state.serializer.writer().send(SendType::RawBuffer(
-                surface_state
-                    .buffer
-                    .as_ref()
-                    .unwrap()
-                    .as_new()
-                    .unwrap()
-                    .data
-                    .clone(),
-            ));
+            surface_state
+                .convert_buffer_to_supported(&state.client_capabilities.buffer_formats)
+                .location(loc!())?;
+
+            // wprsc is too far behind on this surface: keep the buffer in
+            // surface_state, to be sent once wprsc catches up, and send the
+            // rest of the commit without it.
+            if !state.frame_budget.try_send(&surface.id()) {
+                state.serializer.stats().record_frame_dropped();
+                presentation_callbacks
+                    .into_iter()
+                    .for_each(PresentationFeedbackCallback::discarded);
+            } else {
+                // surface_state.set_buffer (called above) sets buffer to
+                // Some(BufferAssignment::New(...)), so this always sends it.
+                let compress = state.compress_video || !is_video(surface_data);
+                send_buffer(state, surface_state, &mut surface_state_to_send, compress);
+                // wprsc reports the presentation of each buffer it receives,
+                // see SurfaceEventPayload::PresentationFeedback.
+                state
+                    .presentation_feedback
+                    .entry(surface.id())
+                    .or_default()
+                    .push_back(presentation_callbacks);
+            }
         },
-        Some(SmithayBufferAssignment::Removed) => {
+        (Some(SmithayBufferAssignment::Removed), None) => {
+            presentation_callbacks
+                .into_iter()
+                .for_each(PresentationFeedbackCallback::discarded);
             surface_state.buffer = None;
             surface_state_to_send.buffer = Some(BufferAssignment::Removed);
         },
-        Some(SmithayBufferAssignment::NewBuffer(_)) | None => {
-            if (surface_state_to_send == prev_without_buffer) && !children_dirty {
+        (Some(SmithayBufferAssignment::NewBuffer(_)) | None, None) => {
+            presentation_callbacks
+                .into_iter()
+                .for_each(PresentationFeedbackCallback::discarded);
+            if (surface_state_to_send == prev_without_buffer) && !children_dirty && !forward_damage
+            {
                 return Ok(false);
             }
             if children_dirty && sync {
@@ -862,17 +1168,20 @@ pub fn commit_impl(
         },
     }
 
+    let buffer_size = surface_state
+        .buffer
+        .as_ref()
+        .and_then(BufferAssignment::as_new)
+        .map(|buffer| Size::from((buffer.metadata.width, buffer.metadata.height)));
     let damage = mem::take(&mut surface_attributes.damage)
         .iter()
         .map(|damage| match damage {
             Damage::Buffer(rect) => *rect,
-            Damage::Surface(rect) => rect.to_buffer(
+            Damage::Surface(rect) => surface_damage_to_buffer(
+                rect,
                 surface_state.buffer_scale,
-                surface_state
-                    .buffer_transform
-                    .unwrap_or(Transform::Normal)
-                    .into(),
-                &rect.size,
+                surface_state.buffer_transform.unwrap_or(Transform::Normal),
+                buffer_size,
             ),
         })
         .map(Into::into)
@@ -907,6 +1216,10 @@ impl SeatHandler for WprsServerState {
         &mut self.seat_state
     }
 
+    fn focus_changed(&mut self, _seat: &Seat<Self>, focused: Option<&WlSurface>) {
+        self.set_text_input_focus(focused);
+    }
+
     #[instrument(skip(self, _seat), level = "debug")]
     fn cursor_image(&mut self, _seat: &Seat<Self>, image: SmithayCursorImageStatus) {
         // TODO: move to a fn on serialization::CursorImaveStatus
@@ -1215,8 +1528,392 @@ impl ClientData for ClientState {
 
 impl OutputHandler for WprsServerState {}
 
+/// The preferred scale wprsc reported for a surface, in 120ths. It's held
+/// back until the application creates a wp_fractional_scale_v1 for the surface
+/// and then sent explicitly.
+#[derive(Debug, Default)]
+pub(crate) struct PreferredFractionalScale {
+    scale: Option<u32>,
+    bound: bool,
+}
+
+impl PreferredFractionalScale {
+    /// Records a scale reported by wprsc. Returns the scale to send, if the
+    /// application is listening for it.
+    pub(crate) fn report(&mut self, scale: u32) -> Option<u32> {
+        self.scale = Some(scale);
+        self.bound.then_some(scale)
+    }
+
+    /// Records that the application created a wp_fractional_scale_v1. Returns
+    /// the scale to send, if wprsc already reported one.
+    pub(crate) fn bind(&mut self) -> Option<u32> {
+        self.bound = true;
+        self.scale
+    }
+}
+
+#[derive(Debug, Default)]
+struct LockedPreferredFractionalScale(Mutex<PreferredFractionalScale>);
+
+/// Updates the preferred fractional scale of a surface with f and sends the
+/// scale it returns to the application.
+pub(crate) fn update_preferred_fractional_scale(
+    surface_data: &SurfaceData,
+    f: impl FnOnce(&mut PreferredFractionalScale) -> Option<u32>,
+) {
+    let scale = f(&mut surface_data
+        .data_map
+        .get_or_insert_threadsafe(LockedPreferredFractionalScale::default)
+        .0
+        .lock()
+        .unwrap());
+    if let Some(scale) = scale {
+        fractional_scale::with_fractional_scale(surface_data, |fractional_scale| {
+            fractional_scale
+                .set_preferred_scale(serialization::wayland::fractional_scale_to_f64(scale));
+        });
+    }
+}
+
+impl FractionalScaleHandler for WprsServerState {
+    fn new_fractional_scale(&mut self, surface: WlSurface) {
+        compositor::with_states(&surface, |surface_data| {
+            update_preferred_fractional_scale(surface_data, PreferredFractionalScale::bind);
+        });
+    }
+}
+
+impl XdgActivationHandler for WprsServerState {
+    fn activation_state(&mut self) -> &mut XdgActivationState {
+        &mut self.xdg_activation_state
+    }
+
+    /// Tokens without a valid serial are still handed out, but the activation
+    /// isn't forwarded as following user input, so wprsc's compositor may
+    /// only mark the surface as urgent.
+    #[instrument(skip(self, data), level = "debug")]
+    fn token_created(&mut self, token: XdgActivationToken, data: XdgActivationTokenData) -> bool {
+        let user_input = data.serial.as_ref().is_some_and(|(serial, seat)| {
+            Seat::<Self>::from_resource(seat).as_ref() == Some(&self.seat)
+                && self
+                    .seat
+                    .get_keyboard()
+                    .and_then(|keyboard| keyboard.last_enter())
+                    .is_some_and(|last_enter| serial.is_no_older_than(&last_enter))
+        });
+        if user_input {
+            data.user_data.insert_if_missing(|| UserInputToken);
+        } else {
+            debug!("activation token {token:?} wasn't requested with a valid serial");
+        }
+        true
+    }
+
+    #[instrument(skip(self, token_data), level = "debug")]
+    fn request_activation(
+        &mut self,
+        token: XdgActivationToken,
+        token_data: XdgActivationTokenData,
+        surface: WlSurface,
+    ) {
+        // Tokens are single-use.
+        self.xdg_activation_state.remove_token(&token);
+        if token_data.timestamp.elapsed() > ACTIVATION_TOKEN_TIMEOUT {
+            debug!("ignoring expired activation token {token:?}");
+            return;
+        }
+
+        let user_input = token_data.user_data.get::<UserInputToken>().is_some();
+        self.serializer
+            .writer()
+            .send(SendType::Object(Request::Surface(log_and_return!(
+                SurfaceRequest::new(&surface, SurfaceRequestPayload::Activate { user_input })
+            ))));
+    }
+}
+
+impl WprsServerState {
+    /// Layer surfaces are configured when wprsc's compositor configures the
+    /// corresponding local layer surface, so without a connected wprsc the
+    /// application would wait forever for the initial configure. Configure it
+    /// with the size it asked for instead, 0 meaning that it decides.
+    fn configure_unshown_layer_surface(&mut self, surface: &WlSurface) {
+        if self.serializer.other_end_connected() {
+            return;
+        }
+        let Some(layer_surface) = self
+            .wlr_layer_shell_state
+            .layer_surfaces()
+            .into_iter()
+            .find(|layer_surface| layer_surface.wl_surface() == surface)
+        else {
+            return;
+        };
+        let (initial_configure_sent, size) = compositor::with_states(surface, |surface_data| {
+            let initial_configure_sent = surface_data
+                .data_map
+                .get::<LayerSurfaceData>()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .initial_configure_sent;
+            let size = surface_data
+                .cached_state
+                .current::<LayerSurfaceCachedState>()
+                .size;
+            (initial_configure_sent, size)
+        });
+        if initial_configure_sent {
+            return;
+        }
+        layer_surface.with_pending_state(|state| {
+            state.size = Some(size);
+        });
+        layer_surface.send_configure();
+    }
+}
+
+impl WlrLayerShellHandler for WprsServerState {
+    fn shell_state(&mut self) -> &mut WlrLayerShellState {
+        &mut self.wlr_layer_shell_state
+    }
+
+    #[instrument(skip(self), level = "debug")]
+    fn new_layer_surface(
+        &mut self,
+        surface: LayerSurface,
+        output: Option<wl_output::WlOutput>,
+        layer: Layer,
+        namespace: String,
+    ) {
+        let output_id = output.as_ref().and_then(|output| self.output_id(output));
+
+        self.insert_surface(surface.wl_surface())
+            .log_and_ignore(loc!());
+        compositor::with_states(surface.wl_surface(), |surface_data| {
+            let surface_state = &mut surface_data
+                .data_map
+                .get::<LockedSurfaceState>()
+                .unwrap()
+                .0
+                .lock()
+                .unwrap();
+            surface_state.role = Some(Role::LayerSurface(LayerSurfaceState::new(
+                &surface, output_id, layer, namespace,
+            )));
+        });
+        // The initial configure is sent when wprsc's compositor configures
+        // the corresponding local layer surface, see
+        // configure_unshown_layer_surface.
+    }
+
+    #[instrument(skip(self), level = "debug")]
+    fn layer_destroyed(&mut self, surface: LayerSurface) {
+        let wl_surface = surface.wl_surface();
+        // As with toplevels, if the wl_surface is gone, wprsc was already told
+        // to destroy it.
+        if !wl_surface.is_alive() {
+            return;
+        }
+
+        compositor::with_states(wl_surface, |surface_data| {
+            if let Some(surface_state) = surface_data.data_map.get::<LockedSurfaceState>() {
+                surface_state.0.lock().unwrap().role = None;
+            }
+        });
+        self.serializer
+            .writer()
+            .send(SendType::Object(Request::Surface(log_and_return!(
+                SurfaceRequest::new(wl_surface, SurfaceRequestPayload::Destroyed)
+            ))));
+    }
+}
+
+// Tablet tool cursor images aren't forwarded, wprsc's compositor draws its own.
+impl TabletSeatHandler for WprsServerState {}
+
+impl SessionLockHandler for WprsServerState {
+    fn lock_state(&mut self) -> &mut SessionLockManagerState {
+        &mut self.session_lock_state
+    }
+
+    #[instrument(skip_all, level = "debug")]
+    fn lock(&mut self, confirmation: SessionLocker) {
+        let Some(client) = confirmation.ext_session_lock().client() else {
+            return;
+        };
+        let holder_alive = self
+            .session_lock
+            .as_ref()
+            .is_some_and(|session_lock| self.dh.get_client(session_lock.client.clone()).is_ok());
+        if holder_alive {
+            // Otherwise any application could take over the lock and unlock
+            // the session behind the screen locker's back.
+            debug!("refusing lock, the session is already locked");
+            confirmation.ext_session_lock().finished();
+            return;
+        }
+        // If the application holding the lock died, the session stays locked
+        // and another application may take over the lock.
+        let held_surfaces = self
+            .session_lock
+            .take()
+            .map(|session_lock| session_lock.held_surfaces)
+            .unwrap_or_default();
+
+        let locker = if self.serializer.other_end_connected() {
+            self.serializer
+                .writer()
+                .send(SendType::Object(Request::SessionLock(
+                    SessionLockRequest::Lock,
+                )));
+            Some(confirmation)
+        } else {
+            // Nothing is shown while wprsc isn't connected, so the session
+            // is already locked as far as anyone can tell. handle_connect
+            // locks the local session.
+            confirmation.lock();
+            None
+        };
+        self.session_lock = Some(SessionLock {
+            client: client.id(),
+            locker,
+            lock_surfaces: Vec::new(),
+            held_surfaces,
+        });
+    }
+
+    #[instrument(skip_all, level = "debug")]
+    fn unlock(&mut self) {
+        self.end_session_lock();
+    }
+
+    #[instrument(skip_all, level = "debug")]
+    fn new_surface(&mut self, surface: LockSurface, output: wl_output::WlOutput) {
+        let Some(output_id) = self.output_id(&output) else {
+            warn!("lock surface created for unknown output {output:?}");
+            return;
+        };
+        let Some(session_lock) = &mut self.session_lock else {
+            return;
+        };
+        session_lock.lock_surfaces.push(surface.clone());
+
+        self.insert_surface(surface.wl_surface())
+            .log_and_ignore(loc!());
+        compositor::with_states(surface.wl_surface(), |surface_data| {
+            let surface_state = &mut surface_data
+                .data_map
+                .get::<LockedSurfaceState>()
+                .unwrap()
+                .0
+                .lock()
+                .unwrap();
+            surface_state.role = Some(Role::SessionLockSurface(SessionLockSurfaceState {
+                output: output_id,
+            }));
+        });
+        // The surface is configured when wprsc's compositor configures the
+        // corresponding local lock surface.
+    }
+}
+
+impl PointerConstraintsHandler for WprsServerState {
+    fn new_constraint(&mut self, _surface: &WlSurface, _pointer: &PointerHandle<Self>) {
+        // The constraint is sent to wprsc with the next commit of the surface
+        // and activated when the local compositor activates the corresponding
+        // local constraint.
+    }
+
+    fn cursor_position_hint(
+        &mut self,
+        _surface: &WlSurface,
+        _pointer: &PointerHandle<Self>,
+        _location: Point<f64, Logical>,
+    ) {
+        // TODO: forward to wprsc.
+    }
+}
+
+impl KeyboardShortcutsInhibitHandler for WprsServerState {
+    fn keyboard_shortcuts_inhibit_state(&mut self) -> &mut KeyboardShortcutsInhibitState {
+        &mut self.keyboard_shortcuts_inhibit_state
+    }
+
+    fn new_inhibitor(&mut self, inhibitor: KeyboardShortcutsInhibitor) {
+        // The inhibitor is activated when the local compositor activates the
+        // corresponding local inhibitor.
+        self.update_surface_state(inhibitor.wl_surface(), |surface_state| {
+            surface_state.keyboard_shortcuts_inhibit = true
+        });
+    }
+
+    fn inhibitor_destroyed(&mut self, inhibitor: KeyboardShortcutsInhibitor) {
+        self.update_surface_state(inhibitor.wl_surface(), |surface_state| {
+            surface_state.keyboard_shortcuts_inhibit = false
+        });
+    }
+}
+
+impl IdleInhibitHandler for WprsServerState {
+    fn inhibit(&mut self, surface: WlSurface) {
+        self.update_surface_state(&surface, |surface_state| surface_state.idle_inhibit = true);
+    }
+
+    fn uninhibit(&mut self, surface: WlSurface) {
+        self.update_surface_state(&surface, |surface_state| surface_state.idle_inhibit = false);
+    }
+}
+
+impl WprsServerState {
+    /// Updates the state of surface outside of a commit. If wprsc already has
+    /// the surface, the state is sent right away, otherwise it goes with the
+    /// surface's first commit.
+    pub(crate) fn update_surface_state(
+        &mut self,
+        surface: &WlSurface,
+        update: impl FnOnce(&mut SurfaceState),
+    ) {
+        // wprsc drops the surface's state along with the surface.
+        if !surface.is_alive() {
+            return;
+        }
+        let committed = compositor::with_states(surface, |surface_data| {
+            surface_data.data_map.get::<LockedSurfaceState>().is_some()
+        });
+        if !committed {
+            log_and_return!(self.insert_surface(surface));
+        }
+        let surface_state = compositor::with_states(surface, |surface_data| {
+            let mut surface_state = surface_data
+                .data_map
+                .get::<LockedSurfaceState>()
+                .unwrap()
+                .0
+                .lock()
+                .unwrap();
+            update(&mut surface_state);
+            // See the comment on clone_without_buffer in commit_impl.
+            surface_state.clone_without_buffer()
+        });
+        // Held surfaces are sent when the session is unlocked.
+        if !committed || self.hidden_by_session_lock(surface) {
+            return;
+        }
+        self.serializer
+            .writer()
+            .send(SendType::Object(Request::Surface(SurfaceRequest {
+                client: surface_state.client,
+                surface: surface_state.id,
+                payload: SurfaceRequestPayload::Commit(surface_state),
+            })));
+    }
+}
+
 smithay::delegate_compositor!(WprsServerState);
 smithay::delegate_xdg_shell!(WprsServerState);
+smithay::delegate_layer_shell!(WprsServerState);
 smithay::delegate_xdg_decoration!(WprsServerState);
 smithay::delegate_kde_decoration!(WprsServerState);
 smithay::delegate_shm!(WprsServerState);
@@ -1224,3 +1921,115 @@ smithay::delegate_seat!(WprsServerState);
 smithay::delegate_data_device!(WprsServerState);
 smithay::delegate_output!(WprsServerState);
 smithay::delegate_primary_selection!(WprsServerState);
+smithay::delegate_viewporter!(WprsServerState);
+smithay::delegate_fractional_scale!(WprsServerState);
+smithay::delegate_xdg_activation!(WprsServerState);
+smithay::delegate_relative_pointer!(WprsServerState);
+smithay::delegate_pointer_constraints!(WprsServerState);
+smithay::delegate_session_lock!(WprsServerState);
+smithay::delegate_tablet_manager!(WprsServerState);
+smithay::delegate_content_type!(WprsServerState);
+smithay::delegate_presentation!(WprsServerState);
+smithay::delegate_keyboard_shortcuts_inhibit!(WprsServerState);
+smithay::delegate_idle_inhibit!(WprsServerState);
+
+#[cfg(test)]
+mod tests {
+    use smithay::utils::SERIAL_COUNTER;
+
+    use super::*;
+    use crate::server::testing::TestServer;
+
+    #[test]
+    fn test_activation_follows_input() {
+        let mut server = TestServer::new();
+        let mut client = server.connect();
+        let _keyboard = client.get_keyboard();
+        let (surface, _xdg_surface, _toplevel) = client.create_toplevel();
+        server.roundtrip(&mut client);
+        let surface_id = server
+            .take_requests()
+            .into_iter()
+            .find_map(|request| match request {
+                Request::Surface(SurfaceRequest { surface, .. }) => Some(surface),
+                _ => None,
+            })
+            .unwrap();
+        server
+            .state
+            .set_keyboard_focus(&surface_id, SERIAL_COUNTER.next_serial())
+            .unwrap();
+        server.roundtrip(&mut client);
+        let enter_serial = client.state.keyboard_enter_serial.unwrap();
+
+        for (serial, user_input) in [
+            (Some(enter_serial), true),
+            (None, false),
+            // Input from before the application had keyboard focus.
+            (Some(enter_serial.wrapping_sub(1)), false),
+        ] {
+            client.request_activation_token(&surface, serial);
+            server.roundtrip(&mut client);
+            let token = client.state.activation_token.take().unwrap();
+            client.activate(token, &surface);
+            server.roundtrip(&mut client);
+
+            let activations: Vec<_> = server
+                .take_requests()
+                .into_iter()
+                .filter_map(|request| match request {
+                    Request::Surface(SurfaceRequest {
+                        payload: SurfaceRequestPayload::Activate { user_input },
+                        ..
+                    }) => Some(user_input),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(activations, vec![user_input], "serial {serial:?}");
+        }
+    }
+
+    #[test]
+    fn test_buffer_to_read() {
+        // A commit attaching a buffer reads it.
+        assert_eq!(buffer_to_read(Some(2), false, || Some(1)), Some(2));
+        assert_eq!(buffer_to_read(Some(2), true, || Some(1)), Some(2));
+        // A damage-only commit reads the attached buffer again...
+        assert_eq!(buffer_to_read(None, true, || Some(1)), Some(1));
+        assert_eq!(buffer_to_read(None::<u32>, true, || None), None);
+        // ...other commits without a buffer don't read anything.
+        assert_eq!(buffer_to_read(None, false, || Some(1)), None);
+    }
+
+    #[test]
+    fn test_preferred_fractional_scale() {
+        // A scale reported before the application listens is sent once it
+        // does.
+        let mut preferred = PreferredFractionalScale::default();
+        assert_eq!(preferred.report(150), None);
+        assert_eq!(preferred.report(180), None);
+        assert_eq!(preferred.bind(), Some(180));
+        assert_eq!(preferred.report(120), Some(120));
+
+        // Nothing is sent before wprsc reports a scale.
+        let mut preferred = PreferredFractionalScale::default();
+        assert_eq!(preferred.bind(), None);
+        assert_eq!(preferred.report(240), Some(240));
+    }
+
+    #[test]
+    fn test_surface_damage_to_buffer() {
+        // A 200x100 buffer with a 90 degree transform is a 50x100 surface at
+        // scale 2, whose top left corner is the buffer's top right one.
+        let buffer_size = Some(Size::from((200, 100)));
+        let damage = Rectangle::from_loc_and_size((0, 0), (10, 5));
+        assert_eq!(
+            surface_damage_to_buffer(&damage, 2, Transform::_90, buffer_size),
+            Rectangle::from_loc_and_size((190, 0), (10, 20))
+        );
+        assert_eq!(
+            surface_damage_to_buffer(&damage, 2, Transform::Normal, buffer_size),
+            Rectangle::from_loc_and_size((0, 0), (20, 10))
+        );
+    }
+}
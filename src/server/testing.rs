@@ -0,0 +1,574 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An in-process wprsd and Wayland application for testing the server's
+//! handlers end to end. The server's serializer isn't connected to a wprsc,
+//! everything it sends to wprsc is kept in TestServer::requests instead.
+
+use std::fs::File;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_channel::Receiver;
+use nix::fcntl::OFlag;
+use nix::unistd;
+use smithay::reexports::calloop::EventLoop;
+use smithay::reexports::wayland_server::Display;
+use smithay_client_toolkit::reexports::client::backend::WaylandError;
+use smithay_client_toolkit::reexports::client::delegate_noop;
+use smithay_client_toolkit::reexports::client::event_created_child;
+use smithay_client_toolkit::reexports::client::protocol::wl_compositor::WlCompositor;
+use smithay_client_toolkit::reexports::client::protocol::wl_data_device;
+use smithay_client_toolkit::reexports::client::protocol::wl_data_device::WlDataDevice;
+use smithay_client_toolkit::reexports::client::protocol::wl_data_device_manager::WlDataDeviceManager;
+use smithay_client_toolkit::reexports::client::protocol::wl_data_offer::WlDataOffer;
+use smithay_client_toolkit::reexports::client::protocol::wl_keyboard;
+use smithay_client_toolkit::reexports::client::protocol::wl_keyboard::WlKeyboard;
+use smithay_client_toolkit::reexports::client::protocol::wl_output::WlOutput;
+use smithay_client_toolkit::reexports::client::protocol::wl_registry;
+use smithay_client_toolkit::reexports::client::protocol::wl_registry::WlRegistry;
+use smithay_client_toolkit::reexports::client::protocol::wl_seat::WlSeat;
+use smithay_client_toolkit::reexports::client::protocol::wl_surface;
+use smithay_client_toolkit::reexports::client::protocol::wl_surface::WlSurface;
+use smithay_client_toolkit::reexports::client::Connection;
+use smithay_client_toolkit::reexports::client::Dispatch;
+use smithay_client_toolkit::reexports::client::EventQueue;
+use smithay_client_toolkit::reexports::client::Proxy;
+use smithay_client_toolkit::reexports::client::QueueHandle;
+use smithay_client_toolkit::reexports::protocols::ext::session_lock::v1::client::ext_session_lock_manager_v1::ExtSessionLockManagerV1;
+use smithay_client_toolkit::reexports::protocols::ext::session_lock::v1::client::ext_session_lock_v1;
+use smithay_client_toolkit::reexports::protocols::ext::session_lock::v1::client::ext_session_lock_v1::ExtSessionLockV1;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_manager_v2::ZwpTabletManagerV2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_seat_v2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_seat_v2::ZwpTabletSeatV2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_tool_v2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_tool_v2::ZwpTabletToolV2;
+use smithay_client_toolkit::reexports::protocols::wp::tablet::zv2::client::zwp_tablet_v2::ZwpTabletV2;
+use smithay_client_toolkit::reexports::protocols::xdg::activation::v1::client::xdg_activation_token_v1;
+use smithay_client_toolkit::reexports::protocols::xdg::activation::v1::client::xdg_activation_token_v1::XdgActivationTokenV1;
+use smithay_client_toolkit::reexports::protocols::xdg::activation::v1::client::xdg_activation_v1::XdgActivationV1;
+use smithay_client_toolkit::reexports::protocols::xdg::shell::client::xdg_surface;
+use smithay_client_toolkit::reexports::protocols::xdg::shell::client::xdg_surface::XdgSurface;
+use smithay_client_toolkit::reexports::protocols::xdg::shell::client::xdg_toplevel;
+use smithay_client_toolkit::reexports::protocols::xdg::shell::client::xdg_toplevel::XdgToplevel;
+use smithay_client_toolkit::reexports::protocols::xdg::shell::client::xdg_wm_base;
+use smithay_client_toolkit::reexports::protocols::xdg::shell::client::xdg_wm_base::XdgWmBase;
+use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1;
+use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1::ZwlrLayerShellV1;
+use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1;
+use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1::ZwlrLayerSurfaceV1;
+
+use crate::serialization::Request;
+use crate::serialization::SendType;
+use crate::serialization::Serializer;
+use crate::server::smithay_handlers::ClientState;
+use crate::server::WprsServerState;
+
+/// Each round trip is a few exchanges, enough for requests, the events sent in
+/// response and the applications' replies to those.
+const ROUNDTRIP_EXCHANGES: usize = 4;
+
+pub(crate) struct TestServer {
+    pub display: Display<WprsServerState>,
+    pub state: WprsServerState,
+    /// Everything the server sent to wprsc.
+    pub requests: Receiver<SendType<Request>>,
+    _event_loop: EventLoop<'static, WprsServerState>,
+}
+
+impl TestServer {
+    pub fn new() -> Self {
+        let display = Display::new().unwrap();
+        let event_loop = EventLoop::try_new().unwrap();
+        let (serializer, requests) = Serializer::new_for_test();
+        let mut state = WprsServerState::new(
+            display.handle(),
+            event_loop.handle(),
+            serializer,
+            false,
+            Duration::from_secs_f64(1.0 / 60.0),
+            false,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            true,
+            false,
+            3,
+            16 << 20,
+        );
+        state
+            .seat
+            .add_keyboard(Default::default(), 200, 200)
+            .unwrap();
+        Self {
+            display,
+            state,
+            requests,
+            _event_loop: event_loop,
+        }
+    }
+
+    /// Connects a new application to the server.
+    pub fn connect(&mut self) -> TestClient {
+        let (server_end, client_end) = UnixStream::pair().unwrap();
+        self.display
+            .handle()
+            .insert_client(
+                server_end,
+                Arc::new(ClientState::new(
+                    self.state.serializer.writer().into_inner(),
+                )),
+            )
+            .unwrap();
+
+        let conn = Connection::from_socket(client_end).unwrap();
+        let queue = conn.new_event_queue();
+        let qh = queue.handle();
+        let registry = conn.display().get_registry(&qh, ());
+        let mut client = TestClient {
+            queue,
+            qh,
+            registry,
+            state: TestClientState::default(),
+        };
+        self.roundtrip(&mut client);
+        client
+    }
+
+    /// Exchanges messages between the server and client until both have
+    /// handled everything the other sent.
+    pub fn roundtrip(&mut self, client: &mut TestClient) {
+        for _ in 0..ROUNDTRIP_EXCHANGES {
+            client.queue.flush().unwrap();
+            self.display.dispatch_clients(&mut self.state).unwrap();
+            self.display.flush_clients().unwrap();
+            client.dispatch();
+        }
+    }
+
+    /// Asks for the application's selection. The contents can be read from the
+    /// returned pipe once the server wrote them.
+    pub fn request_paste(&mut self, client: &mut TestClient, mime_type: &str) -> File {
+        let offer = client
+            .state
+            .selection
+            .clone()
+            .expect("there's no selection");
+        let (read_fd, write_fd) = unistd::pipe2(OFlag::O_CLOEXEC).unwrap();
+        offer.receive(mime_type.to_string(), write_fd.as_fd());
+        self.roundtrip(client);
+        File::from(read_fd)
+    }
+
+    /// Pastes the application's selection, which the server has to answer
+    /// without asking wprsc.
+    pub fn paste(&mut self, client: &mut TestClient, mime_type: &str) -> Vec<u8> {
+        let mut pipe = self.request_paste(client, mime_type);
+        // Otherwise the server holds on to the pipe until wprsc answers, and
+        // reading it would never finish.
+        assert_eq!(self.take_requests(), Vec::new());
+
+        let mut data = Vec::new();
+        pipe.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    /// Everything the server sent to wprsc since the last call.
+    pub fn take_requests(&self) -> Vec<Request> {
+        self.requests
+            .try_iter()
+            .filter_map(|send_type| match send_type {
+                SendType::Object(request) => Some(request),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// What the application was told by the server.
+#[derive(Debug, Default)]
+pub(crate) struct TestClientState {
+    /// By interface name.
+    globals: Vec<(String, u32)>,
+    /// The outputs surfaces entered and left, in order.
+    pub surface_enters: Vec<WlOutput>,
+    pub surface_leaves: Vec<WlOutput>,
+    /// The serial of the last keyboard enter.
+    pub keyboard_enter_serial: Option<u32>,
+    /// The last activation token handed out.
+    pub activation_token: Option<String>,
+    /// The selection offered to the application.
+    pub selection: Option<WlDataOffer>,
+    pub toplevel_closes: usize,
+    pub tablet_tools_added: usize,
+    /// Proximity and tip changes of tablet tools.
+    pub tablet_tool_events: Vec<&'static str>,
+    /// The sizes layer surfaces were configured with, acknowledged right away.
+    pub layer_surface_configures: Vec<(u32, u32)>,
+    pub layer_surface_closes: usize,
+    pub session_locked: bool,
+    pub session_lock_finished: bool,
+}
+
+pub(crate) struct TestClient {
+    pub queue: EventQueue<TestClientState>,
+    pub qh: QueueHandle<TestClientState>,
+    registry: WlRegistry,
+    pub state: TestClientState,
+}
+
+impl TestClient {
+    pub fn bind<I>(&self, version: u32) -> I
+    where
+        I: Proxy + 'static,
+        TestClientState: Dispatch<I, ()>,
+    {
+        let interface = I::interface().name;
+        let (_, name) = self
+            .state
+            .globals
+            .iter()
+            .find(|(global, _)| global == interface)
+            .unwrap_or_else(|| panic!("the server doesn't advertise {interface}"));
+        self.registry.bind(*name, version, &self.qh, ())
+    }
+
+    /// Binds every global of the interface, for globals like wl_output with
+    /// one instance per output.
+    pub fn bind_all<I>(&self, version: u32) -> Vec<I>
+    where
+        I: Proxy + 'static,
+        TestClientState: Dispatch<I, ()>,
+    {
+        let interface = I::interface().name;
+        self.state
+            .globals
+            .iter()
+            .filter(|(global, _)| global == interface)
+            .map(|(_, name)| self.registry.bind(*name, version, &self.qh, ()))
+            .collect()
+    }
+
+    /// Creates a toplevel, which the server configures right away.
+    pub fn create_toplevel(&self) -> (WlSurface, XdgSurface, XdgToplevel) {
+        let compositor: WlCompositor = self.bind(1);
+        let wm_base: XdgWmBase = self.bind(1);
+        let surface = compositor.create_surface(&self.qh, ());
+        let xdg_surface = wm_base.get_xdg_surface(&surface, &self.qh, ());
+        let toplevel = xdg_surface.get_toplevel(&self.qh, ());
+        surface.commit();
+        (surface, xdg_surface, toplevel)
+    }
+
+    /// Creates a layer surface of the given size and makes the initial commit,
+    /// which the server has to reply to with a configure.
+    pub fn create_layer_surface(
+        &self,
+        layer: zwlr_layer_shell_v1::Layer,
+        width: u32,
+        height: u32,
+    ) -> (WlSurface, ZwlrLayerSurfaceV1) {
+        let compositor: WlCompositor = self.bind(1);
+        let layer_shell: ZwlrLayerShellV1 = self.bind(1);
+        let surface = compositor.create_surface(&self.qh, ());
+        let layer_surface =
+            layer_shell.get_layer_surface(&surface, None, layer, "test".to_string(), &self.qh, ());
+        layer_surface.set_size(width, height);
+        surface.commit();
+        (surface, layer_surface)
+    }
+
+    pub fn get_keyboard(&self) -> WlKeyboard {
+        let seat: WlSeat = self.bind(1);
+        seat.get_keyboard(&self.qh, ())
+    }
+
+    /// Requests an activation token for surface, with the given serial of
+    /// input on the seat. The token ends up in
+    /// TestClientState::activation_token.
+    pub fn request_activation_token(&self, surface: &WlSurface, serial: Option<u32>) {
+        let activation: XdgActivationV1 = self.bind(1);
+        let token = activation.get_activation_token(&self.qh, ());
+        if let Some(serial) = serial {
+            let seat: WlSeat = self.bind(1);
+            token.set_serial(serial, &seat);
+        }
+        token.set_surface(surface);
+        token.commit();
+    }
+
+    pub fn activate(&self, token: String, surface: &WlSurface) {
+        let activation: XdgActivationV1 = self.bind(1);
+        activation.activate(token, surface);
+    }
+
+    /// Starts listening for the selection, which the server offers once the
+    /// application has keyboard focus.
+    pub fn get_data_device(&self) -> WlDataDevice {
+        let seat: WlSeat = self.bind(1);
+        let data_device_manager: WlDataDeviceManager = self.bind(3);
+        data_device_manager.get_data_device(&seat, &self.qh, ())
+    }
+
+    pub fn get_tablet_seat(&self) -> ZwpTabletSeatV2 {
+        let seat: WlSeat = self.bind(1);
+        let tablet_manager: ZwpTabletManagerV2 = self.bind(1);
+        tablet_manager.get_tablet_seat(&seat, &self.qh, ())
+    }
+
+    pub fn lock_session(&self) -> ExtSessionLockV1 {
+        let session_lock_manager: ExtSessionLockManagerV1 = self.bind(1);
+        session_lock_manager.lock(&self.qh, ())
+    }
+
+    fn dispatch(&mut self) {
+        if let Some(guard) = self.queue.prepare_read() {
+            match guard.read() {
+                Ok(_) => {},
+                Err(WaylandError::Io(e)) if e.kind() == ErrorKind::WouldBlock => {},
+                Err(e) => panic!("reading from the server failed: {e}"),
+            }
+        }
+        self.queue.dispatch_pending(&mut self.state).unwrap();
+    }
+}
+
+impl Dispatch<WlRegistry, ()> for TestClientState {
+    fn event(
+        state: &mut Self,
+        _registry: &WlRegistry,
+        event: wl_registry::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_registry::Event::Global {
+                name, interface, ..
+            } => state.globals.push((interface, name)),
+            wl_registry::Event::GlobalRemove { name } => {
+                state.globals.retain(|(_, global)| *global != name)
+            },
+            _ => {},
+        }
+    }
+}
+
+impl Dispatch<WlSurface, ()> for TestClientState {
+    fn event(
+        state: &mut Self,
+        _surface: &WlSurface,
+        event: wl_surface::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_surface::Event::Enter { output } => state.surface_enters.push(output),
+            wl_surface::Event::Leave { output } => state.surface_leaves.push(output),
+            _ => {},
+        }
+    }
+}
+
+impl Dispatch<WlKeyboard, ()> for TestClientState {
+    fn event(
+        state: &mut Self,
+        _keyboard: &WlKeyboard,
+        event: wl_keyboard::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wl_keyboard::Event::Enter { serial, .. } = event {
+            state.keyboard_enter_serial = Some(serial);
+        }
+    }
+}
+
+impl Dispatch<XdgActivationTokenV1, ()> for TestClientState {
+    fn event(
+        state: &mut Self,
+        _token: &XdgActivationTokenV1,
+        event: xdg_activation_token_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let xdg_activation_token_v1::Event::Done { token } = event {
+            state.activation_token = Some(token);
+        }
+    }
+}
+
+impl Dispatch<XdgWmBase, ()> for TestClientState {
+    fn event(
+        _state: &mut Self,
+        wm_base: &XdgWmBase,
+        event: xdg_wm_base::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let xdg_wm_base::Event::Ping { serial } = event {
+            wm_base.pong(serial);
+        }
+    }
+}
+
+impl Dispatch<XdgSurface, ()> for TestClientState {
+    fn event(
+        _state: &mut Self,
+        xdg_surface: &XdgSurface,
+        event: xdg_surface::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let xdg_surface::Event::Configure { serial } = event {
+            xdg_surface.ack_configure(serial);
+        }
+    }
+}
+
+impl Dispatch<XdgToplevel, ()> for TestClientState {
+    fn event(
+        state: &mut Self,
+        _toplevel: &XdgToplevel,
+        event: xdg_toplevel::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let xdg_toplevel::Event::Close = event {
+            state.toplevel_closes += 1;
+        }
+    }
+}
+
+impl Dispatch<ZwlrLayerSurfaceV1, ()> for TestClientState {
+    fn event(
+        state: &mut Self,
+        layer_surface: &ZwlrLayerSurfaceV1,
+        event: zwlr_layer_surface_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_layer_surface_v1::Event::Configure {
+                serial,
+                width,
+                height,
+            } => {
+                layer_surface.ack_configure(serial);
+                state.layer_surface_configures.push((width, height));
+            },
+            zwlr_layer_surface_v1::Event::Closed => state.layer_surface_closes += 1,
+            _ => {},
+        }
+    }
+}
+
+impl Dispatch<WlDataDevice, ()> for TestClientState {
+    fn event(
+        state: &mut Self,
+        _data_device: &WlDataDevice,
+        event: wl_data_device::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wl_data_device::Event::Selection { id } = event {
+            state.selection = id;
+        }
+    }
+
+    event_created_child!(TestClientState, WlDataDevice, [
+        wl_data_device::EVT_DATA_OFFER_OPCODE => (WlDataOffer, ()),
+    ]);
+}
+
+impl Dispatch<ZwpTabletSeatV2, ()> for TestClientState {
+    fn event(
+        state: &mut Self,
+        _tablet_seat: &ZwpTabletSeatV2,
+        event: zwp_tablet_seat_v2::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let zwp_tablet_seat_v2::Event::ToolAdded { .. } = event {
+            state.tablet_tools_added += 1;
+        }
+    }
+
+    // The server has no tablet pads.
+    event_created_child!(TestClientState, ZwpTabletSeatV2, [
+        zwp_tablet_seat_v2::EVT_TABLET_ADDED_OPCODE => (ZwpTabletV2, ()),
+        zwp_tablet_seat_v2::EVT_TOOL_ADDED_OPCODE => (ZwpTabletToolV2, ()),
+    ]);
+}
+
+impl Dispatch<ZwpTabletToolV2, ()> for TestClientState {
+    fn event(
+        state: &mut Self,
+        _tool: &ZwpTabletToolV2,
+        event: zwp_tablet_tool_v2::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let event = match event {
+            zwp_tablet_tool_v2::Event::ProximityIn { .. } => "proximity_in",
+            zwp_tablet_tool_v2::Event::ProximityOut => "proximity_out",
+            zwp_tablet_tool_v2::Event::Down { .. } => "down",
+            zwp_tablet_tool_v2::Event::Up => "up",
+            _ => return,
+        };
+        state.tablet_tool_events.push(event);
+    }
+}
+
+impl Dispatch<ExtSessionLockV1, ()> for TestClientState {
+    fn event(
+        state: &mut Self,
+        _session_lock: &ExtSessionLockV1,
+        event: ext_session_lock_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_session_lock_v1::Event::Locked => state.session_locked = true,
+            ext_session_lock_v1::Event::Finished => state.session_lock_finished = true,
+            _ => {},
+        }
+    }
+}
+
+delegate_noop!(TestClientState: WlCompositor);
+delegate_noop!(TestClientState: WlDataDeviceManager);
+delegate_noop!(TestClientState: ExtSessionLockManagerV1);
+delegate_noop!(TestClientState: ZwlrLayerShellV1);
+delegate_noop!(TestClientState: XdgActivationV1);
+delegate_noop!(TestClientState: ZwpTabletManagerV2);
+delegate_noop!(TestClientState: ignore WlDataOffer);
+delegate_noop!(TestClientState: ignore WlOutput);
+delegate_noop!(TestClientState: ignore WlSeat);
+delegate_noop!(TestClientState: ignore ZwpTabletV2);
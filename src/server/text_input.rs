@@ -0,0 +1,366 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! zwp_text_input_v3. smithay's implementation connects text inputs to input
+//! methods running against the compositor, while the input method used with
+//! wprs is the one of the client's compositor, so text inputs are implemented
+//! here and forwarded to wprsc instead, see serialization::text_input.
+
+use std::mem;
+use std::sync::Mutex;
+
+use smithay::reexports::wayland_protocols::wp::text_input::zv3::server::zwp_text_input_manager_v3;
+use smithay::reexports::wayland_protocols::wp::text_input::zv3::server::zwp_text_input_manager_v3::ZwpTextInputManagerV3;
+use smithay::reexports::wayland_protocols::wp::text_input::zv3::server::zwp_text_input_v3;
+use smithay::reexports::wayland_protocols::wp::text_input::zv3::server::zwp_text_input_v3::ZwpTextInputV3;
+use smithay::reexports::wayland_server::backend::ClientId;
+use smithay::reexports::wayland_server::backend::GlobalId;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::reexports::wayland_server::Client;
+use smithay::reexports::wayland_server::DataInit;
+use smithay::reexports::wayland_server::Dispatch;
+use smithay::reexports::wayland_server::DisplayHandle;
+use smithay::reexports::wayland_server::GlobalDispatch;
+use smithay::reexports::wayland_server::New;
+use smithay::reexports::wayland_server::Resource;
+use smithay::reexports::wayland_server::WEnum;
+
+use crate::prelude::*;
+use crate::serialization::geometry::Rectangle;
+use crate::serialization::text_input::SurroundingText;
+use crate::serialization::text_input::TextInputEvent;
+use crate::serialization::text_input::TextInputRequest;
+use crate::serialization::text_input::TextInputState;
+use crate::serialization::Request;
+use crate::serialization::SendType;
+use crate::server::WprsServerState;
+
+#[derive(Debug)]
+pub struct TextInputManagerState {
+    _global: GlobalId,
+    text_inputs: Vec<ZwpTextInputV3>,
+    /// The surface with keyboard focus, which the text inputs of its
+    /// application have entered.
+    focus: Option<WlSurface>,
+}
+
+impl TextInputManagerState {
+    pub fn new(dh: &DisplayHandle) -> Self {
+        Self {
+            _global: dh.create_global::<WprsServerState, ZwpTextInputManagerV3, _>(1, ()),
+            text_inputs: Vec::new(),
+            focus: None,
+        }
+    }
+
+    fn focused_text_inputs(&self) -> impl Iterator<Item = &ZwpTextInputV3> {
+        let focus = self.focus.as_ref();
+        self.text_inputs
+            .iter()
+            .filter(move |text_input| focus.is_some_and(|focus| is_for(text_input, focus)))
+    }
+}
+
+fn is_for(text_input: &ZwpTextInputV3, surface: &WlSurface) -> bool {
+    text_input.id().same_client_as(&surface.id())
+}
+
+fn text_input_data(text_input: &ZwpTextInputV3) -> &Mutex<TextInput> {
+    text_input.data::<Mutex<TextInput>>().unwrap()
+}
+
+/// The state of one of an application's text inputs.
+#[derive(Debug, Default)]
+pub struct TextInput {
+    pending_enabled: bool,
+    pending: TextInputState,
+    enabled: bool,
+    /// The number of commit requests, which done events refer to.
+    commits: u32,
+}
+
+impl TextInput {
+    fn enable(&mut self) {
+        // Enabling resets the state.
+        self.pending_enabled = true;
+        self.pending = TextInputState::default();
+    }
+
+    fn disable(&mut self) {
+        self.pending_enabled = false;
+    }
+
+    /// Applies the pending state, returning what to tell wprsc about it.
+    fn commit(&mut self) -> Option<TextInputRequest> {
+        self.commits = self.commits.wrapping_add(1);
+        let state = self.pending.clone();
+        // Unlike the rest of the state, these only apply to a single commit.
+        self.pending.surrounding_text = None;
+        self.pending.text_change_cause = zwp_text_input_v3::ChangeCause::InputMethod.into();
+
+        let was_enabled = mem::replace(&mut self.enabled, self.pending_enabled);
+        match (was_enabled, self.enabled) {
+            (_, true) => Some(TextInputRequest::Enabled(state)),
+            (true, false) => Some(TextInputRequest::Disabled),
+            (false, false) => None,
+        }
+    }
+}
+
+impl WprsServerState {
+    /// Moves the text input focus along with the keyboard focus.
+    pub(crate) fn set_text_input_focus(&mut self, focus: Option<&WlSurface>) {
+        let state = &mut self.text_input_manager_state;
+        if state.focus.as_ref() == focus {
+            return;
+        }
+
+        if let Some(old_focus) = state.focus.take() {
+            let mut was_enabled = false;
+            for text_input in state
+                .text_inputs
+                .iter()
+                .filter(|text_input| is_for(text_input, &old_focus))
+            {
+                text_input.leave(&old_focus);
+                // The application has to enable its text input again after
+                // the next enter.
+                let mut data = text_input_data(text_input).lock().unwrap();
+                was_enabled |= data.enabled;
+                data.enabled = false;
+                data.pending_enabled = false;
+            }
+            if was_enabled {
+                self.serializer
+                    .writer()
+                    .send(SendType::Object(Request::TextInput(
+                        TextInputRequest::Disabled,
+                    )));
+            }
+        }
+
+        if let Some(focus) = focus {
+            for text_input in state
+                .text_inputs
+                .iter()
+                .filter(|text_input| is_for(text_input, focus))
+            {
+                text_input.enter(focus);
+            }
+        }
+        state.focus = focus.cloned();
+    }
+
+    /// Replays the events of wprsc's text input on the enabled text inputs of
+    /// the application with keyboard focus.
+    pub(crate) fn handle_text_input(&mut self, event: TextInputEvent) -> Result<()> {
+        for text_input in self.text_input_manager_state.focused_text_inputs() {
+            let data = text_input_data(text_input).lock().unwrap();
+            if !data.enabled {
+                continue;
+            }
+            match &event {
+                TextInputEvent::PreeditString {
+                    text,
+                    cursor_begin,
+                    cursor_end,
+                } => text_input.preedit_string(text.clone(), *cursor_begin, *cursor_end),
+                TextInputEvent::CommitString(text) => text_input.commit_string(text.clone()),
+                TextInputEvent::DeleteSurroundingText {
+                    before_length,
+                    after_length,
+                } => text_input.delete_surrounding_text(*before_length, *after_length),
+                TextInputEvent::Done => text_input.done(data.commits),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl GlobalDispatch<ZwpTextInputManagerV3, ()> for WprsServerState {
+    fn bind(
+        _state: &mut Self,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwpTextInputManagerV3>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl Dispatch<ZwpTextInputManagerV3, ()> for WprsServerState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        _manager: &ZwpTextInputManagerV3,
+        request: zwp_text_input_manager_v3::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            // wprsd has a single seat.
+            zwp_text_input_manager_v3::Request::GetTextInput { id, seat: _ } => {
+                let text_input = data_init.init(id, Mutex::new(TextInput::default()));
+                let state = &mut state.text_input_manager_state;
+                if let Some(focus) = &state.focus {
+                    if is_for(&text_input, focus) {
+                        text_input.enter(focus);
+                    }
+                }
+                state.text_inputs.push(text_input);
+            },
+            zwp_text_input_manager_v3::Request::Destroy => {},
+            _ => {},
+        }
+    }
+}
+
+impl Dispatch<ZwpTextInputV3, Mutex<TextInput>> for WprsServerState {
+    fn request(
+        state: &mut Self,
+        _client: &Client,
+        text_input: &ZwpTextInputV3,
+        request: zwp_text_input_v3::Request,
+        data: &Mutex<TextInput>,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        let mut data = data.lock().unwrap();
+        match request {
+            zwp_text_input_v3::Request::Enable => data.enable(),
+            zwp_text_input_v3::Request::Disable => data.disable(),
+            zwp_text_input_v3::Request::SetSurroundingText {
+                text,
+                cursor,
+                anchor,
+            } => {
+                data.pending.surrounding_text = Some(SurroundingText {
+                    text,
+                    cursor,
+                    anchor,
+                });
+            },
+            zwp_text_input_v3::Request::SetTextChangeCause { cause } => {
+                data.pending.text_change_cause = match cause {
+                    WEnum::Value(cause) => cause.into(),
+                    WEnum::Unknown(cause) => cause,
+                };
+            },
+            zwp_text_input_v3::Request::SetContentType { hint, purpose } => {
+                data.pending.content_hint = match hint {
+                    WEnum::Value(hint) => hint.bits(),
+                    WEnum::Unknown(hint) => hint,
+                };
+                data.pending.content_purpose = match purpose {
+                    WEnum::Value(purpose) => purpose.into(),
+                    WEnum::Unknown(purpose) => purpose,
+                };
+            },
+            zwp_text_input_v3::Request::SetCursorRectangle {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                data.pending.cursor_rectangle = Some(Rectangle::new(x, y, width, height));
+            },
+            zwp_text_input_v3::Request::Commit => {
+                let text_input_request = data.commit();
+                // Text inputs without focus are ignored, but keep counting
+                // commits.
+                let focused = state
+                    .text_input_manager_state
+                    .focus
+                    .as_ref()
+                    .is_some_and(|focus| is_for(text_input, focus));
+                if let (Some(text_input_request), true) = (text_input_request, focused) {
+                    state
+                        .serializer
+                        .writer()
+                        .send(SendType::Object(Request::TextInput(text_input_request)));
+                }
+            },
+            zwp_text_input_v3::Request::Destroy => {},
+            _ => {},
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: ClientId,
+        text_input: &ZwpTextInputV3,
+        data: &Mutex<TextInput>,
+    ) {
+        let state_ = &mut state.text_input_manager_state;
+        state_.text_inputs.retain(|t| t != text_input);
+        let focused = state_
+            .focus
+            .as_ref()
+            .is_some_and(|focus| is_for(text_input, focus));
+        if focused && data.lock().unwrap().enabled {
+            state
+                .serializer
+                .writer()
+                .send(SendType::Object(Request::TextInput(
+                    TextInputRequest::Disabled,
+                )));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commit() {
+        let mut text_input = TextInput::default();
+        assert_eq!(text_input.commit(), None);
+
+        text_input.enable();
+        text_input.pending.content_purpose = 1;
+        text_input.pending.surrounding_text = Some(SurroundingText {
+            text: "abc".to_string(),
+            cursor: 3,
+            anchor: 3,
+        });
+        let Some(TextInputRequest::Enabled(state)) = text_input.commit() else {
+            panic!("text input wasn't enabled");
+        };
+        assert_eq!(state.content_purpose, 1);
+        assert!(state.surrounding_text.is_some());
+
+        // The surrounding text only applies to a single commit, the content
+        // type until the text input is enabled again.
+        let Some(TextInputRequest::Enabled(state)) = text_input.commit() else {
+            panic!("text input wasn't enabled");
+        };
+        assert_eq!(state.content_purpose, 1);
+        assert_eq!(state.surrounding_text, None);
+
+        text_input.disable();
+        assert_eq!(text_input.commit(), Some(TextInputRequest::Disabled));
+        assert_eq!(text_input.commit(), None);
+
+        text_input.enable();
+        let Some(TextInputRequest::Enabled(state)) = text_input.commit() else {
+            panic!("text input wasn't enabled");
+        };
+        assert_eq!(state.content_purpose, 0);
+        assert_eq!(text_input.commits, 6);
+    }
+}
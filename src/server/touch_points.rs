@@ -0,0 +1,88 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The touch points which are down, and the surfaces they went down on, so
+//! that the rest of each touch point's events go to the same surface.
+
+use std::collections::HashMap;
+
+#[derive(Debug)]
+pub struct TouchPoints<S> {
+    surfaces: HashMap<i32, S>,
+}
+
+impl<S> Default for TouchPoints<S> {
+    fn default() -> Self {
+        Self {
+            surfaces: HashMap::new(),
+        }
+    }
+}
+
+impl<S> TouchPoints<S> {
+    pub fn down(&mut self, id: i32, surface: S) {
+        self.surfaces.insert(id, surface);
+    }
+
+    /// Returns whether the touch point was down.
+    pub fn up(&mut self, id: i32) -> bool {
+        self.surfaces.remove(&id).is_some()
+    }
+
+    /// The surface the touch point went down on, None if it isn't down.
+    pub fn surface(&self, id: i32) -> Option<&S> {
+        self.surfaces.get(&id)
+    }
+
+    /// Lifts all touch points, e.g. because the wprsc they came from went
+    /// away. Returns whether any were down, in which case applications have to
+    /// be told that the touch sequence was cancelled.
+    pub fn cancel(&mut self) -> bool {
+        let any_down = !self.surfaces.is_empty();
+        self.surfaces.clear();
+        any_down
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_touch_points() {
+        let mut touch_points = TouchPoints::default();
+        touch_points.down(0, "a");
+        touch_points.down(1, "b");
+        assert_eq!(touch_points.surface(0), Some(&"a"));
+        assert_eq!(touch_points.surface(1), Some(&"b"));
+
+        assert!(touch_points.up(0));
+        assert!(!touch_points.up(0));
+        assert_eq!(touch_points.surface(0), None);
+        assert_eq!(touch_points.surface(1), Some(&"b"));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut touch_points = TouchPoints::default();
+        assert!(!touch_points.cancel());
+
+        // A touch point which was down when wprsc reconnected.
+        touch_points.down(0, "a");
+        assert!(touch_points.cancel());
+        assert_eq!(touch_points.surface(0), None);
+        assert!(!touch_points.up(0));
+        assert!(!touch_points.cancel());
+    }
+}
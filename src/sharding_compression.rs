@@ -17,6 +17,8 @@ use std::io::Write;
 use std::mem;
 use std::num::NonZeroUsize;
 use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 
 use anyhow::Error;
 use crossbeam_channel::Receiver;
@@ -24,6 +26,8 @@ use crossbeam_channel::Sender;
 use divbuf::DivBufMut;
 use divbuf::DivBufShared;
 use fallible_iterator::FallibleIterator;
+use num_enum::IntoPrimitive;
+use num_enum::TryFromPrimitive;
 use zstd::bulk;
 
 use crate::arc_slice::ArcSlice;
@@ -33,10 +37,81 @@ use crate::utils;
 // TODO: benchmark this and pick a value based on that.
 pub const MIN_SIZE_TO_COMPRESS: usize = 4096;
 
+/// The algorithm shards are compressed with, which is also what
+/// CompressedShard::compression holds.
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    Eq,
+    PartialEq,
+    Hash,
+    IntoPrimitive,
+    TryFromPrimitive,
+    serde_derive::Deserialize,
+    serde_derive::Serialize,
+)]
+#[repr(u32)]
+pub enum CompressionAlgorithm {
+    None,
+    Zstd,
+    /// Requires the lz4 feature.
+    Lz4,
+    /// Requires the brotli feature.
+    Brotli,
+}
+
+impl CompressionAlgorithm {
+    pub const ALL: &'static [Self] = &[Self::None, Self::Zstd, Self::Lz4, Self::Brotli];
+
+    /// Whether this build can compress and decompress data with the
+    /// algorithm.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Self::None | Self::Zstd => true,
+            Self::Lz4 => cfg!(feature = "lz4"),
+            Self::Brotli => cfg!(feature = "brotli"),
+        }
+    }
+
+    /// Checks that data compressed by the other end with the algorithm
+    /// numbered value can be decompressed by this end.
+    pub fn check_supported(value: u32) -> Result<Self> {
+        let algorithm =
+            Self::try_from(value).map_err(|_| anyhow!("unknown compression algorithm {value}"))?;
+        if !algorithm.is_supported() {
+            bail!(
+                "data is compressed with {algorithm:?}, which this build of wprs doesn't support"
+            );
+        }
+        Ok(algorithm)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, serde_derive::Deserialize, serde_derive::Serialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    pub algorithm: CompressionAlgorithm,
+    /// Algorithm-specific: 1 to 22 for zstd (or negative, for faster
+    /// compression), 0 to 11 for brotli. For lz4, levels above 1 use its
+    /// high compression mode. Ignored for None.
+    pub level: i32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: CompressionAlgorithm::Zstd,
+            level: 1,
+        }
+    }
+}
+
 #[derive(Clone, Eq, PartialEq)]
 pub struct CompressedShard {
     pub idx: u32,
-    pub compression: u32, // TODO: this is terrible
+    /// A CompressionAlgorithm.
+    pub compression: u32,
     pub data: Vec<u8>,
 }
 
@@ -55,12 +130,7 @@ impl CompressedShard {
         stream.write_all(&size.to_le_bytes()).location(loc!())?;
 
         debug!("writing data");
-        stream.write_all(&self.data).location(loc!())?;
-
-        // Flush here instaed of after writing all the frames so that the client
-        // can start decompressing the shards sooner.
-        stream.flush().location(loc!())?;
-        Ok(())
+        stream.write_all(&self.data).location(loc!())
     }
 
     pub fn framed_read<R: Read>(stream: &mut R) -> Result<Self> {
@@ -93,63 +163,134 @@ impl CompressedShard {
     }
 }
 
+/// The state of a compressor thread.
+enum Compressor {
+    None,
+    Zstd(bulk::Compressor<'static>),
+    #[cfg(feature = "lz4")]
+    Lz4(Option<lz4::block::CompressionMode>),
+    #[cfg(feature = "brotli")]
+    Brotli {
+        quality: u32,
+    },
+}
+
+impl Compressor {
+    fn new(config: CompressionConfig) -> Result<Self> {
+        Ok(match config.algorithm {
+            CompressionAlgorithm::None => Self::None,
+            CompressionAlgorithm::Zstd => {
+                let mut compressor = bulk::Compressor::new(config.level).location(loc!())?;
+                compressor.long_distance_matching(true).location(loc!())?;
+                Self::Zstd(compressor)
+            },
+            #[cfg(feature = "lz4")]
+            CompressionAlgorithm::Lz4 => Self::Lz4(
+                (config.level > 1).then_some(lz4::block::CompressionMode::HIGHCOMPRESSION(
+                    config.level,
+                )),
+            ),
+            #[cfg(feature = "brotli")]
+            CompressionAlgorithm::Brotli => Self::Brotli {
+                quality: config.level.clamp(0, 11) as u32,
+            },
+            #[allow(unreachable_patterns)]
+            algorithm => bail!(
+                "{algorithm:?} compression isn't supported by this build of wprs, see the lz4 and brotli features"
+            ),
+        })
+    }
+
+    fn algorithm(&self) -> CompressionAlgorithm {
+        match self {
+            Self::None => CompressionAlgorithm::None,
+            Self::Zstd(_) => CompressionAlgorithm::Zstd,
+            #[cfg(feature = "lz4")]
+            Self::Lz4(_) => CompressionAlgorithm::Lz4,
+            #[cfg(feature = "brotli")]
+            Self::Brotli { .. } => CompressionAlgorithm::Brotli,
+        }
+    }
+
+    /// These will allocate as much space as they need, so compression should
+    /// never panic.
+    fn compress(&mut self, input: &[u8]) -> Vec<u8> {
+        match self {
+            Self::None => input.to_vec(),
+            Self::Zstd(compressor) => compressor.compress(input).unwrap(),
+            #[cfg(feature = "lz4")]
+            Self::Lz4(mode) => lz4::block::compress(input, *mode, false).unwrap(),
+            #[cfg(feature = "brotli")]
+            Self::Brotli { quality } => {
+                let mut output = Vec::new();
+                {
+                    let mut writer = brotli::CompressorWriter::new(&mut output, 4096, *quality, 22);
+                    writer.write_all(input).unwrap();
+                }
+                output
+            },
+        }
+    }
+}
+
 fn spawn_compressor(
-    compression_level: i32,
-    input_rx: Receiver<(usize, ArcSlice<u8>)>,
-    output_tx: Sender<CompressedShard>,
+    config: CompressionConfig,
+    input_rx: Receiver<(usize, ArcSlice<u8>, bool)>,
+    output_tx: Sender<(CompressedShard, Duration)>,
 ) -> Result<()> {
-    let mut compressor = bulk::Compressor::new(compression_level).location(loc!())?;
-    compressor.long_distance_matching(true).location(loc!())?;
+    let mut compressor = Compressor::new(config).location(loc!())?;
     thread::spawn(move || {
         // The iterator (and, consequently, the thread) will terminate when all
         // the input senders (which are all in the ShardingCompressor) are
         // dropped.
-        for (idx, input) in input_rx {
+        for (idx, input, compress) in input_rx {
             let _span = debug_span!("compressor").entered();
+            let start = Instant::now();
             // We could pre-allocate a buffer at the end of the loop, while
             // waiting for the next input, and use compress_to_buffer, but that
             // doesn't result in a significant speedup here.
-            //
-            // This will allocate as much space as it needs, so compression
-            // should never panic.
-            let compression = if input.len() > MIN_SIZE_TO_COMPRESS {
-                1
+            let compression = if compress && input.len() > MIN_SIZE_TO_COMPRESS {
+                compressor.algorithm()
             } else {
-                0
+                CompressionAlgorithm::None
             };
-            let data = if compression == 0 {
+            let data = if compression == CompressionAlgorithm::None {
                 input.as_ref().to_vec()
             } else {
-                compressor.compress(&input).unwrap()
+                compressor.compress(&input)
             };
 
             // This will be an error when the ShardingDecompressor is dropped,
             // but the for loop (and consequently this thread) will terminate at
             // the same time for the same reason.
-            _ = output_tx.send(CompressedShard {
-                idx: idx as u32,
-                compression,
-                data,
-            });
+            _ = output_tx.send((
+                CompressedShard {
+                    idx: idx as u32,
+                    compression: compression.into(),
+                    data,
+                },
+                start.elapsed(),
+            ));
         }
     });
     Ok(())
 }
 
 pub struct ShardingCompressor {
-    compressor_input: Sender<(usize, ArcSlice<u8>)>,
-    compressor_output: Receiver<CompressedShard>,
+    compressor_input: Sender<(usize, ArcSlice<u8>, bool)>,
+    compressor_output: Receiver<(CompressedShard, Duration)>,
+    algorithm: CompressionAlgorithm,
 }
 
 impl ShardingCompressor {
-    pub fn new(n_compressors: NonZeroUsize, compression_level: i32) -> Result<Self> {
+    pub fn new(n_compressors: NonZeroUsize, config: CompressionConfig) -> Result<Self> {
         // These channels will have at most n_shards items in them, but we only
         // know n_shards when compress is called, not now.
         let (compressor_input_tx, compressor_input_rx) = crossbeam_channel::unbounded();
         let (compressor_output_tx, compressor_output_rx) = crossbeam_channel::unbounded();
         for _ in 0..n_compressors.get() {
             spawn_compressor(
-                compression_level,
+                config,
                 compressor_input_rx.clone(),
                 compressor_output_tx.clone(),
             )
@@ -159,15 +300,41 @@ impl ShardingCompressor {
         Ok(Self {
             compressor_input: compressor_input_tx,
             compressor_output: compressor_output_rx,
+            algorithm: config.algorithm,
         })
     }
 
-    #[instrument(skip_all, level = "debug")]
+    pub fn algorithm(&self) -> CompressionAlgorithm {
+        self.algorithm
+    }
+
     pub fn compress(
         &self,
         n_shards: NonZeroUsize,
         data: ArcSlice<u8>,
     ) -> impl Iterator<Item = CompressedShard> + '_ {
+        self.compress_timed(n_shards, data).map(|(shard, _)| shard)
+    }
+
+    /// Like compress, but also returns how long each shard took to compress.
+    pub fn compress_timed(
+        &self,
+        n_shards: NonZeroUsize,
+        data: ArcSlice<u8>,
+    ) -> impl Iterator<Item = (CompressedShard, Duration)> + '_ {
+        self.maybe_compress_timed(n_shards, data, true)
+    }
+
+    /// Like compress_timed, but the shards are only compressed if compress is
+    /// true. Otherwise they're sent as is, for data which isn't worth the CPU
+    /// time.
+    #[instrument(skip_all, level = "debug")]
+    pub fn maybe_compress_timed(
+        &self,
+        n_shards: NonZeroUsize,
+        data: ArcSlice<u8>,
+        compress: bool,
+    ) -> impl Iterator<Item = (CompressedShard, Duration)> + '_ {
         let n_shards = n_shards.get();
         let size = data.len();
         let chunk_size = size / n_shards;
@@ -175,7 +342,7 @@ impl ShardingCompressor {
         let chunks = data.chunks(chunk_size);
         let actual_n_shards = chunks.len();
         for (i, chunk) in chunks.enumerate() {
-            self.compressor_input.send((i, chunk)).unwrap();
+            self.compressor_input.send((i, chunk, compress)).unwrap();
         }
 
         // Will only panic is the other end disconnected, which should never
@@ -185,7 +352,9 @@ impl ShardingCompressor {
 }
 
 /// # Panics
-/// If there is a bug and the decompression buffer wasn't resized to be large enough.
+/// If there is a bug and the decompression buffer wasn't resized to be large
+/// enough, or if the shard's algorithm wasn't checked with
+/// CompressionAlgorithm::check_supported.
 pub fn spawn_decompressor(
     input_rx: Receiver<(CompressedShard, DivBufMut)>,
     output_tx: Sender<()>,
@@ -197,15 +366,31 @@ pub fn spawn_decompressor(
         // dropped.
         for (input, mut output) in input_rx.iter() {
             let _span = debug_span!("decompressor").entered();
-            if input.compression == 0 {
-                // The last output block will be larger than the data.
-                let output = &mut output[0..input.data.len()];
-                output.copy_from_slice(&input.data);
-            } else {
-                // We made DivBufMut large enough, so this should never panic.
-                decompressor
-                    .decompress_to_buffer(&input.data, output.as_mut())
-                    .unwrap();
+            // We made DivBufMut large enough, so none of these should panic.
+            match CompressionAlgorithm::try_from(input.compression).unwrap() {
+                CompressionAlgorithm::None => {
+                    // The last output block will be larger than the data.
+                    let output = &mut output[0..input.data.len()];
+                    output.copy_from_slice(&input.data);
+                },
+                CompressionAlgorithm::Zstd => {
+                    decompressor
+                        .decompress_to_buffer(&input.data, output.as_mut())
+                        .unwrap();
+                },
+                #[cfg(feature = "lz4")]
+                CompressionAlgorithm::Lz4 => {
+                    let len = output.len() as i32;
+                    lz4::block::decompress_to_buffer(&input.data, Some(len), output.as_mut())
+                        .unwrap();
+                },
+                #[cfg(feature = "brotli")]
+                CompressionAlgorithm::Brotli => {
+                    let mut output: &mut [u8] = output.as_mut();
+                    brotli::BrotliDecompress(&mut input.data.as_slice(), &mut output).unwrap();
+                },
+                #[allow(unreachable_patterns)]
+                algorithm => unreachable!("{algorithm:?} is not supported"),
             }
             drop(output); // release our handle
 
@@ -310,6 +495,8 @@ impl ShardingDecompressor {
                 .collect();
 
             while let Some(shard) = compressed_shards.next()? {
+                // Fail here rather than in a decompressor thread.
+                CompressionAlgorithm::check_supported(shard.compression).location(loc!())?;
                 let out_block = outs.get_mut(shard.idx as usize).unwrap().take().unwrap();
                 self.decompressor_input.send((shard, out_block)).unwrap();
             }
@@ -369,3 +556,51 @@ impl ShardingDecompressor {
         Ok(vec)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let n_shards = NonZeroUsize::new(4).unwrap();
+        for &algorithm in CompressionAlgorithm::ALL {
+            let config = CompressionConfig {
+                algorithm,
+                ..Default::default()
+            };
+            if !algorithm.is_supported() {
+                assert!(ShardingCompressor::new(n_shards, config).is_err());
+                continue;
+            }
+
+            let compressor = ShardingCompressor::new(n_shards, config).unwrap();
+            let shards: Vec<CompressedShard> = compressor
+                .compress(n_shards, ArcSlice::new(data.clone()))
+                .collect();
+            assert!(shards
+                .iter()
+                .all(|shard| shard.compression == u32::from(algorithm)));
+
+            let mut decompressor = ShardingDecompressor::new(n_shards).unwrap();
+            let decompressed = decompressor
+                .decompress_to_owned(
+                    n_shards,
+                    data.len(),
+                    fallible_iterator::convert(shards.into_iter().map(Ok)),
+                )
+                .unwrap();
+            assert_eq!(decompressed, data, "{algorithm:?}");
+        }
+    }
+
+    #[test]
+    fn test_check_supported() {
+        assert_eq!(
+            CompressionAlgorithm::check_supported(CompressionAlgorithm::Zstd.into()).unwrap(),
+            CompressionAlgorithm::Zstd
+        );
+        assert!(CompressionAlgorithm::check_supported(1000).is_err());
+    }
+}
@@ -14,21 +14,79 @@
 
 /// u8 AoS<>SoA conversion, based on
 /// https://stackoverflow.com/questions/44984724/whats-the-fastest-stride-3-gather-instruction-sequence.
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::uint8x16x4_t;
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::vld1q_u8;
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::vld4q_u8;
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::vst1q_u8;
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::vst4q_u8;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::__m128i;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::__m256i;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_blend_epi32;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_castps_si256;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_castsi128_si256;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_castsi256_ps;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_castsi256_si128;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_extracti128_si256;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_inserti128_si256;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_loadu_si256;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_set_epi8;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_shuffle_epi8;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_shuffle_ps;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_storeu_si256;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm_loadu_si128;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm_storeu_si128;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::__m128i;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::__m256i;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_blend_epi32;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_castps_si256;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_castsi128_si256;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_castsi256_ps;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_castsi256_si128;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_extracti128_si256;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_inserti128_si256;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_loadu_si256;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_set_epi8;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_shuffle_epi8;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_shuffle_ps;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_storeu_si256;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm_loadu_si128;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm_storeu_si128;
 use std::cmp;
 
@@ -427,6 +485,115 @@ pub unsafe fn vec4u8_aos_to_soa_avx2_parallel(aos: BufferPointer<Vec4u8>, soa: &
     soa3[lim..len].copy_from_slice(&rem3[0..rem]);
 }
 
+// SAFETY:
+// * neon must be available.
+// * `input` must be valid for reads of 64 bytes.
+// * Each `out` must be valid for writes of 16 bytes.
+#[allow(unsafe_op_in_unsafe_fn)]
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+#[inline]
+unsafe fn aos_to_soa_u8_16x4(
+    input: BufferPointer<Vec4u8>,
+    out0: &mut [u8],
+    out1: &mut [u8],
+    out2: &mut [u8],
+    out3: &mut [u8],
+) {
+    // vld4q_u8 deinterleaves as it loads, so there's nothing to shuffle.
+    let t: uint8x16x4_t = vld4q_u8(input.ptr().cast::<u8>());
+    vst1q_u8(out0.as_mut_ptr(), t.0);
+    vst1q_u8(out1.as_mut_ptr(), t.1);
+    vst1q_u8(out2.as_mut_ptr(), t.2);
+    vst1q_u8(out3.as_mut_ptr(), t.3);
+}
+
+// SAFETY:
+// * neon must be available.
+// * Each `input` must be valid for reads of 16 bytes.
+// * `out` must be valid for writes of 64 bytes.
+#[allow(unsafe_op_in_unsafe_fn)]
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+#[inline]
+unsafe fn soa_to_aos_u8_16x4(
+    input0: &[u8],
+    input1: &[u8],
+    input2: &[u8],
+    input3: &[u8],
+    out: &mut [Vec4u8],
+) {
+    let t = uint8x16x4_t(
+        vld1q_u8(input0.as_ptr()),
+        vld1q_u8(input1.as_ptr()),
+        vld1q_u8(input2.as_ptr()),
+        vld1q_u8(input3.as_ptr()),
+    );
+    vst4q_u8(out.as_mut_ptr().cast::<u8>(), t);
+}
+
+// SAFETY:
+// * neon must be available.
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+#[instrument(skip_all, level = "debug")]
+pub unsafe fn vec4u8_aos_to_soa_neon_parallel(aos: BufferPointer<Vec4u8>, soa: &mut Vec4u8s) {
+    let len = aos.len();
+    assert_eq!(len, soa.len());
+
+    // aos_to_soa_u8_16x4 operates on blocks of 512 bits aka 64 bytes aka 16
+    // Vec4u8s.
+    let n_blocks = len / 16; // number of 16x Vec4u8 blocks
+    let lim = n_blocks * 16; // number of Vec4u8s to transpose in blocks
+    let n_threads = 4;
+    let blocks_per_thread = cmp::max(n_blocks / n_threads, 1);
+    let thread_chunk_size = blocks_per_thread * 16;
+
+    let (aos_to_lim, aos_remainder) = aos.split_at(lim);
+
+    debug_span!("aos_to_soa_u8_16x4_loop").in_scope(|| {
+        ThreadPool::global().scoped(|s| {
+            for (aos, (soa0, soa1, soa2, soa3)) in izip!(
+                aos_to_lim.chunks(thread_chunk_size),
+                soa.chunks_mut(thread_chunk_size)
+            ) {
+                s.run(move || {
+                    for (aos_chunk, soa0_chunk, soa1_chunk, soa2_chunk, soa3_chunk) in izip!(
+                        aos.chunks_exact(16).0,
+                        soa0.chunks_exact_mut(16),
+                        soa1.chunks_exact_mut(16),
+                        soa2.chunks_exact_mut(16),
+                        soa3.chunks_exact_mut(16)
+                    ) {
+                        unsafe {
+                            // SAFETY:
+                            // * aos_chunk is 16 Vec4u8s, which is 16*4 = 64 bytes.
+                            // * soa chunks are 16 bytes.
+                            aos_to_soa_u8_16x4(
+                                aos_chunk, soa0_chunk, soa1_chunk, soa2_chunk, soa3_chunk,
+                            );
+                        }
+                    }
+                });
+            }
+        });
+    });
+
+    let (soa0, soa1, soa2, soa3) = soa.parts_mut();
+    for (s, r0, r1, r2, r3) in izip!(
+        aos_remainder.into_iter(),
+        &mut soa0[lim..len],
+        &mut soa1[lim..len],
+        &mut soa2[lim..len],
+        &mut soa3[lim..len]
+    ) {
+        *r0 = s.0;
+        *r1 = s.1;
+        *r2 = s.2;
+        *r3 = s.3;
+    }
+}
+
 // TODO: multithread this
 #[instrument(skip_all, level = "debug")]
 pub fn vec4u8_aos_to_soa_scalar(aos: BufferPointer<Vec4u8>, soa: &mut Vec4u8s) {
@@ -451,6 +618,14 @@ pub fn vec4u8_aos_to_soa(aos: BufferPointer<Vec4u8>, soa: &mut Vec4u8s) {
         }
     }
 
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            // SAFETY: checked for neon support.
+            return unsafe { vec4u8_aos_to_soa_neon_parallel(aos, soa) };
+        }
+    }
+
     vec4u8_aos_to_soa_scalar(aos, soa)
 }
 
@@ -512,6 +687,63 @@ pub unsafe fn vec4u8_soa_to_aos_avx2_parallel(soa: &Vec4u8s, aos: &mut [Vec4u8])
     }
 }
 
+// SAFETY:
+// * neon must be available.
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+#[instrument(skip_all, level = "debug")]
+pub unsafe fn vec4u8_soa_to_aos_neon_parallel(soa: &Vec4u8s, aos: &mut [Vec4u8]) {
+    let len = soa.len();
+    assert_eq!(len, aos.len());
+
+    // soa_to_aos_u8_16x4 operates on blocks of 512 bits aka 64 bytes aka 16
+    // Vec4u8s.
+    let n_blocks = len / 16; // number of 16x Vec4u8 blocks
+    let lim = n_blocks * 16; // number of Vec4u8s to transpose in blocks
+    let n_threads = 4;
+    let blocks_per_thread = cmp::max(n_blocks / n_threads, 1);
+    let thread_chunk_size = blocks_per_thread * 16;
+
+    debug_span!("soa_to_aos_u8_16x4_loop").in_scope(|| {
+        ThreadPool::global().scoped(|s| {
+            for ((soa0, soa1, soa2, soa3), aos) in izip!(
+                soa.chunks(thread_chunk_size),
+                aos.chunks_mut(thread_chunk_size)
+            ) {
+                s.run(move || {
+                    for (soa0_chunk, soa1_chunk, soa2_chunk, soa3_chunk, aos_chunk) in izip!(
+                        soa0.chunks_exact(16),
+                        soa1.chunks_exact(16),
+                        soa2.chunks_exact(16),
+                        soa3.chunks_exact(16),
+                        aos.chunks_exact_mut(16)
+                    ) {
+                        unsafe {
+                            // SAFETY:
+                            // * soa chunks are 16 bytes.
+                            // * aos_chunk is 16 Vec4u8s, which is 16*4 = 64 bytes.
+                            soa_to_aos_u8_16x4(
+                                soa0_chunk, soa1_chunk, soa2_chunk, soa3_chunk, aos_chunk,
+                            );
+                        }
+                    }
+                });
+            }
+        });
+    });
+
+    let (soa0, soa1, soa2, soa3) = soa.parts();
+    for (a, s0, s1, s2, s3) in izip!(
+        &mut aos[lim..len],
+        &soa0[lim..len],
+        &soa1[lim..len],
+        &soa2[lim..len],
+        &soa3[lim..len]
+    ) {
+        *a = Vec4u8(*s0, *s1, *s2, *s3);
+    }
+}
+
 // TODO: multithread this
 pub fn vec4u8_soa_to_aos_scalar(soa: &Vec4u8s, aos: &mut [Vec4u8]) {
     let (soa0, soa1, soa2, soa3) = soa.parts();
@@ -529,6 +761,14 @@ pub fn vec4u8_soa_to_aos(soa: &Vec4u8s, aos: &mut [Vec4u8]) {
         }
     }
 
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            // SAFETY: checked for neon support.
+            return unsafe { vec4u8_soa_to_aos_neon_parallel(soa, aos) };
+        }
+    }
+
     vec4u8_soa_to_aos_scalar(soa, aos)
 }
 
@@ -570,15 +810,24 @@ mod tests {
         let aos_ptr = aos.as_ptr();
         let aos_buf_ptr = unsafe { BufferPointer::new(&aos_ptr, aos.len()) };
 
-        let mut soa_avx2 = Vec4u8s::with_total_size(data.len());
-        unsafe { vec4u8_aos_to_soa_avx2_parallel(aos_buf_ptr, &mut soa_avx2) };
+        let expected_soa = generate_soa(data);
 
-        let mut soa_scalar = Vec4u8s::with_total_size(data.len());
-        vec4u8_aos_to_soa_scalar(aos_buf_ptr, &mut soa_scalar);
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            let mut soa_avx2 = Vec4u8s::with_total_size(data.len());
+            unsafe { vec4u8_aos_to_soa_avx2_parallel(aos_buf_ptr, &mut soa_avx2) };
+            assert_eq!(soa_avx2, expected_soa);
+        }
 
-        let expected_soa = generate_soa(data);
+        #[cfg(target_arch = "aarch64")]
+        {
+            let mut soa_neon = Vec4u8s::with_total_size(data.len());
+            unsafe { vec4u8_aos_to_soa_neon_parallel(aos_buf_ptr, &mut soa_neon) };
+            assert_eq!(soa_neon, expected_soa);
+        }
 
-        assert_eq!(soa_avx2, expected_soa);
+        let mut soa_scalar = Vec4u8s::with_total_size(data.len());
+        vec4u8_aos_to_soa_scalar(aos_buf_ptr, &mut soa_scalar);
         assert_eq!(soa_scalar, expected_soa);
     }
 
@@ -624,15 +873,24 @@ mod tests {
 
         let soa = generate_soa(data);
 
-        let mut aos_avx2: Vec<Vec4u8> = vec![Vec4u8::new(); data.len() / 4];
-        unsafe { vec4u8_soa_to_aos_avx2_parallel(&soa, &mut aos_avx2) };
+        let expected_aos = generate_aos(data);
 
-        let mut aos_scalar: Vec<Vec4u8> = vec![Vec4u8::new(); data.len() / 4];
-        vec4u8_soa_to_aos_scalar(&soa, &mut aos_scalar);
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            let mut aos_avx2: Vec<Vec4u8> = vec![Vec4u8::new(); data.len() / 4];
+            unsafe { vec4u8_soa_to_aos_avx2_parallel(&soa, &mut aos_avx2) };
+            assert_eq!(aos_avx2, expected_aos);
+        }
 
-        let expected_aos = generate_aos(data);
+        #[cfg(target_arch = "aarch64")]
+        {
+            let mut aos_neon: Vec<Vec4u8> = vec![Vec4u8::new(); data.len() / 4];
+            unsafe { vec4u8_soa_to_aos_neon_parallel(&soa, &mut aos_neon) };
+            assert_eq!(aos_neon, expected_aos);
+        }
 
-        assert_eq!(aos_avx2, expected_aos);
+        let mut aos_scalar: Vec<Vec4u8> = vec![Vec4u8::new(); data.len() / 4];
+        vec4u8_soa_to_aos_scalar(&soa, &mut aos_scalar);
         assert_eq!(aos_scalar, expected_aos);
     }
 
@@ -0,0 +1,140 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decoding of captured frames (buffers as they are sent over the wire) into
+//! plain images, for tooling and debugging.
+
+use crate::filtering;
+use crate::prelude::*;
+use crate::serialization::wayland::BufferFormat;
+use crate::serialization::wayland::BufferMetadata;
+use crate::vec4u8::Vec4u8s;
+
+/// An 8-bit-per-channel, non-premultiplied RGBA image with no row padding.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// Decodes filtered buffer data (see filtering::filter) into an RgbaImage.
+pub fn decode(metadata: &BufferMetadata, data: &Vec4u8s) -> Result<RgbaImage> {
+    if data.len() * 4 != metadata.len() {
+        bail!(
+            "data size {} doesn't match the size {} implied by metadata {:?}",
+            data.len() * 4,
+            metadata.len(),
+            metadata
+        );
+    }
+    let width = usize::try_from(metadata.width).location(loc!())?;
+    let height = usize::try_from(metadata.height).location(loc!())?;
+    let stride = usize::try_from(metadata.stride).location(loc!())?;
+    if stride < width * 4 {
+        bail!("stride {stride} is smaller than width {width} * 4");
+    }
+
+    let mut bgra = vec![0; metadata.len()];
+    filtering::unfilter(&mut data.clone(), &mut bgra);
+
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for row in bgra.chunks_exact(stride) {
+        // https://afrantzis.com/pixel-format-guide/wayland_drm.html
+        for pixel in row[..width * 4].chunks_exact(4) {
+            let alpha = match metadata.format {
+                BufferFormat::Argb8888 => pixel[3],
+                BufferFormat::Xrgb8888 => u8::MAX,
+            };
+            rgba.extend_from_slice(&[pixel[2], pixel[1], pixel[0], alpha]);
+        }
+    }
+
+    Ok(RgbaImage {
+        width: width as u32,
+        height: height as u32,
+        data: rgba,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer_pointer::BufferPointer;
+
+    fn filtered(bgra: &[u8]) -> Vec4u8s {
+        let ptr = bgra.as_ptr();
+        // SAFETY: ptr was created from a slice, so it is non-null, aligned,
+        // and valid for reads of bgra.len() elements.
+        let buf = unsafe { BufferPointer::new(&ptr, bgra.len()) };
+        let mut out = Vec4u8s::with_total_size(bgra.len());
+        filtering::filter(buf, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_decode() {
+        // 2x2 image with a padding pixel at the end of each row.
+        #[rustfmt::skip]
+        let bgra: Vec<u8> = vec![
+            0, 0, 255, 255,  0, 255, 0, 128,  9, 9, 9, 9,
+            255, 0, 0, 0,    1, 2, 3, 4,      9, 9, 9, 9,
+        ];
+        let metadata = BufferMetadata {
+            width: 2,
+            height: 2,
+            stride: 12,
+            format: BufferFormat::Argb8888,
+        };
+
+        let image = decode(&metadata, &filtered(&bgra)).unwrap();
+
+        #[rustfmt::skip]
+        let expected = RgbaImage {
+            width: 2,
+            height: 2,
+            data: vec![
+                255, 0, 0, 255,  0, 255, 0, 128,
+                0, 0, 255, 0,    3, 2, 1, 4,
+            ],
+        };
+        assert_eq!(image, expected);
+    }
+
+    #[test]
+    fn test_decode_xrgb_is_opaque() {
+        let bgra: Vec<u8> = vec![10, 20, 30, 0];
+        let metadata = BufferMetadata {
+            width: 1,
+            height: 1,
+            stride: 4,
+            format: BufferFormat::Xrgb8888,
+        };
+
+        let image = decode(&metadata, &filtered(&bgra)).unwrap();
+
+        assert_eq!(image.data, vec![30, 20, 10, 255]);
+    }
+
+    #[test]
+    fn test_decode_size_mismatch() {
+        let metadata = BufferMetadata {
+            width: 2,
+            height: 2,
+            stride: 8,
+            format: BufferFormat::Argb8888,
+        };
+        assert!(decode(&metadata, &Vec4u8s::with_total_size(4)).is_err());
+    }
+}
@@ -12,9 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(target_arch = "x86")]
+use std::arch::x86::__m128i;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::__m256i;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm256_storeu_si256;
+#[cfg(target_arch = "x86")]
+use std::arch::x86::_mm_storeu_si128;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::__m128i;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::__m256i;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm256_storeu_si256;
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::_mm_storeu_si128;
 use std::backtrace::Backtrace;
 use std::collections::HashMap;
@@ -22,6 +34,7 @@ use std::fs;
 use std::fs::File;
 use std::io;
 use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
 use std::panic;
 use std::path::Path;
 use std::process;
@@ -38,6 +51,8 @@ use tracing_subscriber::prelude::*;
 
 use crate::prelude::*;
 
+pub mod frame;
+
 pub fn configure_tracing<P: AsRef<Path>>(
     stderr_log_level: Level,
     path: Option<P>,
@@ -108,16 +123,24 @@ impl SerialMap {
     const PRUNE_THRESHOLD: usize = 2000;
     const PRUNE_AGE: u32 = 1000;
 
+    /// Serials wrap around in long sessions, so the age of a serial is the
+    /// wrapping distance back from the last one. Comparing serials directly
+    /// would keep the entries from before a wraparound forever.
     #[instrument(skip(self), level = "debug")]
     fn prune(&mut self) {
         if self.map.len() > Self::PRUNE_THRESHOLD {
+            let last_serial = self.last_serial;
             self.map
-                .retain(|&k, _| k > self.last_serial.saturating_sub(Self::PRUNE_AGE));
+                .retain(|&k, _| last_serial.wrapping_sub(k) < Self::PRUNE_AGE);
         }
     }
 
     pub fn insert(&mut self, client_serial: u32) -> Serial {
-        self.last_serial = SERIAL_COUNTER.next_serial().into();
+        self.insert_with_server_serial(SERIAL_COUNTER.next_serial().into(), client_serial)
+    }
+
+    fn insert_with_server_serial(&mut self, server_serial: u32, client_serial: u32) -> Serial {
+        self.last_serial = server_serial;
         _ = self.map.insert(self.last_serial, client_serial).is_none();
         self.prune();
         self.last_serial.into()
@@ -137,6 +160,7 @@ impl Default for SerialMap {
 // SAFETY:
 // * SSE2 instructions must be available.
 // * `x` must be valid for reads of 32 bytes.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 #[allow(dead_code)]
 fn print_vec_char_128_dec(x: __m128i) {
     unsafe {
@@ -151,6 +175,7 @@ fn print_vec_char_128_dec(x: __m128i) {
 // * AVX2 instructions must be available.
 // * `x` must be valid for reads of 32 bytes.
 #[allow(dead_code, unsafe_op_in_unsafe_fn)]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 unsafe fn print_vec_char_256_hex(x: __m256i) {
     let mut v = [0u8; 32];
     _mm256_storeu_si256(v.as_mut_ptr().cast::<__m256i>(), x);
@@ -181,6 +206,16 @@ pub fn n_chunks(len: usize, chunk_size: usize) -> usize {
 
 pub fn bind_user_socket<P: AsRef<Path>>(sock_path: P) -> Result<UnixListener> {
     if sock_path.as_ref().try_exists().location(loc!())? {
+        // A socket which accepts connections belongs to a running process, so
+        // don't take it over. Otherwise, it was left behind by a process which
+        // crashed and can be removed.
+        if UnixStream::connect(&sock_path).is_ok() {
+            bail!(
+                "socket {:?} is in use by another running process",
+                sock_path.as_ref()
+            );
+        }
+        debug!("removing stale socket {:?}", sock_path.as_ref());
         fs::remove_file(&sock_path).location(loc!())?;
     }
 
@@ -190,3 +225,45 @@ pub fn bind_user_socket<P: AsRef<Path>>(sock_path: P) -> Result<UnixListener> {
 
     Ok(listener)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serial_map_wraparound() {
+        let mut serial_map = SerialMap::new();
+        let start = u32::MAX - 1500;
+        let n = SerialMap::PRUNE_THRESHOLD as u32 + 1;
+        for i in 0..n {
+            serial_map.insert_with_server_serial(start.wrapping_add(i), i);
+        }
+
+        // Pruning kept only the entries newer than PRUNE_AGE, on both sides
+        // of the wraparound.
+        assert!(serial_map.map.len() <= SerialMap::PRUNE_AGE as usize);
+        let last = start.wrapping_add(n - 1);
+        assert!(last < start);
+        assert_eq!(serial_map.remove(last.into()), Some(n - 1));
+        assert_eq!(serial_map.remove((u32::MAX - 1).into()), Some(1499));
+        assert_eq!(serial_map.remove(start.into()), None);
+    }
+
+    #[test]
+    fn test_bind_user_socket() {
+        let sock_path =
+            std::env::temp_dir().join(format!("wprs-test-bind-user-socket-{}", process::id()));
+        let listener = bind_user_socket(&sock_path).unwrap();
+        // The socket of a running process isn't taken over.
+        assert!(bind_user_socket(&sock_path).is_err());
+
+        // A socket left behind by a process which went away is replaced.
+        drop(listener);
+        assert!(sock_path.try_exists().unwrap());
+        let listener = bind_user_socket(&sock_path).unwrap();
+        UnixStream::connect(&sock_path).unwrap();
+
+        drop(listener);
+        fs::remove_file(&sock_path).unwrap();
+    }
+}
@@ -21,12 +21,18 @@ use smithay::backend::input::Axis;
 use smithay::backend::input::AxisSource;
 use smithay::backend::input::ButtonState;
 use smithay::backend::input::KeyState;
+use smithay::backend::input::TouchSlot;
 use smithay::input::keyboard::Layout;
 use smithay::input::keyboard::XkbContext;
 use smithay::input::pointer::AxisFrame;
 use smithay::input::pointer::ButtonEvent;
 use smithay::input::pointer::MotionEvent;
 use smithay::input::pointer::PointerTarget;
+use smithay::input::touch::DownEvent;
+use smithay::input::touch::MotionEvent as TouchMotionEvent;
+use smithay::input::touch::OrientationEvent;
+use smithay::input::touch::ShapeEvent;
+use smithay::input::touch::UpEvent;
 use smithay::reexports::wayland_protocols::wp::primary_selection::zv1::client::zwp_primary_selection_device_v1::ZwpPrimarySelectionDeviceV1;
 use smithay::reexports::wayland_protocols::wp::primary_selection::zv1::client::zwp_primary_selection_source_v1::ZwpPrimarySelectionSourceV1;
 use smithay::reexports::wayland_server::backend::ObjectId;
@@ -73,6 +79,7 @@ use smithay_client_toolkit::reexports::client::protocol::wl_subcompositor::WlSub
 use smithay_client_toolkit::reexports::client::protocol::wl_subsurface::Event as WlSubsurfaceEvent;
 use smithay_client_toolkit::reexports::client::protocol::wl_subsurface::WlSubsurface;
 use smithay_client_toolkit::reexports::client::protocol::wl_surface::WlSurface;
+use smithay_client_toolkit::reexports::client::protocol::wl_touch::WlTouch;
 use smithay_client_toolkit::reexports::client::Connection;
 use smithay_client_toolkit::reexports::client::Dispatch;
 use smithay_client_toolkit::reexports::client::Proxy;
@@ -80,6 +87,14 @@ use smithay_client_toolkit::reexports::client::QueueHandle;
 use smithay_client_toolkit::reexports::csd_frame::CursorIcon;
 use smithay_client_toolkit::reexports::csd_frame::DecorationsFrame;
 use smithay_client_toolkit::reexports::csd_frame::WindowManagerCapabilities;
+use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibit_manager_v1;
+use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibit_manager_v1::ZwpKeyboardShortcutsInhibitManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibitor_v1;
+use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibitor_v1::ZwpKeyboardShortcutsInhibitorV1;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewport;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewport::WpViewport;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewporter;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewporter::WpViewporter;
 use smithay_client_toolkit::reexports::protocols::xdg::shell::client::xdg_positioner::Anchor;
 use smithay_client_toolkit::reexports::protocols::xdg::shell::client::xdg_positioner::Gravity;
 use smithay_client_toolkit::reexports::protocols::xdg::shell::client::xdg_surface::XdgSurface as SctkXdgSurface;
@@ -97,13 +112,14 @@ use smithay_client_toolkit::seat::pointer::PointerEventKind;
 use smithay_client_toolkit::seat::pointer::PointerHandler;
 use smithay_client_toolkit::seat::pointer::ThemeSpec;
 use smithay_client_toolkit::seat::pointer::ThemedPointer;
+use smithay_client_toolkit::seat::touch::TouchHandler;
 use smithay_client_toolkit::seat::Capability;
 use smithay_client_toolkit::seat::SeatHandler;
 use smithay_client_toolkit::seat::SeatState;
-use smithay_client_toolkit::shell::xdg::fallback_frame::FallbackFrame;
 use smithay_client_toolkit::shell::xdg::popup::Popup;
 use smithay_client_toolkit::shell::xdg::popup::PopupConfigure;
 use smithay_client_toolkit::shell::xdg::popup::PopupHandler;
+use smithay_client_toolkit::shell::xdg::window::DecorationMode;
 use smithay_client_toolkit::shell::xdg::window::Window;
 use smithay_client_toolkit::shell::xdg::window::WindowConfigure;
 use smithay_client_toolkit::shell::xdg::window::WindowDecorations;
@@ -125,12 +141,15 @@ use crate::client_utils::SeatObject;
 use crate::prelude::*;
 use crate::serialization;
 use crate::serialization::geometry::Point;
+use crate::serialization::wayland::BufferFormat;
 use crate::serialization::wayland::BufferMetadata;
 use crate::xwayland_xdg_shell::compositor::DecorationBehavior;
+use crate::xwayland_xdg_shell::compositor::FrameTheme;
 use crate::xwayland_xdg_shell::compositor::X11Parent;
 use crate::xwayland_xdg_shell::compositor::X11ParentForPopup;
 use crate::xwayland_xdg_shell::compositor::X11ParentForSubsurface;
 use crate::xwayland_xdg_shell::decoration::handle_window_frame_pointer_event;
+use crate::xwayland_xdg_shell::frame::ThemedFrame;
 use crate::xwayland_xdg_shell::xsurface_from_client_surface;
 use crate::xwayland_xdg_shell::WprsState;
 use crate::xwayland_xdg_shell::XWaylandSurface;
@@ -152,6 +171,8 @@ pub struct WprsClientState {
 
     pub(crate) data_device_manager_state: DataDeviceManagerState,
     pub(crate) primary_selection_manager_state: Option<PrimarySelectionManagerState>,
+    pub(crate) viewporter: Option<WpViewporter>,
+    pub(crate) keyboard_shortcuts_inhibit_manager: Option<ZwpKeyboardShortcutsInhibitManagerV1>,
 
     pub exit: bool,
     pub pool: Option<SlotPool>,
@@ -197,6 +218,19 @@ impl WprsClientState {
                 .context(loc!(), "primary selection manager is not available")
                 .warn(loc!())
                 .ok(),
+            viewporter: globals
+                .bind(&qh, 1..=1, ())
+                .context(loc!(), "wp_viewporter is not available")
+                .warn(loc!())
+                .ok(),
+            keyboard_shortcuts_inhibit_manager: globals
+                .bind(&qh, 1..=1, ())
+                .context(
+                    loc!(),
+                    "zwp_keyboard_shortcuts_inhibit_manager_v1 is not available",
+                )
+                .warn(loc!())
+                .ok(),
 
             exit: false,
             pool,
@@ -213,6 +247,16 @@ impl WprsClientState {
             primary_selection_source: None,
         })
     }
+
+    /// Buffer formats supported by the local compositor which we know how to
+    /// produce.
+    pub fn supported_buffer_formats(&self) -> Vec<BufferFormat> {
+        self.shm_state
+            .formats()
+            .iter()
+            .filter_map(|format| (*format).try_into().ok())
+            .collect()
+    }
 }
 
 impl CompositorHandler for WprsState {
@@ -317,7 +361,7 @@ impl OutputHandler for WprsState {
     #[instrument(skip(self, _conn, _qh), level = "debug")]
     fn output_destroyed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, output: WlOutput) {
         let output_info = self.output_state().info(&output).unwrap();
-        self.compositor_state.destroy_output(output_info.into());
+        self.destroy_output(output_info.into());
     }
 }
 
@@ -338,9 +382,13 @@ impl WindowHandler for WprsState {
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
         window: &Window,
-        configure: WindowConfigure,
+        mut configure: WindowConfigure,
         _serial: u32,
     ) {
+        // The capabilities are only used to pick which titlebar buttons to
+        // draw.
+        configure.capabilities &= self.compositor_state.title_buttons.capabilities();
+
         let Some(compositor_surface_id) =
             self.surface_bimap.get_by_right(&window.wl_surface().id())
         else {
@@ -365,6 +413,17 @@ impl WindowHandler for WprsState {
         x11_surface
             .set_fullscreen(configure.is_fullscreen())
             .log_and_ignore(loc!());
+        xdg_toplevel.set_keyboard_shortcuts_inhibited(
+            configure.is_fullscreen(),
+            self.client_state
+                .keyboard_shortcuts_inhibit_manager
+                .as_ref(),
+            self.client_state
+                .seat_objects
+                .first()
+                .map(|seat_object| &seat_object.seat),
+            &self.client_state.qh,
+        );
 
         xdg_toplevel
             .apply_decoration(
@@ -489,6 +548,8 @@ impl SeatHandler for WprsState {
                 seat: seat.clone(),
                 keyboard: None,
                 pointer: None,
+                relative_pointer: None,
+                touch: None,
                 data_device,
                 primary_selection_device,
             });
@@ -520,6 +581,16 @@ impl SeatHandler for WprsState {
                 .expect("Failed to create pointer");
             seat_obj.pointer.replace(themed_pointer);
         }
+
+        if capability == Capability::Touch && seat_obj.touch.is_none() {
+            debug!("Set touch capability");
+            let touch = self
+                .client_state
+                .seat_state
+                .get_touch(qh, &seat)
+                .expect("Failed to create touch");
+            seat_obj.touch.replace(touch);
+        }
     }
 
     fn remove_capability(
@@ -542,6 +613,9 @@ impl SeatHandler for WprsState {
                 Capability::Pointer => {
                     seat_obj.pointer.take().map(|p| p.pointer().release());
                 },
+                Capability::Touch => {
+                    seat_obj.touch.take().map(|t| t.release());
+                },
                 _ => {},
             }
         }
@@ -626,10 +700,13 @@ impl KeyboardHandler for WprsState {
         surface: &WlSurface,
         serial: u32,
     ) {
+        let serial = self.compositor_state.serial_map.insert(serial);
         let Some(xwayland_surface) =
             xsurface_from_client_surface(&self.surface_bimap, &mut self.surfaces, surface)
         else {
-            // surface was already destroyed
+            // surface was already destroyed, but keys which were held while it
+            // was focused still need to be released or they'll be stuck.
+            self.release_pressed_keys(serial).log_and_ignore(loc!());
             return;
         };
         let x11_surface = log_and_return!(xwayland_surface.get_x11_surface()).clone();
@@ -640,7 +717,6 @@ impl KeyboardHandler for WprsState {
             .get_keyboard()
             .ok_or("seat has no keyboard"));
 
-        let serial = self.compositor_state.serial_map.insert(serial);
         keyboard.set_focus(self, None, serial);
         data_device::set_data_device_focus(
             &self.compositor_state.dh,
@@ -653,9 +729,7 @@ impl KeyboardHandler for WprsState {
             None,
         );
 
-        for keycode in self.compositor_state.pressed_keys.clone() {
-            log_and_return!(self.set_key_state(keycode, KeyState::Released, serial));
-        }
+        self.release_pressed_keys(serial).log_and_ignore(loc!());
     }
 
     // INTENTIONALLY NOT LOGGING KEY EVENTS
@@ -934,6 +1008,210 @@ impl PointerHandler for WprsState {
     }
 }
 
+/// The decoration mode to request from the local compositor, if any, and
+/// whether to draw our own frame around an X11 window, given whether it draws
+/// its own decorations and the mode the local compositor configured it with.
+fn decorations(
+    behavior: DecorationBehavior,
+    x11_decorated: bool,
+    configured_mode: DecorationMode,
+) -> (Option<DecorationMode>, bool) {
+    match behavior {
+        DecorationBehavior::Auto if x11_decorated => (Some(DecorationMode::Client), false),
+        // The local compositor is asked to draw the decorations, and we only
+        // draw them if it won't.
+        DecorationBehavior::Auto => (
+            Some(DecorationMode::Server),
+            configured_mode != DecorationMode::Server,
+        ),
+        // Don't let the local compositor add its own on top of ours.
+        DecorationBehavior::AlwaysEnabled => (Some(DecorationMode::Client), true),
+        DecorationBehavior::AlwaysDisabled => (None, false),
+    }
+}
+
+fn touch_slot(id: i32) -> TouchSlot {
+    TouchSlot::from(Some(id as u32))
+}
+
+// SCTK only calls these handlers once the whole wl_touch frame has been
+// received, but without a frame boundary, so each event is sent as its own
+// frame.
+impl TouchHandler for WprsState {
+    #[instrument(skip(self, _conn, _qh, _touch), level = "debug")]
+    fn down(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &WlTouch,
+        serial: u32,
+        time: u32,
+        surface: WlSurface,
+        id: i32,
+        position: (f64, f64),
+    ) {
+        let Some(xwayland_surface) =
+            xsurface_from_client_surface(&self.surface_bimap, &mut self.surfaces, &surface)
+        else {
+            // Touches on window decorations aren't handled.
+            return;
+        };
+        let x11_surface = log_and_return!(xwayland_surface.get_x11_surface()).clone();
+        let compositor_touch = log_and_return!(self
+            .compositor_state
+            .seat
+            .get_touch()
+            .context(loc!(), "seat has no touch capability"));
+
+        self.compositor_state
+            .touch_points
+            .insert(id, x11_surface.clone());
+        let serial = self.compositor_state.serial_map.insert(serial);
+        compositor_touch.down(
+            self,
+            Some((x11_surface, (0, 0).into())),
+            &DownEvent {
+                slot: touch_slot(id),
+                location: position.into(),
+                serial,
+                time,
+            },
+        );
+        compositor_touch.frame(self);
+    }
+
+    #[instrument(skip(self, _conn, _qh, _touch), level = "debug")]
+    fn up(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &WlTouch,
+        serial: u32,
+        time: u32,
+        id: i32,
+    ) {
+        if self.compositor_state.touch_points.remove(&id).is_none() {
+            return;
+        }
+        let compositor_touch = log_and_return!(self
+            .compositor_state
+            .seat
+            .get_touch()
+            .context(loc!(), "seat has no touch capability"));
+
+        let serial = self.compositor_state.serial_map.insert(serial);
+        compositor_touch.up(
+            self,
+            &UpEvent {
+                slot: touch_slot(id),
+                serial,
+                time,
+            },
+        );
+        compositor_touch.frame(self);
+    }
+
+    #[instrument(skip(self, _conn, _qh, _touch), level = "debug")]
+    fn motion(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &WlTouch,
+        time: u32,
+        id: i32,
+        position: (f64, f64),
+    ) {
+        let Some(x11_surface) = self.compositor_state.touch_points.get(&id).cloned() else {
+            return;
+        };
+        let compositor_touch = log_and_return!(self
+            .compositor_state
+            .seat
+            .get_touch()
+            .context(loc!(), "seat has no touch capability"));
+
+        compositor_touch.motion(
+            self,
+            Some((x11_surface, (0, 0).into())),
+            &TouchMotionEvent {
+                slot: touch_slot(id),
+                location: position.into(),
+                time,
+            },
+        );
+        compositor_touch.frame(self);
+    }
+
+    #[instrument(skip(self, _conn, _qh, _touch), level = "debug")]
+    fn shape(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &WlTouch,
+        id: i32,
+        major: f64,
+        minor: f64,
+    ) {
+        if !self.compositor_state.touch_points.contains_key(&id) {
+            return;
+        }
+        let compositor_touch = log_and_return!(self
+            .compositor_state
+            .seat
+            .get_touch()
+            .context(loc!(), "seat has no touch capability"));
+
+        compositor_touch.shape(
+            self,
+            &ShapeEvent {
+                slot: touch_slot(id),
+                major,
+                minor,
+            },
+        );
+        compositor_touch.frame(self);
+    }
+
+    #[instrument(skip(self, _conn, _qh, _touch), level = "debug")]
+    fn orientation(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &WlTouch,
+        id: i32,
+        orientation: f64,
+    ) {
+        if !self.compositor_state.touch_points.contains_key(&id) {
+            return;
+        }
+        let compositor_touch = log_and_return!(self
+            .compositor_state
+            .seat
+            .get_touch()
+            .context(loc!(), "seat has no touch capability"));
+
+        compositor_touch.orientation(
+            self,
+            &OrientationEvent {
+                slot: touch_slot(id),
+                orientation,
+            },
+        );
+        compositor_touch.frame(self);
+    }
+
+    #[instrument(skip(self, _conn, _qh, _touch), level = "debug")]
+    fn cancel(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _touch: &WlTouch) {
+        self.compositor_state.touch_points.clear();
+        let compositor_touch = log_and_return!(self
+            .compositor_state
+            .seat
+            .get_touch()
+            .context(loc!(), "seat has no touch capability"));
+        compositor_touch.cancel(self);
+    }
+}
+
 impl ShmHandler for WprsState {
     fn shm_state(&mut self) -> &mut Shm {
         &mut self.client_state.shm_state
@@ -943,24 +1221,36 @@ impl ShmHandler for WprsState {
 #[derive(Debug)]
 pub struct XWaylandBuffer {
     pub metadata: BufferMetadata,
+    /// The format of the local buffer, which differs from metadata.format if
+    /// the local compositor doesn't support the latter.
+    pub local_format: BufferFormat,
     pub active_buffer: Buffer,
 }
 
 impl XWaylandBuffer {
     #[instrument(skip_all, level = "debug")]
-    pub fn new(metadata: BufferMetadata, pool: &mut SlotPool) -> Result<Self> {
+    pub fn new(
+        metadata: BufferMetadata,
+        supported_formats: &[BufferFormat],
+        pool: &mut SlotPool,
+    ) -> Result<Self> {
+        let local_format = metadata
+            .format
+            .closest_supported(supported_formats)
+            .location(loc!())?;
         let active_buffer = pool
             .create_buffer(
                 metadata.width,
                 metadata.height,
                 metadata.stride,
-                metadata.format.into(),
+                local_format.into(),
             )
             .location(loc!())?
             .0;
 
         Ok(Self {
             metadata,
+            local_format,
             active_buffer,
         })
     }
@@ -979,7 +1269,7 @@ impl XWaylandBuffer {
                         self.metadata.width,
                         self.metadata.height,
                         self.metadata.stride,
-                        self.metadata.format.into(),
+                        self.local_format.into(),
                     )
                     .location(loc!())?
                     .0;
@@ -987,6 +1277,15 @@ impl XWaylandBuffer {
             },
         };
         data.copy_to_nonoverlapping(canvas);
+        if (self.metadata.format, self.local_format)
+            == (BufferFormat::Xrgb8888, BufferFormat::Argb8888)
+        {
+            // The alpha channel of xrgb buffers is undefined, make it opaque.
+            // https://afrantzis.com/pixel-format-guide/wayland_drm.html
+            for pixel in canvas.chunks_exact_mut(4) {
+                pixel[3] = u8::MAX;
+            }
+        }
         Ok(())
     }
 }
@@ -1007,6 +1306,7 @@ impl XWaylandSurface {
         &mut self,
         metadata: &BufferData,
         data: BufferPointer<u8>,
+        supported_formats: &[BufferFormat],
         pool: &mut SlotPool,
     ) -> Result<()> {
         let metadata =
@@ -1028,13 +1328,15 @@ impl XWaylandSurface {
                         "metadata didn't match, creating new buffer, {:?}, {:?}",
                         buffer.metadata, metadata
                     );
-                    *buffer = XWaylandBuffer::new(metadata, pool).location(loc!())?;
+                    *buffer =
+                        XWaylandBuffer::new(metadata, supported_formats, pool).location(loc!())?;
                     buffer
                 }
             },
             // First commit for surface with a buffer.
             None => {
-                self.buffer = Some(XWaylandBuffer::new(metadata, pool).location(loc!())?);
+                self.buffer =
+                    Some(XWaylandBuffer::new(metadata, supported_formats, pool).location(loc!())?);
                 self.buffer.as_mut().unwrap()
             },
         };
@@ -1084,11 +1386,29 @@ pub enum Role {
 #[derive(Debug)]
 pub struct XWaylandXdgToplevel {
     pub local_window: Window,
-    pub window_frame: FallbackFrame<WprsState>,
+    pub window_frame: ThemedFrame,
     pub frame_offset: Point<i32>,
     pub configured: bool,
     pub decoration_behavior: DecorationBehavior,
+    /// The decoration mode the local compositor last configured the window
+    /// with. Compositors without xdg-decoration always use client-side
+    /// decorations.
+    pub decoration_mode: DecorationMode,
+    /// The decoration mode last requested from the local compositor.
+    pub requested_decoration_mode: Option<DecorationMode>,
     pub x11_offset: Point<i32>,
+    /// Held while the window is fullscreen, so that e.g. games get the key
+    /// combinations which the local compositor would otherwise grab.
+    pub keyboard_shortcuts_inhibitor: Option<KeyboardShortcutsInhibitor>,
+}
+
+#[derive(Debug)]
+pub struct KeyboardShortcutsInhibitor(ZwpKeyboardShortcutsInhibitorV1);
+
+impl Drop for KeyboardShortcutsInhibitor {
+    fn drop(&mut self) {
+        self.0.destroy();
+    }
 }
 
 impl XWaylandXdgToplevel {
@@ -1217,20 +1537,30 @@ impl XWaylandXdgToplevel {
         configure: Option<&WindowConfigure>,
         buffer_metadata: Option<&BufferMetadata>,
     ) -> Result<(i32, i32)> {
-        match self.decoration_behavior {
-            DecorationBehavior::Auto => {
-                if !x11_surface.is_decorated() {
-                    self.enable_decorations(x11_surface, configure, buffer_metadata)
-                } else {
-                    self.disable_decoration(x11_surface, configure, buffer_metadata)
-                }
-            },
-            DecorationBehavior::AlwaysEnabled => {
-                self.enable_decorations(x11_surface, configure, buffer_metadata)
-            },
-            DecorationBehavior::AlwaysDisabled => {
-                self.disable_decoration(x11_surface, configure, buffer_metadata)
-            },
+        if let Some(configure) = configure {
+            self.decoration_mode = configure.decoration_mode;
+        }
+        let (requested_mode, draw_frame) = decorations(
+            self.decoration_behavior,
+            x11_surface.is_decorated(),
+            self.decoration_mode,
+        );
+        if let Some(mode) = requested_mode {
+            self.request_decoration_mode(mode);
+        }
+        if draw_frame {
+            self.enable_decorations(x11_surface, configure, buffer_metadata)
+        } else {
+            self.disable_decoration(x11_surface, configure, buffer_metadata)
+        }
+    }
+
+    /// Requests mode unless it was already requested. The local compositor
+    /// answers with a configure.
+    fn request_decoration_mode(&mut self, mode: DecorationMode) {
+        if self.requested_decoration_mode != Some(mode) {
+            self.requested_decoration_mode = Some(mode);
+            self.local_window.request_decoration_mode(Some(mode));
         }
     }
 
@@ -1242,6 +1572,7 @@ impl XWaylandXdgToplevel {
         subcompositor_state: Arc<SubcompositorState>,
         qh: &QueueHandle<WprsState>,
         decoration_behavior: DecorationBehavior,
+        frame_theme: FrameTheme,
     ) -> Result<()> {
         let local_surface = surface.local_surface.take().location(loc!())?;
         let local_window =
@@ -1262,10 +1593,14 @@ impl XWaylandXdgToplevel {
 
         local_window.commit();
 
-        let window_frame =
-            FallbackFrame::new(&local_window, shm_state, subcompositor_state, qh.clone())
-                .map_err(|e| anyhow!("failed to create client side decorations frame: {e:?}."))
-                .location(loc!())?;
+        let window_frame = ThemedFrame::new(
+            &local_window,
+            shm_state,
+            &subcompositor_state,
+            qh,
+            frame_theme.colors(),
+        )
+        .context(loc!(), "failed to create client side decorations frame")?;
 
         let new_toplevel = Self {
             local_window,
@@ -1273,11 +1608,44 @@ impl XWaylandXdgToplevel {
             frame_offset: (0, 0).into(),
             configured: false,
             decoration_behavior,
+            decoration_mode: DecorationMode::Client,
+            requested_decoration_mode: None,
             x11_offset,
+            keyboard_shortcuts_inhibitor: None,
         };
         surface.role = Some(Role::XdgToplevel(new_toplevel));
         Ok(())
     }
+
+    pub fn set_keyboard_shortcuts_inhibited(
+        &mut self,
+        inhibit: bool,
+        keyboard_shortcuts_inhibit_manager: Option<&ZwpKeyboardShortcutsInhibitManagerV1>,
+        seat: Option<&WlSeat>,
+        qh: &QueueHandle<WprsState>,
+    ) {
+        if !inhibit {
+            self.keyboard_shortcuts_inhibitor = None;
+            return;
+        }
+        if self.keyboard_shortcuts_inhibitor.is_some() {
+            return;
+        }
+        let (Some(keyboard_shortcuts_inhibit_manager), Some(seat)) =
+            (keyboard_shortcuts_inhibit_manager, seat)
+        else {
+            debug!("not inhibiting keyboard shortcuts for fullscreen window");
+            return;
+        };
+        self.keyboard_shortcuts_inhibitor = Some(KeyboardShortcutsInhibitor(
+            keyboard_shortcuts_inhibit_manager.inhibit_shortcuts(
+                self.local_window.wl_surface(),
+                seat,
+                qh,
+                (),
+            ),
+        ));
+    }
 }
 
 impl WaylandSurface for XWaylandXdgToplevel {
@@ -1315,7 +1683,7 @@ pub struct XWaylandSubSurface {
     pub local_subsurface: SubSurface,
     pub parent_surface: WlSurface,
     pub offset: Point<i32>,
-    pub frame: Option<FallbackFrame<WprsState>>,
+    pub frame: Option<ThemedFrame>,
     pub move_active: bool,
     pub move_pointer_location: (f64, f64),
     pub pending_frame_callback: bool,
@@ -1329,6 +1697,7 @@ impl XWaylandSubSurface {
         shm_state: &Shm,
         subcompositor_state: Arc<SubcompositorState>,
         qh: &QueueHandle<WprsState>,
+        frame_theme: FrameTheme,
     ) -> Result<()> {
         let local_surface = surface.local_surface.take().unwrap();
         let subsurface = subcompositor_state
@@ -1346,14 +1715,14 @@ impl XWaylandSubSurface {
 
         // is_decorated means that the surface is already decorated and does NOT want our decorations.
         let frame = if !x11_surface.is_decorated() && !x11_surface.is_override_redirect() {
-            let mut frame = FallbackFrame::new(
+            let mut frame = ThemedFrame::new(
                 &local_subsurface,
                 shm_state,
-                subcompositor_state,
-                qh.clone(),
+                &subcompositor_state,
+                qh,
+                frame_theme.colors(),
             )
-            .map_err(|e| anyhow!("failed to create client side decorations frame: {e:?}."))
-            .location(loc!())?;
+            .context(loc!(), "failed to create client side decorations frame")?;
 
             // not an xdg-shell window, so we can't fullscreen/maximize/etc.
             frame.update_wm_capabilities(WindowManagerCapabilities::empty());
@@ -1820,6 +2189,7 @@ smithay_client_toolkit::delegate_registry!(WprsState);
 smithay_client_toolkit::delegate_seat!(WprsState);
 smithay_client_toolkit::delegate_shm!(WprsState);
 smithay_client_toolkit::delegate_subcompositor!(WprsState);
+smithay_client_toolkit::delegate_touch!(WprsState);
 smithay_client_toolkit::delegate_xdg_popup!(WprsState);
 smithay_client_toolkit::delegate_xdg_shell!(WprsState);
 smithay_client_toolkit::delegate_xdg_window!(WprsState);
@@ -1861,3 +2231,97 @@ impl Dispatch<WlSubsurface, SubSurfaceData> for WprsState {
         dbg!("SUBSURFACE DISPATCH");
     }
 }
+
+impl Dispatch<WpViewporter, ()> for WprsState {
+    fn event(
+        _state: &mut Self,
+        _viewporter: &WpViewporter,
+        _event: wp_viewporter::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // wp_viewporter has no events.
+    }
+}
+
+impl Dispatch<ZwpKeyboardShortcutsInhibitManagerV1, ()> for WprsState {
+    fn event(
+        _state: &mut Self,
+        _keyboard_shortcuts_inhibit_manager: &ZwpKeyboardShortcutsInhibitManagerV1,
+        _event: zwp_keyboard_shortcuts_inhibit_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // zwp_keyboard_shortcuts_inhibit_manager_v1 has no events.
+    }
+}
+
+impl Dispatch<ZwpKeyboardShortcutsInhibitorV1, ()> for WprsState {
+    fn event(
+        _state: &mut Self,
+        _inhibitor: &ZwpKeyboardShortcutsInhibitorV1,
+        _event: zwp_keyboard_shortcuts_inhibitor_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // Whether the local compositor honors the inhibitor doesn't change
+        // anything for the X11 client.
+    }
+}
+
+impl Dispatch<WpViewport, ()> for WprsState {
+    fn event(
+        _state: &mut Self,
+        _viewport: &WpViewport,
+        _event: wp_viewport::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // wp_viewport has no events.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decorations() {
+        // Windows which decorate themselves don't get a second titlebar.
+        assert_eq!(
+            decorations(DecorationBehavior::Auto, true, DecorationMode::Server),
+            (Some(DecorationMode::Client), false)
+        );
+        // The local compositor decorates the others if it agrees to.
+        assert_eq!(
+            decorations(DecorationBehavior::Auto, false, DecorationMode::Server),
+            (Some(DecorationMode::Server), false)
+        );
+        // Compositors without xdg-decoration configure client-side
+        // decorations.
+        assert_eq!(
+            decorations(DecorationBehavior::Auto, false, DecorationMode::Client),
+            (Some(DecorationMode::Server), true)
+        );
+        assert_eq!(
+            decorations(
+                DecorationBehavior::AlwaysEnabled,
+                true,
+                DecorationMode::Server
+            ),
+            (Some(DecorationMode::Client), true)
+        );
+        assert_eq!(
+            decorations(
+                DecorationBehavior::AlwaysDisabled,
+                false,
+                DecorationMode::Client
+            ),
+            (None, false)
+        );
+    }
+}
@@ -61,6 +61,8 @@ use smithay::wayland::selection::SelectionSource;
 use smithay::wayland::selection::SelectionTarget;
 use smithay::wayland::shm::ShmHandler;
 use smithay::wayland::shm::ShmState;
+use smithay::wayland::viewporter::ViewportCachedState;
+use smithay::wayland::viewporter::ViewporterState;
 use smithay::wayland::xwayland_shell::XWaylandShellHandler;
 use smithay::wayland::xwayland_shell::XWaylandShellState;
 use smithay::xwayland::X11Surface;
@@ -70,6 +72,7 @@ use smithay::xwayland::XWaylandClientData;
 use smithay::xwayland::XWaylandEvent;
 use smithay_client_toolkit::reexports::client::protocol::wl_surface::WlSurface as SctkWlSurface;
 use smithay_client_toolkit::reexports::csd_frame::DecorationsFrame;
+use smithay_client_toolkit::reexports::csd_frame::WindowManagerCapabilities;
 use smithay_client_toolkit::reexports::protocols::xdg::shell::client::xdg_surface;
 use smithay_client_toolkit::shell::xdg::XdgSurface;
 use smithay_client_toolkit::shell::WaylandSurface;
@@ -94,6 +97,82 @@ pub enum DecorationBehavior {
     AlwaysDisabled,
 }
 
+/// Buttons shown in the titlebar of the decorations drawn for X11 windows, in
+/// addition to the close button. A button is still only shown if the local
+/// compositor supports the corresponding action.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub enum TitleButtons {
+    #[default]
+    All,
+    Maximize,
+    Minimize,
+    CloseOnly,
+}
+
+impl TitleButtons {
+    /// The window manager capabilities which the decorations may use.
+    pub fn capabilities(self) -> WindowManagerCapabilities {
+        let all = WindowManagerCapabilities::all();
+        match self {
+            Self::All => all,
+            Self::Maximize => all - WindowManagerCapabilities::MINIMIZE,
+            Self::Minimize => all - WindowManagerCapabilities::MAXIMIZE,
+            Self::CloseOnly => {
+                all - WindowManagerCapabilities::MAXIMIZE - WindowManagerCapabilities::MINIMIZE
+            },
+        }
+    }
+}
+
+/// Colors of the decorations drawn for X11 windows, as 0xAARRGGBB.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub struct FrameColors {
+    /// The titlebar and borders of the focused window.
+    pub titlebar_active: u32,
+    /// The titlebar and borders of other windows.
+    pub titlebar_inactive: u32,
+    pub button_icon: u32,
+    /// The background of the button under the pointer.
+    pub button_hover: u32,
+}
+
+impl FrameColors {
+    /// The colors of SCTK's FallbackFrame.
+    pub const DARK: Self = Self {
+        titlebar_active: 0xFF3A3A3A,
+        titlebar_inactive: 0xFF242424,
+        button_icon: 0xFFCCCCCC,
+        button_hover: 0xFF808080,
+    };
+
+    pub const LIGHT: Self = Self {
+        titlebar_active: 0xFFDEDEDE,
+        titlebar_inactive: 0xFFF6F6F6,
+        button_icon: 0xFF2E2E2E,
+        button_hover: 0xFFBDBDBD,
+    };
+}
+
+/// The look of the decorations drawn for X11 windows, so that they can match
+/// the local desktop.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Deserialize, Serialize)]
+pub enum FrameTheme {
+    #[default]
+    Dark,
+    Light,
+    Custom(FrameColors),
+}
+
+impl FrameTheme {
+    pub fn colors(self) -> FrameColors {
+        match self {
+            Self::Dark => FrameColors::DARK,
+            Self::Light => FrameColors::LIGHT,
+            Self::Custom(colors) => colors,
+        }
+    }
+}
+
 pub struct XwaylandOptions<K, V, I>
 where
     I: IntoIterator<Item = (K, V)>,
@@ -114,13 +193,18 @@ pub struct WprsCompositorState {
     pub data_device_state: DataDeviceState,
     pub xwayland_shell_state: XWaylandShellState,
     pub primary_selection_state: PrimarySelectionState,
+    pub viewporter_state: ViewporterState,
     pub decoration_behavior: DecorationBehavior,
+    pub title_buttons: TitleButtons,
+    pub frame_theme: FrameTheme,
 
     pub seat: Seat<WprsState>,
 
     pub outputs: HashMap<u32, (Output, GlobalId)>,
     pub(crate) serial_map: SerialMap,
     pub(crate) pressed_keys: HashSet<u32>,
+    /// X11 surfaces which the active touch points went down on, by touch id.
+    pub(crate) touch_points: HashMap<i32, X11Surface>,
 
     pub xwm: Option<X11Wm>,
 
@@ -137,6 +221,8 @@ impl WprsCompositorState {
         dh: DisplayHandle,
         event_loop_handle: LoopHandle<'static, WprsState>,
         decoration_behavior: DecorationBehavior,
+        title_buttons: TitleButtons,
+        frame_theme: FrameTheme,
         xwayland_options: XwaylandOptions<K, V, I>,
     ) -> Self
     where
@@ -193,11 +279,15 @@ impl WprsCompositorState {
             xwayland_shell_state: XWaylandShellState::new::<WprsState>(&dh),
             data_device_state: DataDeviceState::new::<WprsState>(&dh),
             primary_selection_state: PrimarySelectionState::new::<WprsState>(&dh),
+            viewporter_state: ViewporterState::new::<WprsState>(&dh),
             decoration_behavior,
+            title_buttons,
+            frame_theme,
             seat,
             outputs: HashMap::new(),
             serial_map: SerialMap::new(),
             pressed_keys: HashSet::new(),
+            touch_points: HashMap::new(),
             xwm: None,
             x11_screen_offset: None,
             x11_surfaces: Vec::new(),
@@ -235,6 +325,9 @@ impl WprsCompositorState {
         let mut expanded_output = output.clone();
         expanded_output.mode.dimensions =
             (output.mode.dimensions.w * 3, output.mode.dimensions.h * 3).into();
+        expanded_output.logical_size = output
+            .logical_size
+            .map(|size| (size.w * 3, size.h * 3).into());
         self.x11_screen_offset =
             Some((-output.mode.dimensions.w, -output.mode.dimensions.h).into());
 
@@ -257,6 +350,9 @@ impl WprsCompositorState {
             expanded_output.mode.dimensions.h * 3,
         )
             .into();
+        expanded_output.logical_size = output
+            .logical_size
+            .map(|size| (size.w * 3, size.h * 3).into());
         self.x11_screen_offset =
             Some((-output.mode.dimensions.w, -output.mode.dimensions.h).into());
 
@@ -542,11 +638,22 @@ pub fn commit_inner(
                     state.client_state.subcompositor_state.clone(),
                     &state.client_state.qh,
                     state.compositor_state.decoration_behavior,
+                    state.compositor_state.frame_theme,
                 )
                 .location(loc!())?;
         }
     }
 
+    {
+        let viewport = surface_data.cached_state.current::<ViewportCachedState>();
+        xwayland_surface.update_viewport(
+            viewport.src,
+            viewport.dst,
+            state.client_state.viewporter.as_ref(),
+            &state.client_state.qh,
+        );
+    }
+
     debug!("buffer assignment: {:?}", &surface_attributes.buffer);
 
     match &surface_attributes.buffer {
@@ -555,6 +662,7 @@ pub fn commit_inner(
                 xwayland_surface.update_buffer(
                     &spec,
                     data,
+                    &state.client_state.supported_buffer_formats(),
                     state.client_state.pool.as_mut().location(loc!())?,
                 )
             })
@@ -718,3 +826,48 @@ smithay::delegate_data_device!(WprsState);
 smithay::delegate_output!(WprsState);
 smithay::delegate_primary_selection!(WprsState);
 smithay::delegate_xwayland_shell!(WprsState);
+smithay::delegate_viewporter!(WprsState);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_title_buttons_capabilities() {
+        let all = WindowManagerCapabilities::all();
+        assert_eq!(TitleButtons::All.capabilities(), all);
+        let maximize = TitleButtons::Maximize.capabilities();
+        assert!(maximize.contains(WindowManagerCapabilities::MAXIMIZE));
+        assert!(!maximize.contains(WindowManagerCapabilities::MINIMIZE));
+        let minimize = TitleButtons::Minimize.capabilities();
+        assert!(!minimize.contains(WindowManagerCapabilities::MAXIMIZE));
+        assert!(minimize.contains(WindowManagerCapabilities::MINIMIZE));
+        let close_only = TitleButtons::CloseOnly.capabilities();
+        assert!(!close_only
+            .intersects(WindowManagerCapabilities::MAXIMIZE | WindowManagerCapabilities::MINIMIZE));
+        // Masking never takes away unrelated capabilities.
+        for title_buttons in [
+            TitleButtons::All,
+            TitleButtons::Maximize,
+            TitleButtons::Minimize,
+            TitleButtons::CloseOnly,
+        ] {
+            let capabilities = title_buttons.capabilities();
+            assert!(capabilities.contains(WindowManagerCapabilities::WINDOW_MENU));
+            assert!(capabilities.contains(WindowManagerCapabilities::FULLSCREEN));
+        }
+    }
+
+    #[test]
+    fn test_frame_theme_from_config() {
+        let theme: FrameTheme = ron::from_str("Light").unwrap();
+        assert_eq!(theme.colors(), FrameColors::LIGHT);
+        let theme: FrameTheme = ron::from_str(
+            "Custom((titlebar_active: 0xFF000000, titlebar_inactive: 0xFF111111, \
+             button_icon: 0xFFFFFFFF, button_hover: 0xFF333333))",
+        )
+        .unwrap();
+        assert_eq!(theme.colors().titlebar_inactive, 0xFF111111);
+        assert_eq!(FrameTheme::default().colors(), FrameColors::DARK);
+    }
+}
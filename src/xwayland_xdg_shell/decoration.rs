@@ -19,7 +19,6 @@ use smithay_client_toolkit::seat::pointer::PointerEvent;
 use smithay_client_toolkit::seat::pointer::PointerEventKind;
 use smithay_client_toolkit::seat::pointer::BTN_LEFT;
 use smithay_client_toolkit::seat::pointer::BTN_RIGHT;
-use smithay_client_toolkit::shell::xdg::fallback_frame::FallbackFrame;
 use tracing::warn;
 
 use crate::prelude::*;
@@ -27,6 +26,7 @@ use crate::xwayland_xdg_shell::client::Role;
 use crate::xwayland_xdg_shell::client::WprsClientState;
 use crate::xwayland_xdg_shell::client::XWaylandSubSurface;
 use crate::xwayland_xdg_shell::client::XWaylandXdgToplevel;
+use crate::xwayland_xdg_shell::frame::ThemedFrame;
 use crate::xwayland_xdg_shell::xsurface_from_client_surface;
 use crate::xwayland_xdg_shell::WprsState;
 
@@ -104,7 +104,7 @@ pub trait FramedSurface {
         position: (f64, f64),
     ) -> Result<()>;
 
-    fn frame(&mut self) -> &mut FallbackFrame<WprsState>;
+    fn frame(&mut self) -> &mut ThemedFrame;
 
     fn handle_pointer_event_inner(
         &mut self,
@@ -208,7 +208,7 @@ impl FramedSurface for XWaylandXdgToplevel {
         Ok(())
     }
 
-    fn frame(&mut self) -> &mut FallbackFrame<WprsState> {
+    fn frame(&mut self) -> &mut ThemedFrame {
         &mut self.window_frame
     }
 
@@ -288,7 +288,7 @@ impl FramedSurface for XWaylandSubSurface {
         Ok(())
     }
 
-    fn frame(&mut self) -> &mut FallbackFrame<WprsState> {
+    fn frame(&mut self) -> &mut ThemedFrame {
         self.frame.as_mut().unwrap()
     }
 
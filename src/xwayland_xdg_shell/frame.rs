@@ -0,0 +1,619 @@
+// Copyright 2024 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Client-side decorations for X11 windows. These look like SCTK's
+//! FallbackFrame, which hardcodes its colors, but are drawn with the colors of
+//! the configured FrameTheme so that they can match the local desktop.
+
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+use smithay_client_toolkit::compositor::SurfaceData;
+use smithay_client_toolkit::reexports::client::backend::ObjectId;
+use smithay_client_toolkit::reexports::client::protocol::wl_shm;
+use smithay_client_toolkit::reexports::client::protocol::wl_subsurface::WlSubsurface;
+use smithay_client_toolkit::reexports::client::protocol::wl_surface::WlSurface;
+use smithay_client_toolkit::reexports::client::Dispatch;
+use smithay_client_toolkit::reexports::client::Proxy;
+use smithay_client_toolkit::reexports::client::QueueHandle;
+use smithay_client_toolkit::reexports::csd_frame::CursorIcon;
+use smithay_client_toolkit::reexports::csd_frame::DecorationsFrame;
+use smithay_client_toolkit::reexports::csd_frame::FrameAction;
+use smithay_client_toolkit::reexports::csd_frame::FrameClick;
+use smithay_client_toolkit::reexports::csd_frame::ResizeEdge;
+use smithay_client_toolkit::reexports::csd_frame::WindowManagerCapabilities;
+use smithay_client_toolkit::reexports::csd_frame::WindowState;
+use smithay_client_toolkit::shell::WaylandSurface;
+use smithay_client_toolkit::shm::slot::SlotPool;
+use smithay_client_toolkit::shm::Shm;
+use smithay_client_toolkit::subcompositor::SubcompositorState;
+use smithay_client_toolkit::subcompositor::SubsurfaceData;
+
+use crate::prelude::*;
+use crate::xwayland_xdg_shell::compositor::FrameColors;
+
+const HEADER_SIZE: u32 = 24;
+const BORDER_SIZE: u32 = 4;
+const ICON_SIZE: u32 = 10;
+
+const HEADER: usize = 0;
+const TOP_BORDER: usize = 1;
+const RIGHT_BORDER: usize = 2;
+const BOTTOM_BORDER: usize = 3;
+const LEFT_BORDER: usize = 4;
+
+#[derive(Debug)]
+struct Part {
+    surface: WlSurface,
+    subsurface: WlSubsurface,
+}
+
+impl Part {
+    fn new<State>(
+        parent: &WlSurface,
+        subcompositor: &SubcompositorState,
+        qh: &QueueHandle<State>,
+    ) -> Self
+    where
+        State: Dispatch<WlSurface, SurfaceData> + Dispatch<WlSubsurface, SubsurfaceData> + 'static,
+    {
+        let (subsurface, surface) = subcompositor.create_subsurface(parent.clone(), qh);
+        // The frame is updated together with the window it decorates.
+        subsurface.set_sync();
+        Self {
+            surface,
+            subsurface,
+        }
+    }
+
+    fn hide(&self) {
+        self.surface.attach(None, 0, 0);
+        self.surface.commit();
+    }
+}
+
+impl Drop for Part {
+    fn drop(&mut self) {
+        self.subsurface.destroy();
+        self.surface.destroy();
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Button {
+    Close,
+    Maximize,
+    Minimize,
+}
+
+/// The titlebar buttons shown for capabilities, from right to left.
+fn buttons(capabilities: WindowManagerCapabilities) -> impl Iterator<Item = Button> {
+    [
+        Some(Button::Close),
+        capabilities
+            .contains(WindowManagerCapabilities::MAXIMIZE)
+            .then_some(Button::Maximize),
+        capabilities
+            .contains(WindowManagerCapabilities::MINIMIZE)
+            .then_some(Button::Minimize),
+    ]
+    .into_iter()
+    .flatten()
+}
+
+/// The button at x in a titlebar of the given width. Buttons are HEADER_SIZE
+/// squares aligned to the right of the titlebar.
+fn button_at(width: u32, x: f64, capabilities: WindowManagerCapabilities) -> Option<Button> {
+    if !(0.0..f64::from(width)).contains(&x) {
+        return None;
+    }
+    let from_right = f64::from(width) - x;
+    buttons(capabilities).nth((from_right / f64::from(HEADER_SIZE)) as usize)
+}
+
+/// The edge resized by dragging the border part at (x, y), where (width,
+/// height) is the size of the part. The HEADER_SIZE ends of each border resize
+/// the adjacent corner.
+fn resize_edge(part: usize, x: f64, y: f64, width: u32, height: u32) -> ResizeEdge {
+    let corner = f64::from(HEADER_SIZE);
+    let start = |pos: f64| pos < corner;
+    let end = |pos: f64, len: u32| pos >= f64::from(len) - corner;
+    match part {
+        TOP_BORDER if start(x) => ResizeEdge::TopLeft,
+        TOP_BORDER if end(x, width) => ResizeEdge::TopRight,
+        TOP_BORDER => ResizeEdge::Top,
+        BOTTOM_BORDER if start(x) => ResizeEdge::BottomLeft,
+        BOTTOM_BORDER if end(x, width) => ResizeEdge::BottomRight,
+        BOTTOM_BORDER => ResizeEdge::Bottom,
+        LEFT_BORDER if start(y) => ResizeEdge::TopLeft,
+        LEFT_BORDER if end(y, height) => ResizeEdge::BottomLeft,
+        LEFT_BORDER => ResizeEdge::Left,
+        RIGHT_BORDER if start(y) => ResizeEdge::TopRight,
+        RIGHT_BORDER if end(y, height) => ResizeEdge::BottomRight,
+        RIGHT_BORDER => ResizeEdge::Right,
+        _ => ResizeEdge::None,
+    }
+}
+
+fn resize_cursor(edge: ResizeEdge) -> CursorIcon {
+    match edge {
+        ResizeEdge::Top => CursorIcon::NResize,
+        ResizeEdge::Bottom => CursorIcon::SResize,
+        ResizeEdge::Left => CursorIcon::WResize,
+        ResizeEdge::Right => CursorIcon::EResize,
+        ResizeEdge::TopLeft => CursorIcon::NwResize,
+        ResizeEdge::TopRight => CursorIcon::NeResize,
+        ResizeEdge::BottomLeft => CursorIcon::SwResize,
+        ResizeEdge::BottomRight => CursorIcon::SeResize,
+        _ => CursorIcon::Default,
+    }
+}
+
+/// Fills a rectangle of an argb8888 canvas which is canvas_width pixels wide.
+fn fill_rect(canvas: &mut [u8], canvas_width: u32, rect: (u32, u32, u32, u32), color: u32) {
+    let (x, y, width, height) = rect;
+    let color = color.to_le_bytes();
+    for row in y..y + height {
+        let start = ((row * canvas_width + x) * 4) as usize;
+        let end = start + (width * 4) as usize;
+        for pixel in canvas[start..end].chunks_exact_mut(4) {
+            pixel.copy_from_slice(&color);
+        }
+    }
+}
+
+/// Draws the titlebar buttons onto the canvas of the header, which is width
+/// pixels wide and drawn at scale.
+fn draw_buttons(
+    canvas: &mut [u8],
+    width: u32,
+    scale: u32,
+    capabilities: WindowManagerCapabilities,
+    hovered: Option<Button>,
+    colors: &FrameColors,
+) {
+    let size = HEADER_SIZE * scale;
+    let icon = ICON_SIZE * scale;
+    for (i, button) in buttons(capabilities).enumerate() {
+        let Some(x) = width.checked_sub((i as u32 + 1) * size) else {
+            break;
+        };
+        if hovered == Some(button) {
+            fill_rect(canvas, width, (x, 0, size, size), colors.button_hover);
+        }
+
+        let (x, y) = (x + (size - icon) / 2, (size - icon) / 2);
+        match button {
+            Button::Close => {
+                for d in 0..icon {
+                    let thickness = scale.min(icon - d);
+                    fill_rect(
+                        canvas,
+                        width,
+                        (x + d, y + d, thickness, 1),
+                        colors.button_icon,
+                    );
+                    fill_rect(
+                        canvas,
+                        width,
+                        (x + icon - d - thickness, y + d, thickness, 1),
+                        colors.button_icon,
+                    );
+                }
+            },
+            Button::Maximize => {
+                for rect in [
+                    (x, y, icon, scale),
+                    (x, y + icon - scale, icon, scale),
+                    (x, y, scale, icon),
+                    (x + icon - scale, y, scale, icon),
+                ] {
+                    fill_rect(canvas, width, rect, colors.button_icon);
+                }
+            },
+            Button::Minimize => {
+                fill_rect(
+                    canvas,
+                    width,
+                    (x, y + icon - scale, icon, scale),
+                    colors.button_icon,
+                );
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Location {
+    None,
+    Header,
+    Button(Button),
+    Edge(ResizeEdge),
+}
+
+#[derive(Debug)]
+pub struct ThemedFrame {
+    colors: FrameColors,
+    /// The header, then the top, right, bottom and left borders.
+    parts: [Part; 5],
+    pool: SlotPool,
+    state: WindowState,
+    wm_capabilities: WindowManagerCapabilities,
+    resizable: bool,
+    hidden: bool,
+    dirty: bool,
+    scale: u32,
+    width: NonZeroU32,
+    height: NonZeroU32,
+    pointer_location: Location,
+    /// The position of the pointer relative to the window, for the window
+    /// menu.
+    pointer_position: (i32, i32),
+}
+
+impl ThemedFrame {
+    pub fn new<State>(
+        parent: &impl WaylandSurface,
+        shm: &Shm,
+        subcompositor: &SubcompositorState,
+        qh: &QueueHandle<State>,
+        colors: FrameColors,
+    ) -> Result<Self>
+    where
+        State: Dispatch<WlSurface, SurfaceData> + Dispatch<WlSubsurface, SubsurfaceData> + 'static,
+    {
+        let parent = parent.wl_surface();
+        Ok(Self {
+            colors,
+            parts: [(); 5].map(|()| Part::new(parent, subcompositor, qh)),
+            pool: SlotPool::new(1, shm).location(loc!())?,
+            state: WindowState::empty(),
+            wm_capabilities: WindowManagerCapabilities::all(),
+            resizable: true,
+            hidden: false,
+            dirty: true,
+            scale: 1,
+            width: NonZeroU32::MIN,
+            height: NonZeroU32::MIN,
+            pointer_location: Location::None,
+            pointer_position: (0, 0),
+        })
+    }
+
+    fn borders_shown(&self) -> bool {
+        !self
+            .state
+            .intersects(WindowState::MAXIMIZED | WindowState::TILED)
+    }
+
+    /// The size the frame adds to the window.
+    fn frame_size(&self) -> (u32, u32) {
+        if self.hidden || self.state.contains(WindowState::FULLSCREEN) {
+            (0, 0)
+        } else if self.borders_shown() {
+            (2 * BORDER_SIZE, HEADER_SIZE + 2 * BORDER_SIZE)
+        } else {
+            (0, HEADER_SIZE)
+        }
+    }
+
+    /// The position relative to the window and the size of part, None if it
+    /// isn't shown.
+    fn geometry(&self, part: usize) -> Option<(i32, i32, u32, u32)> {
+        if self.hidden || self.state.contains(WindowState::FULLSCREEN) {
+            return None;
+        }
+        let (width, height) = (self.width.get(), self.height.get());
+        let (header, border) = (HEADER_SIZE as i32, BORDER_SIZE as i32);
+        match part {
+            HEADER => Some((0, -header, width, HEADER_SIZE)),
+            _ if !self.borders_shown() => None,
+            TOP_BORDER => Some((
+                -border,
+                -header - border,
+                width + 2 * BORDER_SIZE,
+                BORDER_SIZE,
+            )),
+            RIGHT_BORDER => Some((width as i32, -header, BORDER_SIZE, height + HEADER_SIZE)),
+            BOTTOM_BORDER => Some((-border, height as i32, width + 2 * BORDER_SIZE, BORDER_SIZE)),
+            LEFT_BORDER => Some((-border, -header, BORDER_SIZE, height + HEADER_SIZE)),
+            _ => None,
+        }
+    }
+}
+
+impl DecorationsFrame for ThemedFrame {
+    fn on_click(
+        &mut self,
+        _timestamp: Duration,
+        click: FrameClick,
+        pressed: bool,
+    ) -> Option<FrameAction> {
+        match (self.pointer_location, click, pressed) {
+            (Location::Header, FrameClick::Normal, true) => Some(FrameAction::Move),
+            (Location::Header, FrameClick::Alternate, true) => Some(FrameAction::ShowMenu(
+                self.pointer_position.0,
+                self.pointer_position.1,
+            )),
+            (Location::Edge(edge), FrameClick::Normal, true) => Some(FrameAction::Resize(edge)),
+            // Buttons act on release, like those of FallbackFrame.
+            (Location::Button(button), FrameClick::Normal, false) => Some(match button {
+                Button::Close => FrameAction::Close,
+                Button::Minimize => FrameAction::Minimize,
+                Button::Maximize if self.state.contains(WindowState::MAXIMIZED) => {
+                    FrameAction::UnMaximize
+                },
+                Button::Maximize => FrameAction::Maximize,
+            }),
+            _ => None,
+        }
+    }
+
+    fn click_point_moved(
+        &mut self,
+        _timestamp: Duration,
+        surface_id: &ObjectId,
+        x: f64,
+        y: f64,
+    ) -> Option<CursorIcon> {
+        let part = self
+            .parts
+            .iter()
+            .position(|part| part.surface.id() == *surface_id)?;
+        let (part_x, part_y, width, height) = self.geometry(part)?;
+        let location = if part == HEADER {
+            button_at(width, x, self.wm_capabilities).map_or(Location::Header, Location::Button)
+        } else if self.resizable {
+            Location::Edge(resize_edge(part, x, y, width, height))
+        } else {
+            Location::None
+        };
+
+        // Hovering a button highlights it.
+        if location != self.pointer_location
+            && (matches!(location, Location::Button(_))
+                || matches!(self.pointer_location, Location::Button(_)))
+        {
+            self.dirty = true;
+        }
+        self.pointer_location = location;
+        self.pointer_position = (x as i32 + part_x, y as i32 + part_y);
+
+        Some(match location {
+            Location::Edge(edge) => resize_cursor(edge),
+            _ => CursorIcon::Default,
+        })
+    }
+
+    fn click_point_left(&mut self) {
+        if matches!(self.pointer_location, Location::Button(_)) {
+            self.dirty = true;
+        }
+        self.pointer_location = Location::None;
+    }
+
+    fn update_state(&mut self, state: WindowState) {
+        if state != self.state {
+            self.state = state;
+            self.dirty = true;
+        }
+    }
+
+    fn update_wm_capabilities(&mut self, wm_capabilities: WindowManagerCapabilities) {
+        if wm_capabilities != self.wm_capabilities {
+            self.wm_capabilities = wm_capabilities;
+            self.dirty = true;
+        }
+    }
+
+    fn resize(&mut self, width: NonZeroU32, height: NonZeroU32) {
+        self.width = width;
+        self.height = height;
+        self.dirty = true;
+    }
+
+    fn set_scaling_factor(&mut self, scale_factor: f64) {
+        let scale = (scale_factor.ceil() as u32).max(1);
+        if scale != self.scale {
+            self.scale = scale;
+            self.dirty = true;
+        }
+    }
+
+    fn location(&self) -> (i32, i32) {
+        let (width, height) = self.frame_size();
+        // The borders are split evenly between both sides, the header is on
+        // top.
+        (-((width / 2) as i32), -((height - width / 2) as i32))
+    }
+
+    fn subtract_borders(
+        &self,
+        width: NonZeroU32,
+        height: NonZeroU32,
+    ) -> (Option<NonZeroU32>, Option<NonZeroU32>) {
+        let (frame_width, frame_height) = self.frame_size();
+        (
+            NonZeroU32::new(width.get().saturating_sub(frame_width)),
+            NonZeroU32::new(height.get().saturating_sub(frame_height)),
+        )
+    }
+
+    fn add_borders(&self, width: u32, height: u32) -> (u32, u32) {
+        let (frame_width, frame_height) = self.frame_size();
+        (width + frame_width, height + frame_height)
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn set_hidden(&mut self, hidden: bool) {
+        if hidden == self.hidden {
+            return;
+        }
+        self.hidden = hidden;
+        self.dirty = true;
+        if hidden {
+            for part in &self.parts {
+                part.hide();
+            }
+        }
+    }
+
+    fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    fn set_resizable(&mut self, resizable: bool) {
+        self.resizable = resizable;
+    }
+
+    fn draw(&mut self) -> bool {
+        self.dirty = false;
+        let geometries: [_; 5] = std::array::from_fn(|part| self.geometry(part));
+        let background = if self.state.contains(WindowState::ACTIVATED) {
+            self.colors.titlebar_active
+        } else {
+            self.colors.titlebar_inactive
+        };
+        let hovered = match self.pointer_location {
+            Location::Button(button) => Some(button),
+            _ => None,
+        };
+
+        for (idx, (part, geometry)) in self.parts.iter().zip(geometries).enumerate() {
+            let Some((x, y, width, height)) = geometry else {
+                part.hide();
+                continue;
+            };
+            let (width, height) = (width * self.scale, height * self.scale);
+            let (buffer, canvas) = log_and_continue!(self
+                .pool
+                .create_buffer(
+                    width as i32,
+                    height as i32,
+                    width as i32 * 4,
+                    wl_shm::Format::Argb8888,
+                )
+                .location(loc!()));
+
+            fill_rect(canvas, width, (0, 0, width, height), background);
+            if idx == HEADER {
+                draw_buttons(
+                    canvas,
+                    width,
+                    self.scale,
+                    self.wm_capabilities,
+                    hovered,
+                    &self.colors,
+                );
+            }
+
+            part.subsurface.set_position(x, y);
+            part.surface.set_buffer_scale(self.scale as i32);
+            log_and_continue!(buffer.attach_to(&part.surface).location(loc!()));
+            part.surface
+                .damage_buffer(0, 0, width as i32, height as i32);
+            part.surface.commit();
+        }
+        true
+    }
+
+    fn set_title(&mut self, _title: impl Into<String>) {
+        // Like FallbackFrame, titles aren't drawn: that would need a font
+        // renderer.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_button_at() {
+        let all = WindowManagerCapabilities::all();
+        assert_eq!(button_at(100, 99.0, all), Some(Button::Close));
+        assert_eq!(button_at(100, 70.0, all), Some(Button::Maximize));
+        assert_eq!(button_at(100, 40.0, all), Some(Button::Minimize));
+        assert_eq!(button_at(100, 10.0, all), None);
+        assert_eq!(button_at(100, 100.0, all), None);
+
+        // Buttons which aren't shown leave no gap.
+        let minimize = WindowManagerCapabilities::MINIMIZE;
+        assert_eq!(button_at(100, 70.0, minimize), Some(Button::Minimize));
+        let none = WindowManagerCapabilities::empty();
+        assert_eq!(button_at(100, 70.0, none), None);
+    }
+
+    #[test]
+    fn test_resize_edge() {
+        assert_eq!(
+            resize_edge(TOP_BORDER, 1.0, 1.0, 200, 4),
+            ResizeEdge::TopLeft
+        );
+        assert_eq!(resize_edge(TOP_BORDER, 100.0, 1.0, 200, 4), ResizeEdge::Top);
+        assert_eq!(
+            resize_edge(TOP_BORDER, 199.0, 1.0, 200, 4),
+            ResizeEdge::TopRight
+        );
+        assert_eq!(
+            resize_edge(RIGHT_BORDER, 1.0, 100.0, 4, 200),
+            ResizeEdge::Right
+        );
+        assert_eq!(
+            resize_edge(RIGHT_BORDER, 1.0, 199.0, 4, 200),
+            ResizeEdge::BottomRight
+        );
+        assert_eq!(
+            resize_edge(BOTTOM_BORDER, 1.0, 1.0, 200, 4),
+            ResizeEdge::BottomLeft
+        );
+        assert_eq!(
+            resize_edge(LEFT_BORDER, 1.0, 1.0, 4, 200),
+            ResizeEdge::TopLeft
+        );
+        assert_eq!(
+            resize_edge(LEFT_BORDER, 1.0, 100.0, 4, 200),
+            ResizeEdge::Left
+        );
+    }
+
+    #[test]
+    fn test_draw_buttons() {
+        let colors = FrameColors::LIGHT;
+        let width = 3 * HEADER_SIZE;
+        let mut canvas = vec![0; (width * HEADER_SIZE * 4) as usize];
+        draw_buttons(
+            &mut canvas,
+            width,
+            1,
+            WindowManagerCapabilities::all(),
+            Some(Button::Maximize),
+            &colors,
+        );
+        let pixel = |x: u32, y: u32| {
+            let start = ((y * width + x) * 4) as usize;
+            u32::from_le_bytes(canvas[start..start + 4].try_into().unwrap())
+        };
+        // Only the hovered button is highlighted.
+        assert_eq!(pixel(HEADER_SIZE + 1, 1), colors.button_hover);
+        assert_eq!(pixel(2 * HEADER_SIZE + 1, 1), 0);
+        // The icons are centered in their buttons.
+        let margin = (HEADER_SIZE - ICON_SIZE) / 2;
+        assert_eq!(pixel(2 * HEADER_SIZE + margin, margin), colors.button_icon);
+        assert_eq!(pixel(HEADER_SIZE + margin, margin), colors.button_icon);
+        assert_eq!(pixel(margin, margin + ICON_SIZE - 1), colors.button_icon);
+    }
+}
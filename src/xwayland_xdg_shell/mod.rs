@@ -18,15 +18,18 @@ use std::ffi::OsStr;
 use std::sync::Arc;
 
 use bimap::BiMap;
+use serde_derive::Serialize;
 use smithay::backend::input::KeyState;
 use smithay::input::keyboard::FilterResult;
-use smithay::output::Output;
 use smithay::reexports::calloop::LoopHandle;
 use smithay::reexports::wayland_server::backend::ObjectId as CompositorObjectId;
 use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface as CompositorWlSurface;
 use smithay::reexports::wayland_server::DisplayHandle;
 use smithay::reexports::wayland_server::Resource;
+use smithay::utils::Logical;
+use smithay::utils::Rectangle as SmithayRectangle;
 use smithay::utils::Serial;
+use smithay::utils::Size;
 use smithay::xwayland::xwm::WmWindowType;
 use smithay::xwayland::X11Surface;
 use smithay_client_toolkit::compositor::CompositorState;
@@ -39,6 +42,8 @@ use smithay_client_toolkit::reexports::client::protocol::wl_surface::WlSurface a
 use smithay_client_toolkit::reexports::client::Connection;
 use smithay_client_toolkit::reexports::client::Proxy;
 use smithay_client_toolkit::reexports::client::QueueHandle;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewport::WpViewport;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewporter::WpViewporter;
 use smithay_client_toolkit::shell::xdg::XdgShell;
 use smithay_client_toolkit::shell::WaylandSurface;
 use smithay_client_toolkit::shm::Shm;
@@ -51,11 +56,13 @@ use crate::constants;
 use crate::prelude::*;
 use crate::serialization::geometry::Point;
 use crate::serialization::geometry::Rectangle;
+use crate::serialization::wayland::OutputInfo;
 use crate::xwayland_xdg_shell::client::XWaylandSubSurface;
 
 pub mod client;
 pub mod compositor;
 pub mod decoration;
+pub mod frame;
 pub mod wmname;
 pub mod xwayland;
 
@@ -65,10 +72,34 @@ use client::XWaylandBuffer;
 use client::XWaylandXdgPopup;
 use client::XWaylandXdgToplevel;
 use compositor::DecorationBehavior;
+use compositor::FrameTheme;
+use compositor::TitleButtons;
 use compositor::WprsCompositorState;
 use compositor::X11Parent;
 use compositor::XwaylandOptions;
 
+/// Counts of the damage sent for a surface, to help find applications which
+/// generate excessive numbers of damage rectangles.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Serialize)]
+pub struct DamageStats {
+    /// Number of buffers attached.
+    pub frames: u64,
+    /// Number of damage rectangles received from the X11 side.
+    pub damage_rects: u64,
+    /// Number of frames with at least `constants::SENT_DAMAGE_LIMIT` damage
+    /// rectangles, for which the whole surface was damaged instead.
+    pub limit_fallbacks: u64,
+}
+
+/// DamageStats for an X11 window, as reported by the control server.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct SurfaceDamageStats {
+    pub window_id: u32,
+    pub title: String,
+    #[serde(flatten)]
+    pub stats: DamageStats,
+}
+
 #[derive(Debug, Default)]
 pub struct XWaylandSurface {
     pub(crate) x11_surface: Option<X11Surface>,
@@ -81,6 +112,13 @@ pub struct XWaylandSurface {
     pub(crate) children: HashSet<CompositorObjectId>,
     pub(crate) output_ids: HashSet<u32>,
     pub(crate) damage: Option<Vec<Rectangle<i32>>>,
+    pub(crate) damage_stats: DamageStats,
+    pub(crate) viewport: Option<WpViewport>,
+    /// The last source rectangle and destination size applied to viewport.
+    pub(crate) viewport_state: (
+        Option<SmithayRectangle<f64, Logical>>,
+        Option<Size<i32, Logical>>,
+    ),
 }
 
 impl XWaylandSurface {
@@ -109,6 +147,9 @@ impl XWaylandSurface {
             children: HashSet::new(),
             output_ids: HashSet::new(),
             damage: None,
+            damage_stats: DamageStats::default(),
+            viewport: None,
+            viewport_state: (None, None),
         })
     }
 
@@ -130,6 +171,44 @@ impl XWaylandSurface {
         Ok(())
     }
 
+    /// Mirrors the wp_viewport state of the xwayland surface onto the local
+    /// surface. Without wp_viewporter support in the local compositor, the
+    /// surface is shown at its buffer size instead.
+    fn update_viewport(
+        &mut self,
+        src: Option<SmithayRectangle<f64, Logical>>,
+        dst: Option<Size<i32, Logical>>,
+        viewporter: Option<&WpViewporter>,
+        qh: &QueueHandle<WprsState>,
+    ) {
+        if self.viewport_state == (src, dst) {
+            return;
+        }
+        let Some(viewporter) = viewporter else {
+            debug!("wp_viewporter is not available, ignoring viewport {src:?} {dst:?}");
+            return;
+        };
+        // Nothing to attach the viewport to yet, try again on the next commit.
+        if self.local_surface.is_none() && self.role.is_none() {
+            return;
+        }
+
+        let wl_surface = self.wl_surface().clone();
+        let viewport = self
+            .viewport
+            .get_or_insert_with(|| viewporter.get_viewport(&wl_surface, qh, ()));
+        // -1 unsets the source rectangle and destination size.
+        match src {
+            Some(src) => viewport.set_source(src.loc.x, src.loc.y, src.size.w, src.size.h),
+            None => viewport.set_source(-1.0, -1.0, -1.0, -1.0),
+        }
+        match dst {
+            Some(dst) => viewport.set_destination(dst.w, dst.h),
+            None => viewport.set_destination(-1, -1),
+        }
+        self.viewport_state = (src, dst);
+    }
+
     fn ready(&self) -> bool {
         match &self.role {
             Some(Role::XdgToplevel(toplevel)) if !toplevel.configured => false,
@@ -153,7 +232,9 @@ impl XWaylandSurface {
                 // The only possible error here is AlreadyActive, which we can
                 // ignore.
                 _ = buffer.active_buffer.attach_to(&surface);
+                self.damage_stats.frames += 1;
                 if let Some(damage_rects) = &self.damage.take() {
+                    self.damage_stats.damage_rects += damage_rects.len() as u64;
                     // avoid overwhelming wayland connection
                     if damage_rects.len() < constants::SENT_DAMAGE_LIMIT {
                         for damage_rect in damage_rects {
@@ -165,6 +246,7 @@ impl XWaylandSurface {
                             );
                         }
                     } else {
+                        self.damage_stats.limit_fallbacks += 1;
                         surface.damage_buffer(0, 0, i32::MAX, i32::MAX);
                     }
                 } else {
@@ -197,6 +279,7 @@ impl XWaylandSurface {
         subcompositor_state: Arc<SubcompositorState>,
         qh: &QueueHandle<WprsState>,
         decoration_behavior: DecorationBehavior,
+        frame_theme: FrameTheme,
     ) -> Result<()> {
         self.x11_surface = Some(x11_surface);
         if self.role.is_some() {
@@ -272,6 +355,7 @@ impl XWaylandSurface {
                     subcompositor_state,
                     qh,
                     decoration_behavior,
+                    frame_theme,
                 )
                 .location(loc!())?;
             },
@@ -288,6 +372,7 @@ impl XWaylandSurface {
                     subcompositor_state,
                     qh,
                     decoration_behavior,
+                    frame_theme,
                 )
                 .location(loc!())?;
             },
@@ -300,6 +385,7 @@ impl XWaylandSurface {
                     shm_state,
                     subcompositor_state,
                     qh,
+                    frame_theme,
                 )
                 .location(loc!())?;
             },
@@ -323,6 +409,7 @@ impl XWaylandSurface {
                     shm_state,
                     subcompositor_state,
                     qh,
+                    frame_theme,
                 )
                 .location(loc!())?;
             },
@@ -354,7 +441,6 @@ pub struct WprsState {
     pub compositor_state: WprsCompositorState,
     pub surface_bimap: BiMap<CompositorObjectId, ClientObjectId>,
     pub surfaces: HashMap<CompositorObjectId, XWaylandSurface>,
-    pub outputs: HashMap<u32, Output>,
 }
 
 impl WprsState {
@@ -365,6 +451,8 @@ impl WprsState {
         conn: Connection,
         event_loop_handle: LoopHandle<'static, Self>,
         decoration_behavior: DecorationBehavior,
+        title_buttons: TitleButtons,
+        frame_theme: FrameTheme,
         xwayland_options: XwaylandOptions<K, V, I>,
     ) -> Result<Self>
     where
@@ -380,11 +468,12 @@ impl WprsState {
                 dh,
                 event_loop_handle,
                 decoration_behavior,
+                title_buttons,
+                frame_theme,
                 xwayland_options,
             ),
             surface_bimap: BiMap::new(),
             surfaces: HashMap::new(),
-            outputs: HashMap::new(),
         })
     }
 
@@ -459,12 +548,42 @@ impl WprsState {
         Ok(())
     }
 
+    pub(crate) fn release_pressed_keys(&mut self, serial: Serial) -> Result<()> {
+        for keycode in self.compositor_state.pressed_keys.clone() {
+            self.set_key_state(keycode, KeyState::Released, serial)
+                .location(loc!())?;
+        }
+        Ok(())
+    }
+
+    /// Damage statistics for every surface which is associated with an X11
+    /// window.
+    pub fn damage_stats(&self) -> Vec<SurfaceDamageStats> {
+        self.surfaces
+            .values()
+            .filter_map(|xwayland_surface| {
+                let x11_surface = xwayland_surface.x11_surface.as_ref()?;
+                Some(SurfaceDamageStats {
+                    window_id: x11_surface.window_id(),
+                    title: x11_surface.title(),
+                    stats: xwayland_surface.damage_stats,
+                })
+            })
+            .collect()
+    }
+
     pub fn compositor_surface_from_client_surface(
         &self,
         client_surface: &ClientWlSurface,
     ) -> Option<CompositorWlSurface> {
         let compositor_surface_id = self.surface_bimap.get_by_right(&client_surface.id())?;
+        self.compositor_surface(compositor_surface_id)
+    }
 
+    fn compositor_surface(
+        &self,
+        compositor_surface_id: &CompositorObjectId,
+    ) -> Option<CompositorWlSurface> {
         let Ok(client) = self.dh.get_client(compositor_surface_id.clone()) else {
             return None;
         };
@@ -497,11 +616,36 @@ impl WprsState {
             &compositor_surface,
             &new_ids,
             &xwayland_surface.output_ids,
-            |id| self.outputs.get(id),
+            |id| {
+                self.compositor_state
+                    .outputs
+                    .get(id)
+                    .map(|(output, _)| output)
+            },
         );
 
         xwayland_surface.output_ids = new_ids;
     }
+
+    /// Removes the output, after sending leave for it to the surfaces which
+    /// were on it.
+    #[instrument(skip(self), level = "debug")]
+    pub(crate) fn destroy_output(&mut self, output: OutputInfo) {
+        if let Some((local_output, _)) = self.compositor_state.outputs.get(&output.id) {
+            let surface_ids: Vec<CompositorObjectId> = self
+                .surfaces
+                .iter_mut()
+                .filter(|(_, xwayland_surface)| xwayland_surface.output_ids.remove(&output.id))
+                .map(|(id, _)| id.clone())
+                .collect();
+            for surface_id in surface_ids {
+                if let Some(surface) = self.compositor_surface(&surface_id) {
+                    local_output.leave(&surface);
+                }
+            }
+        }
+        self.compositor_state.destroy_output(output);
+    }
 }
 
 pub fn xsurface_from_client_surface<'a>(